@@ -0,0 +1,38 @@
+//! Interactive terminal
+//!
+//! Usage: `cargo run --example miniterm --features miniterm -- <PORT> [BAUD]`
+
+use serial_rs::{
+    miniterm::{Miniterm, MinitermConfig},
+    SerialPortSettings,
+};
+
+#[cfg(windows)]
+use serial_rs::windows::COMPort;
+
+#[cfg(unix)]
+use serial_rs::posix::TTYPort;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = match args.next() {
+        Some(p) => p,
+        None => {
+            eprintln!("Usage: miniterm <PORT> [BAUD]");
+            std::process::exit(1);
+        }
+    };
+    let baud = args.next().map(|b| b.parse().expect("Invalid baud rate")).unwrap_or(115200);
+    let settings = SerialPortSettings::default().baud(baud).read_timeout(Some(100));
+
+    #[cfg(windows)]
+    let port = COMPort::new(path, Some(settings)).unwrap();
+
+    #[cfg(unix)]
+    let port = TTYPort::new(path, Some(settings)).unwrap();
+
+    let mut term = Miniterm::new(port, MinitermConfig::default());
+    if let Err(e) = term.run() {
+        eprintln!("Terminal error: {}", e);
+    }
+}
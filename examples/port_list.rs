@@ -1,4 +1,4 @@
-use std::io::{Read, BufReader, BufRead, Write, BufWriter};
+use std::io::{BufReader, BufRead, Write};
 
 use serial_rs::{PortScanner, SerialPortSettings, FlowControl, SerialPort};
 
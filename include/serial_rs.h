@@ -0,0 +1,182 @@
+/*
+ * C API for serial-rs, enabled with the `ffi` feature.
+ *
+ * Build the library with:
+ *   cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * Generated by cbindgen from src/ffi.rs, do not edit by hand.
+ */
+
+#ifndef SERIAL_RS_H
+#define SERIAL_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Success
+ */
+#define SERIAL_RS_OK 0
+
+/**
+ * A pointer or setting passed in was invalid
+ */
+#define SERIAL_RS_ERR_INVALID_ARG -1
+
+/**
+ * An IO error occurred
+ */
+#define SERIAL_RS_ERR_IO -2
+
+/**
+ * The OS reported an error
+ */
+#define SERIAL_RS_ERR_OS -3
+
+/**
+ * The library reported an error
+ */
+#define SERIAL_RS_ERR_LIBRARY -4
+
+/**
+ * The operation timed out
+ */
+#define SERIAL_RS_ERR_TIMEOUT -5
+
+/**
+ * The operation was cancelled
+ */
+#define SERIAL_RS_ERR_CANCELLED -6
+
+/**
+ * The output buffer is too small
+ */
+#define SERIAL_RS_ERR_BUFFER_TOO_SMALL -7
+
+/**
+ * No data was available on a non-blocking port
+ */
+#define SERIAL_RS_ERR_WOULD_BLOCK -8
+
+/**
+ * The port or its driver does not support the operation
+ */
+#define SERIAL_RS_ERR_UNSUPPORTED -9
+
+/**
+ * The device has gone away and the port must be reopened
+ */
+#define SERIAL_RS_ERR_DISCONNECTED -10
+
+/**
+ * The driver reported framing, parity or overrun errors, or a break
+ */
+#define SERIAL_RS_ERR_LINE -11
+
+/**
+ * Opaque handle to an open port
+ */
+typedef struct SerialRsPort SerialRsPort;
+
+/**
+ * Port settings
+ */
+typedef struct SerialRsSettings {
+  /**
+   * Baud rate
+   */
+  uint32_t baud_rate;
+  /**
+   * Data bits, 5 to 8
+   */
+  uint8_t byte_size;
+  /**
+   * 0 = none, 1 = odd, 2 = even
+   */
+  uint8_t parity;
+  /**
+   * 0 = one, 1 = one and a half, 2 = two
+   */
+  uint8_t stop_bits;
+  /**
+   * 0 = none, 1 = XON/XOFF, 2 = RTS/CTS, 3 = DSR/DTR
+   */
+  uint8_t flow_control;
+  /**
+   * Read timeout in milliseconds, or -1 for none
+   */
+  int64_t read_timeout_ms;
+  /**
+   * Write timeout in milliseconds, or -1 for none
+   */
+  int64_t write_timeout_ms;
+} SerialRsSettings;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Fills `out` with the default settings
+ */
+int32_t serial_rs_settings_default(struct SerialRsSettings *out);
+
+/**
+ * Opens the port at `path`. `settings` may be NULL to use the defaults.
+ * On success, `*out` receives a handle which must be freed with [serial_rs_close]
+ */
+int32_t serial_rs_open(const char *path,
+                       const struct SerialRsSettings *settings,
+                       struct SerialRsPort **out);
+
+/**
+ * Closes a port and frees its handle. Passing NULL does nothing
+ */
+void serial_rs_close(struct SerialRsPort *port);
+
+/**
+ * Applies new settings to an open port
+ */
+int32_t serial_rs_configure(struct SerialRsPort *port, const struct SerialRsSettings *settings);
+
+/**
+ * Reads the port's current settings into `out`
+ */
+int32_t serial_rs_get_settings(const struct SerialRsPort *port, struct SerialRsSettings *out);
+
+/**
+ * Reads up to `len` bytes into `buf`. Returns the number of bytes read, or a negative error code
+ */
+ptrdiff_t serial_rs_read(const struct SerialRsPort *port, uint8_t *buf, size_t len);
+
+/**
+ * Writes up to `len` bytes from `buf`. Returns the number of bytes written, or a negative error code
+ */
+ptrdiff_t serial_rs_write(const struct SerialRsPort *port,
+                          const uint8_t *buf,
+                          size_t len);
+
+/**
+ * Waits until all written data has been transmitted
+ */
+int32_t serial_rs_flush(const struct SerialRsPort *port);
+
+/**
+ * Lists the paths of the available ports into `buf` as a newline separated, NUL
+ * terminated string. `*needed` (if not NULL) receives the buffer size required.
+ * Returns [SERIAL_RS_ERR_BUFFER_TOO_SMALL] if `buf` is too small; `buf` may be NULL
+ * to only query the size
+ */
+int32_t serial_rs_list_ports(char *buf, size_t len, size_t *needed);
+
+/**
+ * Returns a description of the last error on the calling thread, or NULL if there
+ * was none. The string is valid until the next call on this thread
+ */
+const char *serial_rs_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SERIAL_RS_H */
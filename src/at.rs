@@ -0,0 +1,345 @@
+//! AT command layer
+//!
+//! An [AtPort] sends commands and collects the response lines up to the final
+//! result code (OK, ERROR, +CME ERROR, ...). Lines which are not part of a response,
+//! such as RING or +CMTI, are unsolicited result codes (URCs), and are passed to
+//! the handler registered with [AtPort::on_urc]. URCs are only seen while the port
+//! is being read, so an idle application should call [AtPort::poll_urcs].
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::at::AtPort;
+//! let mut modem = AtPort::new(port);
+//! modem.on_urc(|urc| println!("URC: {urc}"));
+//! modem.command("ATE0")?;
+//! let signal = modem.send("AT+CSQ")?;
+//! println!("Signal: {:?}", signal.value("+CSQ"));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt::Display,
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+/// Size of each read issued to the port
+const READ_CHUNK: usize = 256;
+
+/// URC prefixes recognised by default
+const DEFAULT_URCS: &[&str] = &[
+    "RING", "+CRING:", "+CLIP:", "+CMTI:", "+CMT:", "+CDS:", "+CBM:", "+CUSD:", "+CREG:", "+CGREG:", "+CEREG:",
+];
+
+type UrcHandler = Box<dyn FnMut(&str) + Send>;
+
+/// Final result code ending a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResultCode {
+    /// OK
+    Ok,
+    /// ERROR
+    Error,
+    /// +CME ERROR, with the error code or text
+    CmeError(String),
+    /// +CMS ERROR, with the error code or text
+    CmsError(String),
+    /// CONNECT, with any text following it such as the line speed
+    Connect(String),
+    /// NO CARRIER
+    NoCarrier,
+    /// BUSY
+    Busy,
+    /// NO DIALTONE
+    NoDialtone,
+    /// NO ANSWER
+    NoAnswer,
+}
+
+impl ResultCode {
+    /// Parses a line, returning None if it is not a final result code
+    pub fn parse(line: &str) -> Option<Self> {
+        Some(match line {
+            "OK" => Self::Ok,
+            "ERROR" => Self::Error,
+            "NO CARRIER" => Self::NoCarrier,
+            "BUSY" => Self::Busy,
+            "NO DIALTONE" | "NO DIAL TONE" => Self::NoDialtone,
+            "NO ANSWER" => Self::NoAnswer,
+            l if l.starts_with("+CME ERROR:") => Self::CmeError(l[11..].trim().to_string()),
+            l if l.starts_with("+CMS ERROR:") => Self::CmsError(l[11..].trim().to_string()),
+            l if l == "CONNECT" || l.starts_with("CONNECT ") => Self::Connect(l[7..].trim().to_string()),
+            _ => return None,
+        })
+    }
+}
+
+impl Display for ResultCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResultCode::Ok => write!(f, "OK"),
+            ResultCode::Error => write!(f, "ERROR"),
+            ResultCode::CmeError(e) => write!(f, "+CME ERROR: {e}"),
+            ResultCode::CmsError(e) => write!(f, "+CMS ERROR: {e}"),
+            ResultCode::Connect(s) if s.is_empty() => write!(f, "CONNECT"),
+            ResultCode::Connect(s) => write!(f, "CONNECT {s}"),
+            ResultCode::NoCarrier => write!(f, "NO CARRIER"),
+            ResultCode::Busy => write!(f, "BUSY"),
+            ResultCode::NoDialtone => write!(f, "NO DIALTONE"),
+            ResultCode::NoAnswer => write!(f, "NO ANSWER"),
+        }
+    }
+}
+
+/// Response to a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtResponse {
+    /// Information lines, without the echo or the result code
+    pub lines: Vec<String>,
+    /// Final result code
+    pub result: ResultCode,
+}
+
+impl AtResponse {
+    /// Returns true if the result code is OK
+    pub fn is_ok(&self) -> bool {
+        self.result == ResultCode::Ok
+    }
+
+    /// Returns the text after `prefix` on the first line starting with it. For
+    /// example `value("+CSQ")` returns `"20,99"` for the line `+CSQ: 20,99`
+    pub fn value(&self, prefix: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .find_map(|l| l.strip_prefix(prefix))
+            .map(|v| v.trim_start_matches(':').trim())
+    }
+}
+
+/// AT command interface on a port
+pub struct AtPort<P: SerialPort> {
+    port: P,
+    rx: Vec<u8>,
+    timeout: Duration,
+    suppress_echo: bool,
+    urc_prefixes: Vec<String>,
+    handler: Option<UrcHandler>,
+}
+
+impl<P: SerialPort> std::fmt::Debug for AtPort<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AtPort")
+            .field("port", &self.port.path())
+            .field("timeout", &self.timeout)
+            .field("suppress_echo", &self.suppress_echo)
+            .field("urc_prefixes", &self.urc_prefixes)
+            .finish()
+    }
+}
+
+impl<P: SerialPort> AtPort<P> {
+    /// Creates an AT interface with a 5 second command timeout, echo suppression,
+    /// and the common URCs (RING, +CMTI, +CREG, ...)
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            rx: Vec::new(),
+            timeout: Duration::from_secs(5),
+            suppress_echo: true,
+            urc_prefixes: DEFAULT_URCS.iter().map(|s| s.to_string()).collect(),
+            handler: None,
+        }
+    }
+
+    /// Sets the default time to wait for a command's result code
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets whether the echo of a command is removed from its response. This is
+    /// on by default, so responses look the same with echo (ATE1) on or off
+    pub fn suppress_echo(mut self, suppress: bool) -> Self {
+        self.suppress_echo = suppress;
+        self
+    }
+
+    /// Adds a prefix which marks a line as a URC, such as `+QIURC:`
+    pub fn urc_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.urc_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Sets the handler called with every URC. URCs received without a handler are
+    /// dropped
+    pub fn on_urc<F: FnMut(&str) + Send + 'static>(&mut self, handler: F) {
+        self.handler = Some(Box::new(handler));
+    }
+
+    /// Sends a command, and waits for its result code using the default timeout.
+    /// `\r` is appended to the command.
+    ///
+    /// A result code other than OK is not an error, see [AtPort::command]
+    pub fn send(&mut self, cmd: &str) -> SerialResult<AtResponse> {
+        self.send_timeout(cmd, self.timeout)
+    }
+
+    /// Sends a command, and waits up to `timeout` for its result code
+    pub fn send_timeout(&mut self, cmd: &str, timeout: Duration) -> SerialResult<AtResponse> {
+        // Anything received before the command cannot be part of its response
+        self.poll_urcs(Duration::ZERO)?;
+        self.write_raw(format!("{cmd}\r").as_bytes())?;
+        self.read_response(cmd, timeout)
+    }
+
+    /// Sends a command, and returns its information lines if the result is OK, else
+    /// an error containing the result code
+    pub fn command(&mut self, cmd: &str) -> SerialResult<Vec<String>> {
+        let resp = self.send(cmd)?;
+        match resp.result {
+            ResultCode::Ok => Ok(resp.lines),
+            r => Err(SerialError::LibraryError(format!("{cmd} failed: {r}"))),
+        }
+    }
+
+    /// Reads the response to `cmd`, which has already been sent, until its result
+    /// code. URCs received meanwhile are passed to the handler
+    pub fn read_response(&mut self, cmd: &str, timeout: Duration) -> SerialResult<AtResponse> {
+        let deadline = Instant::now() + timeout;
+        let mut lines = Vec::new();
+        let mut echo = self.suppress_echo;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let line = match self.read_line(remaining)? {
+                Some(l) => l,
+                None => {
+                    return Err(SerialError::IoError(std::io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("No result code for {cmd}"),
+                    )))
+                }
+            };
+            if echo && line.eq_ignore_ascii_case(cmd.trim()) {
+                echo = false;
+                continue;
+            }
+            if let Some(result) = ResultCode::parse(&line) {
+                return Ok(AtResponse { lines, result });
+            }
+            if self.is_urc(&line, Some(cmd)) {
+                self.dispatch(&line);
+            } else {
+                echo = false;
+                lines.push(line);
+            }
+        }
+    }
+
+    /// Reads lines until `timeout` passes without one, passing each to the URC
+    /// handler. Returns the number of URCs handled
+    pub fn poll_urcs(&mut self, timeout: Duration) -> SerialResult<usize> {
+        let mut count = 0;
+        while let Some(line) = self.read_line(timeout)? {
+            self.dispatch(&line);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reads the next non empty line, without its line ending. Returns None if no
+    /// complete line arrives within `timeout`
+    pub fn read_line(&mut self, timeout: Duration) -> SerialResult<Option<String>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            while let Some(pos) = self.rx.iter().position(|b| *b == b'\r' || *b == b'\n') {
+                let line: Vec<u8> = self.rx.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                if !line.is_empty() {
+                    return Ok(Some(line));
+                }
+            }
+            if !self.fill(deadline)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads up to and including the next `delim`, such as a `>` prompt which has no
+    /// line ending, and returns the bytes before it. Returns None if `delim` does not
+    /// arrive within `timeout`
+    pub fn read_until(&mut self, delim: u8, timeout: Duration) -> SerialResult<Option<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(pos) = self.rx.iter().position(|b| *b == delim) {
+                let mut data: Vec<u8> = self.rx.drain(..=pos).collect();
+                data.pop();
+                return Ok(Some(data));
+            }
+            if !self.fill(deadline)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads whatever is available into the buffer. Returns false if nothing
+    /// arrived before `deadline`
+    fn fill(&mut self, deadline: Instant) -> SerialResult<bool> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !self.port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+            return Ok(false);
+        }
+        let mut buf = [0u8; READ_CHUNK];
+        match self.port.read(&mut buf) {
+            Ok(n) => self.rx.extend_from_slice(&buf[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+        Ok(true)
+    }
+
+    /// Writes bytes to the port as is, for example the body of an SMS after the `>`
+    /// prompt
+    pub fn write_raw(&mut self, data: &[u8]) -> SerialResult<()> {
+        self.port.write_all(data).and_then(|_| self.port.flush()).map_err(SerialError::IoError)
+    }
+
+    /// A line is a URC if it starts with a URC prefix, unless it is the response to
+    /// the command being run, such as `+CREG: 0,1` in response to `AT+CREG?`
+    fn is_urc(&self, line: &str, cmd: Option<&str>) -> bool {
+        self.urc_prefixes.iter().any(|p| {
+            let name = p.trim_end_matches(':');
+            line.starts_with(p.as_str()) && !cmd.map(|c| c.to_ascii_uppercase().contains(name)).unwrap_or(false)
+        })
+    }
+
+    fn dispatch(&mut self, line: &str) {
+        if let Some(handler) = self.handler.as_mut() {
+            handler(line);
+        }
+    }
+
+    /// Takes any bytes received but not yet returned as lines, such as data which
+    /// followed CONNECT
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rx)
+    }
+
+    /// Gets a reference to the underlying port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the underlying port. Reading from it directly
+    /// may lose buffered response lines
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port, discarding any buffered data
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+}
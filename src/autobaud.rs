@@ -0,0 +1,138 @@
+//! Baud rate detection
+//!
+//! When the baud rate of a device is unknown, [detect_baud] listens at each
+//! candidate rate, optionally sending a probe such as `\r` to provoke a prompt,
+//! and scores what it receives. At the wrong rate, received data is mostly
+//! non-printable and causes framing errors, so the rate giving the most readable
+//! text with the fewest line errors wins. This suits consoles and text protocols,
+//! not binary ones.
+//!
+//! Line errors are read with [crate::BufferControl::line_error_counts] where the driver
+//! supports it, otherwise only the received text is scored.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(mut port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::autobaud::{detect_baud, COMMON_BAUDS};
+//! match detect_baud(&mut port, COMMON_BAUDS, Some(b"\r"))? {
+//!     Some(baud) => println!("Device is at {baud} baud"),
+//!     None => println!("No readable response"),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+/// Common baud rates, most likely first
+pub const COMMON_BAUDS: &[u32] = &[115_200, 9600, 57_600, 38_400, 19_200, 4800, 2400, 1200, 230_400, 460_800, 921_600];
+
+/// How long [detect_baud] listens at each rate
+const LISTEN: Duration = Duration::from_millis(500);
+
+/// Lowest score [detect_baud] accepts
+const MIN_SCORE: f32 = 0.5;
+
+/// Most data read at each rate
+const MAX_SAMPLE: usize = 4096;
+
+/// Result of listening at one baud rate
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BaudScore {
+    /// Baud rate
+    pub baud: u32,
+    /// Number of bytes received
+    pub received: usize,
+    /// Fraction of the bytes received which were printable ASCII or whitespace
+    pub printable: f32,
+    /// Framing, parity and break errors counted, if the driver counts them
+    pub line_errors: Option<u32>,
+    /// Overall score from 0 (nothing received, or garbage) to 1 (clean text)
+    pub score: f32,
+}
+
+/// Tries each of `candidates` in turn and returns the baud rate which received
+/// the most readable data, or None if nothing readable was received. `probe` is
+/// sent after switching to each rate. Put the most likely rates first, as they
+/// win ties.
+///
+/// The port is left at the detected rate, or at its original rate if none was
+/// detected. Other settings are not changed
+pub fn detect_baud<P: SerialPort + ?Sized>(port: &mut P, candidates: &[u32], probe: Option<&[u8]>) -> SerialResult<Option<u32>> {
+    let original = port.settings().baud_rate;
+    let best = score_bauds(port, candidates, probe, LISTEN)?
+        .into_iter()
+        .filter(|s| s.score >= MIN_SCORE)
+        // On a tie, the earlier candidate wins
+        .reduce(|best, s| if s.score > best.score { s } else { best })
+        .map(|s| s.baud);
+    port.set_baud_live(best.unwrap_or(original))?;
+    Ok(best)
+}
+
+/// Listens at each of `candidates` for `listen`, sending `probe` first, and
+/// returns the score of every rate in the order tried. Leaves the port at the
+/// last rate tried
+pub fn score_bauds<P: SerialPort + ?Sized>(
+    port: &mut P,
+    candidates: &[u32],
+    probe: Option<&[u8]>,
+    listen: Duration,
+) -> SerialResult<Vec<BaudScore>> {
+    let mut scores = Vec::with_capacity(candidates.len());
+    for &baud in candidates {
+        port.set_baud_live(baud)?;
+        port.clear_input_buffer()?;
+        let errors_before = port.line_error_counts().ok();
+        if let Some(probe) = probe {
+            port.write_all(probe).and_then(|_| port.flush()).map_err(SerialError::IoError)?;
+        }
+        let data = sample(port, listen)?;
+        let line_errors = errors_before.and_then(|before| {
+            let errors = port.line_error_counts().ok()?.since(&before);
+            Some(errors.framing.wrapping_add(errors.parity).wrapping_add(errors.breaks))
+        });
+        scores.push(score(baud, &data, line_errors));
+    }
+    Ok(scores)
+}
+
+/// Reads whatever arrives within `listen`
+fn sample<P: SerialPort + ?Sized>(port: &mut P, listen: Duration) -> SerialResult<Vec<u8>> {
+    let deadline = Instant::now() + listen;
+    let mut data = Vec::new();
+    let mut chunk = [0u8; 256];
+    while data.len() < MAX_SAMPLE {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || !port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+            break;
+        }
+        match port.read_shared(&mut chunk) {
+            Ok(n) => data.extend_from_slice(&chunk[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+    }
+    Ok(data)
+}
+
+fn score(baud: u32, data: &[u8], line_errors: Option<u32>) -> BaudScore {
+    let printable = match data.len() {
+        0 => 0.0,
+        len => {
+            let text = data.iter().filter(|b| b.is_ascii_graphic() || b" \r\n\t".contains(b)).count();
+            text as f32 / len as f32
+        }
+    };
+    // Each line error is roughly one corrupted character
+    let clean = match (data.len(), line_errors) {
+        (0, _) => 0.0,
+        (len, Some(errors)) => 1.0 - (errors as f32 / len as f32).min(1.0),
+        (_, None) => 1.0,
+    };
+    BaudScore { baud, received: data.len(), printable, line_errors, score: printable * clean }
+}
@@ -0,0 +1,302 @@
+//! Bit error rate testing
+//!
+//! [PatternGenerator] produces a test pattern, and [PatternChecker] compares
+//! received data against it, counting bit errors. Pseudo-random (PRBS) patterns
+//! exercise the link with every bit sequence up to the pattern's order, and the
+//! checker synchronises itself to the received data, so it does not matter where
+//! in the pattern reception starts, and sync is regained after dropped bytes.
+//!
+//! [run_loopback] sends and checks a pattern on one port at the same time, for a
+//! port with a loopback plug, a looped-back radio link, or one end of a virtual
+//! port pair whose other end echoes. For links between two machines, run a
+//! generator on one end and a checker on the other.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use std::time::Duration;
+//! use serial_rs::bert::{run_loopback, Limit, Pattern};
+//! let report = run_loopback(&port, Pattern::Prbs15, Limit::Duration(Duration::from_secs(10)))?;
+//! println!("{} bit errors in {} bits, BER {:e}", report.bit_errors, report.bits_checked(), report.bit_error_rate());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+/// Consecutive bytes with errors after which the checker assumes sync was lost,
+/// for example because a byte was dropped
+const SYNC_LOSS_BYTES: u32 = 4;
+
+/// How long [run_loopback] waits for the last of the data to return
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Size of each write made by [run_loopback]
+const WRITE_CHUNK: usize = 256;
+
+/// Test pattern
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// PRBS-9 (x^9 + x^5 + 1), repeating every 511 bits
+    Prbs9,
+    /// PRBS-15 (x^15 + x^14 + 1), repeating every 32767 bits
+    Prbs15,
+    /// 0x55, which alternates every bit on the line including the start bit
+    Alternating,
+    /// The same byte repeated
+    Fixed(u8),
+}
+
+impl Pattern {
+    /// Register length and feedback taps of a PRBS pattern
+    fn lfsr(&self) -> Option<(u32, u32)> {
+        match self {
+            Pattern::Prbs9 => Some((9, 5)),
+            Pattern::Prbs15 => Some((15, 14)),
+            _ => None,
+        }
+    }
+}
+
+/// Generates a test pattern
+#[derive(Debug, Copy, Clone)]
+pub struct PatternGenerator {
+    pattern: Pattern,
+    /// The last bits of a PRBS pattern, newest in bit 0
+    state: u32,
+}
+
+impl PatternGenerator {
+    /// Creates a generator at the start of the pattern
+    pub fn new(pattern: Pattern) -> Self {
+        let state = pattern.lfsr().map_or(0, |(len, _)| (1 << len) - 1);
+        Self { pattern, state }
+    }
+
+    fn next_bit(&mut self) -> u8 {
+        let (len, tap) = self.pattern.lfsr().unwrap_or((1, 1));
+        let bit = ((self.state >> (len - 1)) ^ (self.state >> (tap - 1))) & 1;
+        self.push_bit(bit as u8);
+        bit as u8
+    }
+
+    fn push_bit(&mut self, bit: u8) {
+        if let Some((len, _)) = self.pattern.lfsr() {
+            self.state = ((self.state << 1) | bit as u32) & ((1 << len) - 1);
+        }
+    }
+
+    /// Returns the next byte of the pattern. Bits are sent least significant first,
+    /// so PRBS bits go out on the line in the order they are generated
+    pub fn next_byte(&mut self) -> u8 {
+        match self.pattern {
+            Pattern::Alternating => 0x55,
+            Pattern::Fixed(b) => b,
+            _ => (0..8).fold(0, |byte, i| byte | (self.next_bit() << i)),
+        }
+    }
+
+    /// Fills `buf` with the next bytes of the pattern
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for b in buf {
+            *b = self.next_byte();
+        }
+    }
+
+    /// Loads the state from received bytes, so the next byte generated is the one
+    /// expected to follow them
+    fn sync_to(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.push_bit((byte >> i) & 1);
+        }
+    }
+}
+
+/// Checks received data against a test pattern
+#[derive(Debug, Copy, Clone)]
+pub struct PatternChecker {
+    generator: PatternGenerator,
+    /// Bytes still needed to load the generator's state before checking
+    syncing: u32,
+    /// Consecutive bytes with errors
+    errored_run: u32,
+    report: BertReport,
+}
+
+impl PatternChecker {
+    /// Creates a checker for `pattern`
+    pub fn new(pattern: Pattern) -> Self {
+        let mut checker = Self { generator: PatternGenerator::new(pattern), syncing: 0, errored_run: 0, report: BertReport::default() };
+        checker.resync();
+        checker
+    }
+
+    fn resync(&mut self) {
+        self.syncing = self.generator.pattern.lfsr().map_or(0, |(len, _)| len.div_ceil(8));
+        self.errored_run = 0;
+    }
+
+    /// Checks received bytes
+    pub fn check(&mut self, data: &[u8]) {
+        for &b in data {
+            self.report.bytes_received += 1;
+            if self.syncing > 0 {
+                self.generator.sync_to(b);
+                self.syncing -= 1;
+                continue;
+            }
+            let errors = (b ^ self.generator.next_byte()).count_ones();
+            self.report.bytes_checked += 1;
+            self.report.bit_errors += errors as u64;
+            if errors == 0 {
+                self.errored_run = 0;
+                continue;
+            }
+            self.report.errored_bytes += 1;
+            self.errored_run += 1;
+            if self.errored_run >= SYNC_LOSS_BYTES && self.generator.pattern.lfsr().is_some() {
+                self.report.sync_losses += 1;
+                self.resync();
+            }
+        }
+    }
+
+    /// Returns the counts so far
+    pub fn report(&self) -> BertReport {
+        self.report
+    }
+}
+
+/// Results of a bit error rate test
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BertReport {
+    /// Bytes sent. Only set by [run_loopback]
+    pub bytes_sent: u64,
+    /// Bytes received
+    pub bytes_received: u64,
+    /// Bytes compared against the pattern. Bytes used to synchronise are not checked
+    pub bytes_checked: u64,
+    /// Bytes with at least one bit error
+    pub errored_bytes: u64,
+    /// Bit errors in the checked bytes
+    pub bit_errors: u64,
+    /// Times synchronisation was lost and regained, usually as bytes were dropped
+    pub sync_losses: u64,
+    /// Time the test took. Only set by [run_loopback]
+    pub elapsed: Duration,
+}
+
+impl BertReport {
+    /// Number of bits compared against the pattern
+    pub fn bits_checked(&self) -> u64 {
+        self.bytes_checked * 8
+    }
+
+    /// Bit errors per bit checked, or 0 if nothing was checked
+    pub fn bit_error_rate(&self) -> f64 {
+        match self.bits_checked() {
+            0 => 0.0,
+            bits => self.bit_errors as f64 / bits as f64,
+        }
+    }
+
+    /// Bytes sent which were never received
+    pub fn bytes_lost(&self) -> u64 {
+        self.bytes_sent.saturating_sub(self.bytes_received)
+    }
+}
+
+/// How much data a test sends
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    /// Sends this many bytes
+    Bytes(u64),
+    /// Sends for this long
+    Duration(Duration),
+}
+
+/// Sends `pattern` on `port` whilst checking what is received, for a port whose
+/// output is looped back to its input. Data still in flight is waited for after
+/// the last write, for up to a second
+pub fn run_loopback<P: SerialPort>(port: &P, pattern: Pattern, limit: Limit) -> SerialResult<BertReport> {
+    port.clear_input_buffer()?;
+    let start = Instant::now();
+    let sent = AtomicU64::new(0);
+    let done = AtomicBool::new(false);
+    let mut checker = PatternChecker::new(pattern);
+
+    let (write_res, read_res) = std::thread::scope(|s| {
+        let writer = s.spawn(|| {
+            let res = write_pattern(port, pattern, limit, start, &sent);
+            done.store(true, Ordering::SeqCst);
+            res
+        });
+        let read_res = read_pattern(port, &mut checker, &sent, &done);
+        (writer.join().unwrap_or_else(|_| Err(SerialError::LibraryError("BERT writer panicked".into()))), read_res)
+    });
+    write_res?;
+    read_res?;
+    let mut report = checker.report();
+    report.bytes_sent = sent.load(Ordering::SeqCst);
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+fn write_pattern<P: SerialPort>(port: &P, pattern: Pattern, limit: Limit, start: Instant, sent: &AtomicU64) -> SerialResult<()> {
+    let mut generator = PatternGenerator::new(pattern);
+    let mut buf = [0u8; WRITE_CHUNK];
+    let mut total = 0u64;
+    loop {
+        let len = match limit {
+            Limit::Bytes(max) if total >= max => break,
+            Limit::Bytes(max) => (max - total).min(WRITE_CHUNK as u64) as usize,
+            Limit::Duration(d) if start.elapsed() >= d => break,
+            Limit::Duration(_) => WRITE_CHUNK,
+        };
+        generator.fill(&mut buf[..len]);
+        let mut written = 0;
+        while written < len {
+            match port.write_shared(&buf[written..len]) {
+                Ok(n) => written += n,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        }
+        total += len as u64;
+        sent.store(total, Ordering::SeqCst);
+    }
+    port.flush_shared().map_err(SerialError::IoError)
+}
+
+fn read_pattern<P: SerialPort>(port: &P, checker: &mut PatternChecker, sent: &AtomicU64, done: &AtomicBool) -> SerialResult<()> {
+    let mut buf = [0u8; WRITE_CHUNK];
+    // Time of the last read, or of the end of writing if later
+    let mut last_rx = Instant::now();
+    let mut finished = false;
+    loop {
+        if !finished && done.load(Ordering::SeqCst) {
+            finished = true;
+            last_rx = Instant::now();
+        }
+        if finished && (checker.report.bytes_received >= sent.load(Ordering::SeqCst) || last_rx.elapsed() >= DRAIN_TIMEOUT) {
+            return Ok(());
+        }
+        // Poll briefly, so the end of the test is noticed promptly
+        if !port.poll_readable(Some(Duration::from_millis(50))).map_err(SerialError::IoError)? {
+            continue;
+        }
+        match port.read_shared(&mut buf) {
+            Ok(n) => {
+                checker.check(&buf[..n]);
+                last_rx = Instant::now();
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Read buffering with lookahead
+//!
+//! [BufferedSerialPort] keeps received bytes in a buffer in front of the port, so
+//! parsers can look at upcoming data with [BufferedSerialPort::peek], or hand data
+//! back with [BufferedSerialPort::unread], before deciding how to consume it.
+//!
+//! Unlike [std::io::BufReader], timeouts are not hidden: a read which times out
+//! before anything was buffered returns the port's error, and data received before
+//! a timeout is never lost
+
+use std::{collections::VecDeque, io::{BufRead, ErrorKind, Read, Write}, sync::{Mutex, MutexGuard}};
+
+use crate::SerialPort;
+
+/// Size of each read issued to the port
+const READ_CHUNK: usize = 256;
+
+/// Bytes read ahead by [crate::SerialIo::peek], shared by a port and its clones.
+/// Reads return these before reading from the driver
+#[derive(Debug, Default)]
+pub(crate) struct PeekBuffer {
+    staged: Mutex<Staged>,
+    /// Held whilst reading from the driver, so data read by a peek is staged before
+    /// any later data can be returned by a read
+    reading: Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct Staged {
+    bytes: VecDeque<u8>,
+    /// Number of times the buffer has been cleared, so a peek which read before a
+    /// clear can drop what it read
+    clears: u64,
+}
+
+impl PeekBuffer {
+    fn lock(&self) -> MutexGuard<'_, Staged> {
+        self.staged.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_reading(&self) -> MutexGuard<'_, ()> {
+        self.reading.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Number of bytes read ahead
+    pub(crate) fn len(&self) -> usize {
+        self.lock().bytes.len()
+    }
+
+    /// Drops the bytes read ahead, and any a peek is reading at the moment
+    pub(crate) fn clear(&self) {
+        let mut staged = self.lock();
+        staged.bytes.clear();
+        staged.clears += 1;
+    }
+
+    /// Moves bytes read ahead into `buf`. If there are none, reads with `read` instead
+    pub(crate) fn read<F: FnOnce(&mut [u8]) -> std::io::Result<usize>>(&self, buf: &mut [u8], read: F) -> std::io::Result<usize> {
+        let _reading = self.lock_reading();
+        {
+            let mut staged = self.lock();
+            let n = staged.bytes.len().min(buf.len());
+            if n > 0 {
+                for (dst, src) in buf.iter_mut().zip(staged.bytes.drain(..n)) {
+                    *dst = src;
+                }
+                return Ok(n);
+            }
+        }
+        read(buf)
+    }
+
+    /// Copies the next bytes into `buf` without consuming them. If nothing has been
+    /// read ahead, reads once with `read`, which may wait. Otherwise only the
+    /// `queued` bytes waiting in the driver are read, so it does not wait.
+    ///
+    /// The buffer is not locked during the read, so it can be checked or cleared
+    /// meanwhile. Reads and other peeks wait for it to finish
+    pub(crate) fn peek<F: FnOnce(&mut [u8]) -> std::io::Result<usize>>(&self, buf: &mut [u8], queued: usize, read: F) -> std::io::Result<usize> {
+        let reading = self.lock_reading();
+        let (want, clears) = {
+            let staged = self.lock();
+            let want = match staged.bytes.len() {
+                0 => buf.len(),
+                n => queued.min(buf.len().saturating_sub(n)),
+            };
+            (want, staged.clears)
+        };
+        if want > 0 {
+            let mut chunk = vec![0u8; want];
+            let res = read(&mut chunk);
+            let mut staged = self.lock();
+            match res {
+                Ok(n) if staged.clears == clears => staged.bytes.extend(&chunk[..n]),
+                Ok(_) => {}
+                Err(_) if !staged.bytes.is_empty() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        drop(reading);
+        let staged = self.lock();
+        let n = staged.bytes.len().min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(staged.bytes.iter()) {
+            *dst = *src;
+        }
+        Ok(n)
+    }
+}
+
+/// Buffering wrapper around a port with peek and push-back support
+#[derive(Debug)]
+pub struct BufferedSerialPort<P: SerialPort> {
+    port: P,
+    buf: VecDeque<u8>,
+}
+
+impl<P: SerialPort> BufferedSerialPort<P> {
+    /// Wraps `port` with an empty buffer
+    pub fn new(port: P) -> Self {
+        Self { port, buf: VecDeque::new() }
+    }
+
+    /// Reads once from the port into the buffer. Returns the number of bytes added
+    fn fill(&mut self) -> std::io::Result<usize> {
+        let mut chunk = [0u8; READ_CHUNK];
+        loop {
+            match self.port.read_shared(&mut chunk) {
+                Ok(read) => {
+                    self.buf.extend(&chunk[..read]);
+                    return Ok(read);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns true if the error ends a read which already has data to return
+    fn is_timeout(e: &std::io::Error) -> bool {
+        matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+    }
+
+    /// Returns the next `n` bytes without consuming them, reading from the port as needed.
+    ///
+    /// If the port times out (or end of file is reached) with fewer than `n` bytes buffered,
+    /// the buffered bytes are returned. If nothing is buffered, the timeout error is returned
+    pub fn peek(&mut self, n: usize) -> std::io::Result<&[u8]> {
+        while self.buf.len() < n {
+            match self.fill() {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if Self::is_timeout(&e) && !self.buf.is_empty() => break,
+                Err(e) => return Err(e),
+            }
+        }
+        let len = n.min(self.buf.len());
+        Ok(&self.buf.make_contiguous()[..len])
+    }
+
+    /// Pushes `bytes` back to the front of the buffer, so they are returned by the next read
+    pub fn unread(&mut self, bytes: &[u8]) {
+        for b in bytes.iter().rev() {
+            self.buf.push_front(*b);
+        }
+    }
+
+    /// Consumes and returns everything up to and including `delim`.
+    ///
+    /// If the port times out before `delim` is received, the error is returned and the
+    /// bytes read so far stay buffered for the next call
+    pub fn drain_to(&mut self, delim: u8) -> std::io::Result<Vec<u8>> {
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = self.buf.iter().skip(searched).position(|b| *b == delim) {
+                return Ok(self.buf.drain(..=searched + pos).collect());
+            }
+            searched = self.buf.len();
+            if self.fill()? == 0 {
+                return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "End of file before delimiter"));
+            }
+        }
+    }
+
+    /// Gets the bytes received but not yet consumed
+    pub fn buffered(&mut self) -> &[u8] { self.buf.make_contiguous() }
+    /// Gets a reference to the wrapped port
+    pub fn get_ref(&self) -> &P { &self.port }
+    /// Gets a mutable reference to the wrapped port
+    pub fn get_mut(&mut self) -> &mut P { &mut self.port }
+    /// Unwraps the port, returning it along with any data still buffered
+    pub fn into_parts(self) -> (P, Vec<u8>) { (self.port, self.buf.into()) }
+}
+
+impl<P: SerialPort> Read for BufferedSerialPort<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.buf.is_empty() {
+            return self.port.read_shared(buf);
+        }
+        let len = buf.len().min(self.buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.buf.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl<P: SerialPort> BufRead for BufferedSerialPort<P> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.buf.is_empty() {
+            self.fill()?;
+        }
+        Ok(self.buf.make_contiguous())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buf.drain(..amt.min(self.buf.len()));
+    }
+}
+
+impl<P: SerialPort> Write for BufferedSerialPort<P> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write_shared(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush_shared()
+    }
+}
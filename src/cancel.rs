@@ -0,0 +1,119 @@
+//! Cancellation tokens for structured shutdown of port I/O
+//!
+//! A [CancelToken] can be shared between any number of threads, and passed to
+//! [crate::SerialIo::read_with_cancel] / [crate::SerialIo::write_with_cancel]. Once
+//! cancelled, a token stays cancelled: any operation using it, blocked or not,
+//! fails with an error for which [SerialError::is_cancelled] is true.
+//!
+//! [CancelToken::cancel] only performs an atomic store and a single `write` (POSIX)
+//! or `SetEvent` (Windows), so it is safe to call from a signal handler
+
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+use crate::{SerialError, SerialResult};
+
+#[derive(Debug)]
+struct Inner {
+    cancelled: AtomicBool,
+    #[cfg(unix)]
+    read: std::os::unix::prelude::RawFd,
+    #[cfg(unix)]
+    write: std::os::unix::prelude::RawFd,
+    #[cfg(windows)]
+    event: winapi::um::winnt::HANDLE,
+}
+
+#[cfg(windows)]
+unsafe impl Send for Inner {}
+#[cfg(windows)]
+unsafe impl Sync for Inner {}
+
+/// Token which cancels every operation it is passed to once triggered
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    /// Creates a new, untriggered token
+    #[cfg(unix)]
+    pub fn new() -> SerialResult<Self> {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+        let (read, write) = nix::unistd::pipe()?;
+        let token = Self {
+            inner: Arc::new(Inner { cancelled: AtomicBool::new(false), read, write }),
+        };
+        for fd in [read, write] {
+            fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        }
+        Ok(token)
+    }
+
+    /// Creates a new, untriggered token
+    #[cfg(windows)]
+    pub fn new() -> SerialResult<Self> {
+        use winapi::um::synchapi::CreateEventW;
+        let event = unsafe { CreateEventW(std::ptr::null_mut(), 1, 0, std::ptr::null_mut()) };
+        if event.is_null() {
+            return Err(crate::windows::error::get_win_error());
+        }
+        Ok(Self {
+            inner: Arc::new(Inner { cancelled: AtomicBool::new(false), event }),
+        })
+    }
+
+    /// Triggers the token, cancelling every operation using it. This is async-signal-safe
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        #[cfg(unix)]
+        unsafe {
+            nix::libc::write(self.inner.write, [1u8].as_ptr() as *const nix::libc::c_void, 1);
+        }
+        #[cfg(windows)]
+        unsafe {
+            winapi::um::synchapi::SetEvent(self.inner.event);
+        }
+    }
+
+    /// Returns true if the token has been triggered
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns a cancelled error if the token has been triggered
+    pub(crate) fn check(&self) -> std::io::Result<()> {
+        match self.is_cancelled() {
+            true => Err(SerialError::cancelled_io()),
+            false => Ok(()),
+        }
+    }
+
+    /// Fd which becomes readable once the token is triggered
+    #[cfg(unix)]
+    pub(crate) fn fd(&self) -> std::os::unix::prelude::RawFd {
+        self.inner.read
+    }
+
+    /// Manual reset event which is signalled once the token is triggered
+    #[cfg(windows)]
+    pub(crate) fn event(&self) -> winapi::um::winnt::HANDLE {
+        self.inner.event
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            nix::libc::close(self.read);
+            nix::libc::close(self.write);
+        }
+        #[cfg(windows)]
+        unsafe {
+            winapi::um::handleapi::CloseHandle(self.event);
+        }
+    }
+}
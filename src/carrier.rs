@@ -0,0 +1,124 @@
+//! Carrier detect monitoring
+//!
+//! On dial-up and leased line links, the remote end hanging up shows as the
+//! carrier detect (DCD) line dropping. A [CarrierWatcher] checks the line from a
+//! background thread and calls back as soon as carrier is lost, so the link can
+//! be torn down without waiting for a read to time out.
+//!
+//! ```no_run
+//! # fn example(port: std::sync::Arc<dyn serial_rs::SerialPort>) -> serial_rs::SerialResult<()> {
+//! use std::time::Duration;
+//! use serial_rs::carrier::CarrierWatcher;
+//! if !port.wait_for_carrier(Some(Duration::from_secs(60)))? {
+//!     return Ok(());
+//! }
+//! let _watcher = CarrierWatcher::new(port.clone(), || println!("Remote hung up"));
+//! // ... use the link ...
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::SerialPort;
+
+/// How often the carrier detect line is checked
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug)]
+struct State {
+    carrier: bool,
+    stop: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    cvar: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Watches carrier detect on a port, calling back whenever it drops
+#[derive(Debug)]
+pub struct CarrierWatcher {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CarrierWatcher {
+    /// Starts watching `port`, calling `on_drop` from the watcher thread each time
+    /// carrier goes from present to absent. An error reading the line, such as the
+    /// device being unplugged, counts as carrier being lost.
+    ///
+    /// The port is only used to read the modem lines, so it can be shared with the
+    /// code using the link
+    pub fn new<P, F>(port: Arc<P>, mut on_drop: F) -> Self
+    where
+        P: SerialPort + ?Sized + 'static,
+        F: FnMut() + Send + 'static,
+    {
+        let carrier = port.read_carrier_detect().unwrap_or(false);
+        let shared = Arc::new(Shared { state: Mutex::new(State { carrier, stop: false }), cvar: Condvar::new() });
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || {
+            let shared = thread_shared;
+            loop {
+                let now = port.read_carrier_detect().unwrap_or(false);
+                let mut state = shared.lock();
+                if state.stop {
+                    break;
+                }
+                let dropped = state.carrier && !now;
+                if state.carrier != now {
+                    state.carrier = now;
+                    shared.cvar.notify_all();
+                }
+                drop(state);
+                if dropped {
+                    on_drop();
+                }
+                let state = shared.lock();
+                if state.stop {
+                    break;
+                }
+                let _ = shared.cvar.wait_timeout(state, POLL_INTERVAL);
+            }
+        });
+        Self { shared, thread: Some(thread) }
+    }
+
+    /// Returns true if carrier was present when last checked
+    pub fn has_carrier(&self) -> bool {
+        self.shared.lock().carrier
+    }
+
+    /// Blocks until carrier is lost. Returns false if `timeout` expired first
+    pub fn wait_for_drop(&self, timeout: Option<Duration>) -> bool {
+        let state = self.shared.lock();
+        match timeout {
+            Some(t) => {
+                !self.shared.cvar.wait_timeout_while(state, t, |s| s.carrier).unwrap_or_else(|e| e.into_inner()).0.carrier
+            }
+            None => !self.shared.cvar.wait_while(state, |s| s.carrier).unwrap_or_else(|e| e.into_inner()).carrier,
+        }
+    }
+}
+
+impl Drop for CarrierWatcher {
+    fn drop(&mut self) {
+        self.shared.lock().stop = true;
+        self.shared.cvar.notify_all();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
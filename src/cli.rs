@@ -0,0 +1,159 @@
+//! [clap] integration for port settings
+//!
+//! The settings enums implement [clap::ValueEnum], so they can be used directly as
+//! arguments, and [Baud] / [PortSpec] parse baud rates and complete port
+//! specifications such as `/dev/ttyUSB0:115200,8N1,rtscts`.
+//!
+//! ```
+//! use clap::{value_parser, Arg, Command};
+//! use serial_rs::{cli::PortSpec, Parity};
+//!
+//! let matches = Command::new("tool")
+//!     .arg(Arg::new("port").value_parser(value_parser!(PortSpec)))
+//!     .arg(Arg::new("parity").long("parity").value_parser(value_parser!(Parity)))
+//!     .get_matches_from(["tool", "/dev/ttyUSB0:115200,8N1", "--parity", "even"]);
+//! let spec = matches.get_one::<PortSpec>("port").unwrap();
+//! assert_eq!(spec.path, "/dev/ttyUSB0");
+//! assert_eq!(matches.get_one::<Parity>("parity"), Some(&Parity::Even));
+//! ```
+
+use std::str::FromStr;
+
+use clap::{builder::PossibleValue, ValueEnum};
+
+use crate::{ByteSize, FlowControl, Parity, SerialPort, SerialPortSettings, SerialResult, StopBits};
+
+impl ValueEnum for Parity {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Parity::None, Parity::Even, Parity::Odd]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Parity::None => PossibleValue::new("none").alias("n").alias("N"),
+            Parity::Even => PossibleValue::new("even").alias("e").alias("E"),
+            Parity::Odd => PossibleValue::new("odd").alias("o").alias("O"),
+        })
+    }
+}
+
+impl ValueEnum for ByteSize {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[ByteSize::Five, ByteSize::Six, ByteSize::Seven, ByteSize::Eight]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(match self {
+            ByteSize::Five => "5",
+            ByteSize::Six => "6",
+            ByteSize::Seven => "7",
+            ByteSize::Eight => "8",
+        }))
+    }
+}
+
+impl ValueEnum for StopBits {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[StopBits::One, StopBits::OnePointFive, StopBits::Two]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(match self {
+            StopBits::One => "1",
+            StopBits::OnePointFive => "1.5",
+            StopBits::Two => "2",
+        }))
+    }
+}
+
+impl ValueEnum for FlowControl {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[FlowControl::None, FlowControl::XonXoff, FlowControl::RtsCts, FlowControl::DsrDtr]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            FlowControl::None => PossibleValue::new("none"),
+            FlowControl::XonXoff => PossibleValue::new("xonxoff").alias("software"),
+            FlowControl::RtsCts => PossibleValue::new("rtscts").alias("hardware"),
+            FlowControl::DsrDtr => PossibleValue::new("dsrdtr"),
+        })
+    }
+}
+
+/// Baud rate argument, a positive integer
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Baud(pub u32);
+
+impl FromStr for Baud {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().parse::<u32>() {
+            Ok(b) if b > 0 => Ok(Baud(b)),
+            _ => Err(format!("invalid baud rate '{s}', expected a positive integer")),
+        }
+    }
+}
+
+/// Parses a frame format such as `8N1` or `7E1.5`
+fn parse_frame(s: &str) -> Option<(ByteSize, Parity, StopBits)> {
+    let mut chars = s.chars();
+    let byte_size = ByteSize::from_str(&chars.next()?.to_string(), true).ok()?;
+    let parity = Parity::from_str(&chars.next()?.to_string(), true).ok()?;
+    let stop_bits = StopBits::from_str(chars.as_str(), true).ok()?;
+    Some((byte_size, parity, stop_bits))
+}
+
+/// Complete port specification in the form `PATH[:BAUD[,FRAME][,FLOW]]`, for example
+/// `/dev/ttyUSB0:115200,8N1` or `COM3:9600,7E1,xonxoff`.
+///
+/// Settings which are not given keep their defaults
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortSpec {
+    /// Path of the port
+    pub path: String,
+    /// Settings parsed from the specification
+    pub settings: SerialPortSettings,
+}
+
+impl PortSpec {
+    /// Opens the port with the parsed settings
+    pub fn open(&self) -> SerialResult<Box<dyn SerialPort>> {
+        crate::new_from_path(&self.path, Some(self.settings))
+    }
+}
+
+impl FromStr for PortSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        // Only treat the text after the last ':' as settings if it starts with a baud
+        // rate, so paths containing ':' still parse
+        let (path, options) = match s.rsplit_once(':') {
+            Some((path, opts)) if opts.starts_with(|c: char| c.is_ascii_digit()) => (path, Some(opts)),
+            _ => (s, None),
+        };
+        if path.is_empty() {
+            return Err(format!("invalid port '{s}', the path is empty"));
+        }
+        let mut settings = SerialPortSettings::default();
+        if let Some(options) = options {
+            let mut parts = options.split(',');
+            let baud: Baud = parts.next().unwrap_or_default().parse()?;
+            settings = settings.baud(baud.0);
+            for part in parts {
+                if let Some((byte_size, parity, stop_bits)) = parse_frame(part) {
+                    settings = settings.byte_size(byte_size).parity(parity).stop_bits(stop_bits);
+                } else if let Ok(flow) = FlowControl::from_str(part, true) {
+                    settings = settings.set_flow_control(flow);
+                } else {
+                    return Err(format!(
+                        "invalid port option '{part}', expected a frame format like 8N1 or one of none, xonxoff, rtscts, dsrdtr"
+                    ));
+                }
+            }
+        }
+        Ok(PortSpec { path: path.to_string(), settings })
+    }
+}
@@ -0,0 +1,440 @@
+//! Encoder / decoder traits for typed frames, and a blocking [FramedPort]
+//!
+//! A [Decoder] turns buffered bytes into frames, and an [Encoder] turns frames into
+//! bytes. [FramedPort] owns the receive buffer and drives the codec from a port,
+//! so protocol implementations only need to describe their wire format
+
+use std::io::ErrorKind;
+
+use crate::{framing::LineEnding, SerialPort};
+
+/// Size of each read issued to the port
+const READ_CHUNK: usize = 256;
+
+/// Decodes frames from a buffer of received bytes
+pub trait Decoder {
+    /// Decoded frame type
+    type Item;
+    /// Error type. IO errors from the port are converted into this
+    type Error: From<std::io::Error>;
+
+    /// Attempts to decode a frame from the front of `src`, removing the bytes it used.
+    ///
+    /// Returns `Ok(None)` if `src` does not hold a complete frame yet, in which case
+    /// more data is read and this is called again
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<Self::Item>, Self::Error>;
+
+    /// Called once the port reaches end of file. By default, decodes as normal and
+    /// reports leftover bytes as [ErrorKind::UnexpectedEof]
+    fn decode_eof(&mut self, src: &mut Vec<u8>) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            None => Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Bytes remaining at end of stream").into()),
+        }
+    }
+}
+
+/// Encodes frames into bytes to be sent
+pub trait Encoder<Item> {
+    /// Error type. IO errors from the port are converted into this
+    type Error: From<std::io::Error>;
+
+    /// Appends the encoded form of `item` to `dst`
+    fn encode(&mut self, item: Item, dst: &mut Vec<u8>) -> Result<(), Self::Error>;
+}
+
+/// What a [FramedPort] does with its receive buffer when decoding fails
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// Return the error and leave the buffer to the decoder
+    Propagate,
+    /// Return the error and discard all buffered data
+    ClearBuffer,
+    /// Discard all buffered data and carry on reading without returning the error
+    Ignore,
+}
+
+/// Blocking adapter which reads and writes typed frames on a port using a codec
+#[derive(Debug)]
+pub struct FramedPort<P: SerialPort, C> {
+    port: P,
+    codec: C,
+    buf: Vec<u8>,
+    max_buffer: Option<usize>,
+    policy: RecoveryPolicy,
+    eof: bool,
+}
+
+impl<P: SerialPort, C> FramedPort<P, C> {
+    /// Creates a new framed port using `codec`
+    pub fn new(port: P, codec: C) -> Self {
+        Self {
+            port,
+            codec,
+            buf: Vec::new(),
+            max_buffer: None,
+            policy: RecoveryPolicy::Propagate,
+            eof: false,
+        }
+    }
+
+    /// Sets the maximum number of bytes buffered without a complete frame. Exceeding it
+    /// is reported as [ErrorKind::InvalidData] and clears the buffer
+    pub fn max_buffer(mut self, max: usize) -> Self {
+        self.max_buffer = Some(max);
+        self
+    }
+
+    /// Sets what happens to buffered data when decoding fails
+    pub fn recovery_policy(mut self, policy: RecoveryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Gets the bytes received but not yet decoded
+    pub fn buffered(&self) -> &[u8] { &self.buf }
+    /// Gets a reference to the codec
+    pub fn codec(&self) -> &C { &self.codec }
+    /// Gets a mutable reference to the codec
+    pub fn codec_mut(&mut self) -> &mut C { &mut self.codec }
+    /// Gets a reference to the wrapped port
+    pub fn get_ref(&self) -> &P { &self.port }
+    /// Gets a mutable reference to the wrapped port
+    pub fn get_mut(&mut self) -> &mut P { &mut self.port }
+    /// Unwraps the port and codec, discarding any buffered data
+    pub fn into_parts(self) -> (P, C) { (self.port, self.codec) }
+}
+
+impl<P: SerialPort, C: Decoder> FramedPort<P, C> {
+    /// Reads the next frame.
+    ///
+    /// Returns `None` once the port reaches end of file. Timeouts from the port are
+    /// returned as errors without losing buffered data, so a partially received frame
+    /// is completed by the next call
+    pub fn read_frame(&mut self) -> Result<Option<C::Item>, C::Error> {
+        loop {
+            if self.eof {
+                return match self.codec.decode_eof(&mut self.buf) {
+                    Err(e) => self.recover(e),
+                    res => res,
+                };
+            }
+            match self.codec.decode(&mut self.buf) {
+                Ok(Some(frame)) => return Ok(Some(frame)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.recover(e)?;
+                    continue;
+                }
+            }
+            if self.max_buffer.map(|max| self.buf.len() > max).unwrap_or(false) {
+                self.buf.clear();
+                return Err(std::io::Error::new(ErrorKind::InvalidData, "Frame exceeds maximum buffer size").into());
+            }
+            let start = self.buf.len();
+            self.buf.resize(start + READ_CHUNK, 0);
+            let res = self.port.read_shared(&mut self.buf[start..]);
+            self.buf.truncate(start + *res.as_ref().unwrap_or(&0));
+            match res {
+                Ok(0) => self.eof = true,
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Applies the recovery policy to a decode error. Returns Ok if reading should carry on
+    fn recover(&mut self, e: C::Error) -> Result<Option<C::Item>, C::Error> {
+        match self.policy {
+            RecoveryPolicy::Propagate => Err(e),
+            RecoveryPolicy::ClearBuffer => {
+                self.buf.clear();
+                Err(e)
+            }
+            RecoveryPolicy::Ignore => {
+                self.buf.clear();
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<P: SerialPort, C> FramedPort<P, C> {
+    /// Encodes `item` and writes it to the port
+    pub fn send<I>(&mut self, item: I) -> Result<(), C::Error>
+    where
+        C: Encoder<I>,
+    {
+        let mut out = Vec::new();
+        self.codec.encode(item, &mut out)?;
+        let mut written = 0;
+        while written < out.len() {
+            match self.port.write_shared(&out[written..]) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until everything sent has been transmitted
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush_shared()
+    }
+}
+
+impl<P: SerialPort, C: Decoder> Iterator for FramedPort<P, C> {
+    type Item = Result<C::Item, C::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// Codec for terminated lines. Decoded lines have their terminator stripped unless
+/// [LineCodec::keep_terminator] is set, and encoded lines have it appended
+#[derive(Debug, Clone)]
+pub struct LineCodec {
+    terminator: Vec<u8>,
+    max_length: Option<usize>,
+    keep_terminator: bool,
+    /// Set while dropping the rest of a line which exceeded `max_length`
+    discarding: bool,
+    /// Bytes of the buffer already searched for the terminator
+    searched: usize,
+}
+
+impl LineCodec {
+    /// Creates a new codec for lines ended by `ending`.
+    ///
+    /// ## Panics
+    /// If `ending` is an empty custom terminator
+    pub fn new(ending: LineEnding) -> Self {
+        let terminator = ending.as_bytes().to_vec();
+        assert!(!terminator.is_empty(), "Line terminator cannot be empty");
+        Self { terminator, max_length: None, keep_terminator: false, discarding: false, searched: 0 }
+    }
+
+    /// Sets the maximum length of a line, excluding its terminator. Longer lines
+    /// are discarded and reported as [ErrorKind::InvalidData]
+    pub fn max_length(mut self, max: usize) -> Self {
+        self.max_length = Some(max);
+        self
+    }
+
+    /// Keeps the terminator at the end of decoded lines. By default it is stripped
+    pub fn keep_terminator(mut self, keep: bool) -> Self {
+        self.keep_terminator = keep;
+        self
+    }
+}
+
+impl Decoder for LineCodec {
+    type Item = Vec<u8>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let term_len = self.terminator.len();
+        // The buffer may have been cleared since the last call
+        let from = self.searched.min(src.len()).saturating_sub(term_len - 1);
+        let pos = src[from..]
+            .windows(term_len)
+            .position(|w| w == self.terminator.as_slice())
+            .map(|p| p + from);
+        self.searched = src.len();
+        let too_long = |len: usize| self.max_length.map(|max| len > max).unwrap_or(false);
+        match pos {
+            Some(pos) => {
+                let mut line: Vec<u8> = src.drain(..pos + term_len).collect();
+                self.searched = 0;
+                if std::mem::take(&mut self.discarding) {
+                    // Tail of a line which was already reported as too long
+                    return self.decode(src);
+                }
+                if !self.keep_terminator {
+                    line.truncate(pos);
+                }
+                match too_long(pos) {
+                    true => Err(line_too_long()),
+                    false => Ok(Some(line)),
+                }
+            }
+            None if too_long(src.len().saturating_sub(term_len - 1)) => {
+                // Keep a possible partial terminator, so the next read can still complete it
+                src.drain(..src.len() - (term_len - 1));
+                self.searched = 0;
+                match std::mem::replace(&mut self.discarding, true) {
+                    true => Ok(None),
+                    false => Err(line_too_long()),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut Vec<u8>) -> Result<Option<Vec<u8>>, std::io::Error> {
+        match self.decode(src)? {
+            Some(line) => Ok(Some(line)),
+            None if src.is_empty() => Ok(None),
+            None if self.discarding => {
+                src.clear();
+                self.discarding = false;
+                Ok(None)
+            }
+            None => {
+                self.searched = 0;
+                Ok(Some(std::mem::take(src)))
+            }
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for LineCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: T, dst: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        dst.extend_from_slice(item.as_ref());
+        dst.extend_from_slice(&self.terminator);
+        Ok(())
+    }
+}
+
+fn line_too_long() -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, "Line exceeds maximum length")
+}
+
+/// SLIP frame delimiter
+const SLIP_END: u8 = 0xC0;
+/// SLIP escape byte
+const SLIP_ESC: u8 = 0xDB;
+/// Escaped [SLIP_END]
+const SLIP_ESC_END: u8 = 0xDC;
+/// Escaped [SLIP_ESC]
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// Codec for SLIP (RFC 1055) framed packets.
+///
+/// Encoded packets are both preceded and followed by an END byte, which flushes any
+/// line noise received by the peer. Empty frames between consecutive END bytes are
+/// skipped when decoding
+#[derive(Debug, Copy, Clone)]
+pub struct SlipCodec {
+    max_frame_len: usize,
+    /// Set while dropping the rest of a frame which exceeded `max_frame_len`
+    discarding: bool,
+}
+
+impl Default for SlipCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SlipCodec {
+    /// Creates a new codec accepting frames of up to 1006 bytes, the RFC 1055 maximum
+    pub fn new() -> Self {
+        Self { max_frame_len: 1006, discarding: false }
+    }
+
+    /// Sets the maximum length of a decoded frame. Longer frames are discarded and
+    /// reported as [ErrorKind::InvalidData]
+    pub fn max_frame_len(mut self, max: usize) -> Self {
+        self.max_frame_len = max;
+        self
+    }
+}
+
+impl Decoder for SlipCodec {
+    type Item = Vec<u8>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<Vec<u8>>, std::io::Error> {
+        loop {
+            let end = match src.iter().position(|b| *b == SLIP_END) {
+                Some(end) => end,
+                None => {
+                    // Every decoded byte takes at most 2 bytes on the wire
+                    if src.len() > self.max_frame_len * 2 {
+                        src.clear();
+                        if !std::mem::replace(&mut self.discarding, true) {
+                            return Err(slip_too_long());
+                        }
+                    }
+                    return Ok(None);
+                }
+            };
+            let raw: Vec<u8> = src.drain(..=end).take(end).collect();
+            if std::mem::take(&mut self.discarding) || raw.is_empty() {
+                continue;
+            }
+            let mut frame = Vec::with_capacity(raw.len());
+            let mut escaped = false;
+            for b in raw {
+                match (escaped, b) {
+                    (false, SLIP_ESC) => escaped = true,
+                    (false, b) => frame.push(b),
+                    (true, SLIP_ESC_END) => {
+                        frame.push(SLIP_END);
+                        escaped = false;
+                    }
+                    (true, SLIP_ESC_ESC) => {
+                        frame.push(SLIP_ESC);
+                        escaped = false;
+                    }
+                    (true, b) => {
+                        return Err(std::io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Invalid SLIP escape sequence 0x{SLIP_ESC:02X} 0x{b:02X}"),
+                        ))
+                    }
+                }
+            }
+            if escaped {
+                return Err(std::io::Error::new(ErrorKind::InvalidData, "SLIP frame ends with an escape byte"));
+            }
+            if frame.len() > self.max_frame_len {
+                return Err(slip_too_long());
+            }
+            return Ok(Some(frame));
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut Vec<u8>) -> Result<Option<Vec<u8>>, std::io::Error> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            // A partial frame without its END byte cannot be trusted
+            None if src.is_empty() || self.discarding => {
+                src.clear();
+                Ok(None)
+            }
+            None => Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Incomplete SLIP frame at end of stream")),
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for SlipCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: T, dst: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        let item = item.as_ref();
+        dst.reserve(item.len() + 2);
+        dst.push(SLIP_END);
+        for b in item {
+            match *b {
+                SLIP_END => dst.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+                SLIP_ESC => dst.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+                b => dst.push(b),
+            }
+        }
+        dst.push(SLIP_END);
+        Ok(())
+    }
+}
+
+fn slip_too_long() -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, "SLIP frame exceeds maximum length")
+}
@@ -0,0 +1,233 @@
+//! DMX512 output
+//!
+//! DMX512 runs at 250000 baud, 8N2. Each frame (packet) starts with a break of at
+//! least 88µs and a mark after break (MAB) of at least 12µs, followed by a start
+//! code and up to 512 channel slots. The break is generated with
+//! [crate::ControlLines::set_break_state], and timed by the host, so it is always at least
+//! as long as configured but may be longer.
+//!
+//! Receivers expect frames to be repeated, and fall back to a default state if
+//! they stop, so [DmxOutput::refresh] sends a universe continuously from a
+//! background thread.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort + 'static>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::dmx::DmxOutput;
+//! let refresh = DmxOutput::new(port)?.refresh(std::time::Duration::from_millis(25));
+//! refresh.update(|universe| universe[0] = 255);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{ByteSize, FlowControl, Parity, SerialError, SerialPort, SerialResult, StopBits};
+
+/// DMX512 line rate
+pub const DMX_BAUD: u32 = 250_000;
+/// Number of channel slots in a universe
+pub const UNIVERSE_SIZE: usize = 512;
+
+const MIN_BREAK: Duration = Duration::from_micros(88);
+const MIN_MAB: Duration = Duration::from_micros(12);
+
+/// DMX512 transmitter on a port
+#[derive(Debug)]
+pub struct DmxOutput<P: SerialPort> {
+    port: P,
+    break_time: Duration,
+    mark_after_break: Duration,
+    start_code: u8,
+}
+
+impl<P: SerialPort> DmxOutput<P> {
+    /// Creates a transmitter, setting the port to 250000 baud 8N2 without flow
+    /// control. Frames use a 176µs break, a 12µs MAB and the null start code
+    pub fn new(mut port: P) -> SerialResult<Self> {
+        let settings = port
+            .settings()
+            .baud(DMX_BAUD)
+            .byte_size(ByteSize::Eight)
+            .parity(Parity::None)
+            .stop_bits(StopBits::Two)
+            .set_flow_control(FlowControl::None);
+        *port.setting() = settings;
+        port.reconfigure_port()?;
+        Ok(Self { port, break_time: Duration::from_micros(176), mark_after_break: MIN_MAB, start_code: 0 })
+    }
+
+    /// Sets the break length. Values below the 88µs minimum are raised to it
+    pub fn break_time(mut self, time: Duration) -> Self {
+        self.break_time = time.max(MIN_BREAK);
+        self
+    }
+
+    /// Sets the mark after break length. Values below the 12µs minimum are raised
+    /// to it
+    pub fn mark_after_break(mut self, time: Duration) -> Self {
+        self.mark_after_break = time.max(MIN_MAB);
+        self
+    }
+
+    /// Sets the start code sent before the slots. 0 is used for dimmer levels, other
+    /// values mark alternate data such as RDM or text packets
+    pub fn start_code(mut self, code: u8) -> Self {
+        self.start_code = code;
+        self
+    }
+
+    /// Sends one frame containing a full universe
+    pub fn send_dmx_universe(&mut self, universe: &[u8; UNIVERSE_SIZE]) -> SerialResult<()> {
+        self.send_frame(universe)
+    }
+
+    /// Sends one frame containing up to 512 slots. Shorter frames refresh faster,
+    /// and leave the remaining channels of receivers unchanged
+    pub fn send_frame(&mut self, slots: &[u8]) -> SerialResult<()> {
+        if slots.len() > UNIVERSE_SIZE {
+            return Err(SerialError::LibraryError(format!("DMX frame of {} slots exceeds 512", slots.len())));
+        }
+        // The previous frame must be fully on the line before the break starts
+        self.port.flush().map_err(SerialError::IoError)?;
+        std::thread::sleep(self.port.settings().char_duration());
+        self.port.set_break_state(true)?;
+        std::thread::sleep(self.break_time);
+        self.port.set_break_state(false)?;
+        std::thread::sleep(self.mark_after_break);
+        let mut frame = Vec::with_capacity(slots.len() + 1);
+        frame.push(self.start_code);
+        frame.extend_from_slice(slots);
+        self.port.write_all(&frame).map_err(SerialError::IoError)
+    }
+
+    /// Starts a background thread sending a universe every `interval`, initially
+    /// all zero. A full frame takes about 23ms, so shorter intervals send frames
+    /// back to back
+    pub fn refresh(self, interval: Duration) -> DmxRefresh<P>
+    where
+        P: 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(RefreshState { universe: [0; UNIVERSE_SIZE], stop: false, error: None }),
+            cvar: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || {
+            let mut output = self;
+            let shared = thread_shared;
+            let mut next = Instant::now();
+            loop {
+                let universe = {
+                    let mut state = shared.lock();
+                    while !state.stop && Instant::now() < next {
+                        state = shared
+                            .cvar
+                            .wait_timeout(state, next.saturating_duration_since(Instant::now()))
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0;
+                    }
+                    if state.stop {
+                        break;
+                    }
+                    state.universe
+                };
+                next = Instant::now() + interval;
+                if let Err(e) = output.send_dmx_universe(&universe) {
+                    shared.lock().error = Some(e);
+                    break;
+                }
+            }
+            output
+        });
+        DmxRefresh { shared, thread: Some(thread) }
+    }
+
+    /// Gets a reference to the underlying port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the underlying port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+}
+
+#[derive(Debug)]
+struct RefreshState {
+    universe: [u8; UNIVERSE_SIZE],
+    stop: bool,
+    error: Option<SerialError>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<RefreshState>,
+    cvar: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, RefreshState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Background refresh of a DMX universe, started by [DmxOutput::refresh]. Dropping
+/// it stops the thread
+#[derive(Debug)]
+pub struct DmxRefresh<P: SerialPort> {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<DmxOutput<P>>>,
+}
+
+impl<P: SerialPort> DmxRefresh<P> {
+    /// Replaces the universe sent from the next frame onwards
+    pub fn set(&self, universe: &[u8; UNIVERSE_SIZE]) {
+        self.shared.lock().universe = *universe;
+    }
+
+    /// Modifies the universe in place, for example to change a few channels
+    pub fn update<F: FnOnce(&mut [u8; UNIVERSE_SIZE])>(&self, f: F) {
+        f(&mut self.shared.lock().universe);
+    }
+
+    /// Returns a description of the error which stopped the refresh thread, if any
+    pub fn error(&self) -> Option<String> {
+        self.shared.lock().error.as_ref().map(|e| e.to_string())
+    }
+
+    /// Stops the refresh thread after the frame in progress, and returns the
+    /// transmitter, or the error which stopped it
+    pub fn stop(mut self) -> SerialResult<DmxOutput<P>> {
+        self.shared.lock().stop = true;
+        self.shared.cvar.notify_all();
+        let output = self
+            .thread
+            .take()
+            .and_then(|t| t.join().ok())
+            .ok_or_else(|| SerialError::LibraryError("DMX refresh thread panicked".into()))?;
+        match self.shared.lock().error.take() {
+            Some(e) => Err(e),
+            None => Ok(output),
+        }
+    }
+}
+
+impl<P: SerialPort> Drop for DmxRefresh<P> {
+    fn drop(&mut self) {
+        self.shared.lock().stop = true;
+        self.shared.cvar.notify_all();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! ELM327 OBD-II adapters
+//!
+//! ELM327 adapters (and their many clones) take AT commands to configure the
+//! adapter, and hex strings such as `010C` which are sent to the vehicle as OBD
+//! requests. Each response ends with a `>` prompt rather than a result code, so
+//! [Elm327] reads up to the prompt using [AtPort::read_until], and parses the hex
+//! response lines into payload bytes. Multi-frame CAN responses, which the adapter
+//! prints as numbered lines, are reassembled.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::elm327::{Elm327, ObdProtocol};
+//! let mut elm = Elm327::new(port);
+//! println!("{}", elm.init(ObdProtocol::Auto)?);
+//! // Engine RPM
+//! for response in elm.query(&[0x01, 0x0C])? {
+//!     println!("{response:02X?}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use crate::{at::AtPort, SerialError, SerialPort, SerialResult};
+
+const PROMPT: u8 = b'>';
+
+/// Responses reporting that the request failed
+const ERRORS: &[&str] = &[
+    "?",
+    "NO DATA",
+    "UNABLE TO CONNECT",
+    "CAN ERROR",
+    "BUS ERROR",
+    "BUS BUSY",
+    "DATA ERROR",
+    "FB ERROR",
+    "BUFFER FULL",
+    "STOPPED",
+    "ERROR",
+];
+
+/// Vehicle protocol, selected with `ATSP`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ObdProtocol {
+    /// Search for the protocol on the first request
+    Auto,
+    /// SAE J1850 PWM (41.6 kbaud)
+    J1850Pwm,
+    /// SAE J1850 VPW (10.4 kbaud)
+    J1850Vpw,
+    /// ISO 9141-2 (5 baud init)
+    Iso9141,
+    /// ISO 14230-4 KWP (5 baud init)
+    Kwp5Baud,
+    /// ISO 14230-4 KWP (fast init)
+    KwpFast,
+    /// ISO 15765-4 CAN (11 bit ID, 500 kbaud)
+    Can11Bit500k,
+    /// ISO 15765-4 CAN (29 bit ID, 500 kbaud)
+    Can29Bit500k,
+    /// ISO 15765-4 CAN (11 bit ID, 250 kbaud)
+    Can11Bit250k,
+    /// ISO 15765-4 CAN (29 bit ID, 250 kbaud)
+    Can29Bit250k,
+    /// SAE J1939 CAN (29 bit ID, 250 kbaud)
+    J1939,
+}
+
+impl ObdProtocol {
+    fn code(&self) -> char {
+        match self {
+            ObdProtocol::Auto => '0',
+            ObdProtocol::J1850Pwm => '1',
+            ObdProtocol::J1850Vpw => '2',
+            ObdProtocol::Iso9141 => '3',
+            ObdProtocol::Kwp5Baud => '4',
+            ObdProtocol::KwpFast => '5',
+            ObdProtocol::Can11Bit500k => '6',
+            ObdProtocol::Can29Bit500k => '7',
+            ObdProtocol::Can11Bit250k => '8',
+            ObdProtocol::Can29Bit250k => '9',
+            ObdProtocol::J1939 => 'A',
+        }
+    }
+}
+
+/// ELM327 adapter on a port
+#[derive(Debug)]
+pub struct Elm327<P: SerialPort> {
+    at: AtPort<P>,
+    timeout: Duration,
+    reset_timeout: Duration,
+}
+
+impl<P: SerialPort> Elm327<P> {
+    /// Creates an adapter interface. Requests wait up to 10 seconds for the
+    /// prompt, as the first request after [ObdProtocol::Auto] searches through the
+    /// protocols
+    pub fn new(port: P) -> Self {
+        Self { at: AtPort::new(port), timeout: Duration::from_secs(10), reset_timeout: Duration::from_secs(3) }
+    }
+
+    /// Sets how long to wait for the prompt after a request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Resets the adapter (`ATZ`), turns off echo, linefeeds and headers, and
+    /// selects the protocol. Returns the adapter's identification, such as
+    /// `ELM327 v1.5`
+    pub fn init(&mut self, protocol: ObdProtocol) -> SerialResult<String> {
+        // Wake the adapter if it is part way through a command, and drop the
+        // partial line
+        self.at.write_raw(b"\r")?;
+        self.at.read_until(PROMPT, Duration::from_millis(500))?;
+        self.at.take_buffered();
+
+        self.at.write_raw(b"ATZ\r")?;
+        let lines = self.read_prompt("ATZ", self.reset_timeout)?;
+        let id = lines.into_iter().find(|l| l.starts_with("ELM")).unwrap_or_default();
+        for cmd in ["ATE0", "ATL0", "ATH0", "ATS1"] {
+            self.at_command(cmd)?;
+        }
+        self.set_protocol(protocol)?;
+        Ok(id)
+    }
+
+    /// Selects the vehicle protocol
+    pub fn set_protocol(&mut self, protocol: ObdProtocol) -> SerialResult<()> {
+        self.at_command(&format!("ATSP{}", protocol.code()))
+    }
+
+    /// Turns response headers on or off. With headers on, each response starts with
+    /// the ECU's address, which tells apart responses from several ECUs
+    pub fn set_headers(&mut self, enable: bool) -> SerialResult<()> {
+        self.at_command(if enable { "ATH1" } else { "ATH0" })
+    }
+
+    /// Describes the protocol in use (`ATDP`), such as `AUTO, ISO 15765-4 (CAN 11/500)`
+    pub fn describe_protocol(&mut self) -> SerialResult<String> {
+        Ok(self.command("ATDP")?.join(" "))
+    }
+
+    /// Reads the vehicle battery voltage (`ATRV`)
+    pub fn voltage(&mut self) -> SerialResult<f32> {
+        let resp = self.command("ATRV")?.join("");
+        resp.trim_end_matches(['V', 'v'])
+            .parse()
+            .map_err(|_| SerialError::LibraryError(format!("Bad voltage response {resp}")))
+    }
+
+    /// Sends an AT command which responds with `OK`
+    pub fn at_command(&mut self, cmd: &str) -> SerialResult<()> {
+        let lines = self.command(cmd)?;
+        match lines.iter().any(|l| l == "OK") {
+            true => Ok(()),
+            false => Err(SerialError::LibraryError(format!("{cmd} failed: {}", lines.join(" ")))),
+        }
+    }
+
+    /// Sends a command, and returns the response lines up to the prompt. Error
+    /// responses such as `NO DATA` or `?` are returned as errors
+    pub fn command(&mut self, cmd: &str) -> SerialResult<Vec<String>> {
+        // Anything left over cannot be part of this response
+        self.at.take_buffered();
+        self.at.write_raw(format!("{cmd}\r").as_bytes())?;
+        let lines = self.read_prompt(cmd, self.timeout)?;
+        match lines.iter().find(|l| ERRORS.contains(&l.as_str())) {
+            Some(err) => Err(SerialError::LibraryError(format!("{cmd} failed: {err}"))),
+            None => Ok(lines),
+        }
+    }
+
+    /// Sends an OBD request, such as `[0x01, 0x0C]` for engine RPM, and returns the
+    /// payload of each response, in the order received. Several ECUs may respond
+    pub fn query(&mut self, request: &[u8]) -> SerialResult<Vec<Vec<u8>>> {
+        if request.is_empty() {
+            return Err(SerialError::LibraryError("Empty OBD request".into()));
+        }
+        let cmd: String = request.iter().map(|b| format!("{b:02X}")).collect();
+        let lines = self.command(&cmd)?;
+        parse_responses(&lines)
+    }
+
+    /// Gets the AT command layer
+    pub fn at(&mut self) -> &mut AtPort<P> {
+        &mut self.at
+    }
+
+    /// Unwraps the AT command layer
+    pub fn into_inner(self) -> AtPort<P> {
+        self.at
+    }
+
+    /// Reads up to the prompt, and returns the non empty lines without the echo
+    /// of `cmd` or progress messages
+    fn read_prompt(&mut self, cmd: &str, timeout: Duration) -> SerialResult<Vec<String>> {
+        let data = self.at.read_until(PROMPT, timeout)?.ok_or_else(|| {
+            SerialError::IoError(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("No prompt after {cmd}")))
+        })?;
+        Ok(String::from_utf8_lossy(&data)
+            .split(['\r', '\n'])
+            .map(|l| l.trim())
+            // Clones print NUL bytes around the prompt
+            .map(|l| l.trim_matches('\0').to_string())
+            .filter(|l| !l.is_empty() && !l.eq_ignore_ascii_case(cmd) && l != "SEARCHING..." && !l.starts_with("BUS INIT"))
+            .collect())
+    }
+}
+
+/// Parses response lines into payloads. A 3 digit length line followed by lines
+/// numbered `0:`, `1:`, ... is a multi-frame CAN response, which is joined and
+/// trimmed to the length
+fn parse_responses(lines: &[String]) -> SerialResult<Vec<Vec<u8>>> {
+    let mut out = Vec::new();
+    let mut multi: Option<(usize, Vec<u8>)> = None;
+    for line in lines {
+        if let Some((index, data)) = line.split_once(':') {
+            if index.trim().len() == 1 && u8::from_str_radix(index.trim(), 16).is_ok() {
+                match multi.as_mut() {
+                    Some((_, buf)) => buf.extend(parse_hex(data)?),
+                    None => return Err(SerialError::LibraryError(format!("Frame {line} without a length line"))),
+                }
+                continue;
+            }
+        }
+        if let Some((len, buf)) = multi.take() {
+            out.push(buf.into_iter().take(len).collect());
+        }
+        let compact: String = line.split_whitespace().collect();
+        if compact.len() == 3 {
+            let len = usize::from_str_radix(&compact, 16)
+                .map_err(|_| SerialError::LibraryError(format!("Bad response line {line}")))?;
+            multi = Some((len, Vec::new()));
+        } else {
+            out.push(parse_hex(line)?);
+        }
+    }
+    if let Some((len, buf)) = multi {
+        out.push(buf.into_iter().take(len).collect());
+    }
+    Ok(out)
+}
+
+/// Parses a line of hex bytes. With headers on, an 11 bit CAN ID is printed as 3
+/// digits, and is returned as 2 bytes
+fn parse_hex(line: &str) -> SerialResult<Vec<u8>> {
+    let bad = || SerialError::LibraryError(format!("Bad response line {line}"));
+    let mut out = Vec::new();
+    for token in line.split_whitespace() {
+        match token.len() {
+            3 => out.extend_from_slice(&u16::from_str_radix(token, 16).map_err(|_| bad())?.to_be_bytes()),
+            len if len % 2 == 0 => {
+                for i in (0..len).step_by(2) {
+                    out.push(u8::from_str_radix(token.get(i..i + 2).ok_or_else(bad)?, 16).map_err(|_| bad())?);
+                }
+            }
+            _ => return Err(bad()),
+        }
+    }
+    Ok(out)
+}
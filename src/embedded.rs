@@ -0,0 +1,90 @@
+//! [embedded_io] trait implementations
+//!
+//! Lets protocol crates written against `embedded-io` run unchanged on a host serial
+//! port. [crate::SerialError] is the error type, with its [embedded_io::ErrorKind]
+//! derived from the underlying IO error where there is one.
+//!
+//! Reads follow the port's timeouts: a read which times out fails with
+//! [embedded_io::ErrorKind::TimedOut] rather than returning 0 bytes.
+//!
+//! With the `embedded-io-async` feature, the `embedded_io_async` traits are also
+//! implemented. This crate has no async port, so their futures make the same
+//! blocking calls and are ready when first polled. Run them where blocking is
+//! allowed, or set short timeouts, so an executor thread is not held up for long
+
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+
+use crate::{BufferControl, SerialError, SerialIo};
+
+impl embedded_io::Error for SerialError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            SerialError::IoError(e) => e.kind().into(),
+            SerialError::Cancelled => ErrorKind::Interrupted,
+            SerialError::Unsupported(_) => ErrorKind::Unsupported,
+            SerialError::Disconnected(_) => ErrorKind::NotConnected,
+            SerialError::LineError(_) => ErrorKind::InvalidData,
+            SerialError::OsError { .. } | SerialError::LibraryError(_) => ErrorKind::Other,
+        }
+    }
+}
+
+macro_rules! impl_embedded_io {
+    ($port:ty) => {
+        impl ErrorType for $port {
+            type Error = SerialError;
+        }
+
+        impl Read for $port {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
+                self.read_shared(buf).map_err(SerialError::IoError)
+            }
+        }
+
+        impl Write for $port {
+            fn write(&mut self, buf: &[u8]) -> Result<usize, SerialError> {
+                self.write_shared(buf).map_err(SerialError::IoError)
+            }
+
+            fn flush(&mut self) -> Result<(), SerialError> {
+                self.flush_shared().map_err(SerialError::IoError)
+            }
+        }
+
+        impl ReadReady for $port {
+            fn read_ready(&mut self) -> Result<bool, SerialError> {
+                Ok(self.bytes_to_read()? > 0)
+            }
+        }
+
+        impl WriteReady for $port {
+            fn write_ready(&mut self) -> Result<bool, SerialError> {
+                self.is_write_ready()
+            }
+        }
+
+        #[cfg(feature = "embedded-io-async")]
+        impl embedded_io_async::Read for $port {
+            async fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
+                Read::read(self, buf)
+            }
+        }
+
+        #[cfg(feature = "embedded-io-async")]
+        impl embedded_io_async::Write for $port {
+            async fn write(&mut self, buf: &[u8]) -> Result<usize, SerialError> {
+                Write::write(self, buf)
+            }
+
+            async fn flush(&mut self) -> Result<(), SerialError> {
+                Write::flush(self)
+            }
+        }
+    };
+}
+
+#[cfg(unix)]
+impl_embedded_io!(crate::posix::TTYPort);
+
+#[cfg(windows)]
+impl_embedded_io!(crate::windows::COMPort);
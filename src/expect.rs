@@ -0,0 +1,166 @@
+//! Expect-style console automation
+//!
+//! [Expect] waits for one of several patterns to appear in the received data, such
+//! as a login prompt or a bootloader's autoboot message, and reports which one
+//! matched along with everything received before it. Data after the match stays
+//! buffered for the next call, so nothing is lost between patterns.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use std::time::Duration;
+//! use serial_rs::expect::Expect;
+//! let mut console = Expect::new(port);
+//! console.expect(&["Hit any key to stop autoboot"], Duration::from_secs(30))?;
+//! console.send(b" ")?;
+//! console.expect(&["=> "], Duration::from_secs(2))?;
+//! console.send_line("printenv bootcmd")?;
+//! let m = console.expect(&["=> "], Duration::from_secs(2))?;
+//! println!("{}", String::from_utf8_lossy(&m.before));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use regex::bytes::Regex;
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+/// Result of a successful [Expect::expect]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectMatch {
+    /// Index of the pattern which matched
+    pub index: usize,
+    /// Data received before the match
+    pub before: Vec<u8>,
+    /// The matching data
+    pub matched: Vec<u8>,
+}
+
+/// Port wrapper which waits for patterns in the received data
+#[derive(Debug)]
+pub struct Expect<P: SerialPort> {
+    port: P,
+    buf: Vec<u8>,
+    line_ending: String,
+    max_buffer: usize,
+}
+
+impl<P: SerialPort> Expect<P> {
+    /// Wraps `port`. Lines are sent with `\r\n`, and up to 1MiB of unmatched data
+    /// is kept
+    pub fn new(port: P) -> Self {
+        Self { port, buf: Vec::new(), line_ending: "\r\n".into(), max_buffer: 1 << 20 }
+    }
+
+    /// Sets the line ending appended by [Expect::send_line]
+    pub fn line_ending(mut self, ending: &str) -> Self {
+        self.line_ending = ending.into();
+        self
+    }
+
+    /// Sets how much unmatched data is kept. Beyond this, the oldest data is
+    /// discarded, so a chatty console cannot exhaust memory whilst waiting
+    pub fn max_buffer(mut self, bytes: usize) -> Self {
+        self.max_buffer = bytes.max(1);
+        self
+    }
+
+    /// Sends raw data
+    pub fn send(&mut self, data: &[u8]) -> SerialResult<()> {
+        self.port.write_all(data).and_then(|_| self.port.flush()).map_err(SerialError::IoError)
+    }
+
+    /// Sends `line` followed by the line ending
+    pub fn send_line(&mut self, line: &str) -> SerialResult<()> {
+        self.send(format!("{line}{}", self.line_ending).as_bytes())
+    }
+
+    /// Waits up to `timeout` for any of the literal `patterns`. If several match,
+    /// the one starting earliest wins, then the first in `patterns`.
+    ///
+    /// On timeout, the data received stays buffered, see [Expect::buffer]
+    pub fn expect<T: AsRef<[u8]>>(&mut self, patterns: &[T], timeout: Duration) -> SerialResult<ExpectMatch> {
+        self.wait(timeout, |buf| {
+            patterns
+                .iter()
+                .enumerate()
+                .filter_map(|(i, p)| {
+                    let p = p.as_ref();
+                    match p.is_empty() {
+                        true => Some((0, 0, i)),
+                        false => buf.windows(p.len()).position(|w| w == p).map(|start| (start, start + p.len(), i)),
+                    }
+                })
+                .min_by_key(|(start, _, i)| (*start, *i))
+        })
+    }
+
+    /// Waits up to `timeout` for any of the regular expressions in `patterns`, which
+    /// match against the raw bytes. See [Expect::expect]
+    pub fn expect_regex(&mut self, patterns: &[Regex], timeout: Duration) -> SerialResult<ExpectMatch> {
+        self.wait(timeout, |buf| {
+            patterns
+                .iter()
+                .enumerate()
+                .filter_map(|(i, re)| re.find(buf).map(|m| (m.start(), m.end(), i)))
+                .min_by_key(|(start, _, i)| (*start, *i))
+        })
+    }
+
+    /// Data received but not yet consumed by a match
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Discards the buffered data, and anything queued in the driver
+    pub fn clear(&mut self) -> SerialResult<()> {
+        self.buf.clear();
+        self.port.clear_input_buffer()
+    }
+
+    /// Gets a reference to the port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port, returning it along with the buffered data
+    pub fn into_parts(self) -> (P, Vec<u8>) {
+        (self.port, self.buf)
+    }
+
+    /// Reads until `find` returns the start, end and index of a match
+    fn wait<F: Fn(&[u8]) -> Option<(usize, usize, usize)>>(&mut self, timeout: Duration, find: F) -> SerialResult<ExpectMatch> {
+        let deadline = Instant::now() + timeout;
+        let mut chunk = [0u8; 256];
+        loop {
+            if let Some((start, end, index)) = find(&self.buf) {
+                let matched = self.buf[start..end].to_vec();
+                let before = self.buf[..start].to_vec();
+                self.buf.drain(..end);
+                return Ok(ExpectMatch { index, before, matched });
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+                return Err(SerialError::IoError(std::io::Error::new(ErrorKind::TimedOut, "No pattern matched")));
+            }
+            match self.port.read_shared(&mut chunk) {
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+            if self.buf.len() > self.max_buffer {
+                let excess = self.buf.len() - self.max_buffer;
+                self.buf.drain(..excess);
+            }
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! Flat C API
+//!
+//! Exposes the crate's backends to C, C++ and other languages through an opaque
+//! [SerialRsPort] handle. The matching header, `include/serial_rs.h`, is generated
+//! from this file with cbindgen, see `cbindgen.toml`.
+//!
+//! Functions returning `int32_t` return [SERIAL_RS_OK] or a negative error code, and
+//! read / write return the number of bytes transferred or a negative error code.
+//! A description of the last error on the calling thread is available from
+//! [serial_rs_last_error].
+//!
+//! Build a C library with `cargo rustc --release --features ffi --crate-type cdylib`
+//! (or `staticlib`)
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    ptr,
+};
+
+use crate::{ByteSize, FlowControl, Parity, SerialError, SerialPort, SerialPortSettings, StopBits};
+
+/// Success
+pub const SERIAL_RS_OK: i32 = 0;
+/// A pointer or setting passed in was invalid
+pub const SERIAL_RS_ERR_INVALID_ARG: i32 = -1;
+/// An IO error occurred
+pub const SERIAL_RS_ERR_IO: i32 = -2;
+/// The OS reported an error
+pub const SERIAL_RS_ERR_OS: i32 = -3;
+/// The library reported an error
+pub const SERIAL_RS_ERR_LIBRARY: i32 = -4;
+/// The operation timed out
+pub const SERIAL_RS_ERR_TIMEOUT: i32 = -5;
+/// The operation was cancelled
+pub const SERIAL_RS_ERR_CANCELLED: i32 = -6;
+/// The output buffer is too small
+pub const SERIAL_RS_ERR_BUFFER_TOO_SMALL: i32 = -7;
+/// No data was available on a non-blocking port
+pub const SERIAL_RS_ERR_WOULD_BLOCK: i32 = -8;
+/// The port or its driver does not support the operation
+pub const SERIAL_RS_ERR_UNSUPPORTED: i32 = -9;
+/// The device has gone away and the port must be reopened
+pub const SERIAL_RS_ERR_DISCONNECTED: i32 = -10;
+/// The driver reported framing, parity or overrun errors, or a break
+pub const SERIAL_RS_ERR_LINE: i32 = -11;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Records `e` as the last error and returns its code
+fn error_code(e: SerialError) -> i32 {
+    let code = match &e {
+        SerialError::IoError(io) => io_error_code(io),
+        SerialError::OsError { .. } => SERIAL_RS_ERR_OS,
+        SerialError::LibraryError(_) => SERIAL_RS_ERR_LIBRARY,
+        SerialError::Cancelled => SERIAL_RS_ERR_CANCELLED,
+        SerialError::Unsupported(_) => SERIAL_RS_ERR_UNSUPPORTED,
+        SerialError::Disconnected(_) => SERIAL_RS_ERR_DISCONNECTED,
+        SerialError::LineError(_) => SERIAL_RS_ERR_LINE,
+    };
+    set_last_error(e.to_string());
+    code
+}
+
+fn io_error_code(e: &std::io::Error) -> i32 {
+    if SerialError::is_cancelled(e) {
+        return SERIAL_RS_ERR_CANCELLED;
+    }
+    if SerialError::is_disconnected(e) {
+        return SERIAL_RS_ERR_DISCONNECTED;
+    }
+    if SerialError::line_errors(e).is_some() {
+        return SERIAL_RS_ERR_LINE;
+    }
+    match e.kind() {
+        std::io::ErrorKind::TimedOut => SERIAL_RS_ERR_TIMEOUT,
+        std::io::ErrorKind::WouldBlock => SERIAL_RS_ERR_WOULD_BLOCK,
+        _ => SERIAL_RS_ERR_IO,
+    }
+}
+
+fn invalid_arg(msg: &str) -> i32 {
+    set_last_error(msg.to_string());
+    SERIAL_RS_ERR_INVALID_ARG
+}
+
+/// Opaque handle to an open port
+pub struct SerialRsPort {
+    port: Box<dyn SerialPort>,
+}
+
+impl std::fmt::Debug for SerialRsPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SerialRsPort").field("path", &self.port.path()).finish()
+    }
+}
+
+/// Port settings
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SerialRsSettings {
+    /// Baud rate
+    pub baud_rate: u32,
+    /// Data bits, 5 to 8
+    pub byte_size: u8,
+    /// 0 = none, 1 = odd, 2 = even
+    pub parity: u8,
+    /// 0 = one, 1 = one and a half, 2 = two
+    pub stop_bits: u8,
+    /// 0 = none, 1 = XON/XOFF, 2 = RTS/CTS, 3 = DSR/DTR
+    pub flow_control: u8,
+    /// Read timeout in milliseconds, or -1 for none
+    pub read_timeout_ms: i64,
+    /// Write timeout in milliseconds, or -1 for none
+    pub write_timeout_ms: i64,
+}
+
+impl From<&SerialPortSettings> for SerialRsSettings {
+    fn from(s: &SerialPortSettings) -> Self {
+        Self {
+            baud_rate: s.baud_rate,
+            byte_size: match s.byte_size {
+                ByteSize::Five => 5,
+                ByteSize::Six => 6,
+                ByteSize::Seven => 7,
+                ByteSize::Eight => 8,
+            },
+            parity: match s.parity {
+                Parity::None => 0,
+                Parity::Odd => 1,
+                Parity::Even => 2,
+            },
+            stop_bits: match s.stop_bits {
+                StopBits::One => 0,
+                StopBits::OnePointFive => 1,
+                StopBits::Two => 2,
+            },
+            flow_control: match s.flow_control {
+                FlowControl::None => 0,
+                FlowControl::XonXoff => 1,
+                FlowControl::RtsCts => 2,
+                FlowControl::DsrDtr => 3,
+            },
+            read_timeout_ms: s.read_timeout.map(|t| t as i64).unwrap_or(-1),
+            write_timeout_ms: s.write_timeout.map(|t| t as i64).unwrap_or(-1),
+        }
+    }
+}
+
+impl SerialRsSettings {
+    /// Applies these settings on top of `base`
+    fn apply(&self, base: &mut SerialPortSettings) -> Result<(), &'static str> {
+        base.baud_rate = self.baud_rate;
+        base.byte_size = match self.byte_size {
+            5 => ByteSize::Five,
+            6 => ByteSize::Six,
+            7 => ByteSize::Seven,
+            8 => ByteSize::Eight,
+            _ => return Err("byte_size must be between 5 and 8"),
+        };
+        base.parity = match self.parity {
+            0 => Parity::None,
+            1 => Parity::Odd,
+            2 => Parity::Even,
+            _ => return Err("Invalid parity"),
+        };
+        base.stop_bits = match self.stop_bits {
+            0 => StopBits::One,
+            1 => StopBits::OnePointFive,
+            2 => StopBits::Two,
+            _ => return Err("Invalid stop_bits"),
+        };
+        base.flow_control = match self.flow_control {
+            0 => FlowControl::None,
+            1 => FlowControl::XonXoff,
+            2 => FlowControl::RtsCts,
+            3 => FlowControl::DsrDtr,
+            _ => return Err("Invalid flow_control"),
+        };
+        base.read_timeout = u128::try_from(self.read_timeout_ms).ok();
+        base.write_timeout = u128::try_from(self.write_timeout_ms).ok();
+        Ok(())
+    }
+}
+
+/// Fills `out` with the default settings
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_settings_default(out: *mut SerialRsSettings) -> i32 {
+    match out.as_mut() {
+        Some(out) => {
+            *out = SerialRsSettings::from(&SerialPortSettings::default());
+            SERIAL_RS_OK
+        }
+        None => invalid_arg("out is NULL"),
+    }
+}
+
+/// Opens the port at `path`. `settings` may be NULL to use the defaults.
+/// On success, `*out` receives a handle which must be freed with [serial_rs_close]
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_open(path: *const c_char, settings: *const SerialRsSettings, out: *mut *mut SerialRsPort) -> i32 {
+    if path.is_null() || out.is_null() {
+        return invalid_arg("path or out is NULL");
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(p) => p,
+        Err(_) => return invalid_arg("path is not valid UTF-8"),
+    };
+    let mut s = SerialPortSettings::default();
+    if let Some(settings) = settings.as_ref() {
+        if let Err(e) = settings.apply(&mut s) {
+            return invalid_arg(e);
+        }
+    }
+    match crate::new_from_path(path, Some(s)) {
+        Ok(port) => {
+            *out = Box::into_raw(Box::new(SerialRsPort { port }));
+            SERIAL_RS_OK
+        }
+        Err(e) => error_code(e),
+    }
+}
+
+/// Closes a port and frees its handle. Passing NULL does nothing
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_close(port: *mut SerialRsPort) {
+    if !port.is_null() {
+        drop(Box::from_raw(port));
+    }
+}
+
+/// Applies new settings to an open port
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_configure(port: *mut SerialRsPort, settings: *const SerialRsSettings) -> i32 {
+    let (port, settings) = match (port.as_mut(), settings.as_ref()) {
+        (Some(p), Some(s)) => (p, s),
+        _ => return invalid_arg("port or settings is NULL"),
+    };
+    let mut s = *port.port.settings();
+    if let Err(e) = settings.apply(&mut s) {
+        return invalid_arg(e);
+    }
+    *port.port.setting() = s;
+    match port.port.reconfigure_port() {
+        Ok(()) => SERIAL_RS_OK,
+        Err(e) => error_code(e),
+    }
+}
+
+/// Reads the port's current settings into `out`
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_get_settings(port: *const SerialRsPort, out: *mut SerialRsSettings) -> i32 {
+    match (port.as_ref(), out.as_mut()) {
+        (Some(p), Some(out)) => {
+            *out = SerialRsSettings::from(p.port.settings());
+            SERIAL_RS_OK
+        }
+        _ => invalid_arg("port or out is NULL"),
+    }
+}
+
+/// Reads up to `len` bytes into `buf`. Returns the number of bytes read, or a negative error code
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_read(port: *const SerialRsPort, buf: *mut u8, len: usize) -> isize {
+    let port = match port.as_ref() {
+        Some(p) if !buf.is_null() || len == 0 => p,
+        _ => return invalid_arg("port or buf is NULL") as isize,
+    };
+    let buf = match len {
+        0 => &mut [][..],
+        _ => std::slice::from_raw_parts_mut(buf, len),
+    };
+    match port.port.read_shared(buf) {
+        Ok(n) => n as isize,
+        Err(e) => error_code(SerialError::IoError(e)) as isize,
+    }
+}
+
+/// Writes up to `len` bytes from `buf`. Returns the number of bytes written, or a negative error code
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_write(port: *const SerialRsPort, buf: *const u8, len: usize) -> isize {
+    let port = match port.as_ref() {
+        Some(p) if !buf.is_null() || len == 0 => p,
+        _ => return invalid_arg("port or buf is NULL") as isize,
+    };
+    let buf = match len {
+        0 => &[][..],
+        _ => std::slice::from_raw_parts(buf, len),
+    };
+    match port.port.write_shared(buf) {
+        Ok(n) => n as isize,
+        Err(e) => error_code(SerialError::IoError(e)) as isize,
+    }
+}
+
+/// Waits until all written data has been transmitted
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_flush(port: *const SerialRsPort) -> i32 {
+    match port.as_ref() {
+        Some(p) => match p.port.flush_shared() {
+            Ok(()) => SERIAL_RS_OK,
+            Err(e) => error_code(SerialError::IoError(e)),
+        },
+        None => invalid_arg("port is NULL"),
+    }
+}
+
+/// Lists the paths of the available ports into `buf` as a newline separated, NUL
+/// terminated string. `*needed` (if not NULL) receives the buffer size required.
+/// Returns [SERIAL_RS_ERR_BUFFER_TOO_SMALL] if `buf` is too small; `buf` may be NULL
+/// to only query the size
+#[no_mangle]
+pub unsafe extern "C" fn serial_rs_list_ports(buf: *mut c_char, len: usize, needed: *mut usize) -> i32 {
+    let ports = match crate::list_ports() {
+        Ok(p) => p,
+        Err(e) => return error_code(e),
+    };
+    let list = ports.iter().map(|p| p.get_port()).collect::<Vec<_>>().join("\n");
+    let required = list.len() + 1;
+    if let Some(needed) = needed.as_mut() {
+        *needed = required;
+    }
+    if buf.is_null() || len < required {
+        set_last_error(format!("Port list needs a {required} byte buffer"));
+        return SERIAL_RS_ERR_BUFFER_TOO_SMALL;
+    }
+    ptr::copy_nonoverlapping(list.as_ptr(), buf as *mut u8, list.len());
+    *buf.add(list.len()) = 0;
+    SERIAL_RS_OK
+}
+
+/// Returns a description of the last error on the calling thread, or NULL if there
+/// was none. The string is valid until the next call on this thread
+#[no_mangle]
+pub extern "C" fn serial_rs_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(serial_rs_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let settings = SerialPortSettings::default()
+            .baud(115200)
+            .byte_size(ByteSize::Seven)
+            .parity(Parity::Even)
+            .stop_bits(StopBits::Two)
+            .set_flow_control(FlowControl::RtsCts)
+            .read_timeout(Some(250))
+            .write_timeout(None);
+        let mut applied = SerialPortSettings::default();
+        SerialRsSettings::from(&settings).apply(&mut applied).unwrap();
+        assert_eq!(applied, settings);
+    }
+
+    #[test]
+    fn default_settings_match_the_crate() {
+        let mut out = SerialRsSettings::from(&SerialPortSettings::default().baud(1));
+        assert_eq!(unsafe { serial_rs_settings_default(&mut out) }, SERIAL_RS_OK);
+        let mut applied = SerialPortSettings::default().baud(1);
+        out.apply(&mut applied).unwrap();
+        assert_eq!(applied, SerialPortSettings::default());
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let valid = SerialRsSettings::from(&SerialPortSettings::default());
+        for invalid in [
+            SerialRsSettings { byte_size: 9, ..valid },
+            SerialRsSettings { parity: 3, ..valid },
+            SerialRsSettings { stop_bits: 3, ..valid },
+            SerialRsSettings { flow_control: 4, ..valid },
+        ] {
+            assert!(invalid.apply(&mut SerialPortSettings::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn error_codes() {
+        let io = |kind: std::io::ErrorKind| SerialError::IoError(kind.into());
+        assert_eq!(error_code(io(std::io::ErrorKind::TimedOut)), SERIAL_RS_ERR_TIMEOUT);
+        assert_eq!(error_code(io(std::io::ErrorKind::WouldBlock)), SERIAL_RS_ERR_WOULD_BLOCK);
+        assert_eq!(error_code(io(std::io::ErrorKind::BrokenPipe)), SERIAL_RS_ERR_IO);
+        assert_eq!(error_code(SerialError::IoError(SerialError::cancelled_io())), SERIAL_RS_ERR_CANCELLED);
+        assert_eq!(error_code(SerialError::LibraryError("broken".into())), SERIAL_RS_ERR_LIBRARY);
+        assert!(last_error().contains("broken"));
+    }
+
+    #[test]
+    fn null_arguments_are_rejected() {
+        unsafe {
+            assert_eq!(serial_rs_settings_default(ptr::null_mut()), SERIAL_RS_ERR_INVALID_ARG);
+            assert_eq!(serial_rs_open(ptr::null(), ptr::null(), ptr::null_mut()), SERIAL_RS_ERR_INVALID_ARG);
+            assert_eq!(serial_rs_read(ptr::null(), ptr::null_mut(), 0), SERIAL_RS_ERR_INVALID_ARG as isize);
+            assert_eq!(serial_rs_flush(ptr::null()), SERIAL_RS_ERR_INVALID_ARG);
+            serial_rs_close(ptr::null_mut());
+        }
+        assert!(last_error().contains("NULL"));
+    }
+
+    #[test]
+    fn list_ports_reports_the_size_needed() {
+        let mut needed = 0;
+        assert_eq!(unsafe { serial_rs_list_ports(ptr::null_mut(), 0, &mut needed) }, SERIAL_RS_ERR_BUFFER_TOO_SMALL);
+        let mut buf = vec![0 as c_char; needed];
+        assert_eq!(unsafe { serial_rs_list_ports(buf.as_mut_ptr(), buf.len(), ptr::null_mut()) }, SERIAL_RS_OK);
+        assert_eq!(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_bytes().len(), needed - 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn data_round_trip() {
+        use std::os::unix::io::IntoRawFd;
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let open = |fd| {
+            let port = unsafe { crate::posix::TTYPort::from_raw_fd_with_settings(fd, SerialPortSettings::default()) }.unwrap();
+            Box::into_raw(Box::new(SerialRsPort { port: Box::new(port) }))
+        };
+        let (a, b) = (open(pty.master.into_raw_fd()), open(pty.slave.into_raw_fd()));
+        unsafe {
+            let mut settings = SerialRsSettings::from(&SerialPortSettings::default());
+            settings.read_timeout_ms = 1000;
+            assert_eq!(serial_rs_configure(b, &settings), SERIAL_RS_OK);
+            let mut read_back = SerialRsSettings::from(&SerialPortSettings::default());
+            assert_eq!(serial_rs_get_settings(b, &mut read_back), SERIAL_RS_OK);
+            assert_eq!(read_back.read_timeout_ms, 1000);
+
+            let data = b"round trip";
+            assert_eq!(serial_rs_write(a, data.as_ptr(), data.len()), data.len() as isize);
+            let mut buf = [0u8; 32];
+            let mut read = 0;
+            while read < data.len() {
+                let n = serial_rs_read(b, buf[read..].as_mut_ptr(), buf.len() - read);
+                assert!(n > 0, "read failed with {n}: {}", last_error());
+                read += n as usize;
+            }
+            assert_eq!(&buf[..read], data);
+            serial_rs_close(a);
+            serial_rs_close(b);
+        }
+    }
+}
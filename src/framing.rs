@@ -0,0 +1,211 @@
+//! Framing layers on top of a port
+//!
+//! Serial ports deliver a byte stream with no message boundaries, and a single
+//! read can return half a message or several at once. The framers in this module
+//! buffer incoming data and hand out complete frames
+//!
+//! Timeouts and other errors from the underlying reader are returned to the caller
+//! without discarding any data, so a partially received frame is completed by the
+//! next call
+
+use std::{io::{ErrorKind, Read}, time::Duration};
+
+use crate::{codec::{Decoder, LineCodec}, SerialPort};
+
+/// Size of each read issued to the underlying reader
+const READ_CHUNK: usize = 256;
+
+/// Terminator which ends a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineEnding {
+    /// Carriage return (`\r`)
+    Cr,
+    /// Line feed (`\n`)
+    Lf,
+    /// Carriage return followed by line feed (`\r\n`)
+    CrLf,
+    /// Any non-empty byte sequence
+    Custom(Vec<u8>),
+}
+
+impl LineEnding {
+    /// Gets the bytes making up this terminator
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            LineEnding::Cr => b"\r",
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+            LineEnding::Custom(b) => b,
+        }
+    }
+}
+
+/// Splits a byte stream into lines. This is [LineCodec] driven by any reader
+///
+/// ## Example
+/// ```no_run
+/// use serial_rs::framing::{LineEnding, LineFramer};
+/// # fn run(port: std::fs::File) -> std::io::Result<()> {
+/// let mut lines = LineFramer::new(port, LineEnding::CrLf).max_length(512);
+/// while let Some(line) = lines.read_line()? {
+///     println!("{}", String::from_utf8_lossy(&line));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct LineFramer<R: Read> {
+    inner: R,
+    codec: LineCodec,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> LineFramer<R> {
+    /// Creates a new framer reading lines ended by `ending` from `inner`.
+    ///
+    /// ## Panics
+    /// If `ending` is an empty custom terminator
+    pub fn new(inner: R, ending: LineEnding) -> Self {
+        Self {
+            inner,
+            codec: LineCodec::new(ending),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Sets the maximum length of a line, excluding its terminator. Longer lines
+    /// are discarded and reported as [ErrorKind::InvalidData]
+    pub fn max_length(mut self, max: usize) -> Self {
+        self.codec = self.codec.max_length(max);
+        self
+    }
+
+    /// Keeps the terminator at the end of returned lines. By default it is stripped
+    pub fn keep_terminator(mut self, keep: bool) -> Self {
+        self.codec = self.codec.keep_terminator(keep);
+        self
+    }
+
+    /// Reads the next complete line.
+    ///
+    /// Returns `None` once the reader reaches end of file with no buffered data.
+    /// An unterminated line at end of file is returned as is
+    pub fn read_line(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(line) = self.codec.decode(&mut self.buf)? {
+                return Ok(Some(line));
+            }
+            let start = self.buf.len();
+            self.buf.resize(start + READ_CHUNK, 0);
+            let res = self.inner.read(&mut self.buf[start..]);
+            let read = *res.as_ref().unwrap_or(&0);
+            self.buf.truncate(start + read);
+            match res {
+                Ok(0) => return self.codec.decode_eof(&mut self.buf),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Gets the bytes received but not yet returned as part of a line
+    pub fn buffered(&self) -> &[u8] { &self.buf }
+    /// Gets a reference to the wrapped reader
+    pub fn get_ref(&self) -> &R { &self.inner }
+    /// Gets a mutable reference to the wrapped reader
+    pub fn get_mut(&mut self) -> &mut R { &mut self.inner }
+    /// Unwraps the reader, discarding any buffered data
+    pub fn into_inner(self) -> R { self.inner }
+}
+
+impl<R: Read> Iterator for LineFramer<R> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_line().transpose()
+    }
+}
+
+/// Splits a byte stream into frames separated by idle gaps on the line, as used by
+/// Modbus RTU and many RS-485 protocols
+///
+/// A frame starts with the first byte received, and ends once no further byte has
+/// been received for the configured gap. The gap is timed by the host, so USB
+/// adapters which batch received data (see the `ftdi` feature) may need a larger gap
+#[derive(Debug)]
+pub struct IdleGapFramer<P: SerialPort> {
+    port: P,
+    gap: Duration,
+    max_length: Option<usize>,
+}
+
+impl<P: SerialPort> IdleGapFramer<P> {
+    /// Creates a new framer ending frames after `gap` of silence
+    pub fn new(port: P, gap: Duration) -> Self {
+        Self { port, gap, max_length: None }
+    }
+
+    /// Creates a new framer ending frames after `chars` character times of silence,
+    /// computed from the port's current settings
+    pub fn with_char_gap(port: P, chars: f64) -> Self {
+        let gap = port.settings().char_duration().mul_f64(chars);
+        Self::new(port, gap)
+    }
+
+    /// Creates a new framer using the Modbus RTU inter-frame delay: 3.5 character times,
+    /// fixed at 1.75ms above 19200 baud
+    pub fn modbus_rtu(port: P) -> Self {
+        match port.settings().baud_rate > 19200 {
+            true => Self::new(port, Duration::from_micros(1750)),
+            false => Self::with_char_gap(port, 3.5),
+        }
+    }
+
+    /// Sets the maximum length of a frame. Longer frames are discarded up to the
+    /// next gap and reported as [ErrorKind::InvalidData]
+    pub fn max_length(mut self, max: usize) -> Self {
+        self.max_length = Some(max);
+        self
+    }
+
+    /// Gets the idle gap which ends a frame
+    pub fn gap(&self) -> Duration { self.gap }
+
+    /// Waits up to `timeout` for a frame to start, then reads until the line goes idle.
+    /// If `timeout` is None, waits forever. Returns [ErrorKind::TimedOut] if no frame started
+    pub fn read_frame(&mut self, timeout: Option<Duration>) -> std::io::Result<Vec<u8>> {
+        if !self.port.poll_readable(timeout)? {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "No frame received"));
+        }
+        let mut frame = Vec::new();
+        let mut overflow = false;
+        let mut chunk = [0u8; READ_CHUNK];
+        loop {
+            match self.port.read_shared(&mut chunk) {
+                Ok(read) => {
+                    overflow |= self.max_length.map(|max| frame.len() + read > max).unwrap_or(false);
+                    if !overflow {
+                        frame.extend_from_slice(&chunk[..read]);
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(e),
+            }
+            if !self.port.poll_readable(Some(self.gap))? {
+                break;
+            }
+        }
+        match overflow {
+            true => Err(std::io::Error::new(ErrorKind::InvalidData, "Frame exceeds maximum length")),
+            false => Ok(frame),
+        }
+    }
+
+    /// Gets a reference to the wrapped port
+    pub fn get_ref(&self) -> &P { &self.port }
+    /// Gets a mutable reference to the wrapped port
+    pub fn get_mut(&mut self) -> &mut P { &mut self.port }
+    /// Unwraps the port
+    pub fn into_inner(self) -> P { self.port }
+}
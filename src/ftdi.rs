@@ -0,0 +1,197 @@
+//! FTDI latency timer configuration
+//!
+//! FTDI USB-serial bridges buffer received data for up to the latency timer
+//! (16ms by default) before sending it to the host, which ruins turnaround
+//! times for request/response protocols such as Modbus RTU.
+//!
+//! On Linux the timer is changed through the `latency_timer` sysfs attribute of
+//! the ftdi_sio driver (writing requires root, or a udev rule). On Windows the
+//! `LatencyTimer` value of the FTDIBUS driver's registry key is used, which requires
+//! administrator rights to change and only takes effect when the port is next opened.
+//!
+//! Ports which are not driven by an FTDI driver return [SerialError::LibraryError]
+
+use crate::{PortInfo, SerialError, SerialResult};
+
+/// Returns true if the port at `path` is driven by an FTDI driver which supports
+/// the latency timer
+pub fn is_ftdi(path: &str) -> bool {
+    imp::latency_timer_location(path).is_ok()
+}
+
+/// Reads the latency timer (in milliseconds) of the port at `path`
+pub fn get_latency_timer(path: &str) -> SerialResult<u8> {
+    imp::get_latency_timer(path)
+}
+
+/// Sets the latency timer (in milliseconds, 1-255) of the port at `path`
+pub fn set_latency_timer(path: &str, ms: u8) -> SerialResult<()> {
+    if ms == 0 {
+        return Err(SerialError::LibraryError("FTDI latency timer must be between 1 and 255ms".to_string()));
+    }
+    imp::set_latency_timer(path, ms)
+}
+
+/// Reads the latency timer of a listed port
+pub fn get_latency_timer_for(info: &PortInfo) -> SerialResult<u8> {
+    get_latency_timer(info.get_port())
+}
+
+/// Sets the latency timer of a listed port
+pub fn set_latency_timer_for(info: &PortInfo, ms: u8) -> SerialResult<()> {
+    set_latency_timer(info.get_port(), ms)
+}
+
+fn not_ftdi(path: &str) -> SerialError {
+    SerialError::LibraryError(format!("{path} is not an FTDI device"))
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::path::PathBuf;
+
+    use crate::{SerialError, SerialResult};
+
+    pub(super) fn latency_timer_location(path: &str) -> SerialResult<PathBuf> {
+        // Stable names such as /dev/serial/by-id/... are symlinks to the tty
+        let real_path = std::fs::canonicalize(path).unwrap_or_else(|_| path.into());
+        let dev_name = real_path.file_name().and_then(|n| n.to_str()).unwrap_or(path);
+        let attr = PathBuf::from(format!("/sys/class/tty/{dev_name}/device/latency_timer"));
+        match attr.exists() {
+            true => Ok(attr),
+            false => Err(super::not_ftdi(path)),
+        }
+    }
+
+    pub(super) fn get_latency_timer(path: &str) -> SerialResult<u8> {
+        let s = std::fs::read_to_string(latency_timer_location(path)?).map_err(SerialError::IoError)?;
+        s.trim().parse::<u8>().map_err(|e| {
+            SerialError::LibraryError(format!("Invalid latency_timer value '{}': {e}", s.trim()))
+        })
+    }
+
+    pub(super) fn set_latency_timer(path: &str, ms: u8) -> SerialResult<()> {
+        std::fs::write(latency_timer_location(path)?, format!("{ms}")).map_err(SerialError::IoError)
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{ffi::CString, ptr};
+
+    use winapi::{
+        shared::{minwindef::{DWORD, HKEY}, winerror::ERROR_SUCCESS},
+        um::{
+            errhandlingapi::SetLastError,
+            winnt::{KEY_READ, KEY_WRITE, REG_DWORD},
+            winreg::{RegCloseKey, RegEnumKeyExA, RegOpenKeyExA, RegQueryValueExA, RegSetValueExA, HKEY_LOCAL_MACHINE},
+        },
+    };
+
+    use crate::{windows::error::get_win_error, SerialResult};
+
+    const FTDIBUS_KEY: &str = r"SYSTEM\CurrentControlSet\Enum\FTDIBUS";
+
+    fn reg_error(status: i32) -> crate::SerialError {
+        unsafe { SetLastError(status as DWORD) };
+        get_win_error()
+    }
+
+    fn open_key(path: &str, access: DWORD) -> SerialResult<HKEY> {
+        let name = CString::new(path).unwrap();
+        let mut key: HKEY = ptr::null_mut();
+        let status = unsafe { RegOpenKeyExA(HKEY_LOCAL_MACHINE, name.as_ptr(), 0, access, &mut key) };
+        if status as DWORD != ERROR_SUCCESS {
+            return Err(reg_error(status));
+        }
+        Ok(key)
+    }
+
+    fn query_value(key: HKEY, value: &str, buf: &mut [u8]) -> Option<usize> {
+        let name = CString::new(value).unwrap();
+        let mut len = buf.len() as DWORD;
+        let status = unsafe { RegQueryValueExA(key, name.as_ptr(), ptr::null_mut(), ptr::null_mut(), buf.as_mut_ptr(), &mut len) };
+        match status as DWORD {
+            ERROR_SUCCESS => Some(len as usize),
+            _ => None,
+        }
+    }
+
+    /// Finds the `Device Parameters` key of the FTDIBUS device whose PortName matches `path`
+    pub(super) fn latency_timer_location(path: &str) -> SerialResult<String> {
+        let port = path.trim_start_matches(r"\\.\");
+        let root = open_key(FTDIBUS_KEY, KEY_READ).map_err(|_| super::not_ftdi(path))?;
+        let mut idx = 0;
+        let mut found = None;
+        loop {
+            let mut name_buf = [0u8; 256];
+            let mut name_len = name_buf.len() as DWORD;
+            let status = unsafe {
+                RegEnumKeyExA(root, idx, name_buf.as_mut_ptr() as *mut i8, &mut name_len, ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), ptr::null_mut())
+            };
+            if status as DWORD != ERROR_SUCCESS {
+                break;
+            }
+            idx += 1;
+            let dev = String::from_utf8_lossy(&name_buf[..name_len as usize]).to_string();
+            let params = format!(r"{FTDIBUS_KEY}\{dev}\0000\Device Parameters");
+            if let Ok(key) = open_key(&params, KEY_READ) {
+                let mut port_name = [0u8; 64];
+                let matches = query_value(key, "PortName", &mut port_name)
+                    .map(|len| String::from_utf8_lossy(&port_name[..len]).trim_matches(char::from(0)).eq_ignore_ascii_case(port))
+                    .unwrap_or(false);
+                unsafe { RegCloseKey(key) };
+                if matches {
+                    found = Some(params);
+                    break;
+                }
+            }
+        }
+        unsafe { RegCloseKey(root) };
+        found.ok_or_else(|| super::not_ftdi(path))
+    }
+
+    pub(super) fn get_latency_timer(path: &str) -> SerialResult<u8> {
+        let key = open_key(&latency_timer_location(path)?, KEY_READ)?;
+        let mut value = [0u8; 4];
+        let res = query_value(key, "LatencyTimer", &mut value);
+        unsafe { RegCloseKey(key) };
+        match res {
+            Some(4) => Ok(DWORD::from_le_bytes(value) as u8),
+            _ => Ok(16), // Driver default when the value is absent
+        }
+    }
+
+    pub(super) fn set_latency_timer(path: &str, ms: u8) -> SerialResult<()> {
+        let key = open_key(&latency_timer_location(path)?, KEY_WRITE)?;
+        let name = CString::new("LatencyTimer").unwrap();
+        let value = (ms as DWORD).to_le_bytes();
+        let status = unsafe { RegSetValueExA(key, name.as_ptr(), 0, REG_DWORD, value.as_ptr(), value.len() as DWORD) };
+        unsafe { RegCloseKey(key) };
+        match status as DWORD {
+            ERROR_SUCCESS => Ok(()),
+            _ => Err(reg_error(status)),
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod imp {
+    use crate::{SerialError, SerialResult};
+
+    fn unsupported() -> SerialError {
+        SerialError::LibraryError("FTDI latency timer configuration is unsupported on this platform".to_string())
+    }
+
+    pub(super) fn latency_timer_location(_path: &str) -> SerialResult<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn get_latency_timer(_path: &str) -> SerialResult<u8> {
+        Err(unsupported())
+    }
+
+    pub(super) fn set_latency_timer(_path: &str, _ms: u8) -> SerialResult<()> {
+        Err(unsupported())
+    }
+}
@@ -0,0 +1,297 @@
+//! G-code streaming to Marlin and GRBL style firmware
+//!
+//! Firmware acknowledges each line with `ok` once it has room for another, and
+//! may ask for lines to be sent again with `Resend: N` if one was corrupted.
+//! [GcodeSender] keeps a configurable number of lines in flight, adds line
+//! numbers and checksums, resends from its history when asked, and treats
+//! `busy:` messages as a sign of life so long moves do not time out.
+//!
+//! For Marlin, keep the defaults. For GRBL, turn off line numbers and let the
+//! sender fill GRBL's 128 byte receive buffer:
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::gcode::GcodeSender;
+//! let mut grbl = GcodeSender::new(port).line_numbers(false).window(16).rx_buffer(Some(128));
+//! for line in std::fs::read_to_string("part.nc").unwrap().lines() {
+//!     grbl.send(line)?;
+//! }
+//! grbl.wait_all()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+/// Number of sent lines kept for resend requests
+const HISTORY: usize = 256;
+
+/// Streams G-code lines with acknowledgement based flow control
+#[derive(Debug)]
+pub struct GcodeSender<P: SerialPort> {
+    port: P,
+    line_numbers: bool,
+    window: usize,
+    rx_buffer: Option<usize>,
+    timeout: Duration,
+    /// Number of the next new line
+    next_line: u32,
+    /// Sent lines, with their line numbers, for resends
+    history: VecDeque<(u32, String)>,
+    /// Length of each line awaiting its `ok`, oldest first
+    outstanding: VecDeque<usize>,
+    /// Acknowledgements still due for lines sent before the last resend. Resend
+    /// requests for those lines repeat the one already handled
+    stale: usize,
+    /// Partial line received
+    rx: Vec<u8>,
+    /// Lines received which were not acknowledgements
+    messages: Vec<String>,
+}
+
+impl<P: SerialPort> GcodeSender<P> {
+    /// Creates a sender with line numbers and checksums, one line in flight, and a
+    /// 10 second timeout for each acknowledgement
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            line_numbers: true,
+            window: 1,
+            rx_buffer: None,
+            timeout: Duration::from_secs(10),
+            next_line: 1,
+            history: VecDeque::new(),
+            outstanding: VecDeque::new(),
+            stale: 0,
+            rx: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    /// Sets whether lines are sent as `N<n> <line>*<checksum>`. Marlin uses these to
+    /// detect corruption and request resends, GRBL does not support them
+    pub fn line_numbers(mut self, enable: bool) -> Self {
+        self.line_numbers = enable;
+        self
+    }
+
+    /// Sets how many lines can be awaiting acknowledgement at once. Marlin's
+    /// command buffer usually holds 4 lines, but 1 is the only safe value without
+    /// knowing the build's configuration
+    pub fn window(mut self, lines: usize) -> Self {
+        self.window = lines.max(1);
+        self
+    }
+
+    /// Also limits the bytes in flight to the firmware's receive buffer size, for
+    /// GRBL's character counting flow control
+    pub fn rx_buffer(mut self, bytes: Option<usize>) -> Self {
+        self.rx_buffer = bytes;
+        self
+    }
+
+    /// Sets how long to wait for an acknowledgement. `busy:` messages restart the wait
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Resets the firmware's line number with `M110 N0`, after waiting for
+    /// outstanding lines
+    pub fn reset_line_numbers(&mut self) -> SerialResult<()> {
+        self.wait_all()?;
+        self.history.clear();
+        self.next_line = 0;
+        self.send("M110 N0")?;
+        self.wait_all()
+    }
+
+    /// Sends a line, once the window has room for it. Comments are stripped, and
+    /// lines with nothing else are skipped
+    pub fn send(&mut self, line: &str) -> SerialResult<()> {
+        let line = strip_comments(line);
+        if line.is_empty() {
+            return Ok(());
+        }
+        let number = self.next_line;
+        let framed = self.frame(number, &line);
+        while !self.outstanding.is_empty()
+            && (self.outstanding.len() >= self.window
+                || self.rx_buffer.is_some_and(|max| self.outstanding.iter().sum::<usize>() + framed.len() > max))
+        {
+            self.wait_ack()?;
+        }
+        self.write(&framed)?;
+        self.outstanding.push_back(framed.len());
+        self.next_line = self.next_line.wrapping_add(1);
+        self.history.push_back((number, line));
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Waits for outstanding lines, then sends a line and waits for it to be
+    /// acknowledged. Returns the lines received in response, including the `ok`
+    /// line, which carries the response to commands such as `M105`
+    pub fn command(&mut self, line: &str) -> SerialResult<Vec<String>> {
+        self.wait_all()?;
+        let earlier = std::mem::take(&mut self.messages);
+        let res = self.send(line).and_then(|_| {
+            let mut lines = Vec::new();
+            while !self.outstanding.is_empty() {
+                let ack = self.wait_ack()?;
+                lines.append(&mut self.messages);
+                lines.push(ack);
+            }
+            Ok(lines)
+        });
+        self.messages.splice(0..0, earlier);
+        res
+    }
+
+    /// Waits until every line sent has been acknowledged
+    pub fn wait_all(&mut self) -> SerialResult<()> {
+        while !self.outstanding.is_empty() {
+            self.wait_ack()?;
+        }
+        Ok(())
+    }
+
+    /// Number of lines awaiting acknowledgement
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Takes the lines received which were not acknowledgements, such as
+    /// temperature reports and `echo:` messages
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// Gets a reference to the port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn frame(&self, number: u32, line: &str) -> String {
+        if !self.line_numbers {
+            return format!("{line}\n");
+        }
+        let body = format!("N{number} {line}");
+        let checksum = body.bytes().fold(0u8, |cs, b| cs ^ b);
+        format!("{body}*{checksum}\n")
+    }
+
+    fn write(&mut self, data: &str) -> SerialResult<()> {
+        self.port.write_all(data.as_bytes()).and_then(|_| self.port.flush()).map_err(SerialError::IoError)
+    }
+
+    /// Reads lines until the oldest outstanding line is acknowledged, handling
+    /// resend requests and busy messages on the way. Returns the `ok` line
+    fn wait_ack(&mut self) -> SerialResult<String> {
+        let mut deadline = Instant::now() + self.timeout;
+        loop {
+            let line = self.read_line(deadline)?;
+            let lower = line.to_ascii_lowercase();
+            if lower.starts_with("ok") {
+                self.outstanding.pop_front();
+                self.stale = self.stale.saturating_sub(1);
+                return Ok(line);
+            } else if lower.starts_with("resend:") || lower.starts_with("rs ") || lower.starts_with("rs:") {
+                let number = line[line.find([':', ' ']).unwrap_or(0) + 1..].trim().trim_start_matches('N');
+                let number = number
+                    .parse()
+                    .map_err(|_| SerialError::LibraryError(format!("Bad resend request {line}")))?;
+                self.resend(number)?;
+            } else if lower.contains("busy:") {
+                deadline = Instant::now() + self.timeout;
+            } else if lower == "wait" {
+                // Marlin's idle message, sent when it is waiting for input
+            } else if lower.starts_with("error:") && (self.is_grbl_error(&lower) || lower.contains("halted")) {
+                self.outstanding.pop_front();
+                return Err(SerialError::LibraryError(format!("Firmware error: {line}")));
+            } else if lower.starts_with("alarm") || lower.starts_with("!!") {
+                return Err(SerialError::LibraryError(format!("Firmware alarm: {line}")));
+            } else {
+                self.messages.push(line);
+            }
+        }
+    }
+
+    /// GRBL reports `error:<code>` instead of `ok`, whilst Marlin follows its
+    /// errors with a resend request and `ok`
+    fn is_grbl_error(&self, lower: &str) -> bool {
+        !self.line_numbers && lower[6..].trim().parse::<u32>().is_ok()
+    }
+
+    /// Sends every line from `number` onwards again
+    fn resend(&mut self, number: u32) -> SerialResult<()> {
+        if self.stale > 0 {
+            return Ok(());
+        }
+        let start = self
+            .history
+            .iter()
+            .position(|(n, _)| *n == number)
+            .ok_or_else(|| SerialError::LibraryError(format!("Resend of line {number}, which is no longer in the history")))?;
+        self.stale = self.outstanding.len();
+        let lines: Vec<(u32, String)> = self.history.iter().skip(start).cloned().collect();
+        for (n, line) in lines {
+            let framed = self.frame(n, &line);
+            self.write(&framed)?;
+            self.outstanding.push_back(framed.len());
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self, deadline: Instant) -> SerialResult<String> {
+        loop {
+            if let Some(pos) = self.rx.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.rx.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                if line.is_empty() {
+                    continue;
+                }
+                return Ok(line);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+                return Err(SerialError::IoError(std::io::Error::new(ErrorKind::TimedOut, "No acknowledgement from firmware")));
+            }
+            let mut chunk = [0u8; 256];
+            match self.port.read_shared(&mut chunk) {
+                Ok(n) => self.rx.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        }
+    }
+}
+
+/// Removes `;` comments and `(...)` comments, and surrounding whitespace
+fn strip_comments(line: &str) -> String {
+    let line = line.split(';').next().unwrap_or_default();
+    let mut out = String::with_capacity(line.len());
+    let mut depth = 0;
+    for c in line.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            c if depth == 0 => out.push(c),
+            _ => {}
+        }
+    }
+    out.trim().to_string()
+}
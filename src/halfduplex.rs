@@ -0,0 +1,161 @@
+//! Half-duplex request/response transactions
+//!
+//! On a half-duplex link such as 2-wire RS-485, only one end can transmit at a
+//! time. A request is written, the transmitter drained and released, and only then
+//! can the response be read. On many buses the transceiver also receives its own
+//! transmission, which has to be skipped before the response.
+//!
+//! [HalfDuplex] runs this sequence. Adapters which switch the driver themselves
+//! (most USB RS-485 adapters) need no direction control, otherwise the driver
+//! enable can be driven from RTS or DTR with [HalfDuplex::direction].
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use std::time::Duration;
+//! use serial_rs::{halfduplex::{Direction, HalfDuplex}, stm32::ControlLine};
+//! let mut bus = HalfDuplex::new(port)
+//!     .direction(Direction { line: ControlLine::Rts, transmit: true })?
+//!     .echo(true);
+//! let mut resp = [0u8; 16];
+//! let n = bus.transact(b"\x01PING", &mut resp, Duration::from_micros(500), Duration::from_millis(100))?;
+//! println!("{:02X?}", &resp[..n]);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{stm32::ControlLine, SerialError, SerialPort, SerialResult};
+
+/// Control line driving the transceiver's driver enable
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Direction {
+    /// Line wired to the driver enable
+    pub line: ControlLine,
+    /// Line state (as passed to [crate::ControlLines::set_request_to_send] or
+    /// [crate::ControlLines::set_data_terminal_ready]) which enables the driver
+    pub transmit: bool,
+}
+
+impl Direction {
+    fn set(&self, port: &dyn SerialPort, transmit: bool) -> SerialResult<()> {
+        let state = if transmit { self.transmit } else { !self.transmit };
+        match self.line {
+            ControlLine::Dtr => port.set_data_terminal_ready(state),
+            ControlLine::Rts => port.set_request_to_send(state),
+        }
+    }
+}
+
+/// Half-duplex link on a port
+#[derive(Debug)]
+pub struct HalfDuplex<P: SerialPort> {
+    port: P,
+    direction: Option<Direction>,
+    echo: bool,
+    idle_gap: Option<Duration>,
+}
+
+impl<P: SerialPort> HalfDuplex<P> {
+    /// Creates a link without direction control or echo suppression
+    pub fn new(port: P) -> Self {
+        Self { port, direction: None, echo: false, idle_gap: None }
+    }
+
+    /// Drives the transceiver's driver enable from a control line. The line is
+    /// set to receive straight away
+    pub fn direction(mut self, direction: Direction) -> SerialResult<Self> {
+        direction.set(&self.port, false)?;
+        self.direction = Some(direction);
+        Ok(self)
+    }
+
+    /// Sets whether the link receives its own transmission, which is then read
+    /// back and checked before the response. A mismatch means another device
+    /// transmitted at the same time, and fails the transaction
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    /// Ends a response early once the line has been idle for `gap` after the
+    /// first byte, for responses shorter than the buffer. Without this, a
+    /// transaction reads until the buffer is full or the timeout expires
+    pub fn idle_gap(mut self, gap: Option<Duration>) -> Self {
+        self.idle_gap = gap;
+        self
+    }
+
+    /// Writes `tx`, waits for it to be sent, then waits `turnaround` before
+    /// releasing the driver, skips the echo, and reads the response into `rx`.
+    ///
+    /// `timeout` is how long to wait for the response after the turnaround.
+    /// Returns the number of bytes read, which is 0 if nothing was received
+    pub fn transact(&mut self, tx: &[u8], rx: &mut [u8], turnaround: Duration, timeout: Duration) -> SerialResult<usize> {
+        // Nothing received before the request can be part of its response
+        self.port.clear_input_buffer()?;
+        if let Some(direction) = self.direction {
+            direction.set(&self.port, true)?;
+        }
+        let sent = self.port.write_all(tx).and_then(|_| self.port.flush());
+        // Some drivers report the transmit buffer empty whilst the last byte is
+        // still being shifted out
+        std::thread::sleep(turnaround);
+        if let Some(direction) = self.direction {
+            direction.set(&self.port, false)?;
+        }
+        sent.map_err(SerialError::IoError)?;
+
+        if self.echo {
+            let mut echo = vec![0u8; tx.len()];
+            let deadline = Instant::now() + timeout + self.port.settings().char_duration() * tx.len() as u32;
+            let read = self.read(&mut echo, deadline, None)?;
+            if read != tx.len() {
+                return Err(SerialError::IoError(std::io::Error::new(ErrorKind::TimedOut, "Transmission was not echoed")));
+            }
+            if echo != tx {
+                return Err(SerialError::LibraryError("Bus collision, echo does not match the transmission".into()));
+            }
+        }
+        self.read(rx, Instant::now() + timeout, self.idle_gap)
+    }
+
+    /// Reads until `buf` is full, `deadline` passes, or the line is idle for
+    /// `gap` after the first byte
+    fn read(&mut self, buf: &mut [u8], deadline: Instant, gap: Option<Duration>) -> SerialResult<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            let mut wait = deadline.saturating_duration_since(Instant::now());
+            if let Some(gap) = gap.filter(|_| read > 0) {
+                wait = wait.min(gap);
+            }
+            if wait.is_zero() || !self.port.poll_readable(Some(wait)).map_err(SerialError::IoError)? {
+                break;
+            }
+            match self.port.read_shared(&mut buf[read..]) {
+                Ok(n) => read += n,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        }
+        Ok(read)
+    }
+
+    /// Gets a reference to the port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port. The direction line is left set to receive
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+}
@@ -0,0 +1,187 @@
+//! Hayes modem call control
+//!
+//! A [HayesModem] dials and answers calls through the [AtPort] command layer, and
+//! hangs up by dropping DTR. Once connected, the modem is a transparent data link:
+//! it implements [Read] and [Write], which fail with [ErrorKind::NotConnected] once
+//! carrier detect (CD) drops, so a lost call is noticed on the next read or write.
+//!
+//! The modem should be set to hang up when DTR drops (`AT&D2`) and to report
+//! carrier on CD (`AT&C1`), which [HayesModem::init] does
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    at::{AtPort, ResultCode},
+    SerialError, SerialPort, SerialResult,
+};
+
+/// Silence required either side of the `+++` escape sequence
+const GUARD_TIME: Duration = Duration::from_millis(1100);
+
+/// Modem call control on top of an [AtPort]
+#[derive(Debug)]
+pub struct HayesModem<P: SerialPort> {
+    at: AtPort<P>,
+    call_timeout: Duration,
+    dtr_drop: Duration,
+    connected: bool,
+    /// Data received along with CONNECT, not yet read
+    pending: Vec<u8>,
+}
+
+impl<P: SerialPort> HayesModem<P> {
+    /// Creates a modem controller. Dialing and answering wait up to 60 seconds for
+    /// a connection
+    pub fn new(at: AtPort<P>) -> Self {
+        Self { at, call_timeout: Duration::from_secs(60), dtr_drop: Duration::from_secs(1), connected: false, pending: Vec::new() }
+    }
+
+    /// Sets how long [HayesModem::dial] and [HayesModem::answer] wait for a result
+    pub fn call_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = timeout;
+        self
+    }
+
+    /// Sets how long DTR is dropped for when hanging up
+    pub fn dtr_drop(mut self, duration: Duration) -> Self {
+        self.dtr_drop = duration;
+        self
+    }
+
+    /// Resets the modem (`ATZ`), and sets it to hang up on DTR drop and report
+    /// carrier on CD (`AT&D2&C1`)
+    pub fn init(&mut self) -> SerialResult<()> {
+        self.at.command("ATZ")?;
+        self.at.command("AT&D2&C1")?;
+        Ok(())
+    }
+
+    /// Dials `number` with `ATD`. Prefix the number with `T` or `P` to force tone or
+    /// pulse dialing. Returns the result code, which is [ResultCode::Connect] if the
+    /// call connected
+    pub fn dial(&mut self, number: &str) -> SerialResult<ResultCode> {
+        self.call(&format!("ATD{number}"))
+    }
+
+    /// Answers an incoming call with `ATA`. Returns the result code, which is
+    /// [ResultCode::Connect] if the call connected
+    pub fn answer(&mut self) -> SerialResult<ResultCode> {
+        self.call("ATA")
+    }
+
+    fn call(&mut self, cmd: &str) -> SerialResult<ResultCode> {
+        if self.connected {
+            return Err(SerialError::LibraryError("Already connected".into()));
+        }
+        let res = self.at.send_timeout(cmd, self.call_timeout)?.result;
+        if let ResultCode::Connect(_) = res {
+            self.connected = true;
+            self.pending = self.at.take_buffered();
+            self.skip_lf().map_err(SerialError::IoError)?;
+        }
+        Ok(res)
+    }
+
+    /// CONNECT ends with CR LF, and only the CR has been consumed, so drop the LF
+    /// rather than returning it as data
+    fn skip_lf(&mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            if self.pending[0] == b'\n' {
+                self.pending.remove(0);
+            }
+            return Ok(());
+        }
+        let mut lf = [0u8; 1];
+        if self.at.get_ref().poll_readable(Some(Duration::from_millis(50)))? && self.at.get_mut().read(&mut lf)? == 1 && lf[0] != b'\n' {
+            self.pending.push(lf[0]);
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for the modem to report RING. Other lines are passed
+    /// to the URC handler. Returns false if there was no call
+    pub fn wait_for_ring(&mut self, timeout: Duration) -> SerialResult<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.at.read_line(remaining)? {
+                None => return Ok(false),
+                Some(l) if l == "RING" || l.starts_with("+CRING") => return Ok(true),
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Hangs up by dropping DTR. If carrier is still present afterwards, falls back
+    /// to the `+++` escape sequence and `ATH`
+    pub fn hangup(&mut self) -> SerialResult<()> {
+        self.connected = false;
+        self.pending.clear();
+        let port = self.at.get_ref();
+        port.set_data_terminal_ready(false)?;
+        std::thread::sleep(self.dtr_drop);
+        port.set_data_terminal_ready(true)?;
+        if !port.read_carrier_detect()? {
+            return Ok(());
+        }
+        std::thread::sleep(GUARD_TIME);
+        self.at.write_raw(b"+++")?;
+        std::thread::sleep(GUARD_TIME);
+        self.at.take_buffered();
+        self.at.command("ATH")?;
+        Ok(())
+    }
+
+    /// Returns true if a call is connected. If carrier detect has dropped since the
+    /// call connected, the call is marked as ended
+    pub fn is_connected(&mut self) -> SerialResult<bool> {
+        if self.connected && !self.at.get_ref().read_carrier_detect()? {
+            self.connected = false;
+        }
+        Ok(self.connected)
+    }
+
+    fn check_connected(&mut self) -> std::io::Result<()> {
+        match self.is_connected()? {
+            true => Ok(()),
+            false => Err(std::io::Error::new(ErrorKind::NotConnected, "No call in progress")),
+        }
+    }
+
+    /// Gets the AT command layer, for commands outside of a call
+    pub fn at(&mut self) -> &mut AtPort<P> {
+        &mut self.at
+    }
+
+    /// Unwraps the AT command layer
+    pub fn into_inner(self) -> AtPort<P> {
+        self.at
+    }
+}
+
+impl<P: SerialPort> Read for HayesModem<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.pending.is_empty() {
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            return Ok(n);
+        }
+        self.check_connected()?;
+        self.at.get_mut().read(buf)
+    }
+}
+
+impl<P: SerialPort> Write for HayesModem<P> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.check_connected()?;
+        self.at.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.at.get_mut().flush()
+    }
+}
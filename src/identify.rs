@@ -0,0 +1,302 @@
+//! Identifying what is attached to each port
+//!
+//! A [ProbeRegistry] holds [Probe]s, each of which recognises one kind of device,
+//! either from the port's USB information alone or by querying the open port.
+//! [ProbeRegistry::identify_ports] lists the ports and runs the probes against
+//! each, labelling what it finds.
+//!
+//! ```no_run
+//! # fn example() -> serial_rs::SerialResult<()> {
+//! use serial_rs::identify::{ProbeRegistry, UsbIdProbe};
+//! let registry = ProbeRegistry::with_builtin().register(UsbIdProbe::new(0x2341, None, "Arduino"));
+//! for port in registry.identify_ports()? {
+//!     match port.identity {
+//!         Some(id) => println!("{}: {}", port.info.get_port(), id),
+//!         None => println!("{}: unknown", port.info.get_port()),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{list_ports, PortInfo, SerialError, SerialPort, SerialPortSettings, SerialResult};
+
+/// What a probe found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identification {
+    /// Kind of device, such as `GPS` or `ELM327`
+    pub kind: String,
+    /// Further detail, such as a firmware version
+    pub detail: Option<String>,
+}
+
+impl Identification {
+    /// Creates an identification without detail
+    pub fn new(kind: &str) -> Self {
+        Self { kind: kind.to_string(), detail: None }
+    }
+
+    /// Adds detail
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+impl std::fmt::Display for Identification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{} ({detail})", self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// Recognises one kind of device
+pub trait Probe: Send + Sync {
+    /// Name of the probe, for diagnostics
+    fn name(&self) -> &str;
+
+    /// Identifies the device from the port's information alone, without opening
+    /// it. Probes which match here are never queried
+    fn match_info(&self, _info: &PortInfo) -> Option<Identification> {
+        None
+    }
+
+    /// Settings to query the device with, or None if the probe does not query
+    fn settings(&self) -> Option<SerialPortSettings> {
+        None
+    }
+
+    /// Queries the open port, which has been configured with [Probe::settings].
+    /// Errors count as no match
+    fn query(&self, _port: &mut dyn SerialPort) -> SerialResult<Option<Identification>> {
+        Ok(None)
+    }
+}
+
+/// Port and what was found on it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortIdentity {
+    /// The port
+    pub info: PortInfo,
+    /// What is attached, or None if no probe matched
+    pub identity: Option<Identification>,
+}
+
+/// Set of probes to run against ports
+#[derive(Default)]
+pub struct ProbeRegistry {
+    probes: Vec<Box<dyn Probe>>,
+}
+
+impl std::fmt::Debug for ProbeRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.probes.iter().map(|p| p.name())).finish()
+    }
+}
+
+impl ProbeRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry with probes for u-blox receivers, NMEA GPS receivers at
+    /// 9600 and 4800 baud, ELM327 adapters, and Marlin style 3D printers
+    pub fn with_builtin() -> Self {
+        Self::new()
+            .register(UsbIdProbe::new(0x1546, None, "GPS").detail("u-blox"))
+            .register(NmeaProbe::new(9600))
+            .register(NmeaProbe::new(4800))
+            .register(Elm327Probe)
+            .register(GcodeProbe)
+    }
+
+    /// Adds a probe. Probes run in the order they were added, and the first
+    /// match wins
+    pub fn register<T: Probe + 'static>(mut self, probe: T) -> Self {
+        self.probes.push(Box::new(probe));
+        self
+    }
+
+    /// Lists the ports and identifies each one. Ports which cannot be opened,
+    /// for example as they are in use, are reported as unidentified
+    pub fn identify_ports(&self) -> SerialResult<Vec<PortIdentity>> {
+        Ok(list_ports()?
+            .into_iter()
+            .map(|info| {
+                let identity = self.identify(&info).ok().flatten();
+                PortIdentity { info, identity }
+            })
+            .collect())
+    }
+
+    /// Runs the probes against one port. The port is only opened if no probe
+    /// matches its information
+    pub fn identify(&self, info: &PortInfo) -> SerialResult<Option<Identification>> {
+        if let Some(id) = self.probes.iter().find_map(|p| p.match_info(info)) {
+            return Ok(Some(id));
+        }
+        let mut port: Option<Box<dyn SerialPort>> = None;
+        for probe in &self.probes {
+            let settings = match probe.settings() {
+                Some(s) => s,
+                None => continue,
+            };
+            match port.as_mut() {
+                Some(port) => {
+                    *port.setting() = settings;
+                    port.reconfigure_port()?;
+                }
+                None => port = Some(crate::new(info.clone(), Some(settings))?),
+            }
+            if let Some(port) = port.as_mut() {
+                if let Ok(Some(id)) = probe.query(port.as_mut()) {
+                    return Ok(Some(id));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Identifies devices by USB vendor and product ID
+#[derive(Debug, Clone)]
+pub struct UsbIdProbe {
+    vid: u16,
+    pid: Option<u16>,
+    id: Identification,
+}
+
+impl UsbIdProbe {
+    /// Matches `vid`, and `pid` if given, labelling the port as `kind`
+    pub fn new(vid: u16, pid: Option<u16>, kind: &str) -> Self {
+        Self { vid, pid, id: Identification::new(kind) }
+    }
+
+    /// Adds detail to the label
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.id = self.id.detail(detail);
+        self
+    }
+}
+
+impl Probe for UsbIdProbe {
+    fn name(&self) -> &str {
+        "USB ID"
+    }
+
+    fn match_info(&self, info: &PortInfo) -> Option<Identification> {
+        (info.get_vid() == self.vid && self.pid.is_none_or(|pid| info.get_pid() == pid)).then(|| self.id.clone())
+    }
+}
+
+/// Listens for NMEA sentences from a GPS receiver
+#[derive(Debug, Copy, Clone)]
+pub struct NmeaProbe {
+    baud: u32,
+}
+
+impl NmeaProbe {
+    /// Listens at `baud`
+    pub fn new(baud: u32) -> Self {
+        Self { baud }
+    }
+}
+
+impl Probe for NmeaProbe {
+    fn name(&self) -> &str {
+        "NMEA"
+    }
+
+    fn settings(&self) -> Option<SerialPortSettings> {
+        Some(SerialPortSettings::default().baud(self.baud))
+    }
+
+    fn query(&self, port: &mut dyn SerialPort) -> SerialResult<Option<Identification>> {
+        // Receivers send at least one sentence a second
+        let line = exchange(port, b"", b"$G", Duration::from_millis(1500))?;
+        Ok(line.filter(|l| l.contains('*')).map(|l| {
+            let talker = l.get(1..3).unwrap_or("GP").to_string();
+            Identification::new("GPS").detail(&format!("NMEA {talker} at {} baud", self.baud))
+        }))
+    }
+}
+
+/// Queries an ELM327 OBD-II adapter with `ATI`
+#[derive(Debug, Copy, Clone)]
+pub struct Elm327Probe;
+
+impl Probe for Elm327Probe {
+    fn name(&self) -> &str {
+        "ELM327"
+    }
+
+    fn settings(&self) -> Option<SerialPortSettings> {
+        Some(SerialPortSettings::default().baud(38_400))
+    }
+
+    fn query(&self, port: &mut dyn SerialPort) -> SerialResult<Option<Identification>> {
+        let line = exchange(port, b"\rATI\r", b"ELM327", Duration::from_secs(1))?;
+        Ok(line.map(|l| Identification::new("ELM327").detail(l.trim())))
+    }
+}
+
+/// Queries a Marlin style 3D printer with `M115`
+#[derive(Debug, Copy, Clone)]
+pub struct GcodeProbe;
+
+impl Probe for GcodeProbe {
+    fn name(&self) -> &str {
+        "G-code"
+    }
+
+    fn settings(&self) -> Option<SerialPortSettings> {
+        Some(SerialPortSettings::default().baud(115_200))
+    }
+
+    fn query(&self, port: &mut dyn SerialPort) -> SerialResult<Option<Identification>> {
+        // Opening the port resets many boards, which then take a while to boot
+        let line = exchange(port, b"\nM115\n", b"FIRMWARE_NAME:", Duration::from_secs(3))?;
+        Ok(line.map(|l| {
+            let name = l.split("FIRMWARE_NAME:").nth(1).unwrap_or_default();
+            let name = name.split(" SOURCE_CODE_URL").next().unwrap_or_default();
+            Identification::new("3D printer").detail(name.trim())
+        }))
+    }
+}
+
+/// Sends `request`, then reads until a line containing `needle` is complete or
+/// `timeout` expires. Returns that line
+fn exchange(port: &mut dyn SerialPort, request: &[u8], needle: &[u8], timeout: Duration) -> SerialResult<Option<String>> {
+    port.clear_input_buffer()?;
+    if !request.is_empty() {
+        port.write_all(request).and_then(|_| port.flush()).map_err(SerialError::IoError)?;
+    }
+    let deadline = Instant::now() + timeout;
+    let mut data = Vec::new();
+    let mut chunk = [0u8; 256];
+    loop {
+        if let Some(start) = data.windows(needle.len()).position(|w| w == needle) {
+            let line_start = data[..start].iter().rposition(|b| *b == b'\n' || *b == b'\r').map_or(0, |p| p + 1);
+            if let Some(len) = data[start..].iter().position(|b| *b == b'\n' || *b == b'\r') {
+                return Ok(Some(String::from_utf8_lossy(&data[line_start..start + len]).to_string()));
+            }
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || !port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+            return Ok(None);
+        }
+        match port.read_shared(&mut chunk) {
+            Ok(n) => data.extend_from_slice(&chunk[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Idle / inactivity detection
+//!
+//! An [IdleWatchdog] runs a small background thread which fires once no bytes
+//! have been received for a configured duration. Reads are reported to the
+//! watchdog by wrapping the port (or a clone of it) in an [IdleReader].
+//!
+//! This can be used to detect stalled devices, or the end of a message in
+//! protocols which are framed by a gap on the line, without having to
+//! busy-poll [crate::BufferControl::bytes_to_read]
+
+use std::{
+    io::Read,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct State {
+    last_rx: Instant,
+    idle: bool,
+    stop: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    cvar: Condvar,
+    timeout: Duration,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Watchdog which detects when no data has been received for a given duration
+#[derive(Debug)]
+pub struct IdleWatchdog {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl IdleWatchdog {
+    /// Creates a new watchdog without a callback. Use [IdleWatchdog::is_idle]
+    /// or [IdleWatchdog::wait_idle] to observe idle events
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_callback(timeout, || {})
+    }
+
+    /// Creates a new watchdog which calls `callback` from the watchdog thread
+    /// every time the line goes idle. The callback fires once per idle period,
+    /// and is re-armed by the next received byte
+    pub fn with_callback<F: FnMut() + Send + 'static>(timeout: Duration, mut callback: F) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                last_rx: Instant::now(),
+                idle: false,
+                stop: false,
+            }),
+            cvar: Condvar::new(),
+            timeout,
+        });
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || {
+            let shared = thread_shared;
+            let mut state = shared.lock();
+            loop {
+                if state.stop {
+                    break;
+                }
+                if state.idle {
+                    state = shared.cvar.wait(state).unwrap_or_else(|e| e.into_inner());
+                    continue;
+                }
+                let deadline = state.last_rx + shared.timeout;
+                let now = Instant::now();
+                if now >= deadline {
+                    state.idle = true;
+                    shared.cvar.notify_all();
+                    drop(state);
+                    callback();
+                    state = shared.lock();
+                } else {
+                    state = shared
+                        .cvar
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                }
+            }
+        });
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns a handle which can be used to report received data to this watchdog
+    pub fn feeder(&self) -> IdleFeeder {
+        IdleFeeder {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Wraps a reader so that every successful read resets this watchdog
+    pub fn wrap<R: Read>(&self, inner: R) -> IdleReader<R> {
+        IdleReader {
+            inner,
+            feeder: self.feeder(),
+        }
+    }
+
+    /// Returns true if the line is currently idle
+    pub fn is_idle(&self) -> bool {
+        self.shared.lock().idle
+    }
+
+    /// Returns the time elapsed since data was last received
+    pub fn since_last_rx(&self) -> Duration {
+        self.shared.lock().last_rx.elapsed()
+    }
+
+    /// Blocks until the line goes idle. Returns false if `timeout` expired first
+    pub fn wait_idle(&self, timeout: Option<Duration>) -> bool {
+        let state = self.shared.lock();
+        match timeout {
+            Some(t) => {
+                self.shared
+                    .cvar
+                    .wait_timeout_while(state, t, |s| !s.idle)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0
+                    .idle
+            }
+            None => {
+                self.shared
+                    .cvar
+                    .wait_while(state, |s| !s.idle)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .idle
+            }
+        }
+    }
+}
+
+impl Drop for IdleWatchdog {
+    fn drop(&mut self) {
+        self.shared.lock().stop = true;
+        self.shared.cvar.notify_all();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Handle used to report activity to an [IdleWatchdog]
+#[derive(Debug, Clone)]
+pub struct IdleFeeder {
+    shared: Arc<Shared>,
+}
+
+impl IdleFeeder {
+    /// Reports that data was just received, resetting the idle timer
+    pub fn feed(&self) {
+        let mut state = self.shared.lock();
+        state.last_rx = Instant::now();
+        state.idle = false;
+        self.shared.cvar.notify_all();
+    }
+}
+
+/// Reader which reports every non-empty read to an [IdleWatchdog]
+#[derive(Debug)]
+pub struct IdleReader<R: Read> {
+    inner: R,
+    feeder: IdleFeeder,
+}
+
+impl<R: Read> IdleReader<R> {
+    /// Creates a new reader reporting to the watchdog behind `feeder`
+    pub fn new(inner: R, feeder: IdleFeeder) -> Self {
+        Self { inner, feeder }
+    }
+    /// Gets a reference to the wrapped reader
+    pub fn get_ref(&self) -> &R { &self.inner }
+    /// Gets a mutable reference to the wrapped reader
+    pub fn get_mut(&mut self) -> &mut R { &mut self.inner }
+    /// Unwraps the reader
+    pub fn into_inner(self) -> R { self.inner }
+}
+
+impl<R: Read> Read for IdleReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read != 0 {
+            self.feeder.feed();
+        }
+        Ok(read)
+    }
+}
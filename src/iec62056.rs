@@ -0,0 +1,336 @@
+//! IEC 62056-21 (formerly IEC 1107) meter reading, mode C
+//!
+//! Electricity, gas and heat meters with an optical or RS-485 port start every
+//! session at 300 baud 7E1. The reader sends a request message, and the meter
+//! answers with its identification, which includes the highest baud rate it
+//! supports. The reader acknowledges with the baud rate and mode it wants, and
+//! both sides switch to that baud rate for the rest of the session.
+//!
+//! The port is reconfigured mid-session without purging its buffers, so a meter
+//! which answers quickly after the switch is not missed.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::iec62056::Iec62056;
+//! let mut meter = Iec62056::new(port)?;
+//! let readout = meter.readout("")?;
+//! println!("Meter {}", readout.identification.ident);
+//! for set in readout.data_sets() {
+//!     println!("{} = {:?}", set.address, set.values);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{ByteSize, FlowControl, Parity, SerialError, SerialPort, SerialResult, StopBits};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+
+/// Baud rate of the opening handshake
+const INITIAL_BAUD: u32 = 300;
+/// Maximum reaction and inter-character time allowed for the meter
+const METER_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Baud rates selected by the mode C baud rate characters '0' to '6'
+const MODE_C_BAUDS: [u32; 7] = [300, 600, 1200, 2400, 4800, 9600, 19200];
+
+/// Meter identification message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identification {
+    /// 3 letter manufacturer code
+    pub manufacturer: String,
+    /// Highest baud rate the meter supports
+    pub max_baud: u32,
+    /// Identification text
+    pub ident: String,
+}
+
+/// Data set from a readout, such as `1.8.0(001234.5*kWh)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataSet {
+    /// Address (OBIS code), such as `1.8.0`
+    pub address: String,
+    /// Values, each with its unit if present
+    pub values: Vec<(String, Option<String>)>,
+}
+
+/// Result of a data readout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Readout {
+    /// Meter identification
+    pub identification: Identification,
+    /// Data block, without the `!` end marker
+    pub data: String,
+}
+
+impl Readout {
+    /// Parses the data block into data sets
+    pub fn data_sets(&self) -> Vec<DataSet> {
+        let mut sets = Vec::new();
+        for line in self.data.lines() {
+            let mut rest = line.trim();
+            while let Some(open) = rest.find('(') {
+                let address = rest[..open].trim();
+                let mut values = Vec::new();
+                let mut tail = &rest[open..];
+                // A data set can have several values, such as a value and a time
+                while let Some(body) = tail.strip_prefix('(') {
+                    let close = match body.find(')') {
+                        Some(close) => close,
+                        None => break,
+                    };
+                    let value = &body[..close];
+                    values.push(match value.split_once('*') {
+                        Some((v, unit)) => (v.to_string(), Some(unit.to_string())),
+                        None => (value.to_string(), None),
+                    });
+                    tail = &body[close + 1..];
+                }
+                if address.is_empty() && values.is_empty() {
+                    break;
+                }
+                sets.push(DataSet { address: address.to_string(), values });
+                rest = tail;
+            }
+        }
+        sets
+    }
+}
+
+/// Meter session mode requested in the acknowledgement
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Readout,
+    Programming,
+}
+
+/// IEC 62056-21 mode C session on a port
+#[derive(Debug)]
+pub struct Iec62056<P: SerialPort> {
+    port: P,
+    max_baud: u32,
+    timeout: Duration,
+}
+
+impl<P: SerialPort> Iec62056<P> {
+    /// Creates a session, setting the port to 300 baud 7E1
+    pub fn new(mut port: P) -> SerialResult<Self> {
+        let settings = port
+            .settings()
+            .baud(INITIAL_BAUD)
+            .byte_size(ByteSize::Seven)
+            .parity(Parity::Even)
+            .stop_bits(StopBits::One)
+            .set_flow_control(FlowControl::None);
+        *port.setting() = settings;
+        port.reconfigure_port()?;
+        Ok(Self { port, max_baud: 19200, timeout: METER_TIMEOUT + Duration::from_millis(500) })
+    }
+
+    /// Sets the highest baud rate to switch to, for links such as long RS-485
+    /// buses which cannot run at the meter's maximum
+    pub fn max_baud(mut self, baud: u32) -> Self {
+        self.max_baud = baud;
+        self
+    }
+
+    /// Sets how long to wait for the meter to respond, and between characters
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reads all data from the meter at `address` (empty for any meter on the
+    /// link). The port is left at the session baud rate; meters return to 300 baud
+    /// once the readout ends
+    pub fn readout(&mut self, address: &str) -> SerialResult<Readout> {
+        let identification = self.handshake(address, Mode::Readout)?;
+        let data = self.read_block()?;
+        let data = data.trim_end().trim_end_matches('!').trim_end().to_string();
+        Ok(Readout { identification, data })
+    }
+
+    /// Opens a programming mode session with the meter at `address`. Returns the
+    /// identification, and the operand of the meter's P0 message, which is usually
+    /// a challenge for the password
+    pub fn open_programming(&mut self, address: &str) -> SerialResult<(Identification, String)> {
+        let identification = self.handshake(address, Mode::Programming)?;
+        let (cmd, data) = self.read_command_block()?;
+        if cmd != "P0" {
+            return Err(SerialError::LibraryError(format!("Expected P0 from meter, got {cmd}")));
+        }
+        Ok((identification, data))
+    }
+
+    /// Sends a programming mode command, such as `R1` with a data set address to
+    /// read, or `P1` with a password. Returns the data block the meter replied
+    /// with, or None if it replied with ACK
+    pub fn command(&mut self, cmd: &str, data: &str) -> SerialResult<Option<String>> {
+        let mut msg = vec![SOH];
+        msg.extend_from_slice(cmd.as_bytes());
+        msg.push(STX);
+        msg.extend_from_slice(data.as_bytes());
+        msg.push(ETX);
+        msg.push(bcc(&msg[1..]));
+        self.port.clear_input_buffer()?;
+        self.write(&msg)?;
+        match self.read_byte()? {
+            ACK => Ok(None),
+            NAK => Err(SerialError::LibraryError(format!("Meter rejected {cmd}"))),
+            STX => self.read_block_body(0).map(Some),
+            b => Err(SerialError::LibraryError(format!("Unexpected reply {b:#04x} to {cmd}"))),
+        }
+    }
+
+    /// Ends a programming mode session (`B0`)
+    pub fn close(&mut self) -> SerialResult<()> {
+        let mut msg = vec![SOH, b'B', b'0', ETX];
+        msg.push(bcc(&msg[1..]));
+        self.write(&msg)
+    }
+
+    /// Gets a reference to the underlying port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the underlying port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Sends the request at 300 baud, reads the identification, acknowledges with
+    /// the chosen baud rate, and switches to it
+    fn handshake(&mut self, address: &str, mode: Mode) -> SerialResult<Identification> {
+        self.port.set_baud_live(INITIAL_BAUD)?;
+        self.port.clear_input_buffer()?;
+        self.write(format!("/?{address}!\r\n").as_bytes())?;
+        let line = self.read_line()?;
+        let ident = line
+            .strip_prefix('/')
+            .filter(|l| l.len() >= 4 && l.is_char_boundary(3) && l.is_char_boundary(4))
+            .ok_or_else(|| SerialError::LibraryError(format!("Bad identification {line}")))?;
+        let (manufacturer, rest) = ident.split_at(3);
+        let baud_char = rest.as_bytes()[0];
+        let meter_max = match baud_char {
+            b'0'..=b'6' => MODE_C_BAUDS[(baud_char - b'0') as usize],
+            _ => return Err(SerialError::LibraryError(format!("Meter does not support mode C ({line})"))),
+        };
+        // Skip the enhanced capability marker, if present
+        let ident_text = rest[1..].strip_prefix("\\2").unwrap_or(&rest[1..]);
+        let identification = Identification {
+            manufacturer: manufacturer.to_string(),
+            max_baud: meter_max,
+            ident: ident_text.to_string(),
+        };
+
+        let index = MODE_C_BAUDS.iter().rposition(|b| *b <= meter_max.min(self.max_baud)).unwrap_or(0);
+        let mode_char = match mode {
+            Mode::Readout => b'0',
+            Mode::Programming => b'1',
+        };
+        self.write(&[ACK, b'0', b'0' + index as u8, mode_char, b'\r', b'\n'])?;
+        // The acknowledgement must be fully sent at 300 baud before switching. Input
+        // is kept, in case the meter has already started replying
+        self.port.set_baud_live(MODE_C_BAUDS[index])?;
+        Ok(identification)
+    }
+
+    fn write(&mut self, data: &[u8]) -> SerialResult<()> {
+        self.port.write_all(data).and_then(|_| self.port.flush()).map_err(SerialError::IoError)
+    }
+
+    fn read_byte(&mut self) -> SerialResult<u8> {
+        let deadline = Instant::now() + self.timeout;
+        let mut b = [0u8; 1];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !self.port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+                return Err(SerialError::IoError(std::io::Error::new(ErrorKind::TimedOut, "No response from meter")));
+            }
+            match self.port.read(&mut b) {
+                // Strip the parity bit, in case the port delivers it
+                Ok(1) => return Ok(b[0] & 0x7F),
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> SerialResult<String> {
+        let mut line = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'\n' => return Ok(String::from_utf8_lossy(&line).trim_end_matches('\r').to_string()),
+                b => line.push(b),
+            }
+        }
+    }
+
+    /// Reads a data block starting with STX
+    fn read_block(&mut self) -> SerialResult<String> {
+        loop {
+            match self.read_byte()? {
+                STX => return self.read_block_body(0),
+                NAK => return Err(SerialError::LibraryError("Meter sent NAK".into())),
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads a block after its STX, up to ETX (or EOT for a partial block) and the
+    /// BCC. `seed` is the BCC of anything covered before the STX
+    fn read_block_body(&mut self, seed: u8) -> SerialResult<String> {
+        let mut body = Vec::new();
+        loop {
+            let b = self.read_byte()?;
+            body.push(b);
+            if b == ETX || b == EOT {
+                break;
+            }
+        }
+        let check = self.read_byte()?;
+        if seed ^ bcc(&body) != check {
+            return Err(SerialError::IoError(std::io::Error::new(ErrorKind::InvalidData, "BCC mismatch")));
+        }
+        body.pop();
+        Ok(String::from_utf8_lossy(&body).to_string())
+    }
+
+    /// Reads a programming mode block: SOH, command, STX, data, ETX, BCC
+    fn read_command_block(&mut self) -> SerialResult<(String, String)> {
+        while self.read_byte()? != SOH {}
+        let mut cmd = Vec::new();
+        loop {
+            match self.read_byte()? {
+                STX => break,
+                b => cmd.push(b),
+            }
+        }
+        // The BCC also covers the command and STX
+        let data = self.read_block_body(bcc(&cmd) ^ STX)?;
+        Ok((String::from_utf8_lossy(&cmd).to_string(), data))
+    }
+}
+
+/// Block check character: XOR of every byte after the first SOH or STX, up to
+/// and including ETX
+fn bcc(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, b| acc ^ b)
+}
@@ -0,0 +1,354 @@
+//! ISO 9141 / ISO 14230 K-line initialisation
+//!
+//! # 5 baud init
+//!
+//! The tester wakes an ECU by sending its address at 5 baud, which no UART can
+//! generate, so the bits are driven directly with [crate::ControlLines::set_break_state]:
+//! break for a 0, idle for a 1, 200ms each. The ECU then answers at the
+//! communication baud rate (usually 10400) with the sync byte 0x55 and two key
+//! bytes. The tester acknowledges with the inverted second key byte, and the ECU
+//! confirms with the inverted address.
+//!
+//! The K-line is a single wire, so everything the tester sends is echoed back. This
+//! is skipped by default, see [FiveBaudInit::echo].
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(mut port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::kline::FiveBaudInit;
+//! let keys = FiveBaudInit::new(0x33).run(&mut port)?;
+//! println!("Key bytes {:02X} {:02X}", keys.kb1, keys.kb2);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Fast init
+//!
+//! ISO 14230 (KWP2000) ECUs can also be woken with a 25ms low, 25ms high pattern,
+//! followed by a StartCommunication request at 10400 baud, see [FastInit]. The
+//! response carries the same key bytes.
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{pacing::sleep_until, ByteSize, FlowControl, Parity, SerialError, SerialPort, SerialResult, StopBits};
+
+const SYNC: u8 = 0x55;
+/// Length of one bit at 5 baud
+const BIT_TIME: Duration = Duration::from_millis(200);
+/// W1: maximum time from the address stop bit to the sync byte
+const W1_MAX: Duration = Duration::from_millis(300);
+/// W2 / W3: maximum time between the sync and key bytes
+const W2_MAX: Duration = Duration::from_millis(20);
+/// TiniL: low time of the fast init wake up pattern
+const T_INIL: Duration = Duration::from_millis(25);
+/// TWuP: length of the whole fast init wake up pattern
+const T_WUP: Duration = Duration::from_millis(50);
+/// P2: maximum time from a request to the ECU's response
+const P2_MAX: Duration = Duration::from_millis(50);
+/// P1: maximum time between bytes of an ECU response
+const P1_MAX: Duration = Duration::from_millis(20);
+
+const START_COMMUNICATION: u8 = 0x81;
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+/// Key bytes sent by the ECU during initialisation
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyBytes {
+    /// First key byte
+    pub kb1: u8,
+    /// Second key byte
+    pub kb2: u8,
+}
+
+impl KeyBytes {
+    /// Returns true if the key bytes select ISO 9141-2 (08 08 or 94 94)
+    pub fn is_iso9141_2(&self) -> bool {
+        matches!((self.kb1, self.kb2), (0x08, 0x08) | (0x94, 0x94))
+    }
+
+    /// Returns true if the key bytes select ISO 14230 (KWP2000)
+    pub fn is_kwp2000(&self) -> bool {
+        self.kb2 == 0x8F
+    }
+}
+
+/// 5 baud initialisation sequence
+#[derive(Debug, Copy, Clone)]
+pub struct FiveBaudInit {
+    address: u8,
+    baud: u32,
+    echo: bool,
+    idle: Duration,
+    w4: Duration,
+    slack: Duration,
+}
+
+impl FiveBaudInit {
+    /// Creates an initialisation for the ECU at `address`, such as 0x33 for OBD-II.
+    /// The address is sent as is, so it must already include any parity bit
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            baud: 10400,
+            echo: true,
+            idle: Duration::from_millis(300),
+            w4: Duration::from_millis(30),
+            slack: Duration::from_millis(20),
+        }
+    }
+
+    /// Sets the communication baud rate, used once the address has been sent
+    pub fn baud(mut self, baud: u32) -> Self {
+        self.baud = baud;
+        self
+    }
+
+    /// Sets whether bytes sent by the tester are echoed back by the interface
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    /// Sets how long the line is held idle before the address is sent (W0/W5)
+    pub fn idle(mut self, idle: Duration) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// Sets the delay between the last key byte and the tester's acknowledgement
+    /// (W4, 25 to 50ms)
+    pub fn w4(mut self, w4: Duration) -> Self {
+        self.w4 = w4;
+        self
+    }
+
+    /// Sets the time allowed on top of the ECU's timing limits, to cover the
+    /// latency of the host and USB adapters
+    pub fn slack(mut self, slack: Duration) -> Self {
+        self.slack = slack;
+        self
+    }
+
+    /// Runs the initialisation. The port is set to 8N1 at the communication baud
+    /// rate, and stays that way afterwards
+    pub fn run<P: SerialPort + ?Sized>(&self, port: &mut P) -> SerialResult<KeyBytes> {
+        let settings = port
+            .settings()
+            .baud(self.baud)
+            .byte_size(ByteSize::Eight)
+            .parity(Parity::None)
+            .stop_bits(StopBits::One)
+            .set_flow_control(FlowControl::None);
+        *port.setting() = settings;
+        port.reconfigure_port()?;
+
+        port.set_break_state(false)?;
+        std::thread::sleep(self.idle);
+        self.send_address(port)?;
+        // Bits sent as breaks read back as noise
+        port.clear_input_buffer()?;
+
+        let sync = read_byte(port, W1_MAX + self.slack)?;
+        if sync != SYNC {
+            return Err(SerialError::LibraryError(format!("Expected sync byte 0x55, got {sync:#04x}")));
+        }
+        let kb1 = read_byte(port, W2_MAX + self.slack)?;
+        let kb2 = read_byte(port, W2_MAX + self.slack)?;
+
+        std::thread::sleep(self.w4);
+        port.write_all(&[!kb2]).and_then(|_| port.flush()).map_err(SerialError::IoError)?;
+        if self.echo {
+            read_byte(port, self.slack)?;
+        }
+        let confirm = read_byte(port, Duration::from_millis(50) + self.slack)?;
+        if confirm != !self.address {
+            return Err(SerialError::LibraryError(format!("ECU confirmed with {confirm:#04x}, expected {:#04x}", !self.address)));
+        }
+        Ok(KeyBytes { kb1, kb2 })
+    }
+
+    /// Sends the start bit, 8 address bits LSB first and the stop bit. Each bit
+    /// edge is timed from the start of the sequence, so errors do not accumulate
+    fn send_address<P: SerialPort + ?Sized>(&self, port: &mut P) -> SerialResult<()> {
+        let start = Instant::now();
+        let bits = std::iter::once(false).chain((0..8).map(|i| (self.address >> i) & 1 == 1)).chain(std::iter::once(true));
+        for (i, bit) in bits.enumerate() {
+            std::thread::sleep((start + BIT_TIME * i as u32).saturating_duration_since(Instant::now()));
+            port.set_break_state(!bit)?;
+        }
+        std::thread::sleep((start + BIT_TIME * 10).saturating_duration_since(Instant::now()));
+        Ok(())
+    }
+}
+
+/// ISO 14230 target addressing mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Addressing {
+    /// The target is a single ECU
+    Physical,
+    /// The target is a functional group, such as 0x33 for OBD-II emission ECUs
+    Functional,
+}
+
+/// ISO 14230 fast initialisation
+#[derive(Debug, Copy, Clone)]
+pub struct FastInit {
+    target: u8,
+    source: u8,
+    addressing: Addressing,
+    baud: u32,
+    echo: bool,
+    idle: Duration,
+    slack: Duration,
+}
+
+impl FastInit {
+    /// Creates a fast init addressing the ECU at `target` physically, from the
+    /// tester address 0xF1
+    pub fn new(target: u8) -> Self {
+        Self {
+            target,
+            source: 0xF1,
+            addressing: Addressing::Physical,
+            baud: 10400,
+            echo: true,
+            idle: Duration::from_millis(300),
+            slack: Duration::from_millis(20),
+        }
+    }
+
+    /// Sets the tester's address
+    pub fn source(mut self, source: u8) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Sets the target addressing mode
+    pub fn addressing(mut self, addressing: Addressing) -> Self {
+        self.addressing = addressing;
+        self
+    }
+
+    /// Sets the communication baud rate
+    pub fn baud(mut self, baud: u32) -> Self {
+        self.baud = baud;
+        self
+    }
+
+    /// Sets whether bytes sent by the tester are echoed back by the interface
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    /// Sets how long the line is held idle before the wake up pattern (W5)
+    pub fn idle(mut self, idle: Duration) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// Sets the time allowed on top of the ECU's timing limits, to cover the
+    /// latency of the host and USB adapters
+    pub fn slack(mut self, slack: Duration) -> Self {
+        self.slack = slack;
+        self
+    }
+
+    /// Sends the wake up pattern and StartCommunication request, and returns the
+    /// key bytes from the positive response. The port is set to 8N1 at the
+    /// communication baud rate, and stays that way afterwards
+    pub fn run<P: SerialPort + ?Sized>(&self, port: &mut P) -> SerialResult<KeyBytes> {
+        let settings = port
+            .settings()
+            .baud(self.baud)
+            .byte_size(ByteSize::Eight)
+            .parity(Parity::None)
+            .stop_bits(StopBits::One)
+            .set_flow_control(FlowControl::None);
+        *port.setting() = settings;
+        port.reconfigure_port()?;
+
+        port.set_break_state(false)?;
+        std::thread::sleep(self.idle);
+        let start = Instant::now();
+        port.set_break_state(true)?;
+        sleep_until(start + T_INIL);
+        port.set_break_state(false)?;
+        port.clear_input_buffer()?;
+        sleep_until(start + T_WUP);
+
+        let format = match self.addressing {
+            Addressing::Physical => 0x81,
+            Addressing::Functional => 0xC1,
+        };
+        let mut request = vec![format, self.target, self.source, START_COMMUNICATION];
+        request.push(kwp_checksum(&request));
+        port.write_all(&request).and_then(|_| port.flush()).map_err(SerialError::IoError)?;
+        if self.echo {
+            for _ in 0..request.len() {
+                read_byte(port, P1_MAX + self.slack)?;
+            }
+        }
+
+        let response = self.read_message(port)?;
+        match response.as_slice() {
+            [sid, kb1, kb2, ..] if *sid == START_COMMUNICATION | 0x40 => Ok(KeyBytes { kb1: *kb1, kb2: *kb2 }),
+            [NEGATIVE_RESPONSE, _, code, ..] => {
+                Err(SerialError::LibraryError(format!("StartCommunication rejected with code {code:#04x}")))
+            }
+            _ => Err(SerialError::LibraryError(format!("Unexpected StartCommunication response {response:02X?}"))),
+        }
+    }
+
+    /// Reads a KWP2000 message, and returns its service ID and data
+    fn read_message<P: SerialPort + ?Sized>(&self, port: &mut P) -> SerialResult<Vec<u8>> {
+        let mut msg = vec![read_byte(port, P2_MAX + self.slack)?];
+        let mut next = || read_byte(port, P1_MAX + self.slack);
+        // Target and source addresses are present unless the format byte says
+        // otherwise
+        if msg[0] & 0xC0 != 0 {
+            msg.push(next()?);
+            msg.push(next()?);
+        }
+        let len = match msg[0] & 0x3F {
+            0 => {
+                let len = next()?;
+                msg.push(len);
+                len
+            }
+            len => len,
+        };
+        for _ in 0..=len {
+            msg.push(next()?);
+        }
+        let sum = msg.pop().unwrap_or_default();
+        if kwp_checksum(&msg) != sum {
+            return Err(SerialError::IoError(std::io::Error::new(ErrorKind::InvalidData, "KWP2000 checksum mismatch")));
+        }
+        Ok(msg.split_off(msg.len() - len as usize))
+    }
+}
+
+/// KWP2000 checksum: the sum of all bytes, modulo 256
+fn kwp_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn read_byte<P: SerialPort + ?Sized>(port: &mut P, timeout: Duration) -> SerialResult<u8> {
+    let deadline = Instant::now() + timeout;
+    let mut b = [0u8; 1];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+            return Err(SerialError::IoError(std::io::Error::new(ErrorKind::TimedOut, "No response from ECU")));
+        }
+        match port.read(&mut b) {
+            Ok(1) => return Ok(b[0]),
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+    }
+}
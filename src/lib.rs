@@ -8,7 +8,6 @@
     dead_code,
     while_true
 )]
-use std::io::ErrorKind;
 
 #[allow(unused)]
 const XON: i8 = 17;
@@ -25,6 +24,71 @@ pub mod posix;
 #[cfg(windows)]
 pub mod windows;
 
+pub mod at;
+pub mod autobaud;
+pub mod bert;
+pub mod buffered;
+pub mod cancel;
+pub mod carrier;
+pub mod codec;
+pub mod dmx;
+pub mod elm327;
+pub mod expect;
+pub mod framing;
+pub mod gcode;
+pub mod halfduplex;
+pub mod hayes;
+pub mod identify;
+pub mod idle;
+pub mod iec62056;
+pub mod kline;
+pub mod lin;
+pub mod midi;
+pub mod modbus;
+pub mod newline;
+pub mod pacing;
+pub mod prelude;
+pub mod priority;
+pub mod reconnect;
+pub mod shared;
+pub mod slcan;
+pub mod spin;
+pub mod split;
+pub mod stm32;
+pub mod transactor;
+pub mod transfer;
+pub mod ubx;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "ftdi")]
+pub mod ftdi;
+
+#[cfg(feature = "clap")]
+pub mod cli;
+
+#[cfg(feature = "embedded-io")]
+pub mod embedded;
+
+#[cfg(feature = "miniterm")]
+pub mod miniterm;
+
+#[cfg(feature = "profiles")]
+pub mod profiles;
+
+#[cfg(feature = "python")]
+pub mod python;
+
+#[cfg(feature = "serialport")]
+pub mod serialport_compat;
+
+#[cfg(feature = "encoding")]
+pub mod text;
+
+#[cfg(feature = "tokio-codec")]
+pub mod tokio_codec;
+
 /// Serial port result type
 pub type SerialResult<T> = std::result::Result<T, SerialError>;
 
@@ -40,7 +104,51 @@ pub enum SerialError {
         desc: String,
     },
     /// Internal library error
-    LibraryError(String)
+    LibraryError(String),
+    /// The operation was cancelled, for example by [SerialIo::cancel_io]
+    Cancelled,
+    /// The port, its driver or the platform does not support the operation
+    Unsupported(String),
+    /// The device has gone away, for example because it was unplugged or the system
+    /// slept, and the port must be reopened. See [reconnect::ReconnectingPort].
+    ///
+    /// Currently only reported by reads and writes on Windows
+    Disconnected(String),
+    /// The driver reported line errors, see [SerialPortSettings::line_error_policy]
+    LineError(LineErrors),
+}
+
+impl SerialError {
+    /// Returns true if an IO error returned by a port was caused by the
+    /// operation being cancelled
+    pub fn is_cancelled(e: &std::io::Error) -> bool {
+        e.get_ref()
+            .and_then(|e| e.downcast_ref::<SerialError>())
+            .map(|e| matches!(e, SerialError::Cancelled))
+            .unwrap_or(false)
+    }
+
+    /// Returns true if an IO error returned by a port was caused by the device
+    /// going away. See [SerialError::Disconnected]
+    pub fn is_disconnected(e: &std::io::Error) -> bool {
+        e.get_ref()
+            .and_then(|e| e.downcast_ref::<SerialError>())
+            .map(|e| matches!(e, SerialError::Disconnected(_)))
+            .unwrap_or(false)
+    }
+
+    /// Returns the line errors which caused an IO error returned by a port, if it was
+    /// caused by [SerialError::LineError]
+    pub fn line_errors(e: &std::io::Error) -> Option<LineErrors> {
+        match e.get_ref().and_then(|e| e.downcast_ref::<SerialError>()) {
+            Some(SerialError::LineError(errors)) => Some(*errors),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn cancelled_io() -> std::io::Error {
+        std::io::Error::other(SerialError::Cancelled)
+    }
 }
 
 impl std::fmt::Debug for SerialError {
@@ -53,6 +161,10 @@ impl std::fmt::Debug for SerialError {
                 .field("desc", desc)
                 .finish(),
             SerialError::LibraryError(e) => f.debug_tuple("LibraryError").field(e).finish(),
+            SerialError::Cancelled => write!(f, "Cancelled"),
+            SerialError::Unsupported(e) => f.debug_tuple("Unsupported").field(e).finish(),
+            SerialError::Disconnected(e) => f.debug_tuple("Disconnected").field(e).finish(),
+            SerialError::LineError(e) => f.debug_tuple("LineError").field(e).finish(),
         }
     }
 }
@@ -65,6 +177,10 @@ impl std::fmt::Display for SerialError {
             }
             SerialError::OsError { code, desc } => write!(f, "OsError {code} ({desc})"),
             SerialError::LibraryError(e) => write!(f, "Serial-RS Lib error '{e}'"),
+            SerialError::Cancelled => write!(f, "Operation cancelled"),
+            SerialError::Unsupported(e) => write!(f, "Unsupported: {e}"),
+            SerialError::Disconnected(e) => write!(f, "Device disconnected: {e}"),
+            SerialError::LineError(e) => write!(f, "Line error: {e}"),
         }
     }
 }
@@ -81,16 +197,59 @@ impl std::error::Error for SerialError {
 
 /// Serial port settings
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct SerialPortSettings {
     baud_rate: u32,
     byte_size: ByteSize,
     parity: Parity,
     stop_bits: StopBits,
+    #[cfg_attr(feature = "serde", serde(with = "serde_ms"))]
     read_timeout: Option<u128>,
     flow_control: FlowControl,
+    #[cfg_attr(feature = "serde", serde(with = "serde_ms"))]
     write_timeout: Option<u128>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_ms"))]
     inter_byte_timeout: Option<u128>,
-    blocking: bool
+    blocking: bool,
+    low_latency: bool,
+    write_chunk_size: Option<usize>,
+    invert_rts: bool,
+    invert_dtr: bool,
+    initial_dtr: Option<bool>,
+    initial_rts: Option<bool>,
+    xon_char: u8,
+    xoff_char: u8,
+    xon_xoff_limits: Option<(u16, u16)>,
+    tx_continue_on_xoff: bool,
+    fill_reads: bool,
+    retry_interrupted: bool,
+    termios_profile: TermiosProfile,
+    input_processing: InputProcessing,
+    vmin: Option<u8>,
+    vtime: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_ms"))]
+    open_carrier_timeout: Option<u128>,
+    discard_nul: bool,
+    line_error_policy: LineErrorPolicy,
+    parity_error_policy: ParityErrorPolicy,
+    comm_events: CommEventMask,
+}
+
+/// Stores millisecond timeouts as u64, as formats such as TOML have no 128 bit integers
+#[cfg(feature = "serde")]
+mod serde_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(v: &Option<u128>, s: S) -> Result<S::Ok, S::Error> {
+        match v {
+            Some(ms) => s.serialize_some(&u64::try_from(*ms).unwrap_or(u64::MAX)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u128>, D::Error> {
+        Ok(Option::<u64>::deserialize(d)?.map(u128::from))
+    }
 }
 
 impl Default for SerialPortSettings {
@@ -104,7 +263,28 @@ impl Default for SerialPortSettings {
             write_timeout: None,
             flow_control: FlowControl::None,
             inter_byte_timeout: None,
-            blocking: true
+            blocking: true,
+            low_latency: false,
+            write_chunk_size: None,
+            invert_rts: false,
+            invert_dtr: false,
+            initial_dtr: None,
+            initial_rts: None,
+            xon_char: XON as u8,
+            xoff_char: XOFF as u8,
+            xon_xoff_limits: None,
+            tx_continue_on_xoff: false,
+            fill_reads: false,
+            retry_interrupted: true,
+            termios_profile: TermiosProfile::Raw,
+            input_processing: InputProcessing::empty(),
+            vmin: None,
+            vtime: None,
+            open_carrier_timeout: None,
+            discard_nul: false,
+            line_error_policy: LineErrorPolicy::Ignore,
+            parity_error_policy: ParityErrorPolicy::Ignore,
+            comm_events: CommEventMask::ERR,
         }
     }
 }
@@ -127,6 +307,10 @@ impl SerialPortSettings {
         self
     }
 
+    /// Sets the write timeout in milliseconds.
+    ///
+    /// On Windows, writes without a timeout return as soon as the data is queued with
+    /// the driver. A failure to send it is returned by the next write or flush
     pub fn write_timeout(mut self, timeout: Option<u128>) -> Self {
         self.write_timeout = timeout;
         self
@@ -151,14 +335,472 @@ impl SerialPortSettings {
         self.blocking = blocking;
         self
     }
+
+    /// Requests low latency mode from the driver.
+    ///
+    /// On Linux this sets `ASYNC_LOW_LATENCY` via TIOCSSERIAL, which stops the
+    /// kernel from coalescing received bytes before waking up readers. Support is
+    /// per-driver: 8250/16550 UARTs and ftdi_sio honour it (ftdi_sio by dropping
+    /// its latency timer to 1ms), whilst drivers without TIOCSSERIAL support
+    /// (such as cdc_acm on older kernels) will cause [Configurable::reconfigure_port] to fail.
+    ///
+    /// This has no effect on other platforms
+    pub fn low_latency(mut self, enable: bool) -> Self {
+        self.low_latency = enable;
+        self
+    }
+
+    /// Splits writes larger than `size` bytes into multiple driver writes, waiting
+    /// for each one to complete before sending the next. Some USB adapter drivers
+    /// fail or silently truncate writes larger than their transfer size.
+    ///
+    /// This only has an effect on Windows
+    pub fn write_chunk_size(mut self, size: Option<usize>) -> Self {
+        self.write_chunk_size = size;
+        self
+    }
+
+    /// Inverts the RTS line, for adapters and optocoupler boards which invert it,
+    /// so [ControlLines::set_request_to_send] with `true` always asserts the line at
+    /// the connector. Applies to [ControlLines::set_request_to_send] and
+    /// [ControlLines::get_request_to_send], not to hardware flow control
+    pub fn invert_rts(mut self, invert: bool) -> Self {
+        self.invert_rts = invert;
+        self
+    }
+
+    /// Inverts the DTR line. See [SerialPortSettings::invert_rts]
+    pub fn invert_dtr(mut self, invert: bool) -> Self {
+        self.invert_dtr = invert;
+        self
+    }
+
+    /// Sets the state of DTR when the port is opened, for devices which reset when
+    /// DTR is raised. None keeps the default, which is asserted on POSIX and
+    /// deasserted on Windows. Ignored with [FlowControl::DsrDtr].
+    ///
+    /// On Windows the state is part of the DCB applied when the port is opened, so
+    /// the line does not change until the driver opens it. Later calls to
+    /// [Configurable::reconfigure_port] keep the last state set. POSIX drivers raise
+    /// DTR as the port is opened, so it is only set afterwards
+    pub fn initial_dtr(mut self, state: Option<bool>) -> Self {
+        self.initial_dtr = state;
+        self
+    }
+
+    /// Sets the state of RTS when the port is opened. Ignored with
+    /// [FlowControl::RtsCts]. See [SerialPortSettings::initial_dtr]
+    pub fn initial_rts(mut self, state: Option<bool>) -> Self {
+        self.initial_rts = state;
+        self
+    }
+
+    /// Sets the characters used by XON/XOFF flow control. Defaults to the standard
+    /// DC1 (17) and DC3 (19)
+    pub fn xon_xoff_chars(mut self, xon: u8, xoff: u8) -> Self {
+        self.xon_char = xon;
+        self.xoff_char = xoff;
+        self
+    }
+
+    /// Sets the receive buffer thresholds for XON/XOFF flow control as
+    /// `(xon_lim, xoff_lim)`. XOFF is sent once fewer than `xoff_lim` bytes of the
+    /// driver's input buffer are free, and XON once `xon_lim` or fewer bytes are
+    /// queued again. None keeps the driver's defaults.
+    ///
+    /// This only has an effect on Windows, where it sets the DCB's XonLim and XoffLim.
+    /// Other platforms use fixed thresholds
+    pub fn xon_xoff_limits(mut self, limits: Option<(u16, u16)>) -> Self {
+        self.xon_xoff_limits = limits;
+        self
+    }
+
+    /// Keeps transmitting after XOFF has been sent because the input buffer is
+    /// filling up. When disabled, transmission stops until XON is sent, which
+    /// deadlocks with devices that wait for a reply before reading more.
+    ///
+    /// This only has an effect on Windows, where it sets the DCB's fTXContinueOnXoff.
+    /// Other platforms always keep transmitting
+    pub fn tx_continue_on_xoff(mut self, enable: bool) -> Self {
+        self.tx_continue_on_xoff = enable;
+        self
+    }
+
+    /// Makes blocking reads keep reading until the buffer is full, the read timeout
+    /// expires, or the gap between two bytes exceeds the inter-byte timeout. A read
+    /// which receives nothing fails with [std::io::ErrorKind::TimedOut]. Without a
+    /// read timeout, reads wait until the buffer is full.
+    ///
+    /// This only has an effect on Windows. Elsewhere reads return once some data has
+    /// arrived, so use [std::io::Read::read_exact] to wait for a full buffer
+    pub fn fill_reads(mut self, enable: bool) -> Self {
+        self.fill_reads = enable;
+        self
+    }
+
+    /// Retries reads, writes and waits which are interrupted by a signal (EINTR),
+    /// carrying on with the time left of any timeout. Enabled by default. When
+    /// disabled, interrupted calls fail with [std::io::ErrorKind::Interrupted].
+    ///
+    /// This only has an effect on POSIX
+    pub fn retry_interrupted(mut self, enable: bool) -> Self {
+        self.retry_interrupted = enable;
+        self
+    }
+
+    /// Sets VMIN, the number of bytes a blocking read waits for, overriding the value
+    /// derived from the inter-byte timeout. This only has an effect on POSIX
+    pub fn vmin(mut self, vmin: Option<u8>) -> Self {
+        self.vmin = vmin;
+        self
+    }
+
+    /// Sets VTIME, the read timer in tenths of a second, overriding the value derived
+    /// from the inter-byte timeout. This only has an effect on POSIX
+    pub fn vtime(mut self, vtime: Option<u8>) -> Self {
+        self.vtime = vtime;
+        self
+    }
+
+    /// Waits up to `timeout` (in milliseconds) for carrier detect when the port is
+    /// opened, once DTR has been raised, and fails with [std::io::ErrorKind::TimedOut]
+    /// if it is not asserted. None, the default, opens without waiting for carrier
+    pub fn open_carrier_timeout(mut self, timeout: Option<u128>) -> Self {
+        self.open_carrier_timeout = timeout;
+        self
+    }
+
+    /// Sets how the termios state is built before the settings are applied. Defaults
+    /// to [TermiosProfile::Raw]. This only has an effect on POSIX
+    pub fn termios_profile(mut self, profile: TermiosProfile) -> Self {
+        self.termios_profile = profile;
+        self
+    }
+
+    /// Enables input processing which the termios profile turns off, such as IGNBRK.
+    /// This only has an effect on POSIX
+    pub fn input_processing(mut self, processing: InputProcessing) -> Self {
+        self.input_processing = processing;
+        self
+    }
+
+    /// Discards received NUL (0x00) bytes, which some level shifters produce on
+    /// line transitions.
+    ///
+    /// On Windows this sets the DCB's fNull. On POSIX, break conditions are ignored
+    /// (IGNBRK) so they are not read as NUL, and NUL bytes are filtered out of reads
+    pub fn discard_nul(mut self, enable: bool) -> Self {
+        self.discard_nul = enable;
+        self
+    }
+
+    /// Sets what reads and writes do after a line error such as an overrun.
+    ///
+    /// This only has an effect on Windows, where any policy other than
+    /// [LineErrorPolicy::Ignore] sets the DCB's fAbortOnError. Elsewhere errors are
+    /// always ignored, but can be read with [BufferControl::line_error_counts]
+    pub fn line_error_policy(mut self, policy: LineErrorPolicy) -> Self {
+        self.line_error_policy = policy;
+        self
+    }
+
+    /// Sets what happens to bytes received with a parity error. On POSIX this also
+    /// applies to bytes received with a framing error, and to breaks unless
+    /// [SerialPortSettings::discard_nul] is set.
+    ///
+    /// On Windows this sets the DCB's fErrorChar and ErrorChar. On POSIX, INPCK and
+    /// PARMRK are set and the marked bytes are replaced as data is read
+    pub fn parity_error_policy(mut self, policy: ParityErrorPolicy) -> Self {
+        self.parity_error_policy = policy;
+        self
+    }
+
+    /// Sets the comm events `SerialPortExt::wait_comm_event` waits for on Windows.
+    /// Defaults to
+    /// [CommEventMask::ERR]. Has no effect on other platforms
+    pub fn comm_events(mut self, mask: CommEventMask) -> Self {
+        self.comm_events = mask;
+        self
+    }
+}
+
+impl SerialPortSettings {
+    /// Returns the time taken to transmit a single character on the line with these
+    /// settings, including the start, parity and stop bits
+    pub fn char_duration(&self) -> std::time::Duration {
+        let data = match self.byte_size {
+            ByteSize::Five => 5.0,
+            ByteSize::Six => 6.0,
+            ByteSize::Seven => 7.0,
+            ByteSize::Eight => 8.0,
+        };
+        let parity = match self.parity {
+            Parity::None => 0.0,
+            _ => 1.0,
+        };
+        let stop = match self.stop_bits {
+            StopBits::One => 1.0,
+            StopBits::OnePointFive => 1.5,
+            StopBits::Two => 2.0,
+        };
+        std::time::Duration::from_secs_f64((1.0 + data + parity + stop) / self.baud_rate.max(1) as f64)
+    }
+
+    /// Compares these (requested) settings against the settings the driver
+    /// actually applied, returning every setting which differs
+    pub fn diff(&self, applied: &SerialPortSettings) -> Vec<SettingMismatch> {
+        let mut res = Vec::new();
+        if self.baud_rate != applied.baud_rate {
+            res.push(SettingMismatch::BaudRate { requested: self.baud_rate, applied: applied.baud_rate });
+        }
+        if self.byte_size != applied.byte_size {
+            res.push(SettingMismatch::ByteSize { requested: self.byte_size, applied: applied.byte_size });
+        }
+        if self.parity != applied.parity {
+            res.push(SettingMismatch::Parity { requested: self.parity, applied: applied.parity });
+        }
+        if self.stop_bits != applied.stop_bits {
+            res.push(SettingMismatch::StopBits { requested: self.stop_bits, applied: applied.stop_bits });
+        }
+        if self.flow_control != applied.flow_control {
+            res.push(SettingMismatch::FlowControl { requested: self.flow_control, applied: applied.flow_control });
+        }
+        res
+    }
+}
+
+/// A setting which the OS driver did not apply as it was requested
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[allow(missing_docs)]
+pub enum SettingMismatch {
+    /// Baud rate was rounded or rejected by the driver
+    BaudRate { requested: u32, applied: u32 },
+    /// Byte size differs
+    ByteSize { requested: ByteSize, applied: ByteSize },
+    /// Parity differs
+    Parity { requested: Parity, applied: Parity },
+    /// Stop bits differ
+    StopBits { requested: StopBits, applied: StopBits },
+    /// Flow control differs
+    FlowControl { requested: FlowControl, applied: FlowControl },
+}
+
+/// Sizes of the driver's queues and the pending line errors, read at once. See
+/// [BufferControl::queue_status]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct QueueStatus {
+    /// Bytes received and not read yet, as [BufferControl::bytes_to_read]
+    pub input: usize,
+    /// Bytes written and not transmitted yet, as [BufferControl::bytes_to_write]
+    pub output: usize,
+    /// Line errors which have not been reported by a read or write yet. Always empty
+    /// on POSIX, where errors are reported in the received data
+    pub errors: LineErrors,
+}
+
+/// Receive error counters kept by the driver. See [BufferControl::line_error_counts]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct LineErrorCounts {
+    /// Characters received without a valid stop bit
+    pub framing: u32,
+    /// Characters received with a parity error
+    pub parity: u32,
+    /// Characters lost because the UART's receive FIFO was full
+    pub overrun: u32,
+    /// Characters lost because the driver's receive buffer was full
+    pub buffer_overrun: u32,
+    /// Break conditions received
+    pub breaks: u32,
+}
+
+impl LineErrorCounts {
+    /// Returns the errors counted since `earlier` was read
+    pub fn since(&self, earlier: &LineErrorCounts) -> LineErrorCounts {
+        LineErrorCounts {
+            framing: self.framing.wrapping_sub(earlier.framing),
+            parity: self.parity.wrapping_sub(earlier.parity),
+            overrun: self.overrun.wrapping_sub(earlier.overrun),
+            buffer_overrun: self.buffer_overrun.wrapping_sub(earlier.buffer_overrun),
+            breaks: self.breaks.wrapping_sub(earlier.breaks),
+        }
+    }
+
+    /// Total of all the counters
+    pub fn total(&self) -> u32 {
+        self.framing
+            .wrapping_add(self.parity)
+            .wrapping_add(self.overrun)
+            .wrapping_add(self.buffer_overrun)
+            .wrapping_add(self.breaks)
+    }
+}
+
+bitflags::bitflags! {
+    /// State of the modem input lines, see [ControlLines::modem_status]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+    pub struct ModemStatus: u8 {
+        /// Clear to send
+        const CTS = 0x01;
+        /// Data set ready
+        const DSR = 0x02;
+        /// Ring indicator
+        const RI = 0x04;
+        /// Carrier detect
+        const CD = 0x08;
+    }
+}
+
+bitflags::bitflags! {
+    /// Windows comm events (the `EV_` flags of SetCommMask), see
+    /// [SerialPortSettings::comm_events]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct CommEventMask: u16 {
+        /// A character was received
+        const RXCHAR = 0x0001;
+        /// The event character was received
+        const RXFLAG = 0x0002;
+        /// The last character in the output buffer was sent
+        const TXEMPTY = 0x0004;
+        /// CTS changed state
+        const CTS = 0x0008;
+        /// DSR changed state
+        const DSR = 0x0010;
+        /// Carrier detect (RLSD) changed state
+        const RLSD = 0x0020;
+        /// A break was received
+        const BREAK = 0x0040;
+        /// A framing, overrun or parity error occurred
+        const ERR = 0x0080;
+        /// A ring was detected
+        const RING = 0x0100;
+    }
+}
+
+bitflags::bitflags! {
+    /// Line errors and conditions reported by the driver, see [SerialError::LineError]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+    pub struct LineErrors: u8 {
+        /// A character was received without a valid stop bit
+        const FRAMING = 0x01;
+        /// A character was received with a parity error
+        const PARITY = 0x02;
+        /// A character was lost because the UART's receive FIFO was full
+        const OVERRUN = 0x04;
+        /// A character was lost because the driver's receive buffer was full
+        const BUFFER_OVERRUN = 0x08;
+        /// A break was received
+        const BREAK = 0x10;
+    }
+}
+
+impl std::fmt::Display for LineErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = [
+            (LineErrors::FRAMING, "framing error"),
+            (LineErrors::PARITY, "parity error"),
+            (LineErrors::OVERRUN, "overrun"),
+            (LineErrors::BUFFER_OVERRUN, "receive buffer overflow"),
+            (LineErrors::BREAK, "break"),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect();
+        match names.is_empty() {
+            true => write!(f, "unknown"),
+            false => write!(f, "{}", names.join(", ")),
+        }
+    }
+}
+
+/// What reads and writes do after a line error. See [SerialPortSettings::line_error_policy]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum LineErrorPolicy {
+    /// Carry on regardless. Errors are only visible through [BufferControl::line_error_counts]
+    Ignore,
+    /// The next read or write fails with [std::io::ErrorKind::InvalidData], caused by
+    /// [SerialError::LineError]. The port carries on working afterwards
+    Fail,
+    /// The driver stops on the error, which is then cleared and counted, and the
+    /// read or write is retried
+    Recover,
+}
+
+/// How the termios state of a POSIX port is built, see
+/// [SerialPortSettings::termios_profile]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum TermiosProfile {
+    /// Raw mode as set by cfmakeraw, so no flags left by the driver or a previous
+    /// program change the data
+    Raw,
+    /// Like [TermiosProfile::Raw], but software and hardware flow control (IXON,
+    /// IXOFF, IXANY, CRTSCTS and the XON/XOFF characters) are left as the driver has
+    /// them, rather than set from the settings
+    RawKeepFlow,
+    /// Keeps the driver's termios state, turning off only canonical mode, echo,
+    /// signal characters, output processing and newline translation
+    Custom,
+}
+
+bitflags::bitflags! {
+    /// Input processing which can be turned back on after the termios profile, see
+    /// [SerialPortSettings::input_processing]. Each flag enables the termios flag
+    /// of the same name
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct InputProcessing: u8 {
+        /// Ignore breaks
+        const IGNBRK = 0x01;
+        /// Flush the queues and send SIGINT on a break
+        const BRKINT = 0x02;
+        /// Ignore received carriage returns
+        const IGNCR = 0x04;
+        /// Translate received carriage returns to newlines
+        const ICRNL = 0x08;
+        /// Translate received newlines to carriage returns
+        const INLCR = 0x10;
+        /// Strip the eighth bit of received bytes
+        const ISTRIP = 0x20;
+    }
+}
+
+/// What happens to bytes received with a parity error. See
+/// [SerialPortSettings::parity_error_policy]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum ParityErrorPolicy {
+    /// The byte is returned as it was received
+    Ignore,
+    /// The byte is replaced with the given sentinel byte
+    Mark(u8),
+    /// The byte is dropped, and the read fails with [std::io::ErrorKind::InvalidData].
+    /// On POSIX, bytes received before it are returned first. On Windows the error
+    /// is reported by the read after the driver reports it
+    Error,
+}
+
+/// Queues of the driver an operation applies to, see [BufferControl::discard]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Direction {
+    /// Received data
+    Input,
+    /// Data waiting to be transmitted
+    Output,
+    /// Both queues
+    Both,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
 /// Flow control method
 pub enum FlowControl {
     /// No flow control
     None,
-    /// DSR DTR flow control (Software)
+    /// DSR DTR flow control (Software). Not supported on POSIX, where configuring
+    /// it fails with [SerialError::Unsupported]
     DsrDtr,
     /// XON XOFF flow control (Software)
     XonXoff,
@@ -167,6 +809,7 @@ pub enum FlowControl {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
 /// Bytesize for serial port
 pub enum ByteSize {
     /// 5 bits
@@ -180,6 +823,7 @@ pub enum ByteSize {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
 /// Parity definitions
 pub enum Parity {
     /// No parity
@@ -191,6 +835,7 @@ pub enum Parity {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
 /// Stop bits for serial port
 pub enum StopBits {
     /// 1 stop bit
@@ -233,38 +878,250 @@ impl PortInfo {
     pub fn get_desc(&self) -> &str { &self.description }
 }
 
-/// Serial port trait
-pub trait SerialPort: Send + std::io::Write + std::io::Read {
-    /// Make the serial port Settings reconfigurable
-    fn setting(&mut self) -> &mut SerialPortSettings;
-    /// Reconfigures an open port with the current settings
-    fn reconfigure_port(&mut self) -> SerialResult<()>;
-    /// Closes the port
-    fn close(self) -> SerialResult<()>;
-    /// Sets Tx and Rx buffer size. A sensible value for these is 4096 bytes
-    fn set_buffer_size(&mut self, rx_size: usize, tx_size: usize) -> SerialResult<()>;
+/// Snapshot of the configuration the OS driver is actually holding for an open port.
+///
+/// On POSIX this is the live termios structure, on Windows it is the DCB and
+/// COMMTIMEOUTS structures as returned by the driver.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DriverConfigDump {
+    entries: Vec<(String, String)>,
+}
+
+impl DriverConfigDump {
+    pub(crate) fn push<V: std::fmt::Debug>(&mut self, key: &str, value: V) {
+        self.entries.push((key.to_string(), format!("{:?}", value)));
+    }
+
+    /// Gets all entries, in the order the driver structures were read
+    pub fn entries(&self) -> &[(String, String)] { &self.entries }
+    /// Gets the value of a single entry
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+impl std::fmt::Display for DriverConfigDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (k, v) in &self.entries {
+            writeln!(f, "{k} = {v}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reading and writing through a shared reference, so one thread can read whilst
+/// another writes, with waits and cancellation
+pub trait SerialIo: Send + Sync + std::io::Write + std::io::Read {
+    /// Gets the path of the port
+    fn path(&self) -> &str;
+    /// Gets an owned copy of the path of the port. Prefer [SerialIo::path]
+    fn get_path(&self) -> String {
+        self.path().to_string()
+    }
+    /// Reads from the port through a shared reference.
+    ///
+    /// This behaves exactly like [std::io::Read::read], but allows one thread to
+    /// read whilst another writes to the same port without [SerialPort::try_clone]
+    fn read_shared(&self, buf: &mut [u8]) -> std::io::Result<usize>;
+    /// Copies the next received bytes into `buf` without consuming them, so the next
+    /// read returns them again. Returns the number of bytes copied.
+    ///
+    /// If nothing has been peeked yet, this waits like a read. Otherwise only data
+    /// already queued in the driver is added, so it does not wait. Peeked bytes are
+    /// counted by [BufferControl::bytes_to_read] and dropped by
+    /// [BufferControl::clear_input_buffer]
+    fn peek(&self, buf: &mut [u8]) -> std::io::Result<usize>;
+    /// Writes to the port through a shared reference. See [SerialIo::read_shared]
+    fn write_shared(&self, buf: &[u8]) -> std::io::Result<usize>;
+    /// Writes a few bytes which must reach the device quickly, such as flow control
+    /// characters, XMODEM CAN or an emergency stop, even whilst a large transfer is
+    /// queued. This skips the write queues and pacing of wrappers such as
+    /// [shared::SharedPort] and [pacing::PacedWriter].
+    ///
+    /// On Windows each byte is sent with TransmitCommChar, ahead of the driver's
+    /// output queue and any queued write. On POSIX there is no way to overtake the
+    /// kernel's queue, so the bytes go out after data already written to the driver;
+    /// use [BufferControl::discard] first to drop that data
+    fn write_urgent(&self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_shared(buf)
+    }
+    /// Flushes the port through a shared reference. See [SerialIo::read_shared].
+    ///
+    /// This currently waits for queued output to be transmitted, like
+    /// [BufferControl::drain], but [std::io::Write::flush] only promises that data
+    /// has been handed on, so call [BufferControl::drain] where transmission matters
+    fn flush_shared(&self) -> std::io::Result<()>;
+    /// Waits until data is available to read, without consuming it. Returns false
+    /// if `timeout` expired first. If `timeout` is None, waits forever
+    fn poll_readable(&self, timeout: Option<std::time::Duration>) -> std::io::Result<bool>;
+    /// Cancels any read or write currently blocked on this port (or its clones) from
+    /// another thread, without closing the port. Cancelled operations return an error
+    /// for which [SerialError::is_cancelled] is true
+    fn cancel_io(&self) -> SerialResult<()>;
+    /// Reads from the port like [SerialIo::read_shared], but fails with a cancelled
+    /// error as soon as `token` is triggered
+    fn read_with_cancel(&self, buf: &mut [u8], token: &cancel::CancelToken) -> std::io::Result<usize>;
+    /// Writes to the port like [SerialIo::write_shared], but fails with a cancelled
+    /// error as soon as `token` is triggered
+    fn write_with_cancel(&self, buf: &[u8], token: &cancel::CancelToken) -> std::io::Result<usize>;
+}
+
+/// Modem control lines, break and flow control signalling
+pub trait ControlLines {
     /// Sets flow control state manually
     fn set_output_flow_control(&self, enable: bool) -> SerialResult<()>;
+    /// Sends the XON character ahead of any queued output, telling the peer it may
+    /// resume sending. Unlike [ControlLines::set_output_flow_control], this signals
+    /// the peer rather than pausing or resuming our own output
+    fn send_xon(&self) -> SerialResult<()>;
+    /// Sends the XOFF character ahead of any queued output, asking the peer to stop
+    /// sending. See [ControlLines::send_xon]
+    fn send_xoff(&self) -> SerialResult<()>;
     /// Sets data terminal flag
-    fn set_data_terminal_ready(&mut self, enable: bool) -> SerialResult<()>;
+    fn set_data_terminal_ready(&self, enable: bool) -> SerialResult<()>;
     /// Sets request to send flag
-    fn set_request_to_send(&mut self, enable: bool) -> SerialResult<()>;
+    fn set_request_to_send(&self, enable: bool) -> SerialResult<()>;
     /// Sets break state flag
-    fn set_break_state(&mut self, enable: bool) -> SerialResult<()>;
+    fn set_break_state(&self, enable: bool) -> SerialResult<()>;
+    /// Reads back the current state of the data terminal ready output
+    fn get_data_terminal_ready(&self) -> SerialResult<bool>;
+    /// Reads back the current state of the request to send output
+    fn get_request_to_send(&self) -> SerialResult<bool>;
+    /// Enables or disables the UART's internal loopback, which connects its
+    /// transmitter to its receiver so self-tests can run without external wiring.
+    ///
+    /// Fails with [SerialError::Unsupported] if the driver refuses. Some USB
+    /// adapters accept the request but ignore it, so verify with a test pattern
+    fn set_loopback(&self, enable: bool) -> SerialResult<()>;
+    /// Reads all of the modem input lines at once, so they are consistent with
+    /// each other
+    fn modem_status(&self) -> SerialResult<ModemStatus>;
     /// Reads clear to send flag
-    fn read_clear_to_send(&self) -> SerialResult<bool>;
+    fn read_clear_to_send(&self) -> SerialResult<bool> {
+        Ok(self.modem_status()?.contains(ModemStatus::CTS))
+    }
     /// Reads data set ready flag
-    fn read_data_set_ready(&self) -> SerialResult<bool>;
+    fn read_data_set_ready(&self) -> SerialResult<bool> {
+        Ok(self.modem_status()?.contains(ModemStatus::DSR))
+    }
     /// Reads ring indicator flag
-    fn read_ring_indicator(&self) -> SerialResult<bool>;
+    fn read_ring_indicator(&self) -> SerialResult<bool> {
+        Ok(self.modem_status()?.contains(ModemStatus::RI))
+    }
     /// Reads carrier detect flag
-    fn read_carrier_detect(&self) -> SerialResult<bool>;
+    fn read_carrier_detect(&self) -> SerialResult<bool> {
+        Ok(self.modem_status()?.contains(ModemStatus::CD))
+    }
+    /// Waits until carrier detect is asserted, for example once a modem has
+    /// connected. Returns false if `timeout` expired first. If `timeout` is None,
+    /// waits forever. To be told when carrier drops, see [carrier::CarrierWatcher]
+    fn wait_for_carrier(&self, timeout: Option<std::time::Duration>) -> SerialResult<bool> {
+        let deadline = timeout.map(|t| std::time::Instant::now() + t);
+        loop {
+            if self.read_carrier_detect()? {
+                return Ok(true);
+            }
+            let remaining = deadline.map(|d| d.saturating_duration_since(std::time::Instant::now()));
+            if remaining == Some(std::time::Duration::ZERO) {
+                return Ok(false);
+            }
+            std::thread::sleep(remaining.unwrap_or(carrier::POLL_INTERVAL).min(carrier::POLL_INTERVAL));
+        }
+    }
+}
+
+/// Port settings, and reading back what the driver applied
+pub trait Configurable {
+    /// Make the serial port Settings reconfigurable
+    fn setting(&mut self) -> &mut SerialPortSettings;
+    /// Gets the settings the port was configured with
+    fn settings(&self) -> &SerialPortSettings;
+    /// Reconfigures an open port with the current settings
+    fn reconfigure_port(&mut self) -> SerialResult<()>;
+    /// Applies the current baud rate, byte size, parity and stop bits to an open port
+    /// mid-session, leaving every other setting alone.
+    ///
+    /// Queued output is drained at the old settings first, and received data is never
+    /// discarded, so protocols which negotiate a speed change can carry on reading.
+    /// Nothing is applied if the driver already uses these settings
+    fn reconfigure_port_live(&mut self) -> SerialResult<()>;
+    /// Changes the baud rate of an open port. See [Configurable::reconfigure_port_live]
+    fn set_baud_live(&mut self, baud: u32) -> SerialResult<()> {
+        *self.setting() = self.settings().baud(baud);
+        self.reconfigure_port_live()
+    }
+    /// Switches the open port between blocking and non-blocking mode.
+    ///
+    /// In non-blocking mode, reads and writes which cannot make any progress fail
+    /// with [std::io::ErrorKind::WouldBlock] on every platform
+    fn set_blocking(&mut self, blocking: bool) -> SerialResult<()>;
+    /// Dumps the configuration the OS driver actually accepted for this port.
+    /// Useful to check what [Configurable::reconfigure_port] really applied
+    fn debug_dump(&self) -> SerialResult<DriverConfigDump>;
+    /// Reads back the settings the OS driver has actually applied to the port.
+    ///
+    /// Timeouts and blocking mode are managed by the library and are returned as requested
+    fn current_settings(&self) -> SerialResult<SerialPortSettings>;
+    /// Returns every difference between the requested settings and [Configurable::current_settings].
+    /// An empty list means the driver accepted the configuration as-is
+    fn verify_settings(&self) -> SerialResult<Vec<SettingMismatch>>;
+}
+
+/// Driver queue sizes, clearing and error counters
+pub trait BufferControl {
+    /// Sets Tx and Rx buffer size. A sensible value for these is 4096 bytes
+    fn set_buffer_size(&mut self, rx_size: usize, tx_size: usize) -> SerialResult<()>;
     /// Returns number of bytes left to read in serial buffer
     fn bytes_to_read(&self) -> SerialResult<usize>;
     /// Returns number of bytes left to write in serial buffer
     fn bytes_to_write(&self) -> SerialResult<usize>;
-    /// Gets the path of the port
-    fn get_path(&self) -> String;
+    /// Reads both queue sizes and the pending line errors at once. On Windows this
+    /// takes a single ClearCommError call, where [BufferControl::bytes_to_read] and
+    /// [BufferControl::bytes_to_write] take one each, so prefer it when polling often
+    fn queue_status(&self) -> SerialResult<QueueStatus>;
+    /// Clears serial input buffer
+    fn clear_input_buffer(&self) -> SerialResult<()>;
+    /// Clears serial output buffer. Received data is kept
+    fn clear_output_buffer(&self) -> SerialResult<()>;
+    /// Discards data queued in the driver in one or both directions: received data
+    /// which has not been read, and written data which has not been transmitted
+    fn discard(&self, direction: Direction) -> SerialResult<()> {
+        match direction {
+            Direction::Input => self.clear_input_buffer(),
+            Direction::Output => self.clear_output_buffer(),
+            Direction::Both => {
+                self.clear_output_buffer()?;
+                self.clear_input_buffer()
+            }
+        }
+    }
+    /// Waits until all written data has been transmitted by the driver (tcdrain on
+    /// POSIX). Unlike [BufferControl::discard], nothing is lost, and unlike
+    /// [std::io::Write::flush] the wait is guaranteed. A USB adapter may still hold
+    /// a few bytes in its own FIFO when this returns
+    fn drain(&self) -> SerialResult<()>;
+    /// Waits until at least `n` bytes are queued in the driver, without consuming them.
+    /// Returns false if `timeout` expired first. If `timeout` is None, waits forever
+    fn wait_for_bytes(&self, n: usize, timeout: Option<std::time::Duration>) -> SerialResult<bool>;
+    /// Reads the driver's receive error counters. These only ever increase, so use
+    /// [LineErrorCounts::since] to count the errors between two readings.
+    ///
+    /// On Linux these are the kernel's per-port counters (TIOCGICOUNT), which not
+    /// every driver maintains. Windows only reports whether each kind of error has
+    /// happened since the port's status was last checked, so each counter counts
+    /// checks which saw that error. Other platforms return an error
+    fn line_error_counts(&self) -> SerialResult<LineErrorCounts>;
+}
+
+/// Serial port trait, combining every capability of a port.
+///
+/// Code which only needs part of a port can bound on the sub-trait instead, such
+/// as [SerialIo] or [ControlLines], so backends which cannot support everything
+/// need not stub the rest. The sub-traits must be in scope to call their methods
+/// on a concrete port type, see [prelude]
+pub trait SerialPort: SerialIo + ControlLines + Configurable + BufferControl {
+    /// Closes the port
+    fn close(self) -> SerialResult<()>;
     /// Tries to clone the port.
     /// 
     /// # Note about cloning
@@ -272,10 +1129,102 @@ pub trait SerialPort: Send + std::io::Write + std::io::Read {
     /// effects. For example, if one thread tries to close the port but another
     /// thread wants the port open
     fn try_clone(&mut self) -> SerialResult<Box<dyn SerialPort>>;
-    /// Clears serial input buffer
-    fn clear_input_buffer(&mut self) -> SerialResult<()>;
-    /// Clears serial output buffer
-    fn clear_output_buffer(&mut self) -> SerialResult<()>;
+    /// Splits the port into owned read and write halves which can be moved to
+    /// separate threads. The port is closed once both halves are dropped
+    fn into_split(self) -> (split::ReadHalf<Self>, split::WriteHalf<Self>) where Self: Sized {
+        split::into_split(std::sync::Arc::new(self))
+    }
+    /// Splits the port into borrowed read and write halves
+    fn split(&self) -> (split::ReadRef<'_, Self>, split::WriteRef<'_, Self>) where Self: Sized {
+        split::split(self)
+    }
+    /// Reads until `delim` has been received, returning everything read including `delim`.
+    ///
+    /// Bytes are read one at a time, so nothing after the delimiter is consumed and it
+    /// stays available for the next read. Reading stops early, returning what has been
+    /// read so far, if `timeout` expires, or if the gap between two bytes exceeds the
+    /// port's inter-byte timeout. Callers can check for the delimiter at the end of the
+    /// result to tell a complete read from a timed out one.
+    ///
+    /// Both limits are applied whilst waiting for each byte, so they hold even if the
+    /// port has no read timeout
+    fn read_until(&self, delim: u8, timeout: Option<std::time::Duration>) -> std::io::Result<Vec<u8>> {
+        let start = std::time::Instant::now();
+        let inter_byte = self.settings().inter_byte_timeout.map(|t| std::time::Duration::from_millis(t as u64));
+        let mut last_rx = start;
+        let mut res = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            let now = std::time::Instant::now();
+            let mut limit = timeout.map(|t| t.saturating_sub(now.duration_since(start)));
+            if !res.is_empty() {
+                if let Some(gap) = inter_byte.map(|t| t.saturating_sub(now.duration_since(last_rx))) {
+                    limit = Some(limit.map_or(gap, |l| l.min(gap)));
+                }
+            }
+            if limit == Some(std::time::Duration::ZERO) || !self.poll_readable(limit)? {
+                return Ok(res);
+            }
+            match self.read_shared(&mut byte) {
+                Ok(1) => {
+                    res.push(byte[0]);
+                    if byte[0] == delim {
+                        return Ok(res);
+                    }
+                    last_rx = std::time::Instant::now();
+                }
+                // Readable but nothing to read, the other end has hung up
+                Ok(_) => return Ok(res),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Reads a single `\n` terminated line. See [SerialPort::read_until]
+    fn read_line_timeout(&self, timeout: Option<std::time::Duration>) -> std::io::Result<Vec<u8>> {
+        self.read_until(LF as u8, timeout)
+    }
+    /// Reads everything currently queued in the driver, without waiting for more data.
+    /// Returns an empty buffer if nothing has been received
+    fn read_available(&self) -> SerialResult<Vec<u8>> {
+        let mut buf = vec![0u8; self.bytes_to_read()?];
+        let read = self.read_available_into(&mut buf)?;
+        buf.truncate(read);
+        Ok(buf)
+    }
+    /// Reads up to `buf.len()` bytes of the data currently queued in the driver, without
+    /// waiting for more data. Returns the number of bytes read, which may be 0
+    fn read_available_into(&self, buf: &mut [u8]) -> SerialResult<usize> {
+        let to_read = buf.len().min(self.bytes_to_read()?);
+        let mut read = 0;
+        while read < to_read {
+            match self.read_shared(&mut buf[read..to_read]) {
+                Ok(0) => break,
+                Ok(n) => {
+                    read += n;
+                    // The count can include bytes the driver drops, so check there is
+                    // more rather than waiting for it
+                    if read < to_read && !self.poll_readable(Some(std::time::Duration::ZERO)).map_err(SerialError::IoError)? {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                // The queued bytes were taken by another reader of the port
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl dyn SerialPort {
+    /// Splits a boxed port into owned read and write halves. See [SerialPort::into_split].
+    ///
+    /// Boxed ports can be split into borrowed halves with [split::split]
+    pub fn into_split_boxed(self: Box<Self>) -> (split::ReadHalf<dyn SerialPort>, split::WriteHalf<dyn SerialPort>) {
+        split::into_split(std::sync::Arc::from(self))
+    }
 }
 
 /// Scanner to list avaliable serial ports on a system
@@ -288,8 +1237,12 @@ impl From<SerialError> for std::io::Error {
     fn from(e: SerialError) -> Self {
         match e {
             SerialError::IoError(i) => i,
-            SerialError::OsError { code: _ , desc } => std::io::Error::new(ErrorKind::Other, desc),
-            SerialError::LibraryError(e) => std::io::Error::new(ErrorKind::Other, e),
+            SerialError::OsError { code: _ , desc } => std::io::Error::other(desc),
+            SerialError::LibraryError(e) => std::io::Error::other(e),
+            SerialError::Cancelled => SerialError::cancelled_io(),
+            SerialError::Unsupported(e) => std::io::Error::new(std::io::ErrorKind::Unsupported, e),
+            e @ SerialError::Disconnected(_) => std::io::Error::new(std::io::ErrorKind::NotConnected, e),
+            e @ SerialError::LineError(_) => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
         }
     }
 }
@@ -322,6 +1275,17 @@ pub fn new_from_path(path: &str, settings: Option<SerialPortSettings>) -> Serial
     }
 }
 
+/// Waits for carrier detect after a port is opened, if
+/// [SerialPortSettings::open_carrier_timeout] is set
+pub(crate) fn wait_for_open_carrier<P: SerialPort>(port: &P) -> SerialResult<()> {
+    match port.settings().open_carrier_timeout {
+        Some(ms) if !port.wait_for_carrier(Some(std::time::Duration::from_millis(ms as u64)))? => {
+            Err(SerialError::IoError(std::io::Error::new(std::io::ErrorKind::TimedOut, "No carrier detected")))
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Lists all ports on the system
 pub fn list_ports() -> SerialResult<Vec<PortInfo>> {
     #[cfg(unix)]
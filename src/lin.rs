@@ -0,0 +1,324 @@
+//! LIN bus master
+//!
+//! A LIN frame is a header sent by the master (break, sync byte 0x55 and protected
+//! identifier), followed by a response of 1 to 8 data bytes and a checksum, sent by
+//! whichever node publishes that identifier, which may be the master itself. The
+//! master runs a schedule table, sending one header per slot.
+//!
+//! LIN transceivers echo everything sent back to the receiver, as the bus is a
+//! single wire. [LinMaster] reads the echo back by default, and uses it to detect
+//! collisions. Turn this off with [LinMaster::echo] for adapters which suppress it.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::lin::{LinMaster, ScheduleSlot};
+//! use std::time::Duration;
+//! let mut master = LinMaster::new(port)?;
+//! let table = [
+//!     ScheduleSlot::publish(0x10, vec![0x01, 0x02], Duration::from_millis(10)),
+//!     ScheduleSlot::request(0x20, 4, Duration::from_millis(10)),
+//! ];
+//! master.run_schedule(&table, |id, resp| {
+//!     println!("{id:#04x}: {resp:?}");
+//!     true
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{ByteSize, FlowControl, Parity, SerialError, SerialPort, SerialResult, StopBits};
+
+const SYNC: u8 = 0x55;
+/// Diagnostic frames always use the classic checksum
+const MASTER_REQUEST: u8 = 0x3C;
+const SLAVE_RESPONSE: u8 = 0x3D;
+
+/// Checksum model
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChecksumType {
+    /// LIN 1.x: data bytes only
+    Classic,
+    /// LIN 2.x: protected identifier and data bytes. Diagnostic frames (0x3C and
+    /// 0x3D) still use the classic checksum
+    Enhanced,
+}
+
+/// Adds the parity bits to a 6 bit frame identifier
+pub fn protected_id(id: u8) -> u8 {
+    let id = id & 0x3F;
+    let bit = |n: u8| (id >> n) & 1;
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = (bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) ^ 1;
+    id | (p0 << 6) | (p1 << 7)
+}
+
+/// Computes the frame checksum: the inverted sum with carry of the data, and the
+/// protected identifier for [ChecksumType::Enhanced]
+pub fn checksum(pid: u8, data: &[u8], kind: ChecksumType) -> u8 {
+    let id = pid & 0x3F;
+    let init = match kind {
+        ChecksumType::Enhanced if id != MASTER_REQUEST && id != SLAVE_RESPONSE => pid as u16,
+        _ => 0,
+    };
+    let sum = data.iter().fold(init, |sum, b| {
+        let sum = sum + *b as u16;
+        if sum > 0xFF { sum - 0xFF } else { sum }
+    });
+    !(sum as u8)
+}
+
+/// Response length implied by the identifier in LIN 1.x: 2 bytes for 0 to 31, 4 for
+/// 32 to 47 and 8 for 48 to 63
+pub fn lin1_data_len(id: u8) -> usize {
+    match id & 0x3F {
+        0..=31 => 2,
+        32..=47 => 4,
+        _ => 8,
+    }
+}
+
+/// Action in a schedule slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotAction {
+    /// The master sends the header and the response
+    Publish {
+        /// Frame identifier
+        id: u8,
+        /// Response data
+        data: Vec<u8>,
+    },
+    /// The master sends the header, and a slave responds
+    Request {
+        /// Frame identifier
+        id: u8,
+        /// Expected number of data bytes
+        len: usize,
+    },
+}
+
+/// Entry in a schedule table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleSlot {
+    /// Frame sent in this slot
+    pub action: SlotAction,
+    /// Slot length, from the start of the header to the start of the next slot
+    pub slot_time: Duration,
+}
+
+impl ScheduleSlot {
+    /// Creates a slot in which the master publishes `data`
+    pub fn publish(id: u8, data: Vec<u8>, slot_time: Duration) -> Self {
+        Self { action: SlotAction::Publish { id, data }, slot_time }
+    }
+
+    /// Creates a slot in which a slave responds with `len` bytes
+    pub fn request(id: u8, len: usize, slot_time: Duration) -> Self {
+        Self { action: SlotAction::Request { id, len }, slot_time }
+    }
+}
+
+/// LIN master node on a port
+#[derive(Debug)]
+pub struct LinMaster<P: SerialPort> {
+    port: P,
+    checksum: ChecksumType,
+    echo: bool,
+    break_bits: u32,
+    response_slack: Duration,
+}
+
+impl<P: SerialPort> LinMaster<P> {
+    /// Creates a master, setting the port to 8N1 without flow control. The baud rate
+    /// is left as is, LIN usually runs at 19200 or 9600 baud. Frames use the
+    /// enhanced checksum and a 13 bit break
+    pub fn new(mut port: P) -> SerialResult<Self> {
+        let settings = port
+            .settings()
+            .byte_size(ByteSize::Eight)
+            .parity(Parity::None)
+            .stop_bits(StopBits::One)
+            .set_flow_control(FlowControl::None);
+        *port.setting() = settings;
+        port.reconfigure_port()?;
+        Ok(Self { port, checksum: ChecksumType::Enhanced, echo: true, break_bits: 13, response_slack: Duration::from_millis(20) })
+    }
+
+    /// Sets the checksum model
+    pub fn checksum_type(mut self, kind: ChecksumType) -> Self {
+        self.checksum = kind;
+        self
+    }
+
+    /// Sets whether the transceiver echoes sent bytes back
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    /// Sets the break length in bit times. LIN requires at least 13
+    pub fn break_bits(mut self, bits: u32) -> Self {
+        self.break_bits = bits.max(13);
+        self
+    }
+
+    /// Sets the time allowed on top of the LIN response timeout, to cover the
+    /// latency of the host and USB adapters
+    pub fn response_slack(mut self, slack: Duration) -> Self {
+        self.response_slack = slack;
+        self
+    }
+
+    fn bit_time(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.port.settings().baud_rate as f64)
+    }
+
+    /// Sends a header: break, break delimiter, sync byte and protected identifier
+    pub fn send_header(&mut self, id: u8) -> SerialResult<()> {
+        let bit = self.bit_time();
+        self.port.clear_input_buffer()?;
+        self.port.flush().map_err(SerialError::IoError)?;
+        self.port.set_break_state(true)?;
+        std::thread::sleep(bit * self.break_bits);
+        self.port.set_break_state(false)?;
+        // Break delimiter
+        std::thread::sleep(bit);
+        let pid = protected_id(id);
+        self.write(&[SYNC, pid])?;
+        if self.echo {
+            self.skip_header_echo(pid)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a header and the response for it, making the master the publisher
+    pub fn publish(&mut self, id: u8, data: &[u8]) -> SerialResult<()> {
+        if data.is_empty() || data.len() > 8 {
+            return Err(SerialError::LibraryError(format!("LIN response of {} bytes, must be 1 to 8", data.len())));
+        }
+        self.send_header(id)?;
+        let pid = protected_id(id);
+        let mut response = data.to_vec();
+        response.push(checksum(pid, data, self.checksum));
+        self.write(&response)?;
+        if self.echo {
+            let echo = self.read_response(response.len())?;
+            if echo != response {
+                return Err(SerialError::IoError(std::io::Error::new(ErrorKind::InvalidData, "LIN bus collision")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a header, and reads a `len` byte response from the publishing slave.
+    /// A missing response is reported as [ErrorKind::TimedOut], a bad checksum as
+    /// [ErrorKind::InvalidData]
+    pub fn request(&mut self, id: u8, len: usize) -> SerialResult<Vec<u8>> {
+        if len == 0 || len > 8 {
+            return Err(SerialError::LibraryError(format!("LIN response of {len} bytes, must be 1 to 8")));
+        }
+        self.send_header(id)?;
+        let mut response = self.read_response(len + 1)?;
+        let sum = response.pop().unwrap_or_default();
+        match checksum(protected_id(id), &response, self.checksum) == sum {
+            true => Ok(response),
+            false => Err(SerialError::IoError(std::io::Error::new(ErrorKind::InvalidData, "LIN checksum mismatch"))),
+        }
+    }
+
+    /// Runs one pass of a schedule table. Each slot is padded to its slot time. The
+    /// result of every slot is passed to `on_frame` with the frame identifier
+    /// (published frames return their data), which returns false to stop the pass
+    pub fn run_schedule_once<F: FnMut(u8, SerialResult<Vec<u8>>) -> bool>(
+        &mut self,
+        table: &[ScheduleSlot],
+        on_frame: &mut F,
+    ) -> bool {
+        for slot in table {
+            let start = Instant::now();
+            let (id, res) = match &slot.action {
+                SlotAction::Publish { id, data } => (*id, self.publish(*id, data).map(|_| data.clone())),
+                SlotAction::Request { id, len } => (*id, self.request(*id, *len)),
+            };
+            if !on_frame(id, res) {
+                return false;
+            }
+            std::thread::sleep(slot.slot_time.saturating_sub(start.elapsed()));
+        }
+        true
+    }
+
+    /// Runs a schedule table repeatedly, until `on_frame` returns false. Errors
+    /// from individual frames are passed to `on_frame`, so a missing slave does not
+    /// stop the schedule
+    pub fn run_schedule<F: FnMut(u8, SerialResult<Vec<u8>>) -> bool>(
+        &mut self,
+        table: &[ScheduleSlot],
+        mut on_frame: F,
+    ) -> SerialResult<()> {
+        if table.is_empty() {
+            return Err(SerialError::LibraryError("Empty schedule table".into()));
+        }
+        while self.run_schedule_once(table, &mut on_frame) {}
+        Ok(())
+    }
+
+    /// Gets a reference to the underlying port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the underlying port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn write(&mut self, data: &[u8]) -> SerialResult<()> {
+        self.port.write_all(data).and_then(|_| self.port.flush()).map_err(SerialError::IoError)
+    }
+
+    /// The break echoes as a 0x00 byte on most UARTs, so skip up to the sync byte
+    /// and identifier
+    fn skip_header_echo(&mut self, pid: u8) -> SerialResult<()> {
+        let deadline = Instant::now() + self.bit_time() * 30 + self.response_slack;
+        let mut last = None;
+        loop {
+            let b = self.read_byte(deadline)?;
+            if last == Some(SYNC) && b == pid {
+                return Ok(());
+            }
+            last = Some(b);
+        }
+    }
+
+    /// Reads a response within the LIN maximum of 1.4 times its nominal length
+    fn read_response(&mut self, len: usize) -> SerialResult<Vec<u8>> {
+        let deadline = Instant::now() + (self.bit_time() * 10 * len as u32).mul_f64(1.4) + self.response_slack;
+        (0..len).map(|_| self.read_byte(deadline)).collect()
+    }
+
+    fn read_byte(&mut self, deadline: Instant) -> SerialResult<u8> {
+        let mut b = [0u8; 1];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !self.port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+                return Err(SerialError::IoError(std::io::Error::new(ErrorKind::TimedOut, "No LIN response")));
+            }
+            match self.port.read(&mut b) {
+                Ok(1) => return Ok(b[0]),
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        }
+    }
+}
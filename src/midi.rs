@@ -0,0 +1,216 @@
+//! MIDI over a serial port
+//!
+//! MIDI is a 31250 baud 8N1 serial protocol, so USB-UART bridges and optocouplers
+//! make simple MIDI interfaces. [settings] gives the port settings, and [MidiCodec]
+//! splits the byte stream into complete [MidiMessage]s, handling running status,
+//! System Exclusive messages, and real-time messages interleaved with others.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(mut port: P) -> std::io::Result<()> {
+//! use serial_rs::{codec::FramedPort, midi::{self, MidiCodec}};
+//! *port.setting() = midi::settings();
+//! port.reconfigure_port()?;
+//! let mut midi_in = FramedPort::new(port, MidiCodec::new());
+//! while let Some(msg) = midi_in.read_frame()? {
+//!     println!("{msg:02X?}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::ErrorKind;
+
+use crate::{
+    codec::{Decoder, Encoder},
+    ByteSize, FlowControl, Parity, SerialPortSettings, StopBits,
+};
+
+/// MIDI line rate
+pub const MIDI_BAUD: u32 = 31250;
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+/// Port settings for MIDI: 31250 baud 8N1, without flow control
+pub fn settings() -> SerialPortSettings {
+    SerialPortSettings::default()
+        .baud(MIDI_BAUD)
+        .byte_size(ByteSize::Eight)
+        .parity(Parity::None)
+        .stop_bits(StopBits::One)
+        .set_flow_control(FlowControl::None)
+}
+
+/// Number of data bytes following a status byte, or None for SysEx
+fn data_len(status: u8) -> Option<usize> {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF => Some(2),
+        0xC0..=0xDF => Some(1),
+        0xF1 | 0xF3 => Some(1),
+        0xF2 => Some(2),
+        SYSEX_START => None,
+        _ => Some(0),
+    }
+}
+
+/// Complete MIDI message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MidiMessage {
+    /// Status byte. Messages received with running status have it filled in
+    pub status: u8,
+    /// Data bytes. For SysEx, the bytes between 0xF0 and 0xF7
+    pub data: Vec<u8>,
+}
+
+impl MidiMessage {
+    /// Creates a message
+    pub fn new(status: u8, data: Vec<u8>) -> Self {
+        Self { status, data }
+    }
+
+    /// Gets the channel (0 to 15) of a channel message
+    pub fn channel(&self) -> Option<u8> {
+        (self.status < 0xF0).then_some(self.status & 0x0F)
+    }
+
+    /// Returns true for System Exclusive messages
+    pub fn is_sysex(&self) -> bool {
+        self.status == SYSEX_START
+    }
+
+    /// Returns true for single byte real-time messages, such as timing clock
+    pub fn is_realtime(&self) -> bool {
+        self.status >= 0xF8
+    }
+}
+
+/// Codec for a MIDI byte stream
+#[derive(Debug, Clone)]
+pub struct MidiCodec {
+    /// Status of the last channel message, for running status
+    running: Option<u8>,
+    /// Message being received
+    msg: Option<MidiMessage>,
+    max_sysex: usize,
+    sysex_overflow: bool,
+    running_status_out: bool,
+    last_sent: Option<u8>,
+}
+
+impl Default for MidiCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiCodec {
+    /// Creates a codec accepting SysEx messages of up to 64KiB, and sending every
+    /// message with its status byte
+    pub fn new() -> Self {
+        Self { running: None, msg: None, max_sysex: 65536, sysex_overflow: false, running_status_out: false, last_sent: None }
+    }
+
+    /// Sets the longest SysEx message accepted. Longer messages are discarded
+    /// and reported as [ErrorKind::InvalidData]
+    pub fn max_sysex(mut self, max: usize) -> Self {
+        self.max_sysex = max;
+        self
+    }
+
+    /// Sets whether encoded channel messages omit the status byte when it repeats
+    /// the previous one, which saves bandwidth on busy links
+    pub fn running_status_out(mut self, enable: bool) -> Self {
+        self.running_status_out = enable;
+        self
+    }
+
+    /// Processes one byte, returning a message if it completes one
+    fn push(&mut self, b: u8) -> std::io::Result<Option<MidiMessage>> {
+        // Real-time messages can appear anywhere, even inside other messages, and
+        // do not affect running status
+        if b >= 0xF8 {
+            return Ok(Some(MidiMessage::new(b, Vec::new())));
+        }
+        if b == SYSEX_END {
+            let msg = self.msg.take().filter(|m| m.is_sysex());
+            let overflow = std::mem::take(&mut self.sysex_overflow);
+            return match (msg, overflow) {
+                (_, true) => Err(std::io::Error::new(ErrorKind::InvalidData, "SysEx message too long")),
+                (msg, false) => Ok(msg),
+            };
+        }
+        if b & 0x80 != 0 {
+            // A new status ends any unfinished message, including SysEx
+            self.sysex_overflow = false;
+            self.running = (b < 0xF0).then_some(b);
+            let msg = MidiMessage::new(b, Vec::new());
+            return match data_len(b) {
+                Some(0) => Ok(Some(msg)),
+                _ => {
+                    self.msg = Some(msg);
+                    Ok(None)
+                }
+            };
+        }
+        let msg = match self.msg.as_mut() {
+            Some(msg) => msg,
+            None => match self.running {
+                Some(status) => self.msg.insert(MidiMessage::new(status, Vec::new())),
+                // Data without a status, so drop it
+                None => return Ok(None),
+            },
+        };
+        match data_len(msg.status) {
+            None if msg.data.len() >= self.max_sysex => self.sysex_overflow = true,
+            None => msg.data.push(b),
+            Some(len) => {
+                msg.data.push(b);
+                if msg.data.len() == len {
+                    return Ok(self.msg.take());
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Decoder for MidiCodec {
+    type Item = MidiMessage;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<MidiMessage>, std::io::Error> {
+        for i in 0..src.len() {
+            let res = self.push(src[i]);
+            if !matches!(res, Ok(None)) {
+                src.drain(..=i);
+                return res;
+            }
+        }
+        src.clear();
+        Ok(None)
+    }
+}
+
+impl Encoder<MidiMessage> for MidiCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: MidiMessage, dst: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        if item.status & 0x80 == 0 || item.data.iter().any(|b| b & 0x80 != 0) {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "Invalid MIDI message"));
+        }
+        if item.is_realtime() {
+            dst.push(item.status);
+            return Ok(());
+        }
+        let running = self.running_status_out && item.status < 0xF0 && self.last_sent == Some(item.status);
+        if !running {
+            dst.push(item.status);
+        }
+        self.last_sent = (item.status < 0xF0).then_some(item.status);
+        dst.extend_from_slice(&item.data);
+        if item.is_sysex() {
+            dst.push(SYSEX_END);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+//! Interactive terminal, modeled on pyserial's miniterm
+//!
+//! Everything typed is sent to the port, and everything received is written to the
+//! terminal, either as text or as hex. Pressing the menu key (Ctrl+T by default)
+//! followed by a command key gives access to:
+//!
+//! | Key | Action |
+//! |-----|--------|
+//! | `d` | Toggle DTR |
+//! | `r` | Toggle RTS |
+//! | `b` | Send a 250ms break |
+//! | `i` | Show the modem input lines (CTS, DSR, RI, CD) |
+//! | `e` | Toggle local echo |
+//! | `h` | Toggle hex view |
+//! | `u` | Upload a file, prompting for its path |
+//! | `?` | Show this help |
+//! | Ctrl+T | Send the menu key itself |
+//!
+//! The exit key (Ctrl+] by default) quits. See `examples/miniterm.rs` for a complete program
+
+use std::{
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+/// How long the receive thread waits for data before checking if it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Line ending sent when Enter is pressed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SendEol {
+    /// Carriage return
+    Cr,
+    /// Line feed
+    Lf,
+    /// Carriage return followed by line feed
+    CrLf,
+}
+
+impl SendEol {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            SendEol::Cr => b"\r",
+            SendEol::Lf => b"\n",
+            SendEol::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// Terminal options
+#[derive(Debug, Copy, Clone)]
+pub struct MinitermConfig {
+    /// Echo typed characters locally
+    pub echo: bool,
+    /// Show received data as hex
+    pub hex: bool,
+    /// Key which quits the terminal
+    pub exit_char: u8,
+    /// Key which introduces a menu command
+    pub menu_char: u8,
+    /// Line ending sent when Enter is pressed
+    pub send_eol: SendEol,
+}
+
+impl Default for MinitermConfig {
+    fn default() -> Self {
+        Self {
+            echo: false,
+            hex: false,
+            exit_char: 0x1D,
+            menu_char: 0x14,
+            send_eol: SendEol::Cr,
+        }
+    }
+}
+
+/// State shared between the keyboard loop and the receive thread
+#[derive(Debug)]
+struct Shared<W: Write> {
+    output: Mutex<W>,
+    hex: AtomicBool,
+    stop: AtomicBool,
+}
+
+impl<W: Write> Shared<W> {
+    fn print(&self, data: &[u8]) {
+        let mut out = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = out.write_all(data);
+        let _ = out.flush();
+    }
+
+    fn info(&self, msg: &str) {
+        // Raw terminals do not translate \n, so return the carriage explicitly
+        self.print(format!("\r\n--- {msg} ---\r\n").as_bytes());
+    }
+}
+
+/// Interactive terminal on a port
+#[derive(Debug)]
+pub struct Miniterm<P: SerialPort + 'static> {
+    port: Arc<P>,
+    config: MinitermConfig,
+    dtr: bool,
+    rts: bool,
+}
+
+impl<P: SerialPort + 'static> Miniterm<P> {
+    /// Creates a terminal on `port`. DTR and RTS are assumed to be asserted, as they
+    /// are after opening a port
+    pub fn new(port: P, config: MinitermConfig) -> Self {
+        Self { port: Arc::new(port), config, dtr: true, rts: true }
+    }
+
+    /// Runs the terminal on stdin / stdout until the exit key is pressed. The console
+    /// is switched to raw mode for the duration of the session
+    pub fn run(&mut self) -> SerialResult<()> {
+        let _raw = RawConsole::enable()?;
+        self.run_with(std::io::stdin(), std::io::stdout())
+    }
+
+    /// Runs the terminal with the given keyboard input and display output, until the
+    /// exit key is pressed or `input` reaches end of file
+    pub fn run_with<R: Read, W: Write + Send + 'static>(&mut self, mut input: R, output: W) -> SerialResult<()> {
+        let shared = Arc::new(Shared {
+            output: Mutex::new(output),
+            hex: AtomicBool::new(self.config.hex),
+            stop: AtomicBool::new(false),
+        });
+        shared.info(&format!(
+            "Miniterm on {} | Quit: Ctrl+{} | Menu: Ctrl+{} followed by ? for help",
+            self.port.path(),
+            ctrl_name(self.config.exit_char),
+            ctrl_name(self.config.menu_char)
+        ));
+        let rx_thread = {
+            let (port, shared) = (self.port.clone(), shared.clone());
+            std::thread::spawn(move || receive_loop(&*port, &shared))
+        };
+        let res = self.keyboard_loop(&mut input, &shared);
+        shared.stop.store(true, Ordering::SeqCst);
+        let rx_res = rx_thread.join().unwrap_or(Ok(()));
+        shared.info("exit");
+        res.and(rx_res)
+    }
+
+    fn keyboard_loop<R: Read, W: Write>(&mut self, input: &mut R, shared: &Shared<W>) -> SerialResult<()> {
+        let mut menu = false;
+        while let Some(key) = read_key(input)? {
+            if shared.stop.load(Ordering::SeqCst) {
+                break;
+            }
+            if menu {
+                menu = false;
+                self.menu_command(key, input, shared)?;
+            } else if key == self.config.exit_char {
+                break;
+            } else if key == self.config.menu_char {
+                menu = true;
+            } else {
+                let data: &[u8] = match key {
+                    b'\r' | b'\n' => self.config.send_eol.as_bytes(),
+                    _ => std::slice::from_ref(&key),
+                };
+                self.send(data)?;
+                if self.config.echo {
+                    shared.print(match key {
+                        b'\r' | b'\n' => b"\r\n",
+                        _ => data,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn menu_command<R: Read, W: Write>(&mut self, key: u8, input: &mut R, shared: &Shared<W>) -> SerialResult<()> {
+        match key {
+            k if k == self.config.menu_char => self.send(&[k])?,
+            b'd' | b'D' => {
+                self.dtr = !self.dtr;
+                self.port.set_data_terminal_ready(self.dtr)?;
+                shared.info(&format!("DTR {}", on_off(self.dtr)));
+            }
+            b'r' | b'R' => {
+                self.rts = !self.rts;
+                self.port.set_request_to_send(self.rts)?;
+                shared.info(&format!("RTS {}", on_off(self.rts)));
+            }
+            b'b' | b'B' => {
+                self.port.set_break_state(true)?;
+                std::thread::sleep(Duration::from_millis(250));
+                self.port.set_break_state(false)?;
+                shared.info("BREAK sent");
+            }
+            b'i' | b'I' => {
+                shared.info(&format!(
+                    "CTS {} | DSR {} | RI {} | CD {}",
+                    on_off(self.port.read_clear_to_send()?),
+                    on_off(self.port.read_data_set_ready()?),
+                    on_off(self.port.read_ring_indicator()?),
+                    on_off(self.port.read_carrier_detect()?)
+                ));
+            }
+            b'e' | b'E' => {
+                self.config.echo = !self.config.echo;
+                shared.info(&format!("local echo {}", on_off(self.config.echo)));
+            }
+            b'h' | b'H' => {
+                let hex = !shared.hex.load(Ordering::SeqCst);
+                shared.hex.store(hex, Ordering::SeqCst);
+                shared.info(&format!("hex view {}", on_off(hex)));
+            }
+            b'u' | b'U' => self.upload(input, shared)?,
+            b'?' => shared.info(
+                "d: toggle DTR | r: toggle RTS | b: send break | i: show input lines | e: toggle echo | h: toggle hex | u: upload file",
+            ),
+            _ => shared.info(&format!("unknown menu key 0x{key:02X}, press ? for help")),
+        }
+        Ok(())
+    }
+
+    /// Prompts for a file path on the keyboard, and sends the file's contents
+    fn upload<R: Read, W: Write>(&mut self, input: &mut R, shared: &Shared<W>) -> SerialResult<()> {
+        shared.print(b"\r\n--- file to upload: ");
+        let mut path = Vec::new();
+        loop {
+            match read_key(input)? {
+                None | Some(b'\r') | Some(b'\n') => break,
+                // Backspace / delete
+                Some(0x08) | Some(0x7F) => {
+                    if path.pop().is_some() {
+                        shared.print(b"\x08 \x08");
+                    }
+                }
+                Some(k) => {
+                    path.push(k);
+                    shared.print(&[k]);
+                }
+            }
+        }
+        let path = String::from_utf8_lossy(&path).trim().to_string();
+        if path.is_empty() {
+            shared.info("upload cancelled");
+            return Ok(());
+        }
+        match std::fs::read(&path) {
+            Ok(data) => {
+                self.send(&data)?;
+                self.port.flush_shared().map_err(SerialError::IoError)?;
+                shared.info(&format!("sent {} bytes from {path}", data.len()));
+            }
+            Err(e) => shared.info(&format!("cannot read {path}: {e}")),
+        }
+        Ok(())
+    }
+
+    fn send(&self, mut data: &[u8]) -> SerialResult<()> {
+        while !data.is_empty() {
+            match self.port.write_shared(data) {
+                Ok(n) => data = &data[n..],
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Unwraps the port. Returns None if the port is still shared with a running session
+    pub fn into_inner(self) -> Option<P> {
+        Arc::try_unwrap(self.port).ok()
+    }
+}
+
+/// Copies received data to the output until told to stop
+fn receive_loop<P: SerialPort + ?Sized, W: Write>(port: &P, shared: &Shared<W>) -> SerialResult<()> {
+    let mut buf = [0u8; 1024];
+    while !shared.stop.load(Ordering::SeqCst) {
+        if !port.poll_readable(Some(POLL_INTERVAL)).map_err(SerialError::IoError)? {
+            continue;
+        }
+        let read = match port.read_shared(&mut buf) {
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted) => 0,
+            Err(e) => {
+                shared.stop.store(true, Ordering::SeqCst);
+                shared.info(&format!("port error: {e}"));
+                return Err(SerialError::IoError(e));
+            }
+        };
+        if read == 0 {
+            continue;
+        }
+        match shared.hex.load(Ordering::SeqCst) {
+            true => {
+                let hex: String = buf[..read].iter().map(|b| format!("{b:02X} ")).collect();
+                shared.print(hex.as_bytes());
+            }
+            false => shared.print(&buf[..read]),
+        }
+    }
+    Ok(())
+}
+
+fn read_key<R: Read>(input: &mut R) -> SerialResult<Option<u8>> {
+    let mut key = [0u8; 1];
+    loop {
+        match input.read(&mut key) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(key[0])),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+    }
+}
+
+fn on_off(state: bool) -> &'static str {
+    match state {
+        true => "on",
+        false => "off",
+    }
+}
+
+/// Name of a control key, such as `]` for 0x1D
+fn ctrl_name(c: u8) -> char {
+    (c | 0x40) as char
+}
+
+/// Puts the console in raw mode until dropped
+struct RawConsole {
+    #[cfg(unix)]
+    orig: nix::sys::termios::Termios,
+    #[cfg(windows)]
+    orig: winapi::shared::minwindef::DWORD,
+}
+
+impl RawConsole {
+    #[cfg(unix)]
+    fn enable() -> SerialResult<Self> {
+        use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+        let orig = tcgetattr(nix::libc::STDIN_FILENO)?;
+        let mut raw = orig.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(nix::libc::STDIN_FILENO, SetArg::TCSANOW, &raw)?;
+        Ok(Self { orig })
+    }
+
+    #[cfg(windows)]
+    fn enable() -> SerialResult<Self> {
+        use winapi::um::{
+            consoleapi::{GetConsoleMode, SetConsoleMode},
+            processenv::GetStdHandle,
+            winbase::STD_INPUT_HANDLE,
+            wincon::{ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT},
+        };
+        let mut orig = 0;
+        unsafe {
+            let handle = GetStdHandle(STD_INPUT_HANDLE);
+            if GetConsoleMode(handle, &mut orig) == 0
+                || SetConsoleMode(handle, orig & !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT)) == 0
+            {
+                return Err(crate::windows::error::get_win_error());
+            }
+        }
+        Ok(Self { orig })
+    }
+}
+
+impl Drop for RawConsole {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ = nix::sys::termios::tcsetattr(nix::libc::STDIN_FILENO, nix::sys::termios::SetArg::TCSANOW, &self.orig);
+        #[cfg(windows)]
+        unsafe {
+            let handle = winapi::um::processenv::GetStdHandle(winapi::um::winbase::STD_INPUT_HANDLE);
+            winapi::um::consoleapi::SetConsoleMode(handle, self.orig);
+        }
+    }
+}
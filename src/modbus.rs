@@ -0,0 +1,185 @@
+//! Modbus RTU transport
+//!
+//! [ModbusRtu] handles the serial side of Modbus RTU: the CRC-16 on every frame,
+//! and the inter-frame timing. A frame ends after 1.5 character times of silence,
+//! and must be followed by at least 3.5 character times of silence before the next
+//! frame starts. Above 19200 baud these are fixed at 750µs and 1.75ms. A frame
+//! continued after a 1.5 to 3.5 character gap is corrupt, and is discarded.
+//!
+//! Encoding and decoding the PDUs (function codes, registers, exception responses)
+//! is left to the caller.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::modbus::ModbusRtu;
+//! let mut bus = ModbusRtu::new(port);
+//! // Read 2 holding registers from address 0 of device 17
+//! let response = bus.transact(&[17, 0x03, 0x00, 0x00, 0x00, 0x02])?;
+//! println!("{response:02X?}");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{framing::IdleGapFramer, SerialError, SerialPort, SerialResult};
+
+/// Largest RTU frame, including the address and CRC
+const MAX_FRAME: usize = 256;
+/// Address which all devices accept, and none reply to
+const BROADCAST: u8 = 0;
+
+/// Modbus CRC-16 (polynomial 0xA001, initial value 0xFFFF). It is sent low byte
+/// first
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, b| {
+        (0..8).fold(crc ^ *b as u16, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0xA001,
+            _ => crc >> 1,
+        })
+    })
+}
+
+/// Modbus RTU transport on a port
+#[derive(Debug)]
+pub struct ModbusRtu<P: SerialPort> {
+    framer: IdleGapFramer<P>,
+    t35: Duration,
+    response_timeout: Duration,
+    turnaround_delay: Duration,
+    retries: u32,
+    last_activity: Instant,
+}
+
+impl<P: SerialPort> ModbusRtu<P> {
+    /// Creates a transport, with timing computed from the port's current settings.
+    /// Requests wait up to 1 second for a response, and are retried twice
+    pub fn new(port: P) -> Self {
+        let settings = port.settings();
+        let (t15, t35) = match settings.baud_rate > 19200 {
+            true => (Duration::from_micros(750), Duration::from_micros(1750)),
+            false => (settings.char_duration().mul_f64(1.5), settings.char_duration().mul_f64(3.5)),
+        };
+        Self {
+            framer: IdleGapFramer::new(port, t15),
+            t35,
+            response_timeout: Duration::from_secs(1),
+            turnaround_delay: Duration::from_millis(100),
+            retries: 2,
+            last_activity: Instant::now(),
+        }
+    }
+
+    /// Sets how long to wait for a response to start
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = timeout;
+        self
+    }
+
+    /// Sets how long to wait after a broadcast, which has no response, so devices
+    /// can process it before the next request
+    pub fn turnaround_delay(mut self, delay: Duration) -> Self {
+        self.turnaround_delay = delay;
+        self
+    }
+
+    /// Sets how many times a request is resent after a timeout or corrupt response
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sends `request` (the device address and PDU, without CRC) and returns the
+    /// response, without its CRC. Broadcasts to address 0 return an empty response
+    /// once the turnaround delay has passed.
+    ///
+    /// Modbus exception responses are returned as is
+    pub fn transact(&mut self, request: &[u8]) -> SerialResult<Vec<u8>> {
+        let address = *request.first().ok_or_else(|| SerialError::LibraryError("Empty request".into()))?;
+        let mut last_err = None;
+        for _ in 0..=self.retries {
+            self.send(request)?;
+            if address == BROADCAST {
+                std::thread::sleep(self.turnaround_delay);
+                return Ok(Vec::new());
+            }
+            match self.receive(Some(self.response_timeout)) {
+                Ok(resp) if resp[0] == address => return Ok(resp),
+                Ok(_) => last_err = Some(SerialError::LibraryError("Response from wrong device".into())),
+                Err(SerialError::IoError(e)) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::InvalidData) => {
+                    last_err = Some(SerialError::IoError(e))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| SerialError::LibraryError("No response".into())))
+    }
+
+    /// Sends a frame (the device address and PDU), appending the CRC. Waits for
+    /// 3.5 character times of silence since the last frame first
+    pub fn send(&mut self, frame: &[u8]) -> SerialResult<()> {
+        if frame.len() + 2 > MAX_FRAME {
+            return Err(SerialError::LibraryError("Frame exceeds 256 bytes".into()));
+        }
+        let mut adu = frame.to_vec();
+        adu.extend_from_slice(&crc16(frame).to_le_bytes());
+        let wait = (self.last_activity + self.t35).saturating_duration_since(Instant::now());
+        std::thread::sleep(wait);
+        // Anything still pending is a late reply to an earlier request
+        self.framer.get_ref().clear_input_buffer()?;
+        let port = self.framer.get_mut();
+        port.write_all(&adu).and_then(|_| port.flush()).map_err(SerialError::IoError)?;
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for a frame, and returns it without its CRC. Corrupt
+    /// frames are reported as [ErrorKind::InvalidData]
+    pub fn receive(&mut self, timeout: Option<Duration>) -> SerialResult<Vec<u8>> {
+        let mut frame = self.framer.read_frame(timeout).map_err(SerialError::IoError)?;
+        self.last_activity = Instant::now();
+        // The frame ended after 1.5 characters of silence, so any more data before
+        // 3.5 characters means it was broken up
+        let rest = self.t35.saturating_sub(self.framer.gap());
+        let mut broken = false;
+        while self.framer.get_ref().poll_readable(Some(rest)).map_err(SerialError::IoError)? {
+            broken = true;
+            let _ = self.framer.read_frame(Some(Duration::ZERO));
+            self.last_activity = Instant::now();
+        }
+        if broken {
+            return Err(invalid("Gap inside frame"));
+        }
+        if frame.len() < 4 || frame.len() > MAX_FRAME {
+            return Err(invalid("Bad frame length"));
+        }
+        let crc = u16::from_le_bytes([frame[frame.len() - 2], frame[frame.len() - 1]]);
+        frame.truncate(frame.len() - 2);
+        match crc16(&frame) == crc {
+            true => Ok(frame),
+            false => Err(invalid("CRC mismatch")),
+        }
+    }
+
+    /// Gets a reference to the underlying port
+    pub fn get_ref(&self) -> &P {
+        self.framer.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying port
+    pub fn get_mut(&mut self) -> &mut P {
+        self.framer.get_mut()
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.framer.into_inner()
+    }
+}
+
+fn invalid(msg: &str) -> SerialError {
+    SerialError::IoError(std::io::Error::new(ErrorKind::InvalidData, msg.to_string()))
+}
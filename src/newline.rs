@@ -0,0 +1,178 @@
+//! Newline translation
+//!
+//! Ports are always opened in raw mode, so bytes pass through unchanged. Terminal
+//! style devices often expect `\r\n` and send `\r` alone, which [NewlinePort]
+//! translates to and from Rust's `\n`. Translation happens here rather than in
+//! the driver (termios `OPOST` / `ICRNL`), so it behaves the same on every platform.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> std::io::Result<()> {
+//! use std::io::{BufRead, BufReader, Write};
+//! use serial_rs::newline::{NewlinePort, RxNewline, TxNewline};
+//! let mut port = NewlinePort::new(port).tx(TxNewline::LfToCrLf).rx(RxNewline::Any);
+//! writeln!(port, "show version")?;
+//! let mut line = String::new();
+//! BufReader::new(port).read_line(&mut line)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{ErrorKind, Read, Write};
+
+use crate::SerialPort;
+
+/// Translation applied to data written
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TxNewline {
+    /// Sends data unchanged
+    #[default]
+    PassThrough,
+    /// Sends `\n` as `\r\n`
+    LfToCrLf,
+    /// Sends `\n` as `\r`
+    LfToCr,
+}
+
+/// Translation applied to data read
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RxNewline {
+    /// Returns data unchanged
+    #[default]
+    PassThrough,
+    /// Returns `\r` as `\n`. `\r\n` becomes two newlines
+    CrToLf,
+    /// Returns `\r\n` as `\n`, leaving a `\r` on its own unchanged. A `\r` at the
+    /// end of a read is held back until the next byte shows whether a `\n` follows
+    CrLfToLf,
+    /// Returns `\r`, `\n` and `\r\n` all as `\n`
+    Any,
+}
+
+/// Wrapper around a port which translates newlines as data is read and written
+#[derive(Debug)]
+pub struct NewlinePort<P: SerialPort> {
+    port: P,
+    tx: TxNewline,
+    rx: RxNewline,
+    /// Translated data not yet returned by a read
+    pending: Vec<u8>,
+    /// The last byte received was `\r`. In [RxNewline::CrLfToLf] mode it has not
+    /// been returned yet
+    last_cr: bool,
+}
+
+impl<P: SerialPort> NewlinePort<P> {
+    /// Wraps `port`, passing data through unchanged until configured
+    pub fn new(port: P) -> Self {
+        Self { port, tx: TxNewline::PassThrough, rx: RxNewline::PassThrough, pending: Vec::new(), last_cr: false }
+    }
+
+    /// Sets the translation of written data
+    pub fn tx(mut self, mode: TxNewline) -> Self {
+        self.tx = mode;
+        self
+    }
+
+    /// Sets the translation of read data
+    pub fn rx(mut self, mode: RxNewline) -> Self {
+        self.rx = mode;
+        self
+    }
+
+    /// Gets a reference to the port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port. Data read but not yet returned is lost
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn translate_rx(&mut self, data: &[u8]) {
+        for &b in data {
+            match (self.rx, b, self.last_cr) {
+                (RxNewline::PassThrough, b, _) => self.pending.push(b),
+                (RxNewline::CrToLf, b'\r', _) => self.pending.push(b'\n'),
+                (RxNewline::CrToLf, b, _) => self.pending.push(b),
+                (RxNewline::CrLfToLf, b'\n', true) => self.pending.push(b'\n'),
+                (RxNewline::CrLfToLf, b, last_cr) => {
+                    if last_cr {
+                        self.pending.push(b'\r');
+                    }
+                    if b != b'\r' {
+                        self.pending.push(b);
+                    }
+                }
+                (RxNewline::Any, b'\r', _) => self.pending.push(b'\n'),
+                (RxNewline::Any, b'\n', true) => {}
+                (RxNewline::Any, b, _) => self.pending.push(b),
+            }
+            self.last_cr = b == b'\r';
+        }
+    }
+}
+
+impl<P: SerialPort> Read for NewlinePort<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Translation may swallow everything read, which must not look like end of file
+        while self.pending.is_empty() {
+            let mut chunk = [0u8; 256];
+            let n = self.port.read_shared(&mut chunk)?;
+            if n == 0 {
+                // Release a held back `\r`, as nothing can follow it
+                if self.rx == RxNewline::CrLfToLf && std::mem::take(&mut self.last_cr) {
+                    self.pending.push(b'\r');
+                    break;
+                }
+                return Ok(0);
+            }
+            self.translate_rx(&chunk[..n]);
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl<P: SerialPort> Write for NewlinePort<P> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let replacement: &[u8] = match self.tx {
+            TxNewline::PassThrough => return self.port.write_shared(buf),
+            TxNewline::LfToCrLf => b"\r\n",
+            TxNewline::LfToCr => b"\r",
+        };
+        let mut out = Vec::with_capacity(buf.len() + 8);
+        for &b in buf {
+            match b {
+                b'\n' => out.extend_from_slice(replacement),
+                b => out.push(b),
+            }
+        }
+        // All of the translated data is written, as a partial write cannot be
+        // mapped back to a count of the caller's bytes
+        let mut written = 0;
+        while written < out.len() {
+            match self.port.write_shared(&out[written..]) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero)),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush_shared()
+    }
+}
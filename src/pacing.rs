@@ -0,0 +1,144 @@
+//! Write pacing
+//!
+//! Old instruments, and links through slow optocouplers, often drop characters
+//! when a USB adapter sends a write back to back at full speed. [PacedWriter]
+//! spaces out writes with a delay after every character or every chunk, and can
+//! cap the average write rate. Delays start once the data has left the port,
+//! and deadlines are tracked across writes, so the spacing stays accurate however
+//! the data is split into writes.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> std::io::Result<()> {
+//! use std::{io::Write, time::Duration};
+//! use serial_rs::pacing::PacedWriter;
+//! let mut port = PacedWriter::new(port).char_delay(Duration::from_millis(2));
+//! port.write_all(b"*RST\r\n")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::SerialPort;
+
+/// Sleeps until `deadline`, spinning for the last millisecond as sleeps are too
+/// coarse for bit and character timing
+pub(crate) fn sleep_until(deadline: Instant) {
+    let coarse = deadline.saturating_duration_since(Instant::now()).saturating_sub(Duration::from_millis(1));
+    std::thread::sleep(coarse);
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+/// Wrapper around a port which paces its writes. Reads pass straight through
+#[derive(Debug)]
+pub struct PacedWriter<P: SerialPort> {
+    port: P,
+    char_delay: Duration,
+    chunk: Option<(usize, Duration)>,
+    max_rate: Option<u32>,
+    /// Earliest time the next unit may be written
+    next: Instant,
+}
+
+impl<P: SerialPort> PacedWriter<P> {
+    /// Wraps `port`, without any pacing until configured
+    pub fn new(port: P) -> Self {
+        Self { port, char_delay: Duration::ZERO, chunk: None, max_rate: None, next: Instant::now() }
+    }
+
+    /// Waits `delay` after each character has been sent, before sending the next
+    pub fn char_delay(mut self, delay: Duration) -> Self {
+        self.char_delay = delay;
+        self
+    }
+
+    /// Sends at most `size` bytes at a time, waiting `delay` after each chunk has
+    /// been sent. Ignored if a character delay is set
+    pub fn chunk_delay(mut self, size: usize, delay: Duration) -> Self {
+        self.chunk = Some((size.max(1), delay));
+        self
+    }
+
+    /// Limits the average write rate to `bytes_per_sec`
+    pub fn max_rate(mut self, bytes_per_sec: Option<u32>) -> Self {
+        self.max_rate = bytes_per_sec.filter(|r| *r > 0);
+        self
+    }
+
+    /// Number of bytes sent in one go, and the delay after them
+    fn unit(&self) -> (usize, Duration) {
+        match (self.char_delay, self.chunk, self.max_rate) {
+            (delay, _, _) if !delay.is_zero() => (1, delay),
+            (_, Some(chunk), _) => chunk,
+            // Roughly 10ms of data per write keeps the rate smooth
+            (_, None, Some(rate)) => ((rate as usize / 100).max(1), Duration::ZERO),
+            (_, None, None) => (usize::MAX, Duration::ZERO),
+        }
+    }
+
+    /// Writes `buf` straight away, without waiting for the pacing delay or moving the
+    /// next deadline. See [crate::SerialIo::write_urgent]
+    pub fn write_urgent(&self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write_urgent(buf)
+    }
+
+    /// Gets a reference to the wrapped port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the wrapped port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+}
+
+impl<P: SerialPort> Read for PacedWriter<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read_shared(buf)
+    }
+}
+
+impl<P: SerialPort> Write for PacedWriter<P> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let (size, delay) = self.unit();
+        let mut written = 0;
+        for unit in buf.chunks(size) {
+            sleep_until(self.next);
+            let start = Instant::now();
+            let res = self.port.write_shared(unit).and_then(|n| match delay.is_zero() {
+                true => Ok(n),
+                // The delay is measured from when the data has left the port
+                false => self.port.flush_shared().map(|_| n),
+            });
+            let n = match res {
+                Ok(n) => n,
+                Err(_) if written > 0 => break,
+                Err(e) => return Err(e),
+            };
+            written += n;
+            self.next = Instant::now() + delay;
+            if let Some(rate) = self.max_rate {
+                self.next = self.next.max(start + Duration::from_secs_f64(n as f64 / rate as f64));
+            }
+            if n < unit.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush_shared()
+    }
+}
@@ -1,7 +1,9 @@
 #[cfg(target_os = "macos")]
 use std::os::unix::prelude::RawFd;
 
-use nix::{ioctl_none_bad, libc, ioctl_read_bad, ioctl_write_ptr_bad, ioctl_read, ioctl_write_ptr, Result};
+use nix::{ioctl_none, ioctl_none_bad, libc, ioctl_read_bad, ioctl_write_ptr_bad, ioctl_read, ioctl_write_ptr};
+#[cfg(target_os = "macos")]
+use nix::Result;
 
 
 ioctl_none_bad!(tiocexcl, libc::TIOCEXCL);
@@ -27,12 +29,85 @@ ioctl_read_bad!(tiocinq, libc::FIONREAD, libc::c_int);
 ioctl_write_ptr_bad!(tiocmbic, libc::TIOCMBIC, libc::c_int);
 ioctl_write_ptr_bad!(tiocmbis, libc::TIOCMBIS, libc::c_int);
 
+/// Modem control bit for the UART's internal loopback, missing from libc
+#[cfg(target_os = "linux")]
+pub const TIOCM_LOOP: libc::c_int = 0x8000;
+
 #[cfg(target_os = "linux")]
-ioctl_read!(tcgets2, b'T', 0x2A, libc::termios);
+ioctl_read!(tcgets2, b'T', 0x2A, libc::termios2);
 
 #[cfg(target_os = "linux")]
 ioctl_write_ptr!(tcsets2, b'T', 0x2B, libc::termios2);
 
+/// Linux `struct serial_struct`, used by TIOCGSERIAL / TIOCSSERIAL
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SerialStruct {
+    pub type_: libc::c_int,
+    pub line: libc::c_int,
+    pub port: libc::c_uint,
+    pub irq: libc::c_int,
+    pub flags: libc::c_int,
+    pub xmit_fifo_size: libc::c_int,
+    pub custom_divisor: libc::c_int,
+    pub baud_base: libc::c_int,
+    pub close_delay: libc::c_ushort,
+    pub io_type: libc::c_char,
+    pub reserved_char: [libc::c_char; 1],
+    pub hub6: libc::c_int,
+    pub closing_wait: libc::c_ushort,
+    pub closing_wait2: libc::c_ushort,
+    pub iomem_base: *mut libc::c_uchar,
+    pub iomem_reg_shift: libc::c_ushort,
+    pub port_high: libc::c_uint,
+    pub iomap_base: libc::c_ulong,
+}
+
+/// Linux `struct serial_icounter_struct`, used by TIOCGICOUNT
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SerialIcounter {
+    pub cts: libc::c_int,
+    pub dsr: libc::c_int,
+    pub rng: libc::c_int,
+    pub dcd: libc::c_int,
+    pub rx: libc::c_int,
+    pub tx: libc::c_int,
+    pub frame: libc::c_int,
+    pub overrun: libc::c_int,
+    pub parity: libc::c_int,
+    pub brk: libc::c_int,
+    pub buf_overrun: libc::c_int,
+    pub reserved: [libc::c_int; 9],
+}
+
+#[cfg(target_os = "linux")]
+ioctl_read_bad!(tiocgicount, libc::TIOCGICOUNT, SerialIcounter);
+
+// Resets a USB device through its usbfs node
+#[cfg(target_os = "linux")]
+ioctl_none!(usbdevfs_reset, b'U', 20);
+
+/// Driver flag which disables receive coalescing
+#[cfg(target_os = "linux")]
+pub const ASYNC_LOW_LATENCY: libc::c_int = 1 << 13;
+
+/// Mask of the driver flags which select a legacy speed for 38400 baud
+#[cfg(target_os = "linux")]
+pub const ASYNC_SPD_MASK: libc::c_int = 0x1030;
+
+/// Driver flag which makes 38400 baud use the custom divisor
+#[cfg(target_os = "linux")]
+pub const ASYNC_SPD_CUST: libc::c_int = 0x0030;
+
+#[cfg(target_os = "linux")]
+ioctl_read_bad!(tiocgserial, libc::TIOCGSERIAL, SerialStruct);
+
+#[cfg(target_os = "linux")]
+ioctl_write_ptr_bad!(tiocsserial, libc::TIOCSSERIAL, SerialStruct);
+
 #[cfg(target_os = "macos")]
 const IOSSIOSPEED: libc::c_ulong = 0x80045402;
 
@@ -40,6 +115,20 @@ const IOSSIOSPEED: libc::c_ulong = 0x80045402;
 ioctl_write_ptr_bad!(iossiospeedraw, IOSSIOSPEED, libc::speed_t);
 
 
+// Receive latency in microseconds, from IOKit/serial/ioss.h
+#[cfg(target_os = "macos")]
+ioctl_write_ptr!(iossdatalat, b'T', 0, libc::c_ulong);
+
+// Whether the port may be preempted by another open, from IOKit/serial/ioss.h
+#[cfg(target_os = "macos")]
+ioctl_write_ptr!(iosspreempt, b'T', 1, libc::c_int);
+
+#[cfg(target_os = "macos")]
+ioctl_none!(tiocsdtr, b't', 121);
+
+#[cfg(target_os = "macos")]
+ioctl_none!(tioccdtr, b't', 120);
+
 #[cfg(target_os = "macos")]
 pub fn iossiospeed(fd: RawFd, baud_rate: &libc::speed_t) -> Result<()> {
     unsafe { iossiospeedraw(fd, baud_rate) }
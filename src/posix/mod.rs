@@ -1,127 +1,843 @@
 //! TTY port
 
-use std::{os::unix::prelude::RawFd, path::Path, slice, io};
+use std::{os::unix::prelude::{RawFd, AsRawFd, AsFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd}, path::Path, io, sync::{Arc, Mutex, MutexGuard, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
 
-use nix::{libc::{close, self}, fcntl::{OFlag, flock, FlockArg, fcntl, self}, sys::{termios::{tcgetattr, tcsetattr, tcflush, ControlFlags, LocalFlags, OutputFlags, InputFlags, cfsetospeed, cfsetispeed, BaudRate, SpecialCharacterIndices, tcflow, FlowArg, tcdrain}, time::TimeSpec, signal::SigSet}, poll::{PollFlags, PollFd}};
-use crate::{SerialPortSettings, SerialResult, SerialPort, SerialError, FlowControl};
+use nix::{errno::Errno, libc::{close, self}, fcntl::{OFlag, flock, FlockArg, fcntl, FcntlArg, FdFlag}, sys::{termios::{tcgetattr, tcsetattr, tcflush, ControlFlags, LocalFlags, OutputFlags, InputFlags, cfsetospeed, cfsetispeed, BaudRate, SpecialCharacterIndices, tcflow, FlowArg, tcdrain, cfmakeraw}, time::TimeSpec, signal::SigSet}, poll::{PollFlags, PollFd}};
+use crate::{buffered::PeekBuffer, cancel::CancelToken, SerialPortSettings, SerialResult, SerialIo, ControlLines, Configurable, BufferControl, SerialError, FlowControl, DriverConfigDump, InputProcessing, LineErrorCounts, ModemStatus, ParityErrorPolicy, QueueStatus, SettingMismatch, TermiosProfile};
 
 mod error;
 mod ioctl;
 
 pub mod port_lister;
 
+#[cfg(target_os = "linux")]
+const BAUD_RATES: [(u32, BaudRate); 30] = [
+    (50, BaudRate::B50),
+    (75, BaudRate::B75),
+    (110, BaudRate::B110),
+    (134, BaudRate::B134),
+    (150, BaudRate::B150),
+    (200, BaudRate::B200),
+    (300, BaudRate::B300),
+    (600, BaudRate::B600),
+    (1200, BaudRate::B1200),
+    (1800, BaudRate::B1800),
+    (2400, BaudRate::B2400),
+    (4800, BaudRate::B4800),
+    (9600, BaudRate::B9600),
+    (19_200, BaudRate::B19200),
+    (38_400, BaudRate::B38400),
+    (57_600, BaudRate::B57600),
+    (115_200, BaudRate::B115200),
+    (230_400, BaudRate::B230400),
+    (460_800, BaudRate::B460800),
+    (500_000, BaudRate::B500000),
+    (576_000, BaudRate::B576000),
+    (921_600, BaudRate::B921600),
+    (1_000_000, BaudRate::B1000000),
+    (1_152_000, BaudRate::B1152000),
+    (1_500_000, BaudRate::B1500000),
+    (2_000_000, BaudRate::B2000000),
+    (2_500_000, BaudRate::B2500000),
+    (3_000_000, BaudRate::B3000000),
+    (3_500_000, BaudRate::B3500000),
+    (4_000_000, BaudRate::B4000000),
+];
+
+#[cfg(target_os = "linux")]
+fn baud_rate_to_nix(baud: u32) -> Option<BaudRate> {
+    BAUD_RATES.iter().find(|(b, _)| *b == baud).map(|(_, r)| *r)
+}
+
 /// A TTY port
+///
+/// Clones of a port share the same file descriptor, which is only closed
+/// once the last clone is dropped
 #[derive(Debug, Clone)]
 pub struct TTYPort {
     fd: RawFd,
+    owner: Arc<FdOwner>,
+    cancel: Arc<CancelPipe>,
+    /// Data read with PARMRK set which has not been returned yet, see [TTYPort::read_marked]
+    marked: Arc<Mutex<Vec<u8>>>,
+    peeked: Arc<PeekBuffer>,
     settings: SerialPortSettings,
     path: String,
 }
 
+/// Owns a file descriptor shared by all clones of a [TTYPort]
+#[derive(Debug)]
+struct FdOwner(RawFd);
 
-impl TTYPort {
-    /// Creates a new TTY port
-    pub fn new(path: String, settings: Option<SerialPortSettings>) -> SerialResult<Self> {
+impl Drop for FdOwner {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.0);
+        }
+    }
+}
 
-        let mut flags = OFlag::O_RDWR | OFlag::O_NOCTTY;
-        if !settings.unwrap_or_default().blocking {
-            flags |= OFlag::O_NONBLOCK
-        } 
 
+impl TTYPort {
+    /// Creates a new TTY port.
+    ///
+    /// The device is opened with O_NONBLOCK, as dial-in devices otherwise block in
+    /// open until carrier is present, and CLOCAL is set before the port is switched
+    /// to blocking mode. To wait for carrier, see
+    /// [SerialPortSettings::open_carrier_timeout]
+    pub fn new(path: String, settings: Option<SerialPortSettings>) -> SerialResult<Self> {
+        let flags = OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_NONBLOCK;
         let fd = nix::fcntl::open(Path::new(&path), flags, nix::sys::stat::Mode::empty())?;
 
+        let owner = Arc::new(FdOwner(fd));
         let mut port = TTYPort {
             fd,
+            owner,
+            cancel: Arc::new(CancelPipe::new()?),
+            marked: Arc::default(),
+            peeked: Arc::default(),
             settings: settings.unwrap_or_default(),
             path
         };
 
         port.reconfigure_port()?;
-        if port.settings.flow_control != FlowControl::DsrDtr {
-            port.set_data_terminal_ready(true)?;
-        }
+        port.apply_blocking()?;
+        port.set_data_terminal_ready(port.settings.initial_dtr.unwrap_or(true))?;
 
         if port.settings.flow_control != FlowControl::RtsCts {
-            port.set_request_to_send(true)?;
+            port.set_request_to_send(port.settings.initial_rts.unwrap_or(true))?;
         }
+        crate::wait_for_open_carrier(&port)?;
         port.clear_input_buffer()?;
         port.clear_output_buffer()?;
         Ok(port)
     }
+
+    /// Takes ownership of a TTY fd opened elsewhere (for example a PTY created by
+    /// another library, or a device opened by a privileged helper) and applies `settings` to it.
+    ///
+    /// # Safety
+    /// `fd` must be an open file descriptor which is not owned by anything else
+    pub unsafe fn from_raw_fd_with_settings(fd: RawFd, settings: SerialPortSettings) -> SerialResult<Self> {
+        let mut port = TTYPort::from_raw_fd(fd);
+        port.settings = settings;
+        port.apply_blocking()?;
+        port.reconfigure_port()?;
+        Ok(port)
+    }
+
+    /// Resets the USB device behind the port with USBDEVFS_RESET, which recovers
+    /// adapters whose firmware has hung without replugging them. The device
+    /// re-enumerates, so this port stops working and must be reopened, for example
+    /// by [crate::reconnect::ReconnectingPort].
+    ///
+    /// The device's usbfs node is found through sysfs, and opening it needs write
+    /// access, usually root or a udev rule. Only supported on Linux
+    pub fn usb_reset(&self) -> SerialResult<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let node = usbfs_node(&self.path)?;
+            let fd = nix::fcntl::open(&node, OFlag::O_WRONLY | OFlag::O_CLOEXEC, nix::sys::stat::Mode::empty())?;
+            let owner = FdOwner(fd);
+            unsafe { ioctl::usbdevfs_reset(owner.0) }?;
+            Ok(())
+        }
+        #[cfg(not(target_os = "linux"))]
+        Err(SerialError::Unsupported("USB device reset is only supported on Linux".to_string()))
+    }
+
+    /// Returns true if the kernel may autosuspend the USB device behind the port.
+    /// Only supported on Linux
+    pub fn usb_autosuspend(&self) -> SerialResult<bool> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, _, _) = usb_device_dir(&self.path)?;
+            let control = std::fs::read_to_string(dir.join("power/control")).map_err(SerialError::IoError)?;
+            Ok(control.trim() == "auto")
+        }
+        #[cfg(not(target_os = "linux"))]
+        Err(SerialError::Unsupported("USB autosuspend control is only supported on Linux".to_string()))
+    }
+
+    /// Allows or stops the kernel autosuspending the USB device behind the port, by
+    /// writing `auto` or `on` to its `power/control` sysfs attribute. Autosuspend
+    /// causes latency of hundreds of milliseconds on the first byte after an idle
+    /// period, and some bridges drop bytes whilst resuming.
+    ///
+    /// Writing the attribute usually needs root or a udev rule, and the setting is
+    /// lost when the device is replugged. Only supported on Linux
+    pub fn set_usb_autosuspend(&self, enabled: bool) -> SerialResult<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, _, _) = usb_device_dir(&self.path)?;
+            let control = if enabled { "auto" } else { "on" };
+            std::fs::write(dir.join("power/control"), control).map_err(SerialError::IoError)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = enabled;
+            Err(SerialError::Unsupported("USB autosuspend control is only supported on Linux".to_string()))
+        }
+    }
+
+    /// Reads the UART details reported by the serial driver (TIOCGSERIAL). Fails if
+    /// the driver does not support it, as many USB adapters do not
+    #[cfg(target_os = "linux")]
+    pub fn uart_info(&self) -> SerialResult<UartInfo> {
+        let serial = self.serial_struct()?;
+        Ok(UartInfo {
+            uart_type: serial.type_,
+            line: serial.line,
+            port: serial.port as u64 | (serial.port_high as u64) << 32,
+            irq: serial.irq,
+            flags: serial.flags,
+            xmit_fifo_size: serial.xmit_fifo_size,
+            baud_base: serial.baud_base,
+            custom_divisor: serial.custom_divisor,
+        })
+    }
+
+    /// Sets or clears O_NONBLOCK to match the blocking setting
+    fn apply_blocking(&self) -> SerialResult<()> {
+        let mut flags = OFlag::from_bits_truncate(fcntl(self.fd, FcntlArg::F_GETFL)?);
+        flags.set(OFlag::O_NONBLOCK, !self.settings.blocking);
+        fcntl(self.fd, FcntlArg::F_SETFL(flags))?;
+        Ok(())
+    }
+
+    /// In non-blocking mode, a read of nothing means no data was available. With VMIN
+    /// at 0 the tty layer reports this as a 0 byte read rather than EAGAIN, so convert
+    /// it to WouldBlock to match EAGAIN and the Windows backend
+    fn check_would_block(&self, read: usize, requested: usize) -> std::io::Result<usize> {
+        if read == 0 && requested != 0 && !self.settings.blocking {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(read)
+    }
+
+    /// Waits until the port is readable, if a read could block.
+    ///
+    /// Reads only block in blocking mode, and only if a read timeout is set, or if
+    /// VMIN is set by the inter-byte timeout or overridden. Non-blocking reads fail
+    /// with WouldBlock at once, like on Windows
+    fn wait_readable(&self, token: Option<&CancelToken>) -> std::io::Result<()> {
+        let generation = self.cancel.generation();
+        let vmin_set = self.settings.vmin.map(|v| v > 0).unwrap_or(self.settings.inter_byte_timeout.is_some());
+        if self.settings.blocking && (self.settings.read_timeout.is_some() || vmin_set) {
+            wait_fd(self.fd, PollFlags::POLLIN, self.settings.read_timeout, &self.cancel, generation, token, self.settings.retry_interrupted)?;
+        }
+        Ok(())
+    }
+
+    /// Number of bytes queued in the driver, not counting bytes read ahead by peek
+    fn queued_input(&self) -> SerialResult<usize> {
+        let mut bytes: i32 = 0;
+        unsafe {ioctl::tiocinq(self.fd, &mut bytes)?};
+        Ok(bytes as usize)
+    }
+
+    /// Reads the modem control bits with TIOCMGET
+    fn modem_bits(&self) -> SerialResult<libc::c_int> {
+        let mut bits: libc::c_int = 0;
+        unsafe { ioctl::tiocmget(self.fd, &mut bits) }?;
+        Ok(bits)
+    }
+
+    /// Reads from the port, dropping NUL bytes if [SerialPortSettings::discard_nul] is set.
+    /// Bytes read ahead by [SerialIo::peek] are returned first
+    fn read_filtered(&self, buf: &mut [u8], token: Option<&CancelToken>) -> std::io::Result<usize> {
+        self.peeked.read(buf, |buf| self.read_driver(buf, token))
+    }
+
+    /// Reads from the driver, dropping NUL bytes if [SerialPortSettings::discard_nul] is set
+    fn read_driver(&self, buf: &mut [u8], token: Option<&CancelToken>) -> std::io::Result<usize> {
+        loop {
+            let read = if self.settings.parity_error_policy != ParityErrorPolicy::Ignore {
+                self.read_marked(buf, token)?
+            } else {
+                self.wait_readable(token)?;
+                self.retry_interrupted(|| nix::unistd::read(self.fd, buf))?
+            };
+            if !self.settings.discard_nul || read == 0 {
+                return self.check_would_block(read, buf.len());
+            }
+            let mut kept = 0;
+            for i in 0..read {
+                if buf[i] != 0 {
+                    buf[kept] = buf[i];
+                    kept += 1;
+                }
+            }
+            // A read of only NULs must not look like a timeout or end of file
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+
+    /// Reads with PARMRK set, where the driver sends a byte received with an error as
+    /// `\377 \0 <byte>` and a real `\377` as `\377 \377`. Marked bytes are handled
+    /// according to the parity error policy, and a sequence split across reads is kept
+    /// until the rest arrives. Returns 0 only if the port returned nothing
+    fn read_marked(&self, buf: &mut [u8], token: Option<&CancelToken>) -> std::io::Result<usize> {
+        let mark = match self.settings.parity_error_policy {
+            ParityErrorPolicy::Mark(c) => Some(c),
+            _ => None,
+        };
+        loop {
+            {
+                let mut marked = self.lock_marked();
+                let (consumed, produced, error) = unmark(&marked, buf, mark);
+                marked.drain(..consumed);
+                if error {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Received a byte with a parity or framing error"));
+                }
+                if produced > 0 {
+                    return Ok(produced);
+                }
+            }
+            // The buffer is not locked whilst waiting, which would hold up clear_input_buffer
+            self.wait_readable(token)?;
+            let mut chunk = vec![0u8; buf.len()];
+            let read = self.retry_interrupted(|| nix::unistd::read(self.fd, &mut chunk))?;
+            if read == 0 {
+                return Ok(0);
+            }
+            self.lock_marked().extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    /// Locks the data left over by [TTYPort::read_marked]
+    fn lock_marked(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.marked.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs a system call, repeating it if it is interrupted by a signal and
+    /// [SerialPortSettings::retry_interrupted] is set
+    fn retry_interrupted<T, F: FnMut() -> nix::Result<T>>(&self, mut op: F) -> std::io::Result<T> {
+        loop {
+            match op() {
+                Err(Errno::EINTR) if self.settings.retry_interrupted => {}
+                res => return res.map_err(io::Error::from),
+            }
+        }
+    }
+
+    /// Waits until the port is writable, if a write could block
+    fn wait_writable(&self, token: Option<&CancelToken>) -> std::io::Result<()> {
+        let generation = self.cancel.generation();
+        if self.settings.blocking {
+            wait_fd(self.fd, PollFlags::POLLOUT, self.settings.write_timeout, &self.cancel, generation, token, self.settings.retry_interrupted)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if a write would not block
+    #[cfg(feature = "embedded-io")]
+    pub(crate) fn is_write_ready(&self) -> SerialResult<bool> {
+        let mut fds = [PollFd::new(self.fd, PollFlags::POLLOUT)];
+        Ok(nix::poll::poll(&mut fds, 0)? > 0)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn termios2(&self) -> SerialResult<libc::termios2> {
+        let mut attr: libc::termios2 = unsafe { std::mem::zeroed() };
+        unsafe { ioctl::tcgets2(self.fd, &mut attr) }?;
+        Ok(attr)
+    }
+
+    /// Sets a baud rate which has no Bxxx constant, using BOTHER
+    #[cfg(target_os = "linux")]
+    fn apply_custom_baud(&self) -> SerialResult<()> {
+        let mut attr = self.termios2()?;
+        attr.c_cflag &= !(libc::CBAUD | libc::CIBAUD);
+        attr.c_cflag |= libc::BOTHER;
+        attr.c_ispeed = self.settings.baud_rate;
+        attr.c_ospeed = self.settings.baud_rate;
+        unsafe { ioctl::tcsets2(self.fd, &attr) }?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn serial_struct(&self) -> SerialResult<ioctl::SerialStruct> {
+        let mut serial: ioctl::SerialStruct = unsafe { std::mem::zeroed() };
+        unsafe { ioctl::tiocgserial(self.fd, &mut serial) }?;
+        Ok(serial)
+    }
+
+    /// Sets or clears ASYNC_LOW_LATENCY. If low latency is not requested and the
+    /// driver does not support TIOCGSERIAL, this is silently skipped
+    #[cfg(target_os = "linux")]
+    fn apply_low_latency(&self) -> SerialResult<()> {
+        let mut serial = match self.serial_struct() {
+            Ok(serial) => serial,
+            Err(_) if !self.settings.low_latency => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let enabled = serial.flags & ioctl::ASYNC_LOW_LATENCY != 0;
+        if enabled != self.settings.low_latency {
+            serial.flags ^= ioctl::ASYNC_LOW_LATENCY;
+            unsafe { ioctl::tiocsserial(self.fd, &serial) }?;
+        }
+        Ok(())
+    }
+}
+
+/// UART details reported by the Linux serial driver, see [TTYPort::uart_info]
+#[cfg(target_os = "linux")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UartInfo {
+    /// UART type, a `PORT_` constant of linux/serial_core.h. See [UartInfo::uart_name]
+    pub uart_type: i32,
+    /// Line number of the port within its driver
+    pub line: i32,
+    /// I/O port base address, 0 for memory mapped and USB ports
+    pub port: u64,
+    /// Interrupt line, 0 if none
+    pub irq: i32,
+    /// Driver flags (`ASYNC_` constants of linux/tty_flags.h)
+    pub flags: i32,
+    /// Size of the transmit FIFO in bytes
+    pub xmit_fifo_size: i32,
+    /// UART clock divided by 16, the highest standard rate
+    pub baud_base: i32,
+    /// Divisor used in place of 38400 baud, see [SerialPortExt::set_custom_divisor]
+    pub custom_divisor: i32,
+}
+
+#[cfg(target_os = "linux")]
+impl UartInfo {
+    /// Returns the name of the UART type, if it is a common one
+    pub fn uart_name(&self) -> Option<&'static str> {
+        match self.uart_type {
+            1 => Some("8250"),
+            2 => Some("16450"),
+            3 => Some("16550"),
+            4 => Some("16550A"),
+            5 => Some("Cirrus"),
+            6 => Some("16650"),
+            7 => Some("16650V2"),
+            8 => Some("16750"),
+            9 => Some("Startech"),
+            10 => Some("16C950"),
+            11 => Some("16654"),
+            12 => Some("16850"),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the driver's ASYNC_LOW_LATENCY flag is set
+    pub fn low_latency(&self) -> bool {
+        self.flags & ioctl::ASYNC_LOW_LATENCY != 0
+    }
+}
+
+/// Raw termios types, for use with [SerialPortExt::with_termios]
+pub use nix::sys::termios;
+
+/// POSIX specific extensions for flags the portable [crate::SerialPort] API does not cover
+pub trait SerialPortExt {
+    /// Reads the live termios structure of the port, passes it to `f` for modification,
+    /// and applies the result immediately (TCSANOW) once `f` returns.
+    ///
+    /// Note that [Configurable::reconfigure_port] rebuilds the termios flags it manages,
+    /// so changes to those flags made here are overwritten by a later reconfigure
+    fn with_termios<F: FnOnce(&mut termios::Termios)>(&self, f: F) -> SerialResult<()>;
+    /// Enables or disables exclusive mode (TIOCEXCL / TIOCNXCL). Whilst enabled, further
+    /// opens of the device by non-root processes fail with EBUSY
+    fn set_exclusive(&self, exclusive: bool) -> SerialResult<()>;
+    /// Sets how long the driver holds received data before passing it on
+    /// (IOSSDATALAT), rounded to microseconds. The driver default makes
+    /// request/response protocols sluggish, and a zero latency passes on every byte
+    /// as it arrives. Only available on macOS
+    #[cfg(target_os = "macos")]
+    fn set_receive_latency(&self, latency: Duration) -> SerialResult<()>;
+    /// Sets whether another open of the device may take it over from this one
+    /// (IOSSPREEMPT). Only available on macOS
+    #[cfg(target_os = "macos")]
+    fn set_preemptible(&self, preemptible: bool) -> SerialResult<()>;
+    /// Drops DTR for `pulse` and raises it again (TIOCCDTR / TIOCSDTR), to wake or
+    /// reset devices which watch DTR. [SerialPortSettings::invert_dtr] is not applied.
+    /// Only available on macOS
+    #[cfg(target_os = "macos")]
+    fn pulse_dtr(&self, pulse: Duration) -> SerialResult<()>;
+    /// Sets or clears the driver's ASYNC_LOW_LATENCY flag, and
+    /// [SerialPortSettings::low_latency] so a later reconfigure keeps it. Only
+    /// available on Linux
+    #[cfg(target_os = "linux")]
+    fn set_low_latency(&mut self, enable: bool) -> SerialResult<()>;
+    /// Sets the legacy custom divisor (ASYNC_SPD_CUST), which the UART uses in place
+    /// of 38400 baud, giving a rate of [UartInfo::baud_base] / `divisor`. None
+    /// clears it. Prefer a custom [SerialPortSettings::baud], which most drivers
+    /// support directly. Only available on Linux
+    #[cfg(target_os = "linux")]
+    fn set_custom_divisor(&self, divisor: Option<u32>) -> SerialResult<()>;
+}
+
+impl SerialPortExt for TTYPort {
+    fn with_termios<F: FnOnce(&mut termios::Termios)>(&self, f: F) -> SerialResult<()> {
+        let mut attr = tcgetattr(self.fd)?;
+        f(&mut attr);
+        tcsetattr(self.fd, nix::sys::termios::SetArg::TCSANOW, &attr)?;
+        Ok(())
+    }
+
+    fn set_exclusive(&self, exclusive: bool) -> SerialResult<()> {
+        unsafe {
+            match exclusive {
+                true => ioctl::tiocexcl(self.fd),
+                false => ioctl::tiocnxcl(self.fd),
+            }
+        }?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn set_receive_latency(&self, latency: Duration) -> SerialResult<()> {
+        let micros = latency.as_micros().min(libc::c_ulong::MAX as u128) as libc::c_ulong;
+        unsafe { ioctl::iossdatalat(self.fd, &micros) }?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn set_preemptible(&self, preemptible: bool) -> SerialResult<()> {
+        let value = preemptible as libc::c_int;
+        unsafe { ioctl::iosspreempt(self.fd, &value) }?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn pulse_dtr(&self, pulse: Duration) -> SerialResult<()> {
+        unsafe { ioctl::tioccdtr(self.fd) }?;
+        std::thread::sleep(pulse);
+        unsafe { ioctl::tiocsdtr(self.fd) }?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_low_latency(&mut self, enable: bool) -> SerialResult<()> {
+        self.settings.low_latency = enable;
+        self.apply_low_latency()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_custom_divisor(&self, divisor: Option<u32>) -> SerialResult<()> {
+        let mut serial = self.serial_struct()?;
+        serial.flags &= !ioctl::ASYNC_SPD_MASK;
+        serial.custom_divisor = 0;
+        if let Some(divisor) = divisor {
+            if divisor == 0 || divisor > libc::c_int::MAX as u32 {
+                return Err(SerialError::LibraryError(format!("Custom divisor of {divisor} is unsupported")));
+            }
+            serial.flags |= ioctl::ASYNC_SPD_CUST;
+            serial.custom_divisor = divisor as libc::c_int;
+        }
+        unsafe { ioctl::tiocsserial(self.fd, &serial) }?;
+        Ok(())
+    }
+}
+
+impl AsRawFd for TTYPort {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl AsFd for TTYPort {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // The fd stays open for as long as self (and therefore owner) is alive
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+impl IntoRawFd for TTYPort {
+    /// Releases ownership of the fd.
+    ///
+    /// If other clones of this port are still alive, they keep the original fd and
+    /// a duplicate is returned instead, which the caller then owns.
+    ///
+    /// # Panics
+    /// Panics if the fd needs to be duplicated and `dup` fails
+    fn into_raw_fd(self) -> RawFd {
+        match Arc::try_unwrap(self.owner) {
+            Ok(owner) => {
+                let fd = owner.0;
+                std::mem::forget(owner);
+                fd
+            }
+            Err(_) => nix::unistd::dup(self.fd).expect("Failed to duplicate TTY fd"),
+        }
+    }
+}
+
+impl FromRawFd for TTYPort {
+    /// Takes ownership of an already open TTY fd.
+    ///
+    /// The port is not reconfigured, its settings are read back from the
+    /// driver (see [Configurable::current_settings])
+    ///
+    /// # Panics
+    /// Panics if the pipe used by [SerialIo::cancel_io] cannot be created
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        let path = std::fs::read_link(format!("/dev/fd/{fd}"))
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut port = TTYPort {
+            fd,
+            owner: Arc::new(FdOwner(fd)),
+            cancel: Arc::new(CancelPipe::new().expect("Failed to create cancel pipe")),
+            marked: Arc::default(),
+            peeked: Arc::default(),
+            settings: SerialPortSettings::default(),
+            path,
+        };
+        if let Ok(settings) = port.current_settings() {
+            port.settings = settings;
+        }
+        port
+    }
+}
+
+impl From<TTYPort> for OwnedFd {
+    fn from(port: TTYPort) -> Self {
+        unsafe { OwnedFd::from_raw_fd(port.into_raw_fd()) }
+    }
+}
+
+impl From<OwnedFd> for TTYPort {
+    fn from(fd: OwnedFd) -> Self {
+        unsafe { TTYPort::from_raw_fd(fd.into_raw_fd()) }
+    }
 }
 
 impl super::SerialPort for TTYPort {
+    fn close(self) -> crate::SerialResult<()> {
+        // The fd is closed by FdOwner once no other clones remain
+        drop(self);
+        Ok(())
+    }
+
+    fn try_clone(&mut self) -> crate::SerialResult<Box<dyn crate::SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+impl super::SerialIo for TTYPort {
+    fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn read_shared(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_filtered(buf, None)
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.peeked.peek(buf, self.queued_input()?, |chunk| self.read_driver(chunk, None))
+    }
+
+    fn write_shared(&self, buf: &[u8]) -> std::io::Result<usize> {
+        self.wait_writable(None)?;
+        self.retry_interrupted(|| nix::unistd::write(self.fd, buf))
+    }
+
+    fn poll_readable(&self, timeout: Option<Duration>) -> std::io::Result<bool> {
+        if self.peeked.len() > 0 || !self.lock_marked().is_empty() {
+            return Ok(true);
+        }
+        // wait_fd works in whole milliseconds, round up so short gaps are not cut to 0
+        let timeout = timeout.map(|t| t.as_micros().div_ceil(1000));
+        match wait_fd(self.fd, PollFlags::POLLIN, timeout, &self.cancel, self.cancel.generation(), None, self.settings.retry_interrupted) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn flush_shared(&self) -> std::io::Result<()> {
+        self.retry_interrupted(|| tcdrain(self.fd))
+    }
+
+    fn cancel_io(&self) -> SerialResult<()> {
+        self.cancel.cancel()
+    }
+
+    fn read_with_cancel(&self, buf: &mut [u8], token: &CancelToken) -> std::io::Result<usize> {
+        token.check()?;
+        self.read_filtered(buf, Some(token))
+    }
+
+    fn write_with_cancel(&self, buf: &[u8], token: &CancelToken) -> std::io::Result<usize> {
+        token.check()?;
+        self.wait_writable(Some(token))?;
+        self.retry_interrupted(|| nix::unistd::write(self.fd, buf))
+    }
+}
+
+impl super::ControlLines for TTYPort {
+    fn set_output_flow_control(&self, enable: bool) -> crate::SerialResult<()> {
+        match enable {
+            true => tcflow(self.fd, FlowArg::TCOON),
+            false =>  tcflow(self.fd, FlowArg::TCOOFF),
+        }?;
+        Ok(())
+    }
+
+    fn send_xon(&self) -> crate::SerialResult<()> {
+        // Sends the VSTART character set by reconfigure_port, ahead of queued output
+        tcflow(self.fd, FlowArg::TCION)?;
+        Ok(())
+    }
+
+    fn send_xoff(&self) -> crate::SerialResult<()> {
+        tcflow(self.fd, FlowArg::TCIOFF)?;
+        Ok(())
+    }
+
+    fn set_data_terminal_ready(&self, enable: bool) -> crate::SerialResult<()> {
+        unsafe { 
+            match enable != self.settings.invert_dtr {
+                true => ioctl::tiocmbis(self.fd, &libc::TIOCM_DTR),
+                false => ioctl::tiocmbic(self.fd, &libc::TIOCM_DTR)
+            }
+        }?;
+        Ok(())
+    }
+
+    fn set_request_to_send(&self, enable: bool) -> crate::SerialResult<()> {
+        unsafe { 
+            match enable != self.settings.invert_rts {
+                true => ioctl::tiocmbis(self.fd, &libc::TIOCM_RTS),
+                false => ioctl::tiocmbic(self.fd, &libc::TIOCM_RTS)
+            }
+        }?;
+        Ok(())
+    }
+
+    fn set_break_state(&self, enable: bool) -> crate::SerialResult<()> {
+        unsafe { 
+            match enable {
+                true => ioctl::tiocsbrk(self.fd),
+                false => ioctl::tioccbrk(self.fd)
+            }
+        }?;
+        Ok(())
+    }
+
+    fn get_data_terminal_ready(&self) -> crate::SerialResult<bool> {
+        Ok((self.modem_bits()? & libc::TIOCM_DTR != 0) != self.settings.invert_dtr)
+    }
+
+    fn get_request_to_send(&self) -> crate::SerialResult<bool> {
+        Ok((self.modem_bits()? & libc::TIOCM_RTS != 0) != self.settings.invert_rts)
+    }
+
+    fn set_loopback(&self, enable: bool) -> crate::SerialResult<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let res = unsafe {
+                match enable {
+                    true => ioctl::tiocmbis(self.fd, &ioctl::TIOCM_LOOP),
+                    false => ioctl::tiocmbic(self.fd, &ioctl::TIOCM_LOOP),
+                }
+            };
+            match res {
+                Ok(_) => Ok(()),
+                Err(Errno::EINVAL | Errno::ENOTTY | Errno::EOPNOTSUPP) => {
+                    Err(SerialError::Unsupported("Driver does not support internal loopback".to_string()))
+                }
+                Err(e) => Err(e.into()),
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = enable;
+            Err(SerialError::Unsupported("Internal loopback is unsupported on this platform".to_string()))
+        }
+    }
+
+    fn modem_status(&self) -> crate::SerialResult<ModemStatus> {
+        let bits = self.modem_bits()?;
+        let mut status = ModemStatus::empty();
+        for (bit, flag) in [
+            (libc::TIOCM_CTS, ModemStatus::CTS),
+            (libc::TIOCM_DSR, ModemStatus::DSR),
+            (libc::TIOCM_RI, ModemStatus::RI),
+            (libc::TIOCM_CD, ModemStatus::CD),
+        ] {
+            status.set(flag, bits & bit != 0);
+        }
+        Ok(status)
+    }
+}
+
+impl super::Configurable for TTYPort {
     fn setting(&mut self) -> &mut SerialPortSettings{
         &mut self.settings
     }
+
+    fn settings(&self) -> &SerialPortSettings {
+        &self.settings
+    }
+
     fn reconfigure_port(&mut self) -> crate::SerialResult<()> {
+        // termios has no DSR/DTR handshaking, and silently running without flow
+        // control would lose data
+        if self.settings.flow_control == FlowControl::DsrDtr {
+            return Err(SerialError::Unsupported("DSR/DTR flow control is not supported on POSIX".to_string()));
+        }
+        let (vmin, vtime) = vmin_vtime(&self.settings)?;
         flock(self.fd, FlockArg::Unlock)?;
-        let mut vmin: u128 = 0;
-        let mut vtime: u128 = 0;
+        let driver_attr = tcgetattr(self.fd)?;
+        let mut orig_attr = driver_attr.clone();
 
-        if let Some(timeout) = self.settings.inter_byte_timeout {
-            vmin = 1;
-            vtime = timeout*10;
-        }
-        let mut orig_attr = tcgetattr(self.fd)?;
+        match self.settings.termios_profile {
+            TermiosProfile::Raw | TermiosProfile::RawKeepFlow => cfmakeraw(&mut orig_attr),
+            TermiosProfile::Custom => {
+                orig_attr.local_flags &= !(
+                    LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ECHOE |
+                    LocalFlags::ECHOK | LocalFlags::ECHONL | LocalFlags::ISIG |
+                    LocalFlags::IEXTEN
+                );
 
-        orig_attr.control_flags |= ControlFlags::CLOCAL | ControlFlags::CREAD;
-        orig_attr.local_flags &= !(
-            LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ECHOE |
-            LocalFlags::ECHOK | LocalFlags::ECHONL | LocalFlags::ISIG |
-            LocalFlags::IEXTEN
-        );
+                for flag in [LocalFlags::ECHOCTL, LocalFlags::ECHOKE] {
+                    orig_attr.local_flags &= !flag;
+                }
 
-        for flag in [LocalFlags::ECHOCTL, LocalFlags::ECHOKE] {
-            orig_attr.local_flags &= !flag;
+                orig_attr.output_flags &= !(OutputFlags::OPOST | OutputFlags::ONLCR | OutputFlags::OCRNL);
+                orig_attr.input_flags &= !(InputFlags::INLCR | InputFlags::IGNCR | InputFlags::ICRNL | InputFlags::IGNBRK);
+            }
         }
-
-        orig_attr.output_flags &= !(OutputFlags::OPOST | OutputFlags::ONLCR | OutputFlags::OCRNL);
-        orig_attr.input_flags &= !(InputFlags::INLCR | InputFlags::IGNCR | InputFlags::ICRNL | InputFlags::IGNBRK);
-        if orig_attr.input_flags.contains(InputFlags::PARMRK) {
-            orig_attr.input_flags &= !InputFlags::PARMRK;
+        orig_attr.control_flags |= ControlFlags::CLOCAL | ControlFlags::CREAD;
+        orig_attr.input_flags &= !InputFlags::PARMRK;
+        // Without IGNBRK (or BRKINT), a break is read as a NUL byte
+        if self.settings.discard_nul {
+            orig_attr.input_flags |= InputFlags::IGNBRK;
+        }
+        for (processing, flag) in [
+            (InputProcessing::IGNBRK, InputFlags::IGNBRK),
+            (InputProcessing::BRKINT, InputFlags::BRKINT),
+            (InputProcessing::IGNCR, InputFlags::IGNCR),
+            (InputProcessing::ICRNL, InputFlags::ICRNL),
+            (InputProcessing::INLCR, InputFlags::INLCR),
+            (InputProcessing::ISTRIP, InputFlags::ISTRIP),
+        ] {
+            if self.settings.input_processing.contains(processing) {
+                orig_attr.input_flags |= flag;
+            }
         }
+        // Rates without a Bxxx constant are set through termios2 once everything
+        // else has been applied
         #[cfg(target_os="linux")]
-        {
-            let baud = match self.settings.baud_rate {
-                50 => BaudRate::B50,
-                75 => BaudRate::B75,
-                110 => BaudRate::B110,
-                134 => BaudRate::B134,
-                150 => BaudRate::B150,
-                200 => BaudRate::B200,
-                300 => BaudRate::B300,
-                600 => BaudRate::B600,
-                1200 => BaudRate::B1200,
-                1800 => BaudRate::B1800,
-                2400 => BaudRate::B2400,
-                4800 => BaudRate::B4800,
-                9600 => BaudRate::B9600,
-                19_200 => BaudRate::B19200,
-                38_400 => BaudRate::B38400,
-                57_600 => BaudRate::B57600,
-                115_200 => BaudRate::B115200,
-                230_400 => BaudRate::B230400,
-                460_800 => BaudRate::B460800, 
-                500_000 => BaudRate::B500000,
-                576_000 => BaudRate::B576000,
-                921_600 => BaudRate::B921600,
-                1_000_000 => BaudRate::B1000000,
-                1_152_000 => BaudRate::B1152000,
-                1_500_000 => BaudRate::B1500000,
-                2_000_000 => BaudRate::B2000000,
-                2_500_000 => BaudRate::B2500000,
-                3_000_000 => BaudRate::B3000000,
-                3_500_000 => BaudRate::B3500000,
-                4_000_000 => BaudRate::B4000000,
-                _ => return Err(SerialError::LibraryError(format!("Baud rate {} is unsupported on NIX", self.settings.baud_rate)))
-            };
+        let custom_baud = {
+            let baud = baud_rate_to_nix(self.settings.baud_rate);
 
             // Set baudrate
-            cfsetispeed(&mut orig_attr, baud)?;
-            cfsetospeed(&mut orig_attr, baud)?;
-        }
+            cfsetispeed(&mut orig_attr, baud.unwrap_or(BaudRate::B38400))?;
+            cfsetospeed(&mut orig_attr, baud.unwrap_or(BaudRate::B38400))?;
+            baud.is_none()
+        };
 
+        orig_attr.control_flags &= !ControlFlags::CSIZE;
         orig_attr.control_flags |= match self.settings.byte_size {
             crate::ByteSize::Five => ControlFlags::CS5,
             crate::ByteSize::Six => ControlFlags::CS6,
@@ -132,10 +848,17 @@ impl super::SerialPort for TTYPort {
         match self.settings.stop_bits {
             crate::StopBits::One => orig_attr.control_flags &= !(ControlFlags::CSTOPB),
             crate::StopBits::Two => orig_attr.control_flags |= ControlFlags::CSTOPB,
-            crate::StopBits::OnePointFive => { return Err(SerialError::LibraryError(format!("1.5 stop bits is unsupported on NIX"))) },
+            crate::StopBits::OnePointFive => { return Err(SerialError::LibraryError("1.5 stop bits is unsupported on NIX".to_string())) },
         };
 
-        orig_attr.input_flags &= !(InputFlags::INPCK | InputFlags::ISTRIP);
+        orig_attr.input_flags &= !InputFlags::INPCK;
+        if !self.settings.input_processing.contains(InputProcessing::ISTRIP) {
+            orig_attr.input_flags &= !InputFlags::ISTRIP;
+        }
+        if self.settings.parity_error_policy != ParityErrorPolicy::Ignore {
+            orig_attr.input_flags |= InputFlags::INPCK | InputFlags::PARMRK;
+            orig_attr.input_flags &= !InputFlags::IGNPAR;
+        }
         // Parity
 
         #[cfg(not(target_os="macos"))]
@@ -156,7 +879,7 @@ impl super::SerialPort for TTYPort {
 
         // Flow control type
         match self.settings.flow_control {
-            crate::FlowControl::None | crate::FlowControl::DsrDtr => { // DSR/DTR is not supported on UNIX, use no FC in that case
+            crate::FlowControl::None | crate::FlowControl::DsrDtr => {
                 orig_attr.input_flags &= !(InputFlags::IXON | InputFlags::IXOFF | InputFlags::IXANY);
                 orig_attr.control_flags &= !(ControlFlags::CRTSCTS)
             },
@@ -170,93 +893,196 @@ impl super::SerialPort for TTYPort {
             },
         };
 
-        if vmin > 255 {
-            return Err(SerialError::LibraryError(format!("VMIN of {vmin} is unsupported")));
-        }
-        orig_attr.control_chars[SpecialCharacterIndices::VMIN as usize] = vmin as u8;
-        
-        if vtime > 255 {
-            return Err(SerialError::LibraryError(format!("VTIME of {vtime} is unsupported")));
+        orig_attr.control_chars[SpecialCharacterIndices::VSTART as usize] = self.settings.xon_char;
+        orig_attr.control_chars[SpecialCharacterIndices::VSTOP as usize] = self.settings.xoff_char;
+
+        if self.settings.termios_profile == TermiosProfile::RawKeepFlow {
+            let input = InputFlags::IXON | InputFlags::IXOFF | InputFlags::IXANY;
+            orig_attr.input_flags = (orig_attr.input_flags & !input) | (driver_attr.input_flags & input);
+            orig_attr.control_flags.set(ControlFlags::CRTSCTS, driver_attr.control_flags.contains(ControlFlags::CRTSCTS));
+            for index in [SpecialCharacterIndices::VSTART, SpecialCharacterIndices::VSTOP] {
+                orig_attr.control_chars[index as usize] = driver_attr.control_chars[index as usize];
+            }
         }
-        orig_attr.control_chars[SpecialCharacterIndices::VTIME as usize] = vtime as u8;
+
+        orig_attr.control_chars[SpecialCharacterIndices::VMIN as usize] = vmin;
+        orig_attr.control_chars[SpecialCharacterIndices::VTIME as usize] = vtime;
         tcsetattr(self.fd, nix::sys::termios::SetArg::TCSANOW, &orig_attr)?;
+
+        #[cfg(target_os="linux")]
+        if custom_baud {
+            self.apply_custom_baud()?;
+        }
         
         #[cfg(target_os="macos")]
         {
             ioctl::iossiospeed(self.fd, &(self.settings.baud_rate as libc::speed_t))?;
         }
+
+        #[cfg(target_os="linux")]
+        self.apply_low_latency()?;
         Ok(())
     }
 
-    fn close(self) -> crate::SerialResult<()> {
-        unsafe {
-            close(self.fd);
+    fn reconfigure_port_live(&mut self) -> SerialResult<()> {
+        let current = self.current_settings()?;
+        if current.baud_rate == self.settings.baud_rate && current.byte_size == self.settings.byte_size
+            && current.parity == self.settings.parity && current.stop_bits == self.settings.stop_bits {
+            return Ok(());
         }
-        Ok(())
-    }
+        if self.settings.stop_bits == crate::StopBits::OnePointFive {
+            return Err(SerialError::LibraryError("1.5 stop bits is unsupported on NIX".to_string()));
+        }
+        // Let queued output go out at the old settings
+        tcdrain(self.fd)?;
+        let mut attr = tcgetattr(self.fd)?;
 
-    fn set_buffer_size(&mut self, _rx_size: usize, _tx_size: usize) -> crate::SerialResult<()> {
-        Ok(())
-    }
+        #[cfg(target_os="linux")]
+        let custom_baud = {
+            let baud = baud_rate_to_nix(self.settings.baud_rate);
+            cfsetispeed(&mut attr, baud.unwrap_or(BaudRate::B38400))?;
+            cfsetospeed(&mut attr, baud.unwrap_or(BaudRate::B38400))?;
+            baud.is_none()
+        };
 
-    fn set_output_flow_control(&self, enable: bool) -> crate::SerialResult<()> {
-        match enable {
-            true => tcflow(self.fd, FlowArg::TCOON),
-            false =>  tcflow(self.fd, FlowArg::TCOOFF),
-        }?;
-        Ok(())
-    }
+        attr.control_flags &= !(ControlFlags::CSIZE | ControlFlags::CSTOPB | ControlFlags::PARENB | ControlFlags::PARODD);
+        #[cfg(not(target_os="macos"))]
+        {
+            attr.control_flags &= !(ControlFlags::CMSPAR);
+        }
+        attr.control_flags |= match self.settings.byte_size {
+            crate::ByteSize::Five => ControlFlags::CS5,
+            crate::ByteSize::Six => ControlFlags::CS6,
+            crate::ByteSize::Seven => ControlFlags::CS7,
+            crate::ByteSize::Eight => ControlFlags::CS8,
+        };
+        if self.settings.stop_bits == crate::StopBits::Two {
+            attr.control_flags |= ControlFlags::CSTOPB;
+        }
+        match self.settings.parity {
+            crate::Parity::None => {},
+            crate::Parity::Even => attr.control_flags |= ControlFlags::PARENB,
+            crate::Parity::Odd => attr.control_flags |= ControlFlags::PARENB | ControlFlags::PARODD,
+        };
+        // TCSAFLUSH would discard received data, and the output is already drained
+        tcsetattr(self.fd, nix::sys::termios::SetArg::TCSANOW, &attr)?;
 
-    fn set_data_terminal_ready(&mut self, enable: bool) -> crate::SerialResult<()> {
-        unsafe { 
-            match enable {
-                true => ioctl::tiocmbis(self.fd, &libc::TIOCM_DTR),
-                false => ioctl::tiocmbic(self.fd, &libc::TIOCM_DTR)
-            }
-        }?;
-        Ok(())
-    }
+        #[cfg(target_os="linux")]
+        if custom_baud {
+            self.apply_custom_baud()?;
+        }
 
-    fn set_request_to_send(&mut self, enable: bool) -> crate::SerialResult<()> {
-        unsafe { 
-            match enable {
-                true => ioctl::tiocmbis(self.fd, &libc::TIOCM_RTS),
-                false => ioctl::tiocmbic(self.fd, &libc::TIOCM_RTS)
-            }
-        }?;
+        #[cfg(target_os="macos")]
+        {
+            ioctl::iossiospeed(self.fd, &(self.settings.baud_rate as libc::speed_t))?;
+        }
         Ok(())
     }
 
-    fn set_break_state(&mut self, enable: bool) -> crate::SerialResult<()> {
-        unsafe { 
-            match enable {
-                true => ioctl::tiocsbrk(self.fd),
-                false => ioctl::tioccbrk(self.fd)
-            }
-        }?;
-        Ok(())
+    fn set_blocking(&mut self, blocking: bool) -> SerialResult<()> {
+        self.settings.blocking = blocking;
+        self.apply_blocking()
     }
 
-    fn read_clear_to_send(&self) -> crate::SerialResult<bool> {
-        Ok(unsafe { ioctl::tiocmget(self.fd, &mut 0) }? & libc::TIOCM_CTS != 0)
+    fn debug_dump(&self) -> SerialResult<DriverConfigDump> {
+        let attr = tcgetattr(self.fd)?;
+        let mut dump = DriverConfigDump::default();
+        dump.push("c_iflag", attr.input_flags);
+        dump.push("c_oflag", attr.output_flags);
+        dump.push("c_cflag", attr.control_flags);
+        dump.push("c_lflag", attr.local_flags);
+        #[cfg(target_os = "linux")]
+        {
+            let attr2 = self.termios2()?;
+            dump.push("ispeed", attr2.c_ispeed);
+            dump.push("ospeed", attr2.c_ospeed);
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            dump.push("ispeed", nix::sys::termios::cfgetispeed(&attr));
+            dump.push("ospeed", nix::sys::termios::cfgetospeed(&attr));
+        }
+        dump.push("VMIN", attr.control_chars[SpecialCharacterIndices::VMIN as usize]);
+        dump.push("VTIME", attr.control_chars[SpecialCharacterIndices::VTIME as usize]);
+        dump.push("VSTART", attr.control_chars[SpecialCharacterIndices::VSTART as usize]);
+        dump.push("VSTOP", attr.control_chars[SpecialCharacterIndices::VSTOP as usize]);
+        Ok(dump)
     }
 
-    fn read_data_set_ready(&self) -> crate::SerialResult<bool> {
-        Ok(unsafe { ioctl::tiocmget(self.fd, &mut 0) }? & libc::TIOCM_DSR != 0)
+    fn current_settings(&self) -> SerialResult<SerialPortSettings> {
+        let attr = tcgetattr(self.fd)?;
+        let mut settings = self.settings;
+
+        // termios2 reports the actual rate, including custom ones which
+        // cfgetospeed cannot represent
+        #[cfg(target_os = "linux")]
+        {
+            settings.baud_rate = self.termios2()?.c_ospeed;
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            settings.baud_rate = nix::sys::termios::cfgetospeed(&attr) as u32;
+        }
+
+        let size = attr.control_flags & ControlFlags::CSIZE;
+        settings.byte_size = if size == ControlFlags::CS5 {
+            crate::ByteSize::Five
+        } else if size == ControlFlags::CS6 {
+            crate::ByteSize::Six
+        } else if size == ControlFlags::CS7 {
+            crate::ByteSize::Seven
+        } else {
+            crate::ByteSize::Eight
+        };
+
+        settings.stop_bits = match attr.control_flags.contains(ControlFlags::CSTOPB) {
+            true => crate::StopBits::Two,
+            false => crate::StopBits::One,
+        };
+
+        settings.parity = if !attr.control_flags.contains(ControlFlags::PARENB) {
+            crate::Parity::None
+        } else if attr.control_flags.contains(ControlFlags::PARODD) {
+            crate::Parity::Odd
+        } else {
+            crate::Parity::Even
+        };
+
+        settings.flow_control = if attr.control_flags.contains(ControlFlags::CRTSCTS) {
+            FlowControl::RtsCts
+        } else if attr.input_flags.contains(InputFlags::IXON | InputFlags::IXOFF) {
+            FlowControl::XonXoff
+        } else {
+            FlowControl::None
+        };
+        settings.xon_char = attr.control_chars[SpecialCharacterIndices::VSTART as usize];
+        settings.xoff_char = attr.control_chars[SpecialCharacterIndices::VSTOP as usize];
+        if !attr.input_flags.contains(InputFlags::PARMRK) {
+            settings.parity_error_policy = ParityErrorPolicy::Ignore;
+        }
+        Ok(settings)
     }
 
-    fn read_ring_indicator(&self) -> crate::SerialResult<bool> {
-        Ok(unsafe { ioctl::tiocmget(self.fd, &mut 0) }? & libc::TIOCM_RI != 0)
+    fn verify_settings(&self) -> SerialResult<Vec<SettingMismatch>> {
+        Ok(self.settings.diff(&self.current_settings()?))
     }
+}
 
-    fn read_carrier_detect(&self) -> crate::SerialResult<bool> {
-        Ok(unsafe { ioctl::tiocmget(self.fd, &mut 0) }? & libc::TIOCM_CD != 0)
+impl super::BufferControl for TTYPort {
+    fn set_buffer_size(&mut self, _rx_size: usize, _tx_size: usize) -> crate::SerialResult<()> {
+        Ok(())
     }
 
+    /// Bytes still in the driver are counted as received, so with a parity error
+    /// policy or [SerialPortSettings::discard_nul] set this can be more than a read
+    /// returns, as PARMRK escapes and NULs have not been removed from them yet
     fn bytes_to_read(&self) -> crate::SerialResult<usize> {
-        let mut bytes: i32 = 0;
-        unsafe {ioctl::tiocinq(self.fd, &mut bytes)?};
-        Ok(bytes as usize)
+        let mark = match self.settings.parity_error_policy {
+            ParityErrorPolicy::Mark(c) => Some(c),
+            _ => None,
+        };
+        let marked = unmarked_len(&self.lock_marked(), mark);
+        Ok(self.queued_input()? + marked + self.peeked.len())
     }
 
     fn bytes_to_write(&self) -> crate::SerialResult<usize> {
@@ -265,105 +1091,360 @@ impl super::SerialPort for TTYPort {
         Ok(bytes as usize)
     }
 
-    fn get_path(&self) -> String {
-        self.path.clone()
-    }
-
-    fn try_clone(&mut self) -> crate::SerialResult<Box<dyn crate::SerialPort>> {
-        Ok(Box::new(TTYPort {
-            fd: fcntl(self.fd, fcntl::F_DUPFD(self.fd))?,
-            settings: self.settings.clone(),
-            path: self.path.clone()
-        }))
+    fn queue_status(&self) -> SerialResult<QueueStatus> {
+        Ok(QueueStatus {
+            input: self.bytes_to_read()?,
+            output: self.bytes_to_write()?,
+            errors: crate::LineErrors::empty(),
+        })
     }
 
-    fn clear_input_buffer(&mut self) -> SerialResult<()> {
+    fn clear_input_buffer(&self) -> SerialResult<()> {
         tcflush(self.fd, nix::sys::termios::FlushArg::TCIFLUSH)?;
+        self.lock_marked().clear();
+        self.peeked.clear();
         Ok(())
     }
 
-    fn clear_output_buffer(&mut self) -> SerialResult<()> {
-        tcflush(self.fd, nix::sys::termios::FlushArg::TCIOFLUSH)?;
+    fn clear_output_buffer(&self) -> SerialResult<()> {
+        tcflush(self.fd, nix::sys::termios::FlushArg::TCOFLUSH)?;
         Ok(())
     }
+
+    fn drain(&self) -> SerialResult<()> {
+        self.flush_shared().map_err(SerialError::IoError)
+    }
+
+    fn wait_for_bytes(&self, n: usize, timeout: Option<Duration>) -> SerialResult<bool> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            let queued = self.bytes_to_read()?;
+            if queued >= n {
+                return Ok(true);
+            }
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            if remaining == Some(Duration::ZERO) {
+                return Ok(false);
+            }
+            if queued == 0 {
+                self.poll_readable(remaining).map_err(SerialError::IoError)?;
+            } else {
+                // poll only reports that something is readable, so sleep for roughly
+                // the time the missing bytes take to arrive before checking again
+                let mut wait = self.settings.char_duration().mul_f64((n - queued) as f64).max(Duration::from_millis(1));
+                if let Some(r) = remaining {
+                    wait = wait.min(r);
+                }
+                std::thread::sleep(wait);
+            }
+        }
+    }
+
+    fn line_error_counts(&self) -> SerialResult<LineErrorCounts> {
+        #[cfg(target_os = "linux")]
+        {
+            let mut count: ioctl::SerialIcounter = unsafe { std::mem::zeroed() };
+            unsafe { ioctl::tiocgicount(self.fd, &mut count) }?;
+            Ok(LineErrorCounts {
+                framing: count.frame as u32,
+                parity: count.parity as u32,
+                overrun: count.overrun as u32,
+                buffer_overrun: count.buf_overrun as u32,
+                breaks: count.brk as u32,
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Err(SerialError::LibraryError("Line error counters are unsupported on this platform".to_string()))
+        }
+    }
 }
 
 
 impl std::io::Read for TTYPort {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if let Some(timeout) = self.settings.read_timeout {
-            wait_fd(self.fd, PollFlags::POLLIN, timeout)?;
+        (&*self).read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> std::io::Result<usize> {
+        (&*self).read_vectored(bufs)
+    }
+}
+
+/// Reads through a shared reference, like `&TcpStream`, so one thread can read
+/// whilst another writes to the same port
+impl std::io::Read for &TTYPort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_shared(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> std::io::Result<usize> {
+        if self.settings.discard_nul || self.settings.parity_error_policy != ParityErrorPolicy::Ignore || self.peeked.len() > 0 {
+            // Filtering, and returning peeked bytes, needs one contiguous buffer
+            return match bufs.iter_mut().find(|b| !b.is_empty()) {
+                Some(buf) => self.read_shared(buf),
+                None => Ok(0),
+            };
         }
-        nix::unistd::read(self.fd, buf).map_err(|e| {
-            std::io::Error::new(io::ErrorKind::Other, format!("Read failed {}", e))
-        })
+        self.wait_readable(None)?;
+        // IoSliceMut is guaranteed to be ABI compatible with iovec on unix
+        let read = self.retry_interrupted(|| {
+            let res = unsafe {
+                libc::readv(self.fd, bufs.as_ptr() as *const libc::iovec, bufs.len().min(libc::c_int::MAX as usize) as libc::c_int)
+            };
+            Errno::result(res).map(|r| r as usize)
+        })?;
+        self.check_would_block(read, bufs.iter().map(|b| b.len()).sum())
     }
 }
 
 impl std::io::Write for TTYPort {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        if let Some(timeout) = self.settings.write_timeout {
-            wait_fd(self.fd, PollFlags::POLLOUT, timeout)?;
-        }
-        nix::unistd::write(self.fd, buf).map_err(|e| {
-            std::io::Error::new(io::ErrorKind::Other, format!("Write failed {}", e))
+        (&*self).write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> std::io::Result<usize> {
+        (&*self).write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+/// Writes through a shared reference. See the `Read` implementation for `&TTYPort`
+impl std::io::Write for &TTYPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_shared(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> std::io::Result<usize> {
+        self.wait_writable(None)?;
+        // IoSlice is guaranteed to be ABI compatible with iovec on unix
+        self.retry_interrupted(|| {
+            let res = unsafe {
+                libc::writev(self.fd, bufs.as_ptr() as *const libc::iovec, bufs.len().min(libc::c_int::MAX as usize) as libc::c_int)
+            };
+            Errno::result(res).map(|r| r as usize)
         })
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        tcdrain(self.fd)?;
-        Ok(())
+        self.flush_shared()
+    }
+}
+
+/// Self-pipe which wakes up threads blocked in poll when [SerialIo::cancel_io] is called.
+///
+/// Each blocking operation records the generation when it starts. A cancel bumps the
+/// generation and writes to the pipe, so every operation which started before the cancel
+/// fails, whilst operations started afterwards just drain the stale wakeup byte
+#[derive(Debug)]
+struct CancelPipe {
+    read: RawFd,
+    write: RawFd,
+    generation: AtomicU64,
+}
+
+impl CancelPipe {
+    fn new() -> SerialResult<Self> {
+        let (read, write) = nix::unistd::pipe()?;
+        let pipe = Self { read, write, generation: AtomicU64::new(0) };
+        for fd in [read, write] {
+            fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        }
+        Ok(pipe)
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn cancel(&self) -> SerialResult<()> {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        match nix::unistd::write(self.write, &[0]) {
+            // Pipe already full of wakeups
+            Ok(_) | Err(Errno::EAGAIN) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn drain(&self) {
+        let mut buf = [0u8; 64];
+        while let Ok(n) = nix::unistd::read(self.read, &mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
     }
 }
 
-impl Drop for TTYPort {
+impl Drop for CancelPipe {
     fn drop(&mut self) {
         unsafe {
-            close(self.fd);
+            close(self.read);
+            close(self.write);
+        }
+    }
+}
+
+/// Finds the sysfs directory of the USB device behind the tty at `path`, by walking
+/// up its device directory to the one with `busnum` and `devnum` attributes. Returns
+/// the directory with the bus and device numbers
+#[cfg(target_os = "linux")]
+fn usb_device_dir(path: &str) -> SerialResult<(std::path::PathBuf, u32, u32)> {
+    let real_path = std::fs::canonicalize(path).unwrap_or_else(|_| path.into());
+    let dev_name = real_path.file_name().and_then(|n| n.to_str()).unwrap_or(path);
+    let not_usb = || SerialError::LibraryError(format!("{path} is not a USB device"));
+    let device = std::fs::canonicalize(format!("/sys/class/tty/{dev_name}/device")).map_err(|_| not_usb())?;
+    for dir in device.ancestors() {
+        let read = |attr: &str| std::fs::read_to_string(dir.join(attr)).ok().and_then(|s| s.trim().parse::<u32>().ok());
+        if let (Some(bus), Some(dev)) = (read("busnum"), read("devnum")) {
+            return Ok((dir.to_path_buf(), bus, dev));
+        }
+    }
+    Err(not_usb())
+}
+
+/// Finds the usbfs node of the USB device behind the tty at `path`
+#[cfg(target_os = "linux")]
+fn usbfs_node(path: &str) -> SerialResult<std::path::PathBuf> {
+    let (_, bus, dev) = usb_device_dir(path)?;
+    Ok(format!("/dev/bus/usb/{bus:03}/{dev:03}").into())
+}
+
+/// Gets VMIN and VTIME for the settings. The inter-byte timeout sets VMIN to 1 and
+/// VTIME to the timeout in tenths of a second, rounded up, unless either is overridden
+fn vmin_vtime(settings: &SerialPortSettings) -> SerialResult<(u8, u8)> {
+    let (mut vmin, mut vtime) = (0, 0);
+    if let Some(timeout) = settings.inter_byte_timeout {
+        vmin = 1;
+        vtime = u8::try_from(timeout.div_ceil(100)).map_err(|_| {
+            SerialError::LibraryError(format!("Inter-byte timeout of {timeout}ms is over the 25.5s VTIME allows"))
+        })?;
+    }
+    Ok((settings.vmin.unwrap_or(vmin), settings.vtime.unwrap_or(vtime)))
+}
+
+/// Decodes PARMRK marked data from `data` into `out`, replacing bytes received with
+/// an error by `mark`. Returns the number of bytes of `data` consumed and written to
+/// `out`, and whether decoding stopped at an error because `mark` is None. An
+/// incomplete marker at the end of `data` is left unconsumed
+fn unmark(data: &[u8], out: &mut [u8], mark: Option<u8>) -> (usize, usize, bool) {
+    let (mut i, mut n) = (0, 0);
+    while i < data.len() && n < out.len() {
+        match (data[i], data.get(i + 1), data.get(i + 2)) {
+            (0xFF, None, _) | (0xFF, Some(0), None) => break,
+            (0xFF, Some(0xFF), _) => {
+                out[n] = 0xFF;
+                i += 2;
+            }
+            (0xFF, Some(0), Some(_)) => match mark {
+                Some(c) => {
+                    out[n] = c;
+                    i += 3;
+                }
+                // Bytes before the error are returned first
+                None if n > 0 => break,
+                None => return (i + 3, 0, true),
+            },
+            (b, _, _) => {
+                out[n] = b;
+                i += 1;
+            }
         }
+        n += 1;
+    }
+    (i, n, false)
+}
+
+/// Number of reads [unmark] gets from `data`, counting each errored byte as one
+fn unmarked_len(data: &[u8], mark: Option<u8>) -> usize {
+    let mut scratch = [0u8; 256];
+    let (mut i, mut n) = (0, 0);
+    loop {
+        let (consumed, produced, error) = unmark(&data[i..], &mut scratch, mark);
+        if consumed == 0 {
+            return n;
+        }
+        i += consumed;
+        n += produced + error as usize;
     }
 }
 
 /// From Serialport-rs
-fn wait_fd(fd: RawFd, events: PollFlags, timeout: u128) -> std::io::Result<()> {
+///
+/// Waits for `events` on `fd`, or until `timeout` (ms) expires. If `timeout` is None,
+/// waits forever. Returns a cancelled error if `cancel` fired after `generation`, or if `token` fires
+fn wait_fd(fd: RawFd, events: PollFlags, timeout: Option<u128>, cancel: &CancelPipe, generation: u64, token: Option<&CancelToken>, retry_interrupted: bool) -> std::io::Result<()> {
     use nix::errno::Errno::{EIO, EPIPE};
 
-    let mut fd = PollFd::new(fd, events);
+    let deadline = timeout.map(|t| Instant::now() + Duration::from_millis(t as u64));
+    loop {
+        if cancel.generation() != generation {
+            return Err(SerialError::cancelled_io());
+        }
+        // poll ignores negative fds, so the token slot is unused without a token
+        let mut fds = [
+            PollFd::new(fd, events),
+            PollFd::new(cancel.read, PollFlags::POLLIN),
+            PollFd::new(token.map(|t| t.fd()).unwrap_or(-1), PollFlags::POLLIN),
+        ];
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
 
-    #[cfg(target_os = "linux")]
-    let wait_res = {
-        let timespec = TimeSpec::from_duration(std::time::Duration::from_millis(timeout as u64));
-        nix::poll::ppoll(slice::from_mut(&mut fd), Some(timespec), SigSet::empty())
-    };
-
-    #[cfg(not(target_os = "linux"))]
-    let wait_res = nix::poll::poll(slice::from_mut(&mut fd), timeout as nix::libc::c_int);
-
-    let wait = match wait_res {
-        Ok(r) => r,
-        Err(e) => {return Err(io::Error::new(
-            io::ErrorKind::TimedOut,
-            format!("Operation failed {}", e),
-        ))}
-    };
-    // All errors generated by poll or ppoll are already caught by the nix wrapper around libc, so
-    // here we only need to check if there's at least 1 event
-    if wait != 1 {
-        return Err(io::Error::new(
-            io::ErrorKind::TimedOut,
-            "Operation timed out",
-        ));
-    }
-
-    // Check the result of ppoll() by looking at the revents field
-    match fd.revents() {
-        Some(e) if e == events => return Ok(()),
-        // If there was a hangout or invalid request
-        Some(e) if e.contains(PollFlags::POLLHUP) || e.contains(PollFlags::POLLNVAL) => {
-            return Err(io::Error::new(io::ErrorKind::BrokenPipe, EPIPE.desc()));
-        }
-        Some(_) | None => (),
-    }
-
-    Err(io::Error::new(io::ErrorKind::Other, EIO.desc()))
+        #[cfg(target_os = "linux")]
+        let wait_res = nix::poll::ppoll(&mut fds, remaining.map(TimeSpec::from_duration), SigSet::empty());
+
+        #[cfg(not(target_os = "linux"))]
+        let wait_res = nix::poll::poll(&mut fds, remaining.map(|r| r.as_millis() as nix::libc::c_int).unwrap_or(-1));
+
+        let wait = match wait_res {
+            Ok(r) => r,
+            // The remaining time is recomputed from the deadline on the next pass
+            Err(Errno::EINTR) if retry_interrupted => continue,
+            Err(Errno::EINTR) => return Err(io::ErrorKind::Interrupted.into()),
+            Err(e) => {return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Operation failed {}", e),
+            ))}
+        };
+        // All errors generated by poll or ppoll are already caught by the nix wrapper around libc, so
+        // here we only need to check if there's at least 1 event
+        if wait == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Operation timed out",
+            ));
+        }
+
+        if token.map(|t| t.is_cancelled()).unwrap_or(false) {
+            return Err(SerialError::cancelled_io());
+        }
+
+        if fds[1].revents().map(|e| e.contains(PollFlags::POLLIN)).unwrap_or(false) {
+            if cancel.generation() != generation {
+                return Err(SerialError::cancelled_io());
+            }
+            // Stale wakeup from a cancel which happened before this operation started
+            cancel.drain();
+            if fds[0].revents().map(|e| e.is_empty()).unwrap_or(true) {
+                continue;
+            }
+        }
+
+        // Check the result of ppoll() by looking at the revents field
+        match fds[0].revents() {
+            Some(e) if e == events => return Ok(()),
+            // If there was a hangout or invalid request
+            Some(e) if e.contains(PollFlags::POLLHUP) || e.contains(PollFlags::POLLNVAL) => {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, EPIPE.desc()));
+            }
+            // Woken by the token's fd without a cancel, or nothing ready yet
+            Some(e) if e.is_empty() => continue,
+            Some(_) | None => (),
+        }
+
+        return Err(io::Error::other(EIO.desc()));
+    }
 }
@@ -24,6 +24,7 @@ fn get_paths(g: &str) -> Vec<PathBuf> {
 impl crate::PortScanner for TTYPortScanner {
     fn list_devices(&mut self) -> crate::SerialResult<Vec<crate::PortInfo>> {
         let mut res: Vec<PortInfo> = vec![];
+        #[allow(unused_mut)]
         let mut pat: Vec<PathBuf> = get_paths("/dev/ttyS*").into_iter()
         .chain(get_paths("/dev/ttyUSB*"))
         .chain(get_paths("/dev/ttyXRUSB*"))
@@ -49,7 +50,7 @@ impl crate::PortScanner for TTYPortScanner {
                 subsystem = std::fs::canonicalize(format!("{}/subsystem", path.clone().unwrap().to_str().unwrap())).ok();
                 if let Ok(mut f) = File::open(format!("/sys/class/tty/{dev_name}/device/uevent")) {
                     let mut s = String::new();
-                    f.read_to_string(&mut s);
+                    let _ = f.read_to_string(&mut s);
                     for line in s.lines() {
                         if line.starts_with("PRODUCT=") {
                             let p = line.replace("PRODUCT=", "");
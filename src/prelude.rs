@@ -0,0 +1,7 @@
+//! The port traits, for glob importing
+//!
+//! ```
+//! use serial_rs::prelude::*;
+//! ```
+
+pub use crate::{BufferControl, Configurable, ControlLines, SerialIo, SerialPort};
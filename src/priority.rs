@@ -0,0 +1,111 @@
+//! Thread priority hints for reader threads
+//!
+//! At high baud rates on a loaded system, a reader thread which is not scheduled
+//! in time lets the driver's receive buffer overflow. [raise_current_thread] asks
+//! the OS to run the calling thread ahead of normal threads, falling back to a
+//! lower priority when the process is not permitted the one requested, rather
+//! than failing.
+//!
+//! [crate::shared::SharedPort::with_reader_priority] applies this to its reader
+//! thread. Threads which read through a [crate::buffered::BufferedSerialPort] or
+//! directly from a port call [raise_current_thread] themselves.
+//!
+//! ```no_run
+//! use serial_rs::priority::{raise_current_thread, ThreadPriority};
+//! let applied = raise_current_thread(ThreadPriority::Realtime);
+//! if applied != ThreadPriority::Realtime {
+//!     eprintln!("Reader running at {applied:?} priority");
+//! }
+//! ```
+
+/// Scheduling priority for a reader thread
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum ThreadPriority {
+    /// The OS default
+    #[default]
+    Normal,
+    /// Above normal threads. A nice value of -10 on Linux, the highest priority of
+    /// the default scheduling policy on other POSIX systems, and
+    /// THREAD_PRIORITY_HIGHEST on Windows
+    High,
+    /// Realtime scheduling. SCHED_FIFO on POSIX, which usually needs root or
+    /// CAP_SYS_NICE, and the REALTIME priority class with
+    /// THREAD_PRIORITY_TIME_CRITICAL on Windows. Windows runs processes without the
+    /// privilege for the REALTIME class in the HIGH class instead, which affects
+    /// every thread of the process
+    Realtime,
+}
+
+/// Raises the priority of the calling thread to `priority`, or to the highest
+/// lower priority which is permitted. Returns the priority applied
+pub fn raise_current_thread(priority: ThreadPriority) -> ThreadPriority {
+    let mut attempt = priority;
+    loop {
+        if attempt == ThreadPriority::Normal || imp::apply(attempt) {
+            return attempt;
+        }
+        attempt = match attempt {
+            ThreadPriority::Realtime => ThreadPriority::High,
+            _ => ThreadPriority::Normal,
+        };
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use nix::libc;
+
+    use super::ThreadPriority;
+
+    /// Nice value used for [ThreadPriority::High]
+    #[cfg(target_os = "linux")]
+    const HIGH_NICE: libc::c_int = -10;
+
+    pub(super) fn apply(priority: ThreadPriority) -> bool {
+        match priority {
+            ThreadPriority::Normal => true,
+            ThreadPriority::High => apply_high(),
+            ThreadPriority::Realtime => set_policy(libc::SCHED_FIFO, unsafe { libc::sched_get_priority_min(libc::SCHED_FIFO) } + 1),
+        }
+    }
+
+    /// Linux applies nice values to single threads, addressed by their thread ID
+    #[cfg(target_os = "linux")]
+    fn apply_high() -> bool {
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+        unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, HIGH_NICE) == 0 }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn apply_high() -> bool {
+        set_policy(libc::SCHED_OTHER, unsafe { libc::sched_get_priority_max(libc::SCHED_OTHER) })
+    }
+
+    fn set_policy(policy: libc::c_int, priority: libc::c_int) -> bool {
+        let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+        param.sched_priority = priority;
+        unsafe { libc::pthread_setschedparam(libc::pthread_self(), policy, &param) == 0 }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use winapi::um::{
+        processthreadsapi::{GetCurrentProcess, GetCurrentThread, SetPriorityClass, SetThreadPriority},
+        winbase::{REALTIME_PRIORITY_CLASS, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_TIME_CRITICAL},
+    };
+
+    use super::ThreadPriority;
+
+    pub(super) fn apply(priority: ThreadPriority) -> bool {
+        match priority {
+            ThreadPriority::Normal => true,
+            ThreadPriority::High => unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_HIGHEST as i32) != 0 },
+            ThreadPriority::Realtime => unsafe {
+                SetPriorityClass(GetCurrentProcess(), REALTIME_PRIORITY_CLASS) != 0
+                    && SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL as i32) != 0
+            },
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Named port configurations stored in TOML files
+//!
+//! Each table of the file is a profile, selecting a port either by path or by USB
+//! VID / PID, along with its settings. Settings which are not given keep their defaults.
+//!
+//! ```toml
+//! [inverter]
+//! path = "/dev/ttyUSB0"
+//! baud_rate = 19200
+//! parity = "even"
+//! read_timeout = 500
+//!
+//! [gps]
+//! vid = 0x1546
+//! pid = 0x01a7
+//! baud_rate = 115200
+//! exclusive = true
+//! ```
+//!
+//! Enum settings use snake case names, such as `stop_bits = "one_point_five"` or
+//! `flow_control = "rts_cts"`, and timeouts are in milliseconds
+
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{SerialError, SerialPort, SerialPortSettings, SerialResult};
+
+/// A named port configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Path of the port. Takes priority over `vid` / `pid`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// USB vendor ID of the port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vid: Option<u16>,
+    /// USB product ID of the port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u16>,
+    /// Open the port in exclusive mode (POSIX only, ignored elsewhere)
+    #[serde(default)]
+    pub exclusive: bool,
+    /// Port settings
+    #[serde(flatten)]
+    pub settings: SerialPortSettings,
+}
+
+impl Profile {
+    /// Finds the path of the port this profile selects. With a VID / PID selector,
+    /// the first matching port is used
+    pub fn resolve_path(&self) -> SerialResult<String> {
+        if let Some(path) = &self.path {
+            return Ok(path.clone());
+        }
+        if self.vid.is_none() && self.pid.is_none() {
+            return Err(SerialError::LibraryError("Profile has neither a path nor a VID / PID".to_string()));
+        }
+        crate::list_ports()?
+            .into_iter()
+            .find(|p| self.vid.map(|v| v == p.get_vid()).unwrap_or(true) && self.pid.map(|v| v == p.get_pid()).unwrap_or(true))
+            .map(|p| p.get_port().to_string())
+            .ok_or_else(|| {
+                SerialError::LibraryError(format!(
+                    "No port found with VID {} and PID {}",
+                    self.vid.map(|v| format!("{v:04X}")).unwrap_or_else(|| "*".into()),
+                    self.pid.map(|v| format!("{v:04X}")).unwrap_or_else(|| "*".into())
+                ))
+            })
+    }
+
+    /// Opens the port this profile selects
+    pub fn open(&self) -> SerialResult<Box<dyn SerialPort>> {
+        let path = self.resolve_path()?;
+        #[cfg(unix)]
+        {
+            use crate::posix::SerialPortExt;
+            let port = crate::posix::TTYPort::new(path, Some(self.settings))?;
+            if self.exclusive {
+                port.set_exclusive(true)?;
+            }
+            Ok(Box::new(port))
+        }
+        #[cfg(not(unix))]
+        crate::new_from_path(&path, Some(self.settings))
+    }
+}
+
+/// Collection of named profiles
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Profiles {
+    profiles: BTreeMap<String, Profile>,
+}
+
+fn toml_error<E: std::fmt::Display>(e: E) -> SerialError {
+    SerialError::LibraryError(format!("Invalid profile file: {e}"))
+}
+
+impl Profiles {
+    /// Parses profiles from TOML text
+    pub fn from_toml(s: &str) -> SerialResult<Self> {
+        toml::from_str(s).map_err(toml_error)
+    }
+
+    /// Serializes the profiles to TOML text
+    pub fn to_toml(&self) -> SerialResult<String> {
+        toml::to_string(self).map_err(toml_error)
+    }
+
+    /// Loads profiles from a TOML file
+    pub fn load<P: AsRef<Path>>(path: P) -> SerialResult<Self> {
+        Self::from_toml(&std::fs::read_to_string(path).map_err(SerialError::IoError)?)
+    }
+
+    /// Saves the profiles to a TOML file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> SerialResult<()> {
+        std::fs::write(path, self.to_toml()?).map_err(SerialError::IoError)
+    }
+
+    /// Gets a profile by name
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+
+    /// Adds or replaces a profile, returning the previous one
+    pub fn insert<S: Into<String>>(&mut self, name: S, profile: Profile) -> Option<Profile> {
+        self.profiles.insert(name.into(), profile)
+    }
+
+    /// Removes a profile
+    pub fn remove(&mut self, name: &str) -> Option<Profile> {
+        self.profiles.remove(name)
+    }
+
+    /// Iterates over the profile names, in sorted order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(|k| k.as_str())
+    }
+
+    /// Opens the port of the named profile
+    pub fn open(&self, name: &str) -> SerialResult<Box<dyn SerialPort>> {
+        self.get(name)
+            .ok_or_else(|| SerialError::LibraryError(format!("No profile named '{name}'")))?
+            .open()
+    }
+}
@@ -0,0 +1,334 @@
+//! Python bindings
+//!
+//! Builds a `serial_rs` Python extension module whose API follows pyserial where it
+//! makes sense, as this crate is modeled on it:
+//!
+//! ```python
+//! import serial_rs
+//! with serial_rs.Serial("/dev/ttyUSB0", baudrate=115200, timeout=1.0) as port:
+//!     port.write(b"AT\r\n")
+//!     print(port.readline())
+//! print(serial_rs.list_ports())
+//! ```
+//!
+//! Blocking calls release the GIL. Build the module with maturin, or with
+//! `cargo rustc --release --features python --crate-type cdylib`
+
+use std::time::{Duration, Instant};
+
+use pyo3::{
+    exceptions::{PyIOError, PyTimeoutError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+use crate::{ByteSize, FlowControl, Parity, SerialError, SerialPort, SerialPortSettings, StopBits};
+
+/// Converts a serial-rs error into a Python exception
+fn to_py_err(e: SerialError) -> PyErr {
+    match e {
+        SerialError::IoError(e) if e.kind() == std::io::ErrorKind::TimedOut => PyTimeoutError::new_err(e.to_string()),
+        e => PyIOError::new_err(e.to_string()),
+    }
+}
+
+fn io_to_py_err(e: std::io::Error) -> PyErr {
+    to_py_err(SerialError::IoError(e))
+}
+
+/// Converts a pyserial style timeout in seconds
+fn to_duration(timeout: Option<f64>) -> PyResult<Option<Duration>> {
+    timeout
+        .map(|t| Duration::try_from_secs_f64(t).map_err(|_| PyValueError::new_err("Timeout must be a positive number")))
+        .transpose()
+}
+
+/// Serial port, mirroring pyserial's `serial.Serial`
+#[pyclass(module = "serial_rs")]
+struct Serial {
+    port: Option<Box<dyn SerialPort>>,
+    path: String,
+    timeout: Option<Duration>,
+}
+
+impl Serial {
+    fn port(&self) -> PyResult<&dyn SerialPort> {
+        self.port.as_deref().ok_or_else(|| PyIOError::new_err("Port is closed"))
+    }
+
+    /// Reads up to `size` bytes, stopping early if `delim` is read. Waits up to the
+    /// port's timeout in total, or forever if there is none
+    fn read_impl(&self, py: Python<'_>, size: Option<usize>, terminator: Option<&[u8]>) -> PyResult<Vec<u8>> {
+        let port = self.port()?;
+        let timeout = self.timeout;
+        py.allow_threads(|| {
+            let deadline = timeout.map(|t| Instant::now() + t);
+            let mut res = Vec::new();
+            let mut byte = [0u8; 1];
+            while size.map(|s| res.len() < s).unwrap_or(true) {
+                let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                if !port.poll_readable(remaining).map_err(io_to_py_err)? {
+                    break;
+                }
+                match terminator {
+                    // Read a byte at a time so nothing after the terminator is consumed
+                    Some(t) => {
+                        if port.read_shared(&mut byte).map_err(io_to_py_err)? == 1 {
+                            res.push(byte[0]);
+                            if !t.is_empty() && res.ends_with(t) {
+                                break;
+                            }
+                        }
+                    }
+                    None => {
+                        let want = size.unwrap_or(usize::MAX) - res.len();
+                        let mut buf = vec![0u8; want.min(4096)];
+                        let read = port.read_shared(&mut buf).map_err(io_to_py_err)?;
+                        res.extend_from_slice(&buf[..read]);
+                    }
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[pymethods]
+impl Serial {
+    /// Opens a port. Parity is one of 'N', 'E' or 'O', and timeouts are in seconds
+    #[new]
+    #[pyo3(signature = (port, baudrate=9600, bytesize=8, parity="N", stopbits=1.0, timeout=None, write_timeout=None, xonxoff=false, rtscts=false, dsrdtr=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        port: String,
+        baudrate: u32,
+        bytesize: u8,
+        parity: &str,
+        stopbits: f64,
+        timeout: Option<f64>,
+        write_timeout: Option<f64>,
+        xonxoff: bool,
+        rtscts: bool,
+        dsrdtr: bool,
+    ) -> PyResult<Self> {
+        let byte_size = match bytesize {
+            5 => ByteSize::Five,
+            6 => ByteSize::Six,
+            7 => ByteSize::Seven,
+            8 => ByteSize::Eight,
+            _ => return Err(PyValueError::new_err("bytesize must be between 5 and 8")),
+        };
+        let parity = match parity {
+            "N" => Parity::None,
+            "E" => Parity::Even,
+            "O" => Parity::Odd,
+            _ => return Err(PyValueError::new_err("parity must be 'N', 'E' or 'O'")),
+        };
+        let stop_bits = match stopbits {
+            1.0 => StopBits::One,
+            1.5 => StopBits::OnePointFive,
+            2.0 => StopBits::Two,
+            _ => return Err(PyValueError::new_err("stopbits must be 1, 1.5 or 2")),
+        };
+        let flow_control = match (xonxoff, rtscts, dsrdtr) {
+            (false, false, false) => FlowControl::None,
+            (true, false, false) => FlowControl::XonXoff,
+            (false, true, false) => FlowControl::RtsCts,
+            (false, false, true) => FlowControl::DsrDtr,
+            _ => return Err(PyValueError::new_err("Only one flow control method can be enabled")),
+        };
+        if baudrate == 0 {
+            return Err(PyValueError::new_err("baudrate must be positive"));
+        }
+        let timeout = to_duration(timeout)?;
+        let write_timeout = to_duration(write_timeout)?;
+        // Reads are timed by read_impl, so the port itself never waits
+        let settings = SerialPortSettings::default()
+            .baud(baudrate)
+            .byte_size(byte_size)
+            .parity(parity)
+            .stop_bits(stop_bits)
+            .set_flow_control(flow_control)
+            .read_timeout(None)
+            .write_timeout(write_timeout.map(|t| t.as_millis()));
+        let opened = crate::new_from_path(&port, Some(settings)).map_err(to_py_err)?;
+        Ok(Self { port: Some(opened), path: port, timeout })
+    }
+
+    /// Name of the port
+    #[getter]
+    fn port_name(&self) -> &str {
+        &self.path
+    }
+
+    /// True until the port is closed
+    #[getter]
+    fn is_open(&self) -> bool {
+        self.port.is_some()
+    }
+
+    /// Read timeout in seconds, or None to wait forever
+    #[getter]
+    fn get_timeout(&self) -> Option<f64> {
+        self.timeout.map(|t| t.as_secs_f64())
+    }
+
+    #[setter]
+    fn set_timeout(&mut self, timeout: Option<f64>) -> PyResult<()> {
+        self.timeout = to_duration(timeout)?;
+        Ok(())
+    }
+
+    /// Reads up to `size` bytes, returning fewer if the timeout expires
+    #[pyo3(signature = (size=1))]
+    fn read<'py>(&self, py: Python<'py>, size: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.read_impl(py, Some(size), None)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    /// Reads until `expected` is received, `size` bytes have been read, or the timeout expires
+    #[pyo3(signature = (expected=b"\n".to_vec(), size=None))]
+    fn read_until<'py>(&self, py: Python<'py>, expected: Vec<u8>, size: Option<usize>) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.read_impl(py, size, Some(&expected))?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    /// Reads a line terminated by `\n`
+    #[pyo3(signature = (size=None))]
+    fn readline<'py>(&self, py: Python<'py>, size: Option<usize>) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.read_impl(py, size, Some(b"\n"))?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    /// Writes all of `data`, returning the number of bytes written
+    fn write(&self, py: Python<'_>, data: Vec<u8>) -> PyResult<usize> {
+        let port = self.port()?;
+        py.allow_threads(|| {
+            let mut written = 0;
+            while written < data.len() {
+                written += port.write_shared(&data[written..]).map_err(io_to_py_err)?;
+            }
+            Ok(written)
+        })
+    }
+
+    /// Waits until all written data has been transmitted
+    fn flush(&self, py: Python<'_>) -> PyResult<()> {
+        let port = self.port()?;
+        py.allow_threads(|| port.flush_shared().map_err(io_to_py_err))
+    }
+
+    /// Number of bytes waiting to be read
+    #[getter]
+    fn in_waiting(&self) -> PyResult<usize> {
+        self.port()?.bytes_to_read().map_err(to_py_err)
+    }
+
+    /// Number of bytes waiting to be transmitted
+    #[getter]
+    fn out_waiting(&self) -> PyResult<usize> {
+        self.port()?.bytes_to_write().map_err(to_py_err)
+    }
+
+    /// Discards received data which has not been read
+    fn reset_input_buffer(&self) -> PyResult<()> {
+        self.port()?.clear_input_buffer().map_err(to_py_err)
+    }
+
+    /// Discards written data which has not been transmitted
+    fn reset_output_buffer(&self) -> PyResult<()> {
+        self.port()?.clear_output_buffer().map_err(to_py_err)
+    }
+
+    /// Sets the DTR line
+    #[setter]
+    fn set_dtr(&self, state: bool) -> PyResult<()> {
+        self.port()?.set_data_terminal_ready(state).map_err(to_py_err)
+    }
+
+    /// State of the DTR line
+    #[getter]
+    fn get_dtr(&self) -> PyResult<bool> {
+        self.port()?.get_data_terminal_ready().map_err(to_py_err)
+    }
+
+    /// Sets the RTS line
+    #[setter]
+    fn set_rts(&self, state: bool) -> PyResult<()> {
+        self.port()?.set_request_to_send(state).map_err(to_py_err)
+    }
+
+    /// State of the RTS line
+    #[getter]
+    fn get_rts(&self) -> PyResult<bool> {
+        self.port()?.get_request_to_send().map_err(to_py_err)
+    }
+
+    /// Sets or clears the break condition
+    #[setter]
+    fn set_break_condition(&self, state: bool) -> PyResult<()> {
+        self.port()?.set_break_state(state).map_err(to_py_err)
+    }
+
+    /// State of the CTS line
+    #[getter]
+    fn cts(&self) -> PyResult<bool> {
+        self.port()?.read_clear_to_send().map_err(to_py_err)
+    }
+
+    /// State of the DSR line
+    #[getter]
+    fn dsr(&self) -> PyResult<bool> {
+        self.port()?.read_data_set_ready().map_err(to_py_err)
+    }
+
+    /// State of the RI line
+    #[getter]
+    fn ri(&self) -> PyResult<bool> {
+        self.port()?.read_ring_indicator().map_err(to_py_err)
+    }
+
+    /// State of the CD line
+    #[getter]
+    fn cd(&self) -> PyResult<bool> {
+        self.port()?.read_carrier_detect().map_err(to_py_err)
+    }
+
+    /// Closes the port. Closing an already closed port does nothing
+    fn close(&mut self) {
+        self.port = None;
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&mut self, _args: &Bound<'_, pyo3::types::PyTuple>) {
+        self.close();
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Serial(port={:?}, open={})", self.path, self.port.is_some())
+    }
+}
+
+/// Lists available ports as (port, description, hwid) tuples, like
+/// pyserial's `serial.tools.list_ports.comports`
+#[pyfunction]
+fn list_ports() -> PyResult<Vec<(String, String, String)>> {
+    Ok(crate::list_ports()
+        .map_err(to_py_err)?
+        .into_iter()
+        .map(|p| (p.get_port().to_string(), p.get_desc().to_string(), p.get_hwid().to_string()))
+        .collect())
+}
+
+/// Python module entry point
+#[pymodule]
+#[pyo3(name = "serial_rs")]
+fn serial_rs_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Serial>()?;
+    m.add_function(wrap_pyfunction!(list_ports, m)?)?;
+    Ok(())
+}
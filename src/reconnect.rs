@@ -0,0 +1,145 @@
+//! Reopening ports after the device goes away
+//!
+//! USB adapters vanish when unplugged, and on Windows every open handle stops
+//! working once the system has slept. Reads and writes then fail with
+//! [SerialError::Disconnected]. [ReconnectingPort] reopens the port by its path,
+//! with the same settings, and retries the operation, so long running sessions
+//! such as data loggers carry on after the laptop wakes up.
+//!
+//! ```no_run
+//! # fn example() -> serial_rs::SerialResult<()> {
+//! use std::io::Read;
+//! use serial_rs::reconnect::ReconnectingPort;
+//! let port = serial_rs::new_from_path("COM3", None)?;
+//! let mut port = ReconnectingPort::new(port).on_disconnect(|attempt| {
+//!     eprintln!("Port lost, reopening (attempt {attempt})");
+//!     attempt <= 30
+//! });
+//! let mut buf = [0u8; 64];
+//! let n = port.read(&mut buf).map_err(serial_rs::SerialError::IoError)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+/// Called before each attempt to reopen the port, with the attempt number
+/// starting from 1. Returning false gives up
+pub type ReconnectHook = Box<dyn FnMut(u32) -> bool + Send>;
+
+/// Default delay between attempts to reopen the port
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Port wrapper which reopens the port when the device goes away
+pub struct ReconnectingPort {
+    port: Box<dyn SerialPort>,
+    path: String,
+    retry_interval: Duration,
+    hook: Option<ReconnectHook>,
+}
+
+impl std::fmt::Debug for ReconnectingPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectingPort")
+            .field("path", &self.path)
+            .field("retry_interval", &self.retry_interval)
+            .finish()
+    }
+}
+
+impl ReconnectingPort {
+    /// Wraps `port`, which is reopened by its path. Without a hook, reopening is
+    /// retried until it succeeds
+    pub fn new(port: Box<dyn SerialPort>) -> Self {
+        let path = port.path().to_string();
+        Self { port, path, retry_interval: DEFAULT_RETRY_INTERVAL, hook: None }
+    }
+
+    /// Sets the delay between attempts to reopen the port
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Sets a hook called before each attempt to reopen the port. See [ReconnectHook]
+    pub fn on_disconnect<F: FnMut(u32) -> bool + Send + 'static>(mut self, hook: F) -> Self {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Closes and reopens the port with its current settings. The old port is only
+    /// replaced once the new one has opened
+    pub fn reopen(&mut self) -> SerialResult<()> {
+        let settings = *self.port.settings();
+        self.port = crate::new_from_path(&self.path, Some(settings))?;
+        Ok(())
+    }
+
+    /// Gets a reference to the port
+    pub fn get_ref(&self) -> &dyn SerialPort {
+        self.port.as_ref()
+    }
+
+    /// Gets a mutable reference to the port
+    pub fn get_mut(&mut self) -> &mut dyn SerialPort {
+        self.port.as_mut()
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> Box<dyn SerialPort> {
+        self.port
+    }
+
+    /// Reopens the port after `e`, until it succeeds or the hook gives up, in which
+    /// case `e` is returned
+    fn reconnect(&mut self, e: std::io::Error) -> std::io::Result<()> {
+        for attempt in 1.. {
+            if let Some(hook) = self.hook.as_mut() {
+                if !hook(attempt) {
+                    return Err(e);
+                }
+            }
+            if self.reopen().is_ok() {
+                return Ok(());
+            }
+            std::thread::sleep(self.retry_interval);
+        }
+        Err(e)
+    }
+}
+
+impl Read for ReconnectingPort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            match self.port.read(buf) {
+                Err(e) if SerialError::is_disconnected(&e) => self.reconnect(e)?,
+                res => return res,
+            }
+        }
+    }
+}
+
+impl Write for ReconnectingPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        loop {
+            match self.port.write(buf) {
+                Err(e) if SerialError::is_disconnected(&e) => self.reconnect(e)?,
+                res => return res,
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        loop {
+            match self.port.flush() {
+                Err(e) if SerialError::is_disconnected(&e) => self.reconnect(e)?,
+                res => return res,
+            }
+        }
+    }
+}
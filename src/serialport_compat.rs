@@ -0,0 +1,250 @@
+//! Adapter implementing the [serialport] crate's `SerialPort` trait
+//!
+//! [SerialportCompat] lets code written against `serialport::SerialPort` run on a
+//! serial-rs port. Semantic differences between the two crates:
+//!
+//! * The getters ([serialport::SerialPort::baud_rate] etc.) read the settings back
+//!   from the driver with [crate::Configurable::current_settings]
+//! * [FlowControl::DsrDtr] has no serialport equivalent, and is reported as
+//!   [serialport::FlowControl::Hardware]
+//! * serialport has a single timeout for reads and writes. Setting it sets both the
+//!   read and write timeout. A port with no read timeout reports a timeout of zero,
+//!   as its reads return immediately with whatever data is available
+//! * Errors other than IO errors are reported as [serialport::ErrorKind::Unknown]
+
+use std::{
+    io::{Read, Write},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use serialport::{ClearBuffer, DataBits, Error, ErrorKind, Result};
+
+use crate::{ByteSize, FlowControl, Parity, SerialError, SerialPort, StopBits};
+
+/// Converts a serial-rs error into a serialport error
+fn to_sp_error(e: SerialError) -> Error {
+    match e {
+        SerialError::IoError(e) => Error::from(e),
+        SerialError::Cancelled => Error::new(ErrorKind::Io(std::io::ErrorKind::Interrupted), "Operation cancelled"),
+        SerialError::Unsupported(e) => Error::new(ErrorKind::Io(std::io::ErrorKind::Unsupported), e),
+        SerialError::Disconnected(e) => Error::new(ErrorKind::NoDevice, e),
+        e @ SerialError::LineError(_) => Error::new(ErrorKind::Io(std::io::ErrorKind::InvalidData), e.to_string()),
+        e => Error::new(ErrorKind::Unknown, e.to_string()),
+    }
+}
+
+/// serial-rs port exposed through the serialport crate's `SerialPort` trait
+pub struct SerialportCompat {
+    // serialport clones through &self, but serial-rs needs &mut self to clone
+    port: Mutex<Box<dyn SerialPort>>,
+}
+
+impl std::fmt::Debug for SerialportCompat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SerialportCompat").field("path", &self.lock().path()).finish()
+    }
+}
+
+impl SerialportCompat {
+    /// Wraps a port
+    pub fn new<P: SerialPort + 'static>(port: P) -> Self {
+        Self::from_boxed(Box::new(port))
+    }
+
+    /// Wraps an already boxed port
+    pub fn from_boxed(port: Box<dyn SerialPort>) -> Self {
+        Self { port: Mutex::new(port) }
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> Box<dyn SerialPort> {
+        self.port.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<dyn SerialPort>> {
+        self.port.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn current(&self) -> Result<crate::SerialPortSettings> {
+        self.lock().current_settings().map_err(to_sp_error)
+    }
+
+    /// Changes a setting and applies it to the port
+    fn update<F: FnOnce(&mut crate::SerialPortSettings)>(&mut self, f: F) -> Result<()> {
+        let port = self.port.get_mut().unwrap_or_else(|e| e.into_inner());
+        f(port.setting());
+        port.reconfigure_port().map_err(to_sp_error)
+    }
+}
+
+impl Read for SerialportCompat {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.lock().read_shared(buf)
+    }
+}
+
+impl Write for SerialportCompat {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.lock().write_shared(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.lock().flush_shared()
+    }
+}
+
+impl serialport::SerialPort for SerialportCompat {
+    fn name(&self) -> Option<String> {
+        let port = self.lock();
+        match port.path() {
+            "" => None,
+            path => Some(path.to_string()),
+        }
+    }
+
+    fn baud_rate(&self) -> Result<u32> {
+        Ok(self.current()?.baud_rate)
+    }
+
+    fn data_bits(&self) -> Result<DataBits> {
+        Ok(match self.current()?.byte_size {
+            ByteSize::Five => DataBits::Five,
+            ByteSize::Six => DataBits::Six,
+            ByteSize::Seven => DataBits::Seven,
+            ByteSize::Eight => DataBits::Eight,
+        })
+    }
+
+    fn flow_control(&self) -> Result<serialport::FlowControl> {
+        Ok(match self.current()?.flow_control {
+            FlowControl::None => serialport::FlowControl::None,
+            FlowControl::XonXoff => serialport::FlowControl::Software,
+            FlowControl::RtsCts | FlowControl::DsrDtr => serialport::FlowControl::Hardware,
+        })
+    }
+
+    fn parity(&self) -> Result<serialport::Parity> {
+        Ok(match self.current()?.parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Odd => serialport::Parity::Odd,
+            Parity::Even => serialport::Parity::Even,
+        })
+    }
+
+    fn stop_bits(&self) -> Result<serialport::StopBits> {
+        match self.current()?.stop_bits {
+            StopBits::One => Ok(serialport::StopBits::One),
+            StopBits::Two => Ok(serialport::StopBits::Two),
+            StopBits::OnePointFive => Err(Error::new(ErrorKind::Unknown, "1.5 stop bits are not supported by serialport")),
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        let port = self.lock();
+        Duration::from_millis(port.settings().read_timeout.unwrap_or(0) as u64)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        self.update(|s| s.baud_rate = baud_rate)
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> Result<()> {
+        let byte_size = match data_bits {
+            DataBits::Five => ByteSize::Five,
+            DataBits::Six => ByteSize::Six,
+            DataBits::Seven => ByteSize::Seven,
+            DataBits::Eight => ByteSize::Eight,
+        };
+        self.update(|s| s.byte_size = byte_size)
+    }
+
+    fn set_flow_control(&mut self, flow_control: serialport::FlowControl) -> Result<()> {
+        let flow_control = match flow_control {
+            serialport::FlowControl::None => FlowControl::None,
+            serialport::FlowControl::Software => FlowControl::XonXoff,
+            serialport::FlowControl::Hardware => FlowControl::RtsCts,
+        };
+        self.update(|s| s.flow_control = flow_control)
+    }
+
+    fn set_parity(&mut self, parity: serialport::Parity) -> Result<()> {
+        let parity = match parity {
+            serialport::Parity::None => Parity::None,
+            serialport::Parity::Odd => Parity::Odd,
+            serialport::Parity::Even => Parity::Even,
+        };
+        self.update(|s| s.parity = parity)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: serialport::StopBits) -> Result<()> {
+        let stop_bits = match stop_bits {
+            serialport::StopBits::One => StopBits::One,
+            serialport::StopBits::Two => StopBits::Two,
+        };
+        self.update(|s| s.stop_bits = stop_bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let ms = timeout.as_millis();
+        self.update(|s| {
+            s.read_timeout = Some(ms);
+            s.write_timeout = Some(ms);
+        })
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> Result<()> {
+        self.lock().set_request_to_send(level).map_err(to_sp_error)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> Result<()> {
+        self.lock().set_data_terminal_ready(level).map_err(to_sp_error)
+    }
+
+    fn read_clear_to_send(&mut self) -> Result<bool> {
+        self.lock().read_clear_to_send().map_err(to_sp_error)
+    }
+
+    fn read_data_set_ready(&mut self) -> Result<bool> {
+        self.lock().read_data_set_ready().map_err(to_sp_error)
+    }
+
+    fn read_ring_indicator(&mut self) -> Result<bool> {
+        self.lock().read_ring_indicator().map_err(to_sp_error)
+    }
+
+    fn read_carrier_detect(&mut self) -> Result<bool> {
+        self.lock().read_carrier_detect().map_err(to_sp_error)
+    }
+
+    fn bytes_to_read(&self) -> Result<u32> {
+        self.lock().bytes_to_read().map(|b| b as u32).map_err(to_sp_error)
+    }
+
+    fn bytes_to_write(&self) -> Result<u32> {
+        self.lock().bytes_to_write().map(|b| b as u32).map_err(to_sp_error)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> Result<()> {
+        let port = self.lock();
+        match buffer_to_clear {
+            ClearBuffer::Input => port.clear_input_buffer(),
+            ClearBuffer::Output => port.clear_output_buffer(),
+            ClearBuffer::All => port.clear_input_buffer().and_then(|_| port.clear_output_buffer()),
+        }
+        .map_err(to_sp_error)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn serialport::SerialPort>> {
+        let clone = self.lock().try_clone().map_err(to_sp_error)?;
+        Ok(Box::new(SerialportCompat::from_boxed(clone)))
+    }
+
+    fn set_break(&self) -> Result<()> {
+        self.lock().set_break_state(true).map_err(to_sp_error)
+    }
+
+    fn clear_break(&self) -> Result<()> {
+        self.lock().set_break_state(false).map_err(to_sp_error)
+    }
+}
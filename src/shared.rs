@@ -0,0 +1,262 @@
+//! Port shared between several components of an application
+//!
+//! A [SharedPort] owns a background thread which reads from the port and hands
+//! every received chunk to each [Subscription], so a logger, a protocol engine and
+//! a console can all see the same data. Writes are serialized, and granted in the
+//! order they were requested, so messages from different writers never interleave
+//! and no writer is starved
+
+use std::{
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{priority::ThreadPriority, SerialPort};
+
+/// How long the reader thread waits for data before checking if it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Size of each read issued by the reader thread
+const READ_CHUNK: usize = 1024;
+
+type Filter = Box<dyn FnMut(&[u8]) -> bool + Send>;
+
+struct Subscriber {
+    tx: Sender<Vec<u8>>,
+    filter: Option<Filter>,
+}
+
+/// FIFO lock, so writers are served in the order they arrived
+#[derive(Debug, Default)]
+struct TicketLock {
+    state: Mutex<(u64, u64)>,
+    cvar: Condvar,
+}
+
+impl TicketLock {
+    fn lock_state(&self) -> MutexGuard<'_, (u64, u64)> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn acquire(&self) -> TicketGuard<'_> {
+        let mut state = self.lock_state();
+        let ticket = state.0;
+        state.0 += 1;
+        while state.1 != ticket {
+            state = self.cvar.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        TicketGuard { lock: self }
+    }
+}
+
+struct TicketGuard<'a> {
+    lock: &'a TicketLock,
+}
+
+impl Drop for TicketGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.lock_state().1 += 1;
+        self.lock.cvar.notify_all();
+    }
+}
+
+struct Inner<P: SerialPort + 'static> {
+    port: Arc<P>,
+    writers: TicketLock,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    error: Arc<Mutex<Option<std::io::Error>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<P: SerialPort + 'static> Drop for Inner<P> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = self.port.cancel_io();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Port shared between several readers and writers. Clones refer to the same port,
+/// which is closed once the last clone is dropped
+pub struct SharedPort<P: SerialPort + 'static> {
+    inner: Arc<Inner<P>>,
+}
+
+impl<P: SerialPort + 'static> Clone for SharedPort<P> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<P: SerialPort + 'static> std::fmt::Debug for SharedPort<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedPort").field("path", &self.inner.port.path()).finish()
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl<P: SerialPort + 'static> SharedPort<P> {
+    /// Takes ownership of `port` and starts the reader thread. Data received before
+    /// a subscriber is added is not delivered to it
+    pub fn new(port: P) -> Self {
+        Self::with_reader_priority(port, ThreadPriority::Normal)
+    }
+
+    /// Like [SharedPort::new], but raises the reader thread to `priority`, or the
+    /// highest lower priority which is permitted. See [crate::priority]
+    pub fn with_reader_priority(port: P, priority: ThreadPriority) -> Self {
+        let port = Arc::new(port);
+        let subscribers: Arc<Mutex<Vec<Subscriber>>> = Arc::default();
+        let error: Arc<Mutex<Option<std::io::Error>>> = Arc::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (port, subscribers, error, stop) = (port.clone(), subscribers.clone(), error.clone(), stop.clone());
+            std::thread::spawn(move || {
+                crate::priority::raise_current_thread(priority);
+                let mut buf = vec![0u8; READ_CHUNK];
+                while !stop.load(Ordering::SeqCst) {
+                    let res = port.poll_readable(Some(POLL_INTERVAL)).and_then(|ready| match ready {
+                        true => port.read_shared(&mut buf),
+                        false => Ok(0),
+                    });
+                    match res {
+                        Ok(0) => {}
+                        Ok(n) => lock(&subscribers).retain_mut(|s| {
+                            if s.filter.as_mut().map(|f| f(&buf[..n])).unwrap_or(true) {
+                                s.tx.send(buf[..n].to_vec()).is_ok()
+                            } else {
+                                true
+                            }
+                        }),
+                        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                        Err(e) if crate::SerialError::is_cancelled(&e) => {}
+                        Err(e) => {
+                            *lock(&error) = Some(e);
+                            // Dropping the senders ends every subscription
+                            lock(&subscribers).clear();
+                            break;
+                        }
+                    }
+                }
+            })
+        };
+        Self {
+            inner: Arc::new(Inner {
+                port,
+                writers: TicketLock::default(),
+                subscribers,
+                error,
+                stop,
+                thread: Some(thread),
+            }),
+        }
+    }
+
+    /// Subscribes to all data received from now on
+    pub fn subscribe(&self) -> Subscription {
+        self.add_subscriber(None)
+    }
+
+    /// Subscribes to received chunks for which `filter` returns true
+    pub fn subscribe_filtered<F: FnMut(&[u8]) -> bool + Send + 'static>(&self, filter: F) -> Subscription {
+        self.add_subscriber(Some(Box::new(filter)))
+    }
+
+    fn add_subscriber(&self, filter: Option<Filter>) -> Subscription {
+        let (tx, rx) = mpsc::channel();
+        // Once the reader has failed, the subscription is returned already closed
+        if lock(&self.inner.error).is_none() {
+            lock(&self.inner.subscribers).push(Subscriber { tx, filter });
+        }
+        Subscription { rx }
+    }
+
+    /// Writes all of `data` as one message. Concurrent callers are served in order,
+    /// and their messages never interleave
+    pub fn write_all(&self, data: &[u8]) -> std::io::Result<()> {
+        let _guard = self.inner.writers.acquire();
+        let mut written = 0;
+        while written < data.len() {
+            match self.inner.port.write_shared(&data[written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes all of `data` without waiting for other writers, so it can land in
+    /// the middle of another writer's message. Meant for bytes which must not wait
+    /// behind a large transfer, see [crate::SerialIo::write_urgent]
+    pub fn write_urgent(&self, data: &[u8]) -> std::io::Result<()> {
+        let mut written = 0;
+        while written < data.len() {
+            match self.inner.port.write_urgent(&data[written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `f` with exclusive write access, for exchanges made of several writes
+    pub fn with_writer<T, F: FnOnce(&P) -> T>(&self, f: F) -> T {
+        let _guard = self.inner.writers.acquire();
+        f(&self.inner.port)
+    }
+
+    /// Takes the error which stopped the reader thread, if it has stopped
+    pub fn take_error(&self) -> Option<std::io::Error> {
+        lock(&self.inner.error).take()
+    }
+
+    /// Gets the underlying port, for example to set control lines
+    pub fn port(&self) -> &P {
+        &self.inner.port
+    }
+}
+
+/// Receiver of data read by a [SharedPort]. Once the reader thread stops,
+/// receiving fails with a disconnected error
+#[derive(Debug)]
+pub struct Subscription {
+    rx: Receiver<Vec<u8>>,
+}
+
+impl Subscription {
+    /// Waits for the next chunk of received data
+    pub fn recv(&self) -> Option<Vec<u8>> {
+        self.rx.recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next chunk of received data
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Vec<u8>, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+
+    /// Returns the next chunk of received data if one is waiting
+    pub fn try_recv(&self) -> Result<Vec<u8>, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    /// Iterates over received chunks until the reader thread stops
+    pub fn iter(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.rx.iter()
+    }
+}
@@ -0,0 +1,481 @@
+//! SLCAN (Lawicel) serial CAN adapters
+//!
+//! SLCAN adapters carry CAN frames as ASCII records terminated by `\r`, such as
+//! `t1232ABCD` for an 11 bit frame with ID 0x123 and the data AB CD. Commands are
+//! answered with `\r` for success, or BEL (0x07) for failure.
+//!
+//! [Slcan] configures and opens the channel, and sends and receives [CanFrame]s.
+//! [SlcanCodec] only decodes and encodes frame records, so an open channel can be
+//! read as a stream of frames with [crate::codec::FramedPort], or asynchronously
+//! with the `tokio-codec` feature, using
+//! `FramedRead::new(io, Compat::new(SlcanCodec::new()))`.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::slcan::{Bitrate, CanFrame, CanId, Slcan};
+//! use std::time::Duration;
+//! let mut can = Slcan::new(port);
+//! can.close()?;
+//! can.set_bitrate(Bitrate::B500k)?;
+//! can.open()?;
+//! can.send(&CanFrame::new(CanId::Standard(0x7DF), &[0x02, 0x01, 0x00])?)?;
+//! while let Some(frame) = can.recv(Duration::from_millis(100))? {
+//!     println!("{frame}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    codec::{Decoder, Encoder},
+    SerialError, SerialPort, SerialResult,
+};
+
+const CR: u8 = b'\r';
+const BEL: u8 = 0x07;
+/// Longest record: `T`, 8 ID digits, DLC, 16 data digits and a 4 digit timestamp
+const MAX_RECORD: usize = 30;
+const READ_CHUNK: usize = 256;
+
+/// CAN identifier
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CanId {
+    /// 11 bit identifier
+    Standard(u16),
+    /// 29 bit identifier
+    Extended(u32),
+}
+
+impl CanId {
+    /// Gets the raw identifier
+    pub fn raw(&self) -> u32 {
+        match self {
+            CanId::Standard(id) => *id as u32,
+            CanId::Extended(id) => *id,
+        }
+    }
+}
+
+/// CAN 2.0 frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CanFrame {
+    /// Identifier
+    pub id: CanId,
+    /// True for a remote transmission request, which has a length but no data
+    pub remote: bool,
+    /// Data length code, 0 to 8
+    pub dlc: u8,
+    /// Data, of which the first `dlc` bytes are used
+    pub data: [u8; 8],
+    /// Adapter timestamp in milliseconds (0 to 59999), if timestamps are enabled
+    pub timestamp: Option<u16>,
+}
+
+impl CanFrame {
+    /// Creates a data frame. Fails if the identifier is out of range or there are
+    /// more than 8 data bytes
+    pub fn new(id: CanId, data: &[u8]) -> SerialResult<Self> {
+        check_id(id)?;
+        if data.len() > 8 {
+            return Err(SerialError::LibraryError(format!("CAN frame of {} bytes exceeds 8", data.len())));
+        }
+        let mut buf = [0u8; 8];
+        buf[..data.len()].copy_from_slice(data);
+        Ok(Self { id, remote: false, dlc: data.len() as u8, data: buf, timestamp: None })
+    }
+
+    /// Creates a remote transmission request for `dlc` bytes
+    pub fn new_remote(id: CanId, dlc: u8) -> SerialResult<Self> {
+        check_id(id)?;
+        if dlc > 8 {
+            return Err(SerialError::LibraryError(format!("CAN DLC {dlc} exceeds 8")));
+        }
+        Ok(Self { id, remote: true, dlc, data: [0; 8], timestamp: None })
+    }
+
+    /// Gets the data bytes. Empty for remote frames
+    pub fn data(&self) -> &[u8] {
+        match self.remote {
+            true => &[],
+            false => &self.data[..self.dlc as usize],
+        }
+    }
+
+    /// Parses an SLCAN record without its terminator, such as `t1232ABCD`
+    pub fn parse(record: &str) -> Option<Self> {
+        // Everything below slices by byte, so line noise must not reach it
+        if !record.is_ascii() {
+            return None;
+        }
+        let kind = record.chars().next()?;
+        let id_len = match kind {
+            't' | 'r' => 3,
+            'T' | 'R' => 8,
+            _ => return None,
+        };
+        let rest = record.get(1..)?;
+        let raw_id = u32::from_str_radix(hex(rest.get(..id_len)?)?, 16).ok()?;
+        let id = match id_len {
+            3 => CanId::Standard(raw_id as u16),
+            _ => CanId::Extended(raw_id),
+        };
+        check_id(id).ok()?;
+        let dlc = rest.get(id_len..id_len + 1)?.parse::<u8>().ok().filter(|d| *d <= 8)?;
+        let remote = matches!(kind, 'r' | 'R');
+        let data_len = if remote { 0 } else { dlc as usize * 2 };
+        let data_hex = rest.get(id_len + 1..id_len + 1 + data_len)?;
+        let mut data = [0u8; 8];
+        for (i, b) in data.iter_mut().take(data_len / 2).enumerate() {
+            *b = u8::from_str_radix(hex(&data_hex[i * 2..i * 2 + 2])?, 16).ok()?;
+        }
+        let timestamp = match rest.get(id_len + 1 + data_len..)? {
+            "" => None,
+            ts if ts.len() == 4 => Some(u16::from_str_radix(hex(ts)?, 16).ok()?),
+            _ => return None,
+        };
+        Some(Self { id, remote, dlc, data, timestamp })
+    }
+
+    /// Formats the frame as an SLCAN record, without a timestamp or terminator
+    pub fn to_record(&self) -> String {
+        let mut out = match (self.id, self.remote) {
+            (CanId::Standard(id), false) => format!("t{id:03X}"),
+            (CanId::Standard(id), true) => format!("r{id:03X}"),
+            (CanId::Extended(id), false) => format!("T{id:08X}"),
+            (CanId::Extended(id), true) => format!("R{id:08X}"),
+        };
+        out.push_str(&self.dlc.to_string());
+        for b in self.data() {
+            out.push_str(&format!("{b:02X}"));
+        }
+        out
+    }
+}
+
+impl Display for CanFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.id {
+            CanId::Standard(id) => write!(f, "{id:03X}")?,
+            CanId::Extended(id) => write!(f, "{id:08X}")?,
+        }
+        write!(f, " [{}]", self.dlc)?;
+        match self.remote {
+            true => write!(f, " remote request"),
+            false => self.data().iter().try_for_each(|b| write!(f, " {b:02X}")),
+        }
+    }
+}
+
+/// Passes `s` through if it is only hex digits, which `from_str_radix` alone
+/// does not check as it also takes a leading sign
+fn hex(s: &str) -> Option<&str> {
+    s.bytes().all(|b| b.is_ascii_hexdigit()).then_some(s)
+}
+
+fn check_id(id: CanId) -> SerialResult<()> {
+    match id {
+        CanId::Standard(id) if id > 0x7FF => Err(SerialError::LibraryError(format!("Standard CAN ID {id:#x} exceeds 11 bits"))),
+        CanId::Extended(id) if id > 0x1FFF_FFFF => Err(SerialError::LibraryError(format!("Extended CAN ID {id:#x} exceeds 29 bits"))),
+        _ => Ok(()),
+    }
+}
+
+/// Standard CAN bitrates, set with the `Sn` command
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Bitrate {
+    /// 10 kbit/s
+    B10k,
+    /// 20 kbit/s
+    B20k,
+    /// 50 kbit/s
+    B50k,
+    /// 100 kbit/s
+    B100k,
+    /// 125 kbit/s
+    B125k,
+    /// 250 kbit/s
+    B250k,
+    /// 500 kbit/s
+    B500k,
+    /// 800 kbit/s
+    B800k,
+    /// 1 Mbit/s
+    B1M,
+}
+
+impl Bitrate {
+    fn command(&self) -> &'static str {
+        match self {
+            Bitrate::B10k => "S0",
+            Bitrate::B20k => "S1",
+            Bitrate::B50k => "S2",
+            Bitrate::B100k => "S3",
+            Bitrate::B125k => "S4",
+            Bitrate::B250k => "S5",
+            Bitrate::B500k => "S6",
+            Bitrate::B800k => "S7",
+            Bitrate::B1M => "S8",
+        }
+    }
+}
+
+/// Codec for SLCAN frame records. Command responses and transmit acknowledgements
+/// are skipped
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SlcanCodec;
+
+impl SlcanCodec {
+    /// Creates a codec
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Decoder for SlcanCodec {
+    type Item = CanFrame;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<CanFrame>, std::io::Error> {
+        loop {
+            let end = match src.iter().position(|b| *b == CR || *b == BEL) {
+                Some(end) => end,
+                None if src.len() > MAX_RECORD => {
+                    src.clear();
+                    return Err(std::io::Error::new(ErrorKind::InvalidData, "SLCAN record too long"));
+                }
+                None => return Ok(None),
+            };
+            let line: Vec<u8> = src.drain(..=end).collect();
+            let record = String::from_utf8_lossy(&line[..end]);
+            let record = record.trim();
+            if !record.starts_with(['t', 'T', 'r', 'R']) {
+                continue;
+            }
+            return match CanFrame::parse(record) {
+                Some(frame) => Ok(Some(frame)),
+                None => Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad SLCAN record {record}"))),
+            };
+        }
+    }
+}
+
+impl Encoder<CanFrame> for SlcanCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: CanFrame, dst: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        dst.extend_from_slice(item.to_record().as_bytes());
+        dst.push(CR);
+        Ok(())
+    }
+}
+
+/// Line read from the adapter
+enum Reply {
+    /// Line ended by `\r`, without it
+    Ok(String),
+    /// Line ended by BEL
+    Error,
+}
+
+/// SLCAN adapter on a port
+#[derive(Debug)]
+pub struct Slcan<P: SerialPort> {
+    port: P,
+    rx: Vec<u8>,
+    frames: VecDeque<CanFrame>,
+    timeout: Duration,
+}
+
+impl<P: SerialPort> Slcan<P> {
+    /// Creates an adapter interface, with a 1 second command timeout. The port's
+    /// settings are left as they are, most USB adapters ignore the baud rate
+    pub fn new(port: P) -> Self {
+        Self { port, rx: Vec::new(), frames: VecDeque::new(), timeout: Duration::from_secs(1) }
+    }
+
+    /// Sets how long to wait for command responses
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the CAN bitrate. The channel must be closed
+    pub fn set_bitrate(&mut self, bitrate: Bitrate) -> SerialResult<()> {
+        self.command(bitrate.command()).map(|_| ())
+    }
+
+    /// Sets the CAN bit timing registers directly (`sxxyy`), for bitrates without a
+    /// standard code. The values are specific to the adapter's CAN controller
+    pub fn set_bit_timing(&mut self, btr0: u8, btr1: u8) -> SerialResult<()> {
+        self.command(&format!("s{btr0:02X}{btr1:02X}")).map(|_| ())
+    }
+
+    /// Opens the channel
+    pub fn open(&mut self) -> SerialResult<()> {
+        self.command("O").map(|_| ())
+    }
+
+    /// Opens the channel in listen only mode, which never acknowledges or sends
+    /// frames
+    pub fn open_listen_only(&mut self) -> SerialResult<()> {
+        self.command("L").map(|_| ())
+    }
+
+    /// Closes the channel. Adapters which are already closed may report an error,
+    /// which is ignored
+    pub fn close(&mut self) -> SerialResult<()> {
+        match self.command("C") {
+            Err(SerialError::IoError(e)) => Err(SerialError::IoError(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Turns adapter timestamps on received frames on or off. The channel must be
+    /// closed
+    pub fn set_timestamps(&mut self, enable: bool) -> SerialResult<()> {
+        self.command(if enable { "Z1" } else { "Z0" }).map(|_| ())
+    }
+
+    /// Reads the hardware and software version (`V`)
+    pub fn version(&mut self) -> SerialResult<String> {
+        self.command("V").map(|v| v.trim_start_matches('V').to_string())
+    }
+
+    /// Reads the status flags (`F`). Bits report FIFO overruns, error warnings and
+    /// bus errors, see the adapter's documentation
+    pub fn status(&mut self) -> SerialResult<u8> {
+        let resp = self.command("F")?;
+        u8::from_str_radix(resp.trim_start_matches('F'), 16)
+            .map_err(|_| SerialError::LibraryError(format!("Bad status response {resp}")))
+    }
+
+    /// Sends a command, and returns its response without the terminator. Frames
+    /// received meanwhile are queued for [Slcan::recv]
+    pub fn command(&mut self, cmd: &str) -> SerialResult<String> {
+        self.write(format!("{cmd}\r").as_bytes())?;
+        match self.read_reply()? {
+            Reply::Ok(resp) => Ok(resp),
+            Reply::Error => Err(SerialError::LibraryError(format!("Adapter rejected command {cmd}"))),
+        }
+    }
+
+    /// Sends a frame, and waits for the adapter to accept it
+    pub fn send(&mut self, frame: &CanFrame) -> SerialResult<()> {
+        let mut record = Vec::new();
+        SlcanCodec.encode(*frame, &mut record).map_err(SerialError::IoError)?;
+        self.write(&record)?;
+        match self.read_reply()? {
+            Reply::Ok(_) => Ok(()),
+            Reply::Error => Err(SerialError::LibraryError("Adapter rejected frame, is the channel open?".into())),
+        }
+    }
+
+    /// Waits up to `timeout` for a frame. Returns None if none arrived
+    pub fn recv(&mut self, timeout: Duration) -> SerialResult<Option<CanFrame>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(frame) = self.frames.pop_front() {
+                return Ok(Some(frame));
+            }
+            match self.read_line(deadline)? {
+                None => return Ok(None),
+                Some(Reply::Ok(line)) => {
+                    if let Some(frame) = CanFrame::parse(&line) {
+                        return Ok(Some(frame));
+                    }
+                }
+                Some(Reply::Error) => {}
+            }
+        }
+    }
+
+    /// Gets a reference to the underlying port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the underlying port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port, discarding any queued frames
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn write(&mut self, data: &[u8]) -> SerialResult<()> {
+        self.port.write_all(data).and_then(|_| self.port.flush()).map_err(SerialError::IoError)
+    }
+
+    /// Reads the reply to a command or frame, queueing any frames received first
+    fn read_reply(&mut self) -> SerialResult<Reply> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.read_line(deadline)? {
+                None => {
+                    return Err(SerialError::IoError(std::io::Error::new(ErrorKind::TimedOut, "No response from adapter")))
+                }
+                Some(Reply::Ok(line)) => match CanFrame::parse(&line) {
+                    Some(frame) => self.frames.push_back(frame),
+                    None => return Ok(Reply::Ok(line)),
+                },
+                Some(Reply::Error) => return Ok(Reply::Error),
+            }
+        }
+    }
+
+    fn read_line(&mut self, deadline: Instant) -> SerialResult<Option<Reply>> {
+        loop {
+            if let Some(end) = self.rx.iter().position(|b| *b == CR || *b == BEL) {
+                let line: Vec<u8> = self.rx.drain(..=end).collect();
+                return Ok(Some(match line[end] {
+                    BEL => Reply::Error,
+                    _ => Reply::Ok(String::from_utf8_lossy(&line[..end]).trim().to_string()),
+                }));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !self.port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+                return Ok(None);
+            }
+            let mut buf = [0u8; READ_CHUNK];
+            match self.port.read(&mut buf) {
+                Ok(n) => self.rx.extend_from_slice(&buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_records() {
+        let frame = CanFrame::parse("t1232ABCD").unwrap();
+        assert_eq!((frame.id, frame.data(), frame.timestamp), (CanId::Standard(0x123), &[0xAB, 0xCD][..], None));
+        let frame = CanFrame::parse("R1234567830010").unwrap();
+        assert_eq!((frame.id, frame.remote, frame.dlc, frame.timestamp), (CanId::Extended(0x1234_5678), true, 3, Some(0x0010)));
+        assert_eq!(CanFrame::parse(&frame.to_record()).map(|f| f.to_record()), Some(frame.to_record()));
+    }
+
+    #[test]
+    fn rejects_corrupted_records() {
+        for record in ["t1232\u{FFFD}A", "t12\u{FFFD}1AB", "t1232+1AB", "t+231AB", "t1239", "t12"] {
+            assert_eq!(CanFrame::parse(record), None, "{record:?}");
+        }
+        let mut codec = SlcanCodec::new();
+        let mut src = b"t1232\xFFA\rt1231AB\r".to_vec();
+        assert_eq!(codec.decode(&mut src).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(codec.decode(&mut src).unwrap().map(|f| f.to_record()), Some("t1231AB".to_string()));
+    }
+}
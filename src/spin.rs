@@ -0,0 +1,134 @@
+//! Busy-polling reads for latency critical protocols
+//!
+//! A blocking read sleeps until the driver wakes the thread, and on many systems
+//! that wakeup alone takes around a millisecond, which dominates the turnaround
+//! time of fast request/response protocols. [SpinReader] instead checks the
+//! driver's receive queue ([crate::BufferControl::bytes_to_read], FIONREAD on POSIX and
+//! ClearCommError on Windows) in a tight loop, then backs off with exponentially
+//! growing sleeps, and finally falls back to the port's normal blocking wait.
+//!
+//! **This burns a CPU core for the whole spin period of every read.** Only use it
+//! where the latency matters more than power and CPU time, and keep the spin
+//! period short.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> std::io::Result<()> {
+//! use std::{io::Read, time::Duration};
+//! use serial_rs::spin::SpinReader;
+//! let mut port = SpinReader::new(port).spin_for(Duration::from_millis(5));
+//! let mut reply = [0u8; 8];
+//! port.read(&mut reply)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::SerialPort;
+
+/// Default time each read busy-polls for before backing off
+pub const DEFAULT_SPIN: Duration = Duration::from_millis(2);
+
+/// Default longest sleep between polls whilst backing off
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_millis(1);
+
+/// First sleep between polls once the spin period is over
+const FIRST_BACKOFF: Duration = Duration::from_micros(20);
+
+/// Wrapper around a port which busy-polls for received data. Writes pass straight
+/// through
+#[derive(Debug)]
+pub struct SpinReader<P: SerialPort> {
+    port: P,
+    spin: Duration,
+    max_backoff: Duration,
+}
+
+impl<P: SerialPort> SpinReader<P> {
+    /// Wraps `port`, spinning for [DEFAULT_SPIN] and backing off to sleeps of up to
+    /// [DEFAULT_MAX_BACKOFF]
+    pub fn new(port: P) -> Self {
+        Self { port, spin: DEFAULT_SPIN, max_backoff: DEFAULT_MAX_BACKOFF }
+    }
+
+    /// Sets how long each read busy-polls for before backing off
+    pub fn spin_for(mut self, spin: Duration) -> Self {
+        self.spin = spin;
+        self
+    }
+
+    /// Sets the longest sleep between polls whilst backing off. Once the sleeps
+    /// reach it, the read falls back to the port's blocking wait. Zero skips the
+    /// backoff
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Gets a reference to the wrapped port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the wrapped port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Waits until data is queued in the driver, or the read timeout expires.
+    /// Returns false on timeout
+    fn wait_readable(&self) -> std::io::Result<bool> {
+        let start = Instant::now();
+        let deadline = self.port.settings().read_timeout.map(|t| start + Duration::from_millis(t as u64));
+        let mut backoff = FIRST_BACKOFF;
+        loop {
+            if self.port.bytes_to_read()? > 0 {
+                return Ok(true);
+            }
+            let now = Instant::now();
+            if deadline.map(|d| now >= d).unwrap_or(false) {
+                return Ok(false);
+            }
+            if now.duration_since(start) < self.spin {
+                std::hint::spin_loop();
+            } else if backoff <= self.max_backoff {
+                let sleep = deadline.map(|d| d.saturating_duration_since(now).min(backoff)).unwrap_or(backoff);
+                std::thread::sleep(sleep);
+                backoff *= 2;
+            } else {
+                return self.port.poll_readable(deadline.map(|d| d.saturating_duration_since(now)));
+            }
+        }
+    }
+}
+
+impl<P: SerialPort> Read for SpinReader<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Non-blocking reads return at once anyway
+        if buf.is_empty() || !self.port.settings().blocking {
+            return self.port.read_shared(buf);
+        }
+        match self.wait_readable()? {
+            true => self.port.read_shared(buf),
+            false => Err(ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+impl<P: SerialPort> Write for SpinReader<P> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write_shared(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush_shared()
+    }
+}
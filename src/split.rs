@@ -0,0 +1,110 @@
+//! Read and write halves of a serial port
+//!
+//! Splitting a port allows a full-duplex protocol to read on one thread
+//! and write on another, without using [SerialPort::try_clone].
+//!
+//! Owned halves ([ReadHalf] / [WriteHalf]) share the port, which is closed once
+//! both halves have been dropped. Borrowed halves ([ReadRef] / [WriteRef]) are
+//! tied to the lifetime of the port they were split from
+
+use std::{io::{Read, Write}, sync::Arc};
+
+use crate::SerialPort;
+
+/// Owned read half of a serial port, created by [SerialPort::into_split]
+#[derive(Debug)]
+pub struct ReadHalf<P: SerialPort + ?Sized> {
+    port: Arc<P>,
+}
+
+/// Owned write half of a serial port, created by [SerialPort::into_split]
+#[derive(Debug)]
+pub struct WriteHalf<P: SerialPort + ?Sized> {
+    port: Arc<P>,
+}
+
+/// Borrowed read half of a serial port, created by [SerialPort::split]
+#[derive(Debug, Clone, Copy)]
+pub struct ReadRef<'a, P: SerialPort + ?Sized> {
+    port: &'a P,
+}
+
+/// Borrowed write half of a serial port, created by [SerialPort::split]
+#[derive(Debug, Clone, Copy)]
+pub struct WriteRef<'a, P: SerialPort + ?Sized> {
+    port: &'a P,
+}
+
+/// Splits a shared port into owned halves
+pub fn into_split<P: SerialPort + ?Sized>(port: Arc<P>) -> (ReadHalf<P>, WriteHalf<P>) {
+    (ReadHalf { port: port.clone() }, WriteHalf { port })
+}
+
+/// Splits a port into borrowed halves
+pub fn split<P: SerialPort + ?Sized>(port: &P) -> (ReadRef<'_, P>, WriteRef<'_, P>) {
+    (ReadRef { port }, WriteRef { port })
+}
+
+impl<P: SerialPort + ?Sized> ReadHalf<P> {
+    /// Gets the underlying port, for example to query modem lines
+    pub fn port(&self) -> &P { &self.port }
+    /// Returns true if both halves came from the same port
+    pub fn is_pair_of(&self, other: &WriteHalf<P>) -> bool {
+        Arc::ptr_eq(&self.port, &other.port)
+    }
+    /// Joins both halves back together. If the halves came from different ports,
+    /// they are returned unchanged
+    pub fn reunite(self, other: WriteHalf<P>) -> Result<Arc<P>, (ReadHalf<P>, WriteHalf<P>)> {
+        match self.is_pair_of(&other) {
+            true => Ok(self.port),
+            false => Err((self, other)),
+        }
+    }
+}
+
+impl<P: SerialPort + ?Sized> WriteHalf<P> {
+    /// Gets the underlying port, for example to set control lines
+    pub fn port(&self) -> &P { &self.port }
+}
+
+impl<P: SerialPort + ?Sized> ReadRef<'_, P> {
+    /// Gets the underlying port
+    pub fn port(&self) -> &P { self.port }
+}
+
+impl<P: SerialPort + ?Sized> WriteRef<'_, P> {
+    /// Gets the underlying port
+    pub fn port(&self) -> &P { self.port }
+}
+
+impl<P: SerialPort + ?Sized> Read for ReadHalf<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read_shared(buf)
+    }
+}
+
+impl<P: SerialPort + ?Sized> Write for WriteHalf<P> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write_shared(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush_shared()
+    }
+}
+
+impl<P: SerialPort + ?Sized> Read for ReadRef<'_, P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read_shared(buf)
+    }
+}
+
+impl<P: SerialPort + ?Sized> Write for WriteRef<'_, P> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write_shared(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush_shared()
+    }
+}
@@ -0,0 +1,390 @@
+//! STM32 system memory (ROM) bootloader, over USART (ST application note AN3155)
+//!
+//! The bootloader runs at up to 115200 baud with 8 data bits, even parity and one
+//! stop bit, and detects the baud rate from the 0x7F byte sent by
+//! [Stm32Bootloader::connect]. [Stm32Bootloader::new] configures the framing, but
+//! leaves the baud rate as it is.
+//!
+//! Getting the chip into the bootloader needs BOOT0 high during reset. If BOOT0 and
+//! NRST are wired to the port's control lines, a [ControlLineHook] does this on
+//! connect, or a custom [BootHook] can drive them some other way.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::stm32::*;
+//! let hook = ControlLineHook::new(
+//!     Some(PinControl { line: ControlLine::Dtr, active: true }),
+//!     Some(PinControl { line: ControlLine::Rts, active: false }),
+//! );
+//! let mut boot = Stm32Bootloader::new(port)?.boot_hook(hook);
+//! boot.connect()?;
+//! println!("Chip ID: {:#06x}", boot.get_id()?);
+//! boot.erase(Erase::Mass)?;
+//! boot.write_memory(0x0800_0000, &std::fs::read("app.bin").unwrap())?;
+//! boot.go(0x0800_0000)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use crate::{transfer::read_exact_timeout, ByteSize, FlowControl, Parity, SerialError, SerialPort, SerialResult, StopBits};
+
+const SYNC: u8 = 0x7F;
+const ACK: u8 = 0x79;
+const NACK: u8 = 0x1F;
+
+const CMD_GET: u8 = 0x00;
+const CMD_GET_ID: u8 = 0x02;
+const CMD_READ_MEMORY: u8 = 0x11;
+const CMD_GO: u8 = 0x21;
+const CMD_WRITE_MEMORY: u8 = 0x31;
+const CMD_ERASE: u8 = 0x43;
+const CMD_EXTENDED_ERASE: u8 = 0x44;
+
+/// Largest read or write in one command
+const MAX_CHUNK: usize = 256;
+
+/// Most pages in one erase command. Larger counts are the special erase codes
+const MAX_ERASE_PAGES: usize = 255;
+const MAX_EXTENDED_ERASE_PAGES: usize = 0xFFF0;
+
+/// Port control line
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControlLine {
+    /// Data terminal ready
+    Dtr,
+    /// Request to send
+    Rts,
+}
+
+/// Wiring of a chip pin to a control line
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PinControl {
+    /// Line driving the pin
+    pub line: ControlLine,
+    /// Line state (as passed to [crate::ControlLines::set_data_terminal_ready] or
+    /// [crate::ControlLines::set_request_to_send]) which activates the pin: holds NRST low,
+    /// or drives BOOT0 high. Most adapters invert the lines, so asserting one
+    /// drives it low
+    pub active: bool,
+}
+
+impl PinControl {
+    fn set(&self, port: &dyn SerialPort, active: bool) -> SerialResult<()> {
+        let state = if active { self.active } else { !self.active };
+        match self.line {
+            ControlLine::Dtr => port.set_data_terminal_ready(state),
+            ControlLine::Rts => port.set_request_to_send(state),
+        }
+    }
+}
+
+/// Moves the chip between its application and the bootloader
+pub trait BootHook: std::fmt::Debug {
+    /// Restarts the chip into the bootloader
+    fn enter_bootloader(&mut self, port: &dyn SerialPort) -> SerialResult<()>;
+    /// Restarts the chip into its application
+    fn exit_bootloader(&mut self, port: &dyn SerialPort) -> SerialResult<()>;
+}
+
+/// [BootHook] for NRST and BOOT0 wired to the port's control lines
+#[derive(Debug, Copy, Clone)]
+pub struct ControlLineHook {
+    reset: Option<PinControl>,
+    boot0: Option<PinControl>,
+    delay: Duration,
+}
+
+impl ControlLineHook {
+    /// Creates a hook driving the given pins. Either may be None if it is not
+    /// wired, for example if BOOT0 is strapped high
+    pub fn new(reset: Option<PinControl>, boot0: Option<PinControl>) -> Self {
+        Self { reset, boot0, delay: Duration::from_millis(50) }
+    }
+
+    /// Sets how long reset is held, and how long the chip is given to start
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    fn pulse_reset(&self, port: &dyn SerialPort) -> SerialResult<()> {
+        if let Some(reset) = self.reset {
+            reset.set(port, true)?;
+            std::thread::sleep(self.delay);
+            reset.set(port, false)?;
+        }
+        std::thread::sleep(self.delay);
+        Ok(())
+    }
+}
+
+impl BootHook for ControlLineHook {
+    fn enter_bootloader(&mut self, port: &dyn SerialPort) -> SerialResult<()> {
+        if let Some(boot0) = self.boot0 {
+            boot0.set(port, true)?;
+        }
+        self.pulse_reset(port)
+    }
+
+    fn exit_bootloader(&mut self, port: &dyn SerialPort) -> SerialResult<()> {
+        if let Some(boot0) = self.boot0 {
+            boot0.set(port, false)?;
+        }
+        self.pulse_reset(port)
+    }
+}
+
+/// Flash erase request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Erase {
+    /// Erase all of flash
+    Mass,
+    /// Erase the listed pages. Between 1 and 255 pages can be erased at once, or
+    /// 65520 with extended erase
+    Pages(Vec<u16>),
+}
+
+/// Bootloader details returned by GET
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootloaderInfo {
+    /// Bootloader version, for example 0x31 for version 3.1
+    pub version: u8,
+    /// Supported command codes
+    pub commands: Vec<u8>,
+}
+
+/// STM32 ROM bootloader client
+#[derive(Debug)]
+pub struct Stm32Bootloader<P: SerialPort> {
+    port: P,
+    hook: Option<Box<dyn BootHook + Send>>,
+    timeout: Duration,
+    erase_timeout: Duration,
+    info: Option<BootloaderInfo>,
+}
+
+impl<P: SerialPort> Stm32Bootloader<P> {
+    /// Creates a bootloader client, setting the port to 8 data bits, even parity,
+    /// one stop bit and no flow control
+    pub fn new(mut port: P) -> SerialResult<Self> {
+        let settings = port
+            .settings()
+            .byte_size(ByteSize::Eight)
+            .parity(Parity::Even)
+            .stop_bits(StopBits::One)
+            .set_flow_control(FlowControl::None);
+        *port.setting() = settings;
+        port.reconfigure_port()?;
+        Ok(Self { port, hook: None, timeout: Duration::from_secs(1), erase_timeout: Duration::from_secs(40), info: None })
+    }
+
+    /// Sets the hook used to enter and leave the bootloader
+    pub fn boot_hook<H: BootHook + Send + 'static>(mut self, hook: H) -> Self {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets how long to wait for an acknowledgement
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how long to wait for an erase to complete. Mass erasing large parts
+    /// can take tens of seconds
+    pub fn erase_timeout(mut self, timeout: Duration) -> Self {
+        self.erase_timeout = timeout;
+        self
+    }
+
+    /// Enters the bootloader using the boot hook, if any, and synchronises with it.
+    /// A bootloader which is already synchronised is accepted
+    pub fn connect(&mut self) -> SerialResult<()> {
+        if let Some(hook) = self.hook.as_mut() {
+            hook.enter_bootloader(&self.port)?;
+        }
+        self.port.clear_input_buffer()?;
+        let mut synced = false;
+        for _ in 0..3 {
+            self.write(&[SYNC])?;
+            // NACK means the baud rate was already detected
+            if matches!(self.read_byte(self.timeout)?, Some(ACK) | Some(NACK)) {
+                synced = true;
+                break;
+            }
+        }
+        if !synced {
+            return Err(SerialError::LibraryError("No response from bootloader".into()));
+        }
+        self.info = Some(self.get()?);
+        Ok(())
+    }
+
+    /// Restarts the chip into its application using the boot hook
+    pub fn exit(&mut self) -> SerialResult<()> {
+        match self.hook.as_mut() {
+            Some(hook) => hook.exit_bootloader(&self.port),
+            None => Err(SerialError::LibraryError("No boot hook set".into())),
+        }
+    }
+
+    /// Reads the bootloader version and supported commands
+    pub fn get(&mut self) -> SerialResult<BootloaderInfo> {
+        self.command(CMD_GET)?;
+        let data = self.read_counted()?;
+        self.wait_ack(self.timeout)?;
+        let (version, commands) = data
+            .split_first()
+            .ok_or_else(|| SerialError::LibraryError("Empty GET response".into()))?;
+        Ok(BootloaderInfo { version: *version, commands: commands.to_vec() })
+    }
+
+    /// Reads the chip's product ID, for example 0x0410 for STM32F10x medium density
+    pub fn get_id(&mut self) -> SerialResult<u16> {
+        self.command(CMD_GET_ID)?;
+        let data = self.read_counted()?;
+        self.wait_ack(self.timeout)?;
+        match data.as_slice() {
+            [hi, lo, ..] => Ok(u16::from_be_bytes([*hi, *lo])),
+            _ => Err(SerialError::LibraryError("Short GET ID response".into())),
+        }
+    }
+
+    /// Reads memory starting at `address` into `buf`
+    pub fn read_memory(&mut self, address: u32, buf: &mut [u8]) -> SerialResult<()> {
+        for (i, chunk) in buf.chunks_mut(MAX_CHUNK).enumerate() {
+            self.command(CMD_READ_MEMORY)?;
+            self.send_address(address + (i * MAX_CHUNK) as u32)?;
+            let n = (chunk.len() - 1) as u8;
+            self.write(&[n, !n])?;
+            self.wait_ack(self.timeout)?;
+            if !read_exact_timeout(&mut self.port, chunk, self.timeout)? {
+                return Err(timed_out());
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `data` to memory starting at `address`. Flash must be erased first.
+    /// Writes are padded with 0xFF to a multiple of 4 bytes
+    pub fn write_memory(&mut self, address: u32, data: &[u8]) -> SerialResult<()> {
+        for (i, chunk) in data.chunks(MAX_CHUNK).enumerate() {
+            let mut block = chunk.to_vec();
+            block.resize(chunk.len().next_multiple_of(4), 0xFF);
+            self.command(CMD_WRITE_MEMORY)?;
+            self.send_address(address + (i * MAX_CHUNK) as u32)?;
+            let mut frame = vec![(block.len() - 1) as u8];
+            frame.extend_from_slice(&block);
+            frame.push(xor(&frame));
+            self.write(&frame)?;
+            self.wait_ack(self.timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Erases flash, using extended erase if the bootloader supports it
+    pub fn erase(&mut self, erase: Erase) -> SerialResult<()> {
+        let extended = self.info.as_ref().map(|i| i.commands.contains(&CMD_EXTENDED_ERASE)).unwrap_or(false);
+        if let Erase::Pages(pages) = &erase {
+            let max = if extended { MAX_EXTENDED_ERASE_PAGES } else { MAX_ERASE_PAGES };
+            if pages.is_empty() || pages.len() > max {
+                return Err(SerialError::LibraryError(format!("Can only erase 1 to {max} pages at once, not {}", pages.len())));
+            }
+        }
+        let frame = match (extended, erase) {
+            (true, Erase::Mass) => vec![0xFF, 0xFF, 0x00],
+            (true, Erase::Pages(pages)) => {
+                let mut frame = ((pages.len() - 1) as u16).to_be_bytes().to_vec();
+                pages.iter().for_each(|p| frame.extend_from_slice(&p.to_be_bytes()));
+                frame.push(xor(&frame));
+                frame
+            }
+            (false, Erase::Mass) => vec![0xFF, 0x00],
+            (false, Erase::Pages(pages)) => {
+                let mut frame = vec![(pages.len() - 1) as u8];
+                for p in pages {
+                    frame.push(u8::try_from(p).map_err(|_| SerialError::LibraryError(format!("Page {p} needs extended erase")))?);
+                }
+                frame.push(xor(&frame));
+                frame
+            }
+        };
+        self.command(if extended { CMD_EXTENDED_ERASE } else { CMD_ERASE })?;
+        self.write(&frame)?;
+        self.wait_ack(self.erase_timeout)
+    }
+
+    /// Jumps to the application at `address`. The bootloader is left, so
+    /// [Stm32Bootloader::connect] is needed before any further commands
+    pub fn go(&mut self, address: u32) -> SerialResult<()> {
+        self.command(CMD_GO)?;
+        self.send_address(address)?;
+        self.info = None;
+        Ok(())
+    }
+
+    /// Gets a reference to the underlying port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the underlying port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn write(&mut self, data: &[u8]) -> SerialResult<()> {
+        self.port.write_all(data).and_then(|_| self.port.flush()).map_err(SerialError::IoError)
+    }
+
+    fn read_byte(&mut self, timeout: Duration) -> SerialResult<Option<u8>> {
+        let mut b = [0u8; 1];
+        Ok(read_exact_timeout(&mut self.port, &mut b, timeout)?.then_some(b[0]))
+    }
+
+    fn wait_ack(&mut self, timeout: Duration) -> SerialResult<()> {
+        match self.read_byte(timeout)? {
+            Some(ACK) => Ok(()),
+            Some(NACK) => Err(SerialError::LibraryError("Bootloader refused the command".into())),
+            Some(b) => Err(SerialError::LibraryError(format!("Unexpected reply {b:#04x} from bootloader"))),
+            None => Err(timed_out()),
+        }
+    }
+
+    fn command(&mut self, cmd: u8) -> SerialResult<()> {
+        self.write(&[cmd, !cmd])?;
+        self.wait_ack(self.timeout)
+    }
+
+    fn send_address(&mut self, address: u32) -> SerialResult<()> {
+        let mut frame = address.to_be_bytes().to_vec();
+        frame.push(xor(&frame));
+        self.write(&frame)?;
+        self.wait_ack(self.timeout)
+    }
+
+    /// Reads a byte count N, followed by N + 1 bytes
+    fn read_counted(&mut self) -> SerialResult<Vec<u8>> {
+        let n = self.read_byte(self.timeout)?.ok_or_else(timed_out)?;
+        let mut data = vec![0u8; n as usize + 1];
+        match read_exact_timeout(&mut self.port, &mut data, self.timeout)? {
+            true => Ok(data),
+            false => Err(timed_out()),
+        }
+    }
+}
+
+fn xor(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, b| acc ^ b)
+}
+
+fn timed_out() -> SerialError {
+    SerialError::IoError(std::io::Error::new(std::io::ErrorKind::TimedOut, "No reply from bootloader"))
+}
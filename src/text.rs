@@ -0,0 +1,303 @@
+//! Text in 8-bit and legacy encodings
+//!
+//! Serial displays, receipt printers and older instruments rarely speak UTF-8.
+//! [TextPort] converts between Rust strings and the device's [Charset] as text is
+//! written and read, so callers never handle the raw bytes. Multi-byte encodings
+//! such as Shift-JIS are decoded correctly even when a character is split
+//! across reads.
+//!
+//! Bytes which are not valid in the encoding, and characters the encoding cannot
+//! represent, are handled according to a [ReplacementPolicy].
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use serial_rs::text::{Charset, TextPort};
+//! let mut display = TextPort::new(port, Charset::Cp437);
+//! display.write_str("╔══╗ 25°C\r\n")?;
+//! let mut reply = String::new();
+//! display.read_str(&mut reply)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::ErrorKind;
+
+pub use encoding_rs;
+use encoding_rs::{DecoderResult, Encoding, EncoderResult};
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+/// Size of each read issued to the port
+const READ_CHUNK: usize = 256;
+
+/// Code page 437 characters for bytes 0x80 to 0xFF
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Character encoding used by a device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Charset {
+    /// ISO-8859-1, where every byte is the Unicode code point of the same value.
+    /// Unlike encoding_rs's `ISO-8859-1`, which is really Windows-1252, bytes
+    /// 0x80 to 0x9F are control characters
+    Latin1,
+    /// IBM code page 437, the original PC character set with box drawing
+    /// characters, used by many character displays and printers. Bytes below 0x80
+    /// are ASCII
+    Cp437,
+    /// Any encoding supported by encoding_rs, such as [encoding_rs::SHIFT_JIS]
+    Encoding(&'static Encoding),
+}
+
+impl Charset {
+    /// Looks up a charset by name, such as `latin1`, `cp437` or `shift_jis`.
+    /// Names are matched as in the WHATWG Encoding Standard, except that Latin-1
+    /// names give [Charset::Latin1]
+    pub fn for_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" | "l1" => Some(Self::Latin1),
+            "cp437" | "ibm437" | "437" | "oem-us" => Some(Self::Cp437),
+            other => Encoding::for_label(other.as_bytes()).map(Self::Encoding),
+        }
+    }
+
+    /// Name of the charset
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Latin1 => "ISO-8859-1",
+            Self::Cp437 => "IBM437",
+            Self::Encoding(e) => e.name(),
+        }
+    }
+
+    /// Encodes one character, or returns None if the charset cannot represent it
+    fn encode_single(&self, c: char) -> Option<u8> {
+        match (self, c as u32) {
+            (_, 0..=0x7F) => Some(c as u8),
+            (Self::Latin1, 0x80..=0xFF) => Some(c as u8),
+            (Self::Cp437, _) => CP437_HIGH.iter().position(|h| *h == c).map(|i| 0x80 + i as u8),
+            _ => None,
+        }
+    }
+
+    fn decode_single(&self, b: u8) -> char {
+        match (self, b) {
+            (Self::Cp437, 0x80..=0xFF) => CP437_HIGH[b as usize - 0x80],
+            _ => b as char,
+        }
+    }
+}
+
+/// What happens to text which cannot be converted
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplacementPolicy {
+    /// Received bytes which are not valid become U+FFFD, and characters the
+    /// charset cannot represent are sent as the replacement byte, `?` by default
+    Replace,
+    /// Drops whatever cannot be converted
+    Skip,
+    /// Returns an error. On reading, the invalid bytes are dropped and text
+    /// after them is returned by the next read. On writing, nothing is sent
+    Error,
+}
+
+/// Port wrapper which reads and writes text in a device's charset
+pub struct TextPort<P: SerialPort> {
+    port: P,
+    charset: Charset,
+    decode_policy: ReplacementPolicy,
+    encode_policy: ReplacementPolicy,
+    replacement: char,
+    decoder: Option<encoding_rs::Decoder>,
+    encoder: Option<encoding_rs::Encoder>,
+    /// Bytes received but not yet decoded, after a decoding error
+    pending: Vec<u8>,
+}
+
+impl<P: SerialPort> std::fmt::Debug for TextPort<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextPort")
+            .field("charset", &self.charset.name())
+            .field("decode_policy", &self.decode_policy)
+            .field("encode_policy", &self.encode_policy)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl<P: SerialPort> TextPort<P> {
+    /// Wraps `port`, converting text to and from `charset`. Anything which cannot
+    /// be converted is replaced
+    pub fn new(port: P, charset: Charset) -> Self {
+        let (decoder, encoder) = match charset {
+            Charset::Encoding(e) => (Some(e.new_decoder_without_bom_handling()), Some(e.new_encoder())),
+            _ => (None, None),
+        };
+        Self {
+            port,
+            charset,
+            decode_policy: ReplacementPolicy::Replace,
+            encode_policy: ReplacementPolicy::Replace,
+            replacement: '?',
+            decoder,
+            encoder,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Sets what happens to received bytes which are not valid in the charset
+    pub fn decode_policy(mut self, policy: ReplacementPolicy) -> Self {
+        self.decode_policy = policy;
+        self
+    }
+
+    /// Sets what happens to characters the charset cannot represent
+    pub fn encode_policy(mut self, policy: ReplacementPolicy) -> Self {
+        self.encode_policy = policy;
+        self
+    }
+
+    /// Sets the character sent in place of ones the charset cannot represent. It
+    /// must be representable itself, otherwise it is skipped
+    pub fn replacement(mut self, c: char) -> Self {
+        self.replacement = c;
+        self
+    }
+
+    /// Gets the charset
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    /// Encodes `text` and writes it to the port
+    pub fn write_str(&mut self, text: &str) -> SerialResult<()> {
+        let bytes = self.encode(text)?;
+        self.port.write_all(&bytes).and_then(|_| self.port.flush()).map_err(SerialError::IoError)
+    }
+
+    /// Reads from the port once and appends the decoded text to `out`. Returns the
+    /// number of bytes received, which is 0 only at end of file. A character split
+    /// across reads is appended once all of it has arrived.
+    ///
+    /// Timeouts from the port are returned as errors
+    pub fn read_str(&mut self, out: &mut String) -> SerialResult<usize> {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.decode(&pending, out)?;
+            return Ok(pending.len());
+        }
+        let mut chunk = [0u8; READ_CHUNK];
+        let n = loop {
+            match self.port.read(&mut chunk) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+        };
+        self.decode(&chunk[..n], out)?;
+        Ok(n)
+    }
+
+    /// Gets a reference to the port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port. Any partly received character is lost
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn encode(&mut self, text: &str) -> SerialResult<Vec<u8>> {
+        let mut out = Vec::with_capacity(text.len());
+        let encoder = match self.encoder.as_mut() {
+            Some(encoder) => encoder,
+            None => {
+                for c in text.chars() {
+                    match self.charset.encode_single(c) {
+                        Some(b) => out.push(b),
+                        None => match self.encode_policy {
+                            ReplacementPolicy::Replace => out.extend(self.charset.encode_single(self.replacement)),
+                            ReplacementPolicy::Skip => {}
+                            ReplacementPolicy::Error => return Err(unmappable(c, self.charset)),
+                        },
+                    }
+                }
+                return Ok(out);
+            }
+        };
+        let mut src = text;
+        loop {
+            let start = out.len();
+            let max = encoder.max_buffer_length_from_utf8_without_replacement(src.len()).unwrap_or(src.len() * 8 + 16);
+            out.resize(start + max, 0);
+            let (res, read, written) = encoder.encode_from_utf8_without_replacement(src, &mut out[start..], false);
+            out.truncate(start + written);
+            src = &src[read..];
+            match res {
+                EncoderResult::InputEmpty => return Ok(out),
+                EncoderResult::OutputFull => {}
+                EncoderResult::Unmappable(c) => match self.encode_policy {
+                    ReplacementPolicy::Replace => {
+                        // Through the encoder, so stateful encodings stay consistent
+                        let mut tmp = [0u8; 16];
+                        let mut buf = [0u8; 4];
+                        let (res, _, written) =
+                            encoder.encode_from_utf8_without_replacement(self.replacement.encode_utf8(&mut buf), &mut tmp, false);
+                        if res == EncoderResult::InputEmpty {
+                            out.extend_from_slice(&tmp[..written]);
+                        }
+                    }
+                    ReplacementPolicy::Skip => {}
+                    ReplacementPolicy::Error => return Err(unmappable(c, self.charset)),
+                },
+            }
+        }
+    }
+
+    fn decode(&mut self, data: &[u8], out: &mut String) -> SerialResult<()> {
+        let decoder = match self.decoder.as_mut() {
+            Some(decoder) => decoder,
+            None => {
+                out.extend(data.iter().map(|b| self.charset.decode_single(*b)));
+                return Ok(());
+            }
+        };
+        let mut src = data;
+        loop {
+            let max = decoder.max_utf8_buffer_length_without_replacement(src.len()).unwrap_or(src.len() * 4 + 16);
+            out.reserve(max);
+            let (res, read) = decoder.decode_to_string_without_replacement(src, out, false);
+            src = &src[read..];
+            match res {
+                DecoderResult::InputEmpty => return Ok(()),
+                DecoderResult::OutputFull => {}
+                DecoderResult::Malformed(_, _) => match self.decode_policy {
+                    ReplacementPolicy::Replace => out.push(char::REPLACEMENT_CHARACTER),
+                    ReplacementPolicy::Skip => {}
+                    ReplacementPolicy::Error => {
+                        self.pending = src.to_vec();
+                        return Err(SerialError::LibraryError(format!("Received bytes not valid in {}", self.charset.name())));
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn unmappable(c: char, charset: Charset) -> SerialError {
+    SerialError::LibraryError(format!("Character {c:?} cannot be represented in {}", charset.name()))
+}
@@ -0,0 +1,108 @@
+//! Adapters between serial-rs codecs and [tokio_util::codec]
+//!
+//! [Compat] wraps a serial-rs [Decoder] / [Encoder] so it can be used with tokio_util's
+//! `Framed`, `FramedRead` and `FramedWrite` on any `AsyncRead` / `AsyncWrite`.
+//! [FromTokio] goes the other way, letting an existing tokio_util codec drive a
+//! blocking [crate::codec::FramedPort]
+
+use bytes::BytesMut;
+
+use crate::codec::{Decoder, Encoder};
+
+/// Runs a serial-rs codec as a tokio_util codec
+#[derive(Debug, Clone, Default)]
+pub struct Compat<C> {
+    inner: C,
+    /// Bytes taken from tokio_util's buffer but not yet decoded
+    buf: Vec<u8>,
+}
+
+impl<C> Compat<C> {
+    /// Wraps a serial-rs codec
+    pub fn new(inner: C) -> Self {
+        Self { inner, buf: Vec::new() }
+    }
+    /// Gets a reference to the wrapped codec
+    pub fn get_ref(&self) -> &C { &self.inner }
+    /// Gets a mutable reference to the wrapped codec
+    pub fn get_mut(&mut self) -> &mut C { &mut self.inner }
+    /// Unwraps the codec, discarding any buffered data
+    pub fn into_inner(self) -> C { self.inner }
+}
+
+impl<C: Decoder> tokio_util::codec::Decoder for Compat<C> {
+    type Item = C::Item;
+    type Error = C::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<C::Item>, C::Error> {
+        self.buf.extend_from_slice(&src.split());
+        self.inner.decode(&mut self.buf)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<C::Item>, C::Error> {
+        self.buf.extend_from_slice(&src.split());
+        self.inner.decode_eof(&mut self.buf)
+    }
+}
+
+impl<I, C: Encoder<I>> tokio_util::codec::Encoder<I> for Compat<C> {
+    type Error = C::Error;
+
+    fn encode(&mut self, item: I, dst: &mut BytesMut) -> Result<(), C::Error> {
+        let mut out = Vec::new();
+        self.inner.encode(item, &mut out)?;
+        dst.extend_from_slice(&out);
+        Ok(())
+    }
+}
+
+/// Runs a tokio_util codec as a serial-rs codec
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FromTokio<C> {
+    inner: C,
+}
+
+impl<C> FromTokio<C> {
+    /// Wraps a tokio_util codec
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+    /// Gets a reference to the wrapped codec
+    pub fn get_ref(&self) -> &C { &self.inner }
+    /// Gets a mutable reference to the wrapped codec
+    pub fn get_mut(&mut self) -> &mut C { &mut self.inner }
+    /// Unwraps the codec
+    pub fn into_inner(self) -> C { self.inner }
+}
+
+/// Runs `f` on a copy of `src`, then removes whatever `f` consumed from the front of `src`
+fn with_bytes_mut<T>(src: &mut Vec<u8>, f: impl FnOnce(&mut BytesMut) -> T) -> T {
+    let mut bytes = BytesMut::from(&src[..]);
+    let res = f(&mut bytes);
+    src.drain(..src.len() - bytes.len());
+    res
+}
+
+impl<C: tokio_util::codec::Decoder> Decoder for FromTokio<C> {
+    type Item = C::Item;
+    type Error = C::Error;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<C::Item>, C::Error> {
+        with_bytes_mut(src, |b| self.inner.decode(b))
+    }
+
+    fn decode_eof(&mut self, src: &mut Vec<u8>) -> Result<Option<C::Item>, C::Error> {
+        with_bytes_mut(src, |b| self.inner.decode_eof(b))
+    }
+}
+
+impl<I, C: tokio_util::codec::Encoder<I>> Encoder<I> for FromTokio<C> {
+    type Error = C::Error;
+
+    fn encode(&mut self, item: I, dst: &mut Vec<u8>) -> Result<(), C::Error> {
+        let mut out = BytesMut::new();
+        self.inner.encode(item, &mut out)?;
+        dst.extend_from_slice(&out);
+        Ok(())
+    }
+}
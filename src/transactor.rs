@@ -0,0 +1,274 @@
+//! Request/response transactions with retries
+//!
+//! Most instrument, meter and bus protocols send a request and wait for a
+//! response, retrying if it does not arrive, is rejected, or fails its check.
+//! [Transactor] does this given a [Matcher] which finds the end of a response,
+//! and reports failures as a [TransactionError] which tells a timeout apart from a
+//! rejected or garbled response.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> serial_rs::SerialResult<()> {
+//! use std::time::Duration;
+//! use serial_rs::transactor::{Matcher, Transactor};
+//! let mut dev = Transactor::new(port, Matcher::delimiter(b'\n'))
+//!     .nak_prefix(b"ERR")
+//!     .retries(3)
+//!     .backoff(Duration::from_millis(50), Duration::from_millis(500));
+//! let idn = dev.transact(b"*IDN?\n")?;
+//! println!("{}", String::from_utf8_lossy(&idn));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{SerialError, SerialPort};
+
+/// What a [Matcher] makes of the bytes received so far
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// More bytes are needed
+    Incomplete,
+    /// The response is complete, and is this many bytes long
+    Complete(usize),
+    /// The device rejected the request
+    Nak,
+    /// The bytes cannot be a valid response
+    Garbled,
+}
+
+type MatchFn = Box<dyn FnMut(&[u8]) -> Outcome + Send>;
+
+/// Finds the end of a response
+pub struct Matcher(MatchFn);
+
+impl std::fmt::Debug for Matcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Matcher").finish_non_exhaustive()
+    }
+}
+
+impl Matcher {
+    /// Response ending with `delim`, which is included in the response
+    pub fn delimiter(delim: u8) -> Self {
+        Self::custom(move |buf: &[u8]| match buf.iter().position(|&b| b == delim) {
+            Some(pos) => Outcome::Complete(pos + 1),
+            None => Outcome::Incomplete,
+        })
+    }
+
+    /// Response of exactly `len` bytes
+    pub fn length(len: usize) -> Self {
+        Self::custom(move |buf: &[u8]| match buf.len() >= len {
+            true => Outcome::Complete(len),
+            false => Outcome::Incomplete,
+        })
+    }
+
+    /// Response checked by a closure, which is called with everything received so
+    /// far each time more arrives
+    pub fn custom<F: FnMut(&[u8]) -> Outcome + Send + 'static>(f: F) -> Self {
+        Self(Box::new(f))
+    }
+}
+
+/// Reason a transaction failed. Each carries the bytes received on the last attempt
+#[derive(Debug)]
+pub enum TransactionError {
+    /// No complete response arrived in time
+    Timeout(Vec<u8>),
+    /// The device rejected the request
+    Nak(Vec<u8>),
+    /// The response was corrupted, or failed validation
+    Garbled(Vec<u8>),
+    /// The port failed. These are not retried
+    Port(SerialError),
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::Timeout(data) => write!(f, "Response timed out after {} bytes", data.len()),
+            TransactionError::Nak(data) => write!(f, "Request rejected: {data:02X?}"),
+            TransactionError::Garbled(data) => write!(f, "Garbled response: {data:02X?}"),
+            TransactionError::Port(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransactionError::Port(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<SerialError> for TransactionError {
+    fn from(e: SerialError) -> Self {
+        TransactionError::Port(e)
+    }
+}
+
+impl From<TransactionError> for SerialError {
+    fn from(e: TransactionError) -> Self {
+        match e {
+            TransactionError::Port(e) => e,
+            TransactionError::Timeout(_) => SerialError::IoError(std::io::Error::new(ErrorKind::TimedOut, e.to_string())),
+            e => SerialError::LibraryError(e.to_string()),
+        }
+    }
+}
+
+type ValidateFn = Box<dyn Fn(&[u8]) -> bool + Send>;
+
+/// Sends requests and waits for their responses, with retries
+pub struct Transactor<P: SerialPort> {
+    port: P,
+    matcher: Matcher,
+    nak_prefix: Option<Vec<u8>>,
+    validate: Option<ValidateFn>,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl<P: SerialPort + std::fmt::Debug> std::fmt::Debug for Transactor<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transactor")
+            .field("port", &self.port)
+            .field("nak_prefix", &self.nak_prefix)
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .field("backoff", &self.backoff)
+            .field("max_backoff", &self.max_backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<P: SerialPort> Transactor<P> {
+    /// Creates a transactor with a 1 second response timeout, 2 retries, and no
+    /// delay between attempts
+    pub fn new(port: P, matcher: Matcher) -> Self {
+        Self {
+            port,
+            matcher,
+            nak_prefix: None,
+            validate: None,
+            timeout: Duration::from_secs(1),
+            retries: 2,
+            backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    /// Sets how long to wait for each response to complete
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times a request is resent after a timeout, NAK or garbled
+    /// response
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Waits `initial` before the first retry, doubling the delay on each further
+    /// retry up to `max`
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Treats a response starting with `prefix`, such as `[0x15]`, as a NAK
+    pub fn nak_prefix(mut self, prefix: &[u8]) -> Self {
+        self.nak_prefix = Some(prefix.to_vec());
+        self
+    }
+
+    /// Checks each complete response, for example its checksum. Responses which
+    /// fail are reported as garbled
+    pub fn validate<F: Fn(&[u8]) -> bool + Send + 'static>(mut self, f: F) -> Self {
+        self.validate = Some(Box::new(f));
+        self
+    }
+
+    /// Sends `request` and returns its response, retrying as configured. If every
+    /// attempt fails, the error from the last attempt is returned
+    pub fn transact(&mut self, request: &[u8]) -> Result<Vec<u8>, TransactionError> {
+        let mut delay = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match self.attempt(request) {
+                Err(TransactionError::Port(e)) => return Err(TransactionError::Port(e)),
+                Err(e) if attempt >= self.retries => return Err(e),
+                Err(_) => {}
+                Ok(resp) => return Ok(resp),
+            }
+            attempt += 1;
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(self.max_backoff);
+        }
+    }
+
+    fn attempt(&mut self, request: &[u8]) -> Result<Vec<u8>, TransactionError> {
+        // Leftovers from an earlier, failed attempt would be taken as the response
+        self.port.clear_input_buffer()?;
+        self.port.write_all(request).and_then(|_| self.port.flush()).map_err(SerialError::IoError)?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 256];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+                return Err(TransactionError::Timeout(buf));
+            }
+            match self.port.read_shared(&mut chunk) {
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+                Err(e) => return Err(SerialError::IoError(e).into()),
+            }
+            if let Some(prefix) = &self.nak_prefix {
+                if buf.starts_with(prefix) {
+                    return Err(TransactionError::Nak(buf));
+                }
+            }
+            match (self.matcher.0)(&buf) {
+                Outcome::Incomplete => {}
+                Outcome::Complete(len) => {
+                    buf.truncate(len);
+                    return match self.validate.as_ref().is_none_or(|v| v(&buf)) {
+                        true => Ok(buf),
+                        false => Err(TransactionError::Garbled(buf)),
+                    };
+                }
+                Outcome::Nak => return Err(TransactionError::Nak(buf)),
+                Outcome::Garbled => return Err(TransactionError::Garbled(buf)),
+            }
+        }
+    }
+
+    /// Gets a reference to the port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Unwraps the port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+}
@@ -0,0 +1,452 @@
+//! File transfer protocols
+//!
+//! The block, checksum and handshake machinery shared by the protocols lives here,
+//! the protocols themselves are in their own modules:
+//!
+//! * [xmodem] - XMODEM (checksum and CRC), XMODEM-1K and XMODEM-g
+//! * [ymodem] - YMODEM batch transfers and YMODEM-g
+//! * [zmodem] - ZMODEM, with streaming and crash recovery
+//!
+//! Transfers work over any [SerialPort], and report their progress through a callback
+//! which returns `false` to cancel the transfer. A cancelled transfer, either locally
+//! or by the remote end, fails with [SerialError::Cancelled]
+
+use std::{
+    io::Read,
+    time::{Duration, Instant},
+};
+
+use crate::{SerialError, SerialPort, SerialResult};
+
+pub mod xmodem;
+pub mod ymodem;
+pub mod zmodem;
+
+pub(crate) const SOH: u8 = 0x01;
+pub(crate) const STX: u8 = 0x02;
+pub(crate) const EOT: u8 = 0x04;
+pub(crate) const ACK: u8 = 0x06;
+pub(crate) const BS: u8 = 0x08;
+pub(crate) const NAK: u8 = 0x15;
+pub(crate) const CAN: u8 = 0x18;
+pub(crate) const SUB: u8 = 0x1A;
+/// Sent by a receiver instead of NAK to request CRC mode
+pub(crate) const CRC_REQUEST: u8 = b'C';
+/// Sent by a receiver instead of NAK to request streaming CRC mode
+pub(crate) const STREAM_REQUEST: u8 = b'G';
+
+/// Maximum gap between bytes within a block
+pub(crate) const CHAR_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a receiver waits for a reply to each of its start requests
+pub(crate) const START_TIMEOUT: Duration = Duration::from_secs(3);
+/// Number of CRC start requests a receiver sends before falling back to checksums
+pub(crate) const CRC_ATTEMPTS: u32 = 3;
+
+/// Error check appended to each block
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    /// 8 bit arithmetic sum of the block (original XMODEM)
+    Sum,
+    /// CRC-16/XMODEM of the block
+    Crc16,
+}
+
+impl Checksum {
+    fn len(&self) -> usize {
+        match self {
+            Checksum::Sum => 1,
+            Checksum::Crc16 => 2,
+        }
+    }
+
+    fn append(&self, data: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Checksum::Sum => out.push(sum8(data)),
+            Checksum::Crc16 => out.extend_from_slice(&crc16(data).to_be_bytes()),
+        }
+    }
+
+    fn verify(&self, data: &[u8], check: &[u8]) -> bool {
+        let mut expected = Vec::with_capacity(2);
+        self.append(data, &mut expected);
+        expected == check
+    }
+}
+
+/// Size of the data blocks sent
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockSize {
+    /// 128 byte blocks
+    Standard,
+    /// 1024 byte blocks
+    OneK,
+}
+
+impl BlockSize {
+    /// Number of data bytes in a block
+    pub fn bytes(&self) -> usize {
+        match self {
+            BlockSize::Standard => 128,
+            BlockSize::OneK => 1024,
+        }
+    }
+}
+
+/// Progress of a transfer, passed to the progress callback after every block
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of data bytes transferred so far, not counting padding
+    pub transferred: u64,
+    /// Total number of bytes, if known
+    pub total: Option<u64>,
+}
+
+/// Computes the CRC-16/XMODEM (polynomial 0x1021, initial value 0) of `data`
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, b| {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+        crc
+    })
+}
+
+/// Computes the CRC-32 (IEEE 802.3, as used by ZMODEM) of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |mut crc, b| {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                0 => crc >> 1,
+                _ => (crc >> 1) ^ 0xEDB8_8320,
+            };
+        }
+        crc
+    })
+}
+
+fn sum8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// A packet read by a receiver
+#[derive(Debug)]
+pub(crate) enum Packet {
+    Block { num: u8, data: Vec<u8> },
+    Eot,
+    Cancel,
+    /// Corrupted or truncated block, or line noise
+    Invalid,
+    Timeout,
+}
+
+/// Reply to a block read by a sender
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Reply {
+    Ack,
+    Nak,
+    Cancel,
+    Timeout,
+}
+
+/// Reads a single byte, returning None if nothing arrived within `timeout`
+pub(crate) fn read_byte<P: SerialPort + ?Sized>(port: &mut P, timeout: Duration) -> SerialResult<Option<u8>> {
+    let mut b = [0u8; 1];
+    Ok(match read_exact_timeout(port, &mut b, timeout)? {
+        true => Some(b[0]),
+        false => None,
+    })
+}
+
+/// Fills `buf`, allowing at most `timeout` between bytes. Returns false on timeout
+pub(crate) fn read_exact_timeout<P: SerialPort + ?Sized>(port: &mut P, buf: &mut [u8], timeout: Duration) -> SerialResult<bool> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        if !port.poll_readable(Some(timeout)).map_err(SerialError::IoError)? {
+            return Ok(false);
+        }
+        match port.read(&mut buf[pos..]) {
+            Ok(n) => pos += n,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted) => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+    }
+    Ok(true)
+}
+
+/// Discards incoming data until the line has been quiet for [CHAR_TIMEOUT]
+pub(crate) fn purge<P: SerialPort + ?Sized>(port: &mut P) -> SerialResult<()> {
+    let mut buf = [0u8; 256];
+    while port.poll_readable(Some(CHAR_TIMEOUT)).map_err(SerialError::IoError)? {
+        match port.read(&mut buf) {
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted) => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn write_all<P: SerialPort + ?Sized>(port: &mut P, data: &[u8]) -> SerialResult<()> {
+    port.write_all(data).and_then(|_| port.flush()).map_err(SerialError::IoError)
+}
+
+/// Aborts the transfer at the remote end. The backspaces erase the CANs in case the
+/// remote has already dropped back to a terminal
+pub(crate) fn send_cancel<P: SerialPort + ?Sized>(port: &mut P) {
+    let mut seq = [CAN; 16];
+    seq[8..].fill(BS);
+    let _ = write_all(port, &seq);
+}
+
+/// Cancels the transfer and returns `err`
+pub(crate) fn abort<P: SerialPort + ?Sized, T>(port: &mut P, err: SerialError) -> SerialResult<T> {
+    send_cancel(port);
+    Err(err)
+}
+
+/// Waits for a reply to a block or EOT. Anything other than ACK, NAK or CAN is ignored
+pub(crate) fn read_reply<P: SerialPort + ?Sized>(port: &mut P, timeout: Duration) -> SerialResult<Reply> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match read_byte(port, remaining)? {
+            None => return Ok(Reply::Timeout),
+            Some(ACK) => return Ok(Reply::Ack),
+            Some(NAK) => return Ok(Reply::Nak),
+            Some(CAN) if read_byte(port, CHAR_TIMEOUT)? == Some(CAN) => return Ok(Reply::Cancel),
+            Some(_) => {}
+        }
+    }
+}
+
+/// Parameters of a transfer, as negotiated by the receiver's start request
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Link {
+    pub checksum: Checksum,
+    /// Blocks are sent back to back without waiting for an ACK, and any error is
+    /// fatal (the `-g` protocol variants)
+    pub streaming: bool,
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Link {
+    /// Waits for a receiver to request a transfer with NAK, `C` or `G`
+    pub fn wait_for_receiver<P: SerialPort + ?Sized>(port: &mut P, timeout: Duration, retries: u32) -> SerialResult<Self> {
+        for _ in 0..=retries {
+            let (checksum, streaming) = match read_byte(port, timeout)? {
+                Some(NAK) => (Checksum::Sum, false),
+                Some(CRC_REQUEST) => (Checksum::Crc16, false),
+                Some(STREAM_REQUEST) => (Checksum::Crc16, true),
+                Some(CAN) if read_byte(port, CHAR_TIMEOUT)? == Some(CAN) => return Err(SerialError::Cancelled),
+                _ => continue,
+            };
+            return Ok(Self { checksum, streaming, timeout, retries });
+        }
+        abort(port, SerialError::LibraryError("Receiver did not start the transfer".into()))
+    }
+
+    /// Sends `request` until the sender replies, returning the first packet received.
+    /// A CRC request falls back to checksums after [CRC_ATTEMPTS] if `fallback` is set
+    pub fn request_transfer<P: SerialPort + ?Sized>(
+        port: &mut P,
+        request: u8,
+        fallback: bool,
+        timeout: Duration,
+        retries: u32,
+    ) -> SerialResult<(Self, Packet)> {
+        let mut link = Self {
+            checksum: if request == NAK { Checksum::Sum } else { Checksum::Crc16 },
+            streaming: request == STREAM_REQUEST,
+            timeout,
+            retries,
+        };
+        for attempt in 0..=retries {
+            let request = match fallback && attempt >= CRC_ATTEMPTS {
+                true => {
+                    link.checksum = Checksum::Sum;
+                    NAK
+                }
+                false => request,
+            };
+            write_all(port, &[request])?;
+            match link.read_packet(port, START_TIMEOUT)? {
+                Packet::Timeout => {}
+                Packet::Cancel => return Err(SerialError::Cancelled),
+                packet => return Ok((link, packet)),
+            }
+        }
+        abort(port, SerialError::LibraryError("Sender did not start the transfer".into()))
+    }
+
+    /// Reads the next packet. A single CAN is treated as line noise, two in a row cancel
+    pub fn read_packet<P: SerialPort + ?Sized>(&self, port: &mut P, timeout: Duration) -> SerialResult<Packet> {
+        let len = match read_byte(port, timeout)? {
+            None => return Ok(Packet::Timeout),
+            Some(SOH) => 128,
+            Some(STX) => 1024,
+            Some(EOT) => return Ok(Packet::Eot),
+            Some(CAN) => {
+                return Ok(match read_byte(port, CHAR_TIMEOUT)? {
+                    Some(CAN) => Packet::Cancel,
+                    _ => Packet::Invalid,
+                })
+            }
+            Some(_) => return Ok(Packet::Invalid),
+        };
+        let mut frame = vec![0u8; 2 + len + self.checksum.len()];
+        if !read_exact_timeout(port, &mut frame, CHAR_TIMEOUT)? {
+            return Ok(Packet::Invalid);
+        }
+        let (num, inv) = (frame[0], frame[1]);
+        let (data, check) = frame[2..].split_at(len);
+        if num != !inv || !self.checksum.verify(data, check) {
+            return Ok(Packet::Invalid);
+        }
+        Ok(Packet::Block { num, data: data.to_vec() })
+    }
+
+    /// Sends a block, retransmitting it until it is acknowledged unless streaming.
+    /// Blocks of 1024 bytes are sent with STX, anything else with SOH
+    pub fn send_block<P: SerialPort + ?Sized>(&self, port: &mut P, num: u8, data: &[u8]) -> SerialResult<()> {
+        let mut frame = Vec::with_capacity(data.len() + 5);
+        frame.push(if data.len() == 1024 { STX } else { SOH });
+        frame.push(num);
+        frame.push(!num);
+        frame.extend_from_slice(data);
+        self.checksum.append(data, &mut frame);
+        if self.streaming {
+            return write_all(port, &frame);
+        }
+        for _ in 0..=self.retries {
+            write_all(port, &frame)?;
+            match read_reply(port, self.timeout)? {
+                Reply::Ack => return Ok(()),
+                Reply::Cancel => return Err(SerialError::Cancelled),
+                Reply::Nak | Reply::Timeout => {}
+            }
+        }
+        abort(port, SerialError::LibraryError(format!("Block {num} was not acknowledged")))
+    }
+
+    /// Sends everything read from `data` as blocks numbered from 1, padding the last
+    /// block with `padding`. A final block of 128 bytes or less is sent as a 128 byte
+    /// block. `progress` is called with the number of bytes sent after each block,
+    /// and can return false to cancel
+    pub fn send_data<P, R, F>(&self, port: &mut P, mut data: R, size: BlockSize, padding: u8, mut progress: F) -> SerialResult<u64>
+    where
+        P: SerialPort + ?Sized,
+        R: Read,
+        F: FnMut(u64) -> bool,
+    {
+        let mut buf = vec![0u8; size.bytes()];
+        let mut sent = 0u64;
+        let mut num = 1u8;
+        loop {
+            let read = match read_full(&mut data, &mut buf) {
+                Ok(n) => n,
+                Err(e) => return abort(port, e),
+            };
+            if read == 0 {
+                break;
+            }
+            let len = match read <= BlockSize::Standard.bytes() {
+                true => BlockSize::Standard.bytes(),
+                false => buf.len(),
+            };
+            buf[read..len].fill(padding);
+            self.send_block(port, num, &buf[..len])?;
+            sent += read as u64;
+            num = num.wrapping_add(1);
+            if !progress(sent) {
+                return abort(port, SerialError::Cancelled);
+            }
+            if read < buf.len() {
+                break;
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Sends EOT until it is acknowledged
+    pub fn send_eot<P: SerialPort + ?Sized>(&self, port: &mut P) -> SerialResult<()> {
+        for _ in 0..=self.retries {
+            write_all(port, &[EOT])?;
+            match read_reply(port, self.timeout)? {
+                Reply::Ack => return Ok(()),
+                Reply::Cancel => return Err(SerialError::Cancelled),
+                Reply::Nak | Reply::Timeout => {}
+            }
+        }
+        abort(port, SerialError::LibraryError("End of transmission was not acknowledged".into()))
+    }
+
+    /// Receives blocks numbered from 1, starting with `packet`, until EOT. Each new
+    /// block is passed to `on_block`, and the transfer is aborted if it fails.
+    /// With `nak_first_eot` the first EOT is NAKed to confirm it is not line noise
+    pub fn receive_blocks<P, F>(&self, port: &mut P, mut packet: Packet, nak_first_eot: bool, mut on_block: F) -> SerialResult<()>
+    where
+        P: SerialPort + ?Sized,
+        F: FnMut(Vec<u8>) -> SerialResult<()>,
+    {
+        let mut expected = 1u8;
+        let mut errors = 0;
+        let mut eot_seen = false;
+        loop {
+            match packet {
+                Packet::Block { num, data } if num == expected => {
+                    if let Err(e) = on_block(data) {
+                        return abort(port, e);
+                    }
+                    expected = expected.wrapping_add(1);
+                    errors = 0;
+                    if !self.streaming {
+                        write_all(port, &[ACK])?;
+                    }
+                }
+                // Our ACK was lost, and the sender repeated the block
+                Packet::Block { num, .. } if num == expected.wrapping_sub(1) && !self.streaming => write_all(port, &[ACK])?,
+                Packet::Block { num, .. } => {
+                    return abort(port, SerialError::LibraryError(format!("Expected block {expected}, got block {num}")))
+                }
+                Packet::Eot if nak_first_eot && !eot_seen => {
+                    eot_seen = true;
+                    write_all(port, &[NAK])?;
+                }
+                Packet::Eot => return write_all(port, &[ACK]),
+                Packet::Cancel => return Err(SerialError::Cancelled),
+                Packet::Invalid | Packet::Timeout if self.streaming => {
+                    return abort(port, SerialError::LibraryError("Corrupted block while streaming".into()))
+                }
+                Packet::Invalid | Packet::Timeout => {
+                    errors += 1;
+                    if errors > self.retries {
+                        return abort(port, SerialError::LibraryError("Too many errors".into()));
+                    }
+                    purge(port)?;
+                    write_all(port, &[NAK])?;
+                }
+            }
+            packet = self.read_packet(port, self.timeout)?;
+        }
+    }
+}
+
+/// Reads from `data` until `buf` is full or end of file is reached
+pub(crate) fn read_full<R: Read + ?Sized>(data: &mut R, buf: &mut [u8]) -> SerialResult<usize> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match data.read(&mut buf[pos..]) {
+            Ok(0) => break,
+            Ok(n) => pos += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(SerialError::IoError(e)),
+        }
+    }
+    Ok(pos)
+}
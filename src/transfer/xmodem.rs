@@ -0,0 +1,155 @@
+//! XMODEM, XMODEM-1K and XMODEM-g
+//!
+//! ```no_run
+//! use serial_rs::transfer::xmodem::Xmodem;
+//! # fn send(port: &mut dyn serial_rs::SerialPort) -> serial_rs::SerialResult<()> {
+//! let firmware = std::fs::read("firmware.bin").unwrap();
+//! let total = firmware.len() as u64;
+//! Xmodem::xmodem_1k().send(port, &firmware[..], Some(total), |p| {
+//!     println!("{}/{}", p.transferred, total);
+//!     true
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{io::{Read, Write}, time::Duration};
+
+use super::*;
+
+/// XMODEM transfer options
+#[derive(Debug, Copy, Clone)]
+pub struct Xmodem {
+    block_size: BlockSize,
+    checksum: Checksum,
+    timeout: Duration,
+    retries: u32,
+    padding: u8,
+    strip_padding: bool,
+}
+
+impl Default for Xmodem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Xmodem {
+    /// XMODEM with 128 byte blocks. When receiving, CRC mode is requested first,
+    /// falling back to checksums if the sender does not respond
+    pub fn new() -> Self {
+        Self {
+            block_size: BlockSize::Standard,
+            checksum: Checksum::Crc16,
+            timeout: Duration::from_secs(10),
+            retries: 10,
+            padding: SUB,
+            strip_padding: false,
+        }
+    }
+
+    /// XMODEM-1K, sending 1024 byte blocks
+    pub fn xmodem_1k() -> Self {
+        Self::new().block_size(BlockSize::OneK)
+    }
+
+    /// Sets the block size used when sending. Receiving accepts either size. A final
+    /// block of 128 bytes or less is always sent as a 128 byte block
+    pub fn block_size(mut self, size: BlockSize) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    /// Sets the checksum requested when receiving. When sending, the receiver
+    /// chooses the checksum
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Sets how long to wait for the remote end to reply, or to send the next block
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times a block is retried before the transfer is aborted
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the byte used to pad the last block when sending. Defaults to SUB (0x1A)
+    pub fn padding(mut self, padding: u8) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// When receiving, removes trailing padding bytes from the last block. XMODEM
+    /// does not transfer the file size, so this also removes any padding bytes which
+    /// were really at the end of the file
+    pub fn strip_padding(mut self, strip: bool) -> Self {
+        self.strip_padding = strip;
+        self
+    }
+
+    /// Sends everything read from `data`, returning the number of bytes sent.
+    /// `total` is only used to report progress. If the receiver requests XMODEM-g
+    /// with `G`, blocks are streamed without waiting for acknowledgements.
+    ///
+    /// `progress` is called after each block, and can return false to cancel the
+    /// transfer
+    pub fn send<P, R, F>(&self, port: &mut P, data: R, total: Option<u64>, mut progress: F) -> SerialResult<u64>
+    where
+        P: SerialPort + ?Sized,
+        R: Read,
+        F: FnMut(Progress) -> bool,
+    {
+        let link = Link::wait_for_receiver(port, self.timeout, self.retries)?;
+        let sent = link.send_data(port, data, self.block_size, self.padding, |transferred| {
+            progress(Progress { transferred, total })
+        })?;
+        link.send_eot(port)?;
+        Ok(sent)
+    }
+
+    /// Receives a transfer into `out`, returning the number of bytes written. The
+    /// last block is written out once the end of the transfer is seen, so that
+    /// padding can be stripped.
+    ///
+    /// `progress` is called after each block is received, and can return false
+    /// to cancel the transfer
+    pub fn receive<P, W, F>(&self, port: &mut P, mut out: W, mut progress: F) -> SerialResult<u64>
+    where
+        P: SerialPort + ?Sized,
+        W: Write,
+        F: FnMut(Progress) -> bool,
+    {
+        let (request, fallback) = match self.checksum {
+            Checksum::Crc16 => (CRC_REQUEST, true),
+            Checksum::Sum => (NAK, false),
+        };
+        let (link, packet) = Link::request_transfer(port, request, fallback, self.timeout, self.retries)?;
+        let mut received = 0u64;
+        let mut pending: Option<Vec<u8>> = None;
+        link.receive_blocks(port, packet, false, |data| {
+            received += data.len() as u64;
+            if let Some(prev) = pending.replace(data) {
+                out.write_all(&prev).map_err(SerialError::IoError)?;
+            }
+            match progress(Progress { transferred: received, total: None }) {
+                true => Ok(()),
+                false => Err(SerialError::Cancelled),
+            }
+        })?;
+        if let Some(mut last) = pending {
+            if self.strip_padding {
+                let len = last.iter().rposition(|b| *b != self.padding).map_or(0, |p| p + 1);
+                received -= (last.len() - len) as u64;
+                last.truncate(len);
+            }
+            out.write_all(&last).and_then(|_| out.flush()).map_err(SerialError::IoError)?;
+        }
+        Ok(received)
+    }
+}
@@ -0,0 +1,264 @@
+//! YMODEM batch transfers and YMODEM-g
+//!
+//! Each file is preceded by a header block (block 0) carrying its name, and
+//! optionally its size, modification time and mode. The receiver uses the size to
+//! remove the padding from the last block. A header with an empty name ends the batch.
+//!
+//! ```no_run
+//! use serial_rs::transfer::ymodem::{FileInfo, Ymodem};
+//! # fn send(port: &mut dyn serial_rs::SerialPort) -> serial_rs::SerialResult<()> {
+//! let info = FileInfo::from_path("firmware.bin").unwrap();
+//! let file = std::fs::File::open("firmware.bin").unwrap();
+//! Ymodem::new().send(port, [(info, file)], |info, p| {
+//!     println!("{}: {}/{:?}", info.name, p.transferred, p.total);
+//!     true
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::*;
+
+/// File details sent in a YMODEM header block
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FileInfo {
+    /// File name. Directories are separated with `/`.
+    ///
+    /// When receiving, this comes straight from the remote end, so it should be
+    /// sanitised before being used as a path
+    pub name: String,
+    /// File size in bytes
+    pub size: Option<u64>,
+    /// Last modification time, with a resolution of one second
+    pub modified: Option<SystemTime>,
+    /// Unix file mode
+    pub mode: Option<u32>,
+}
+
+impl FileInfo {
+    /// Creates file details with only a name
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), ..Default::default() }
+    }
+
+    /// Reads the details of a file on disk. Only the file name is used, not the
+    /// directory it is in
+    pub fn from_path(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let meta = std::fs::metadata(path)?;
+        #[cfg(unix)]
+        let mode = Some(std::os::unix::fs::PermissionsExt::mode(&meta.permissions()));
+        #[cfg(not(unix))]
+        let mode = None;
+        Ok(Self {
+            name: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            size: Some(meta.len()),
+            modified: meta.modified().ok(),
+            mode,
+        })
+    }
+
+    /// Encodes the name and details, each terminated by a NUL. This is also the
+    /// payload of a ZMODEM ZFILE frame
+    pub(crate) fn to_fields(&self) -> Vec<u8> {
+        let mut fields = self.name.as_bytes().to_vec();
+        fields.push(0);
+        if let Some(size) = self.size {
+            fields.extend_from_slice(size.to_string().as_bytes());
+            let mtime = self.modified.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs());
+            if mtime.is_some() || self.mode.is_some() {
+                fields.extend_from_slice(format!(" {:o}", mtime.unwrap_or(0)).as_bytes());
+            }
+            if let Some(mode) = self.mode {
+                fields.extend_from_slice(format!(" {mode:o}").as_bytes());
+            }
+        }
+        fields.push(0);
+        fields
+    }
+
+    /// Builds the header block, 128 bytes if it fits, else 1024 bytes
+    fn encode(&self) -> SerialResult<Vec<u8>> {
+        let mut block = self.to_fields();
+        let len = match block.len() {
+            l if l <= BlockSize::Standard.bytes() => BlockSize::Standard.bytes(),
+            l if l <= BlockSize::OneK.bytes() => BlockSize::OneK.bytes(),
+            _ => return Err(SerialError::LibraryError(format!("File name {} is too long", self.name))),
+        };
+        block.resize(len, 0);
+        Ok(block)
+    }
+
+    /// Parses a header block, or the fields of a ZMODEM ZFILE frame. Returns None for
+    /// the empty header ending a batch
+    pub(crate) fn decode(block: &[u8]) -> Option<Self> {
+        let mut fields = block.split(|b| *b == 0);
+        let name = String::from_utf8_lossy(fields.next()?).into_owned();
+        if name.is_empty() {
+            return None;
+        }
+        let rest = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+        let mut rest = rest.split_ascii_whitespace();
+        let size = rest.next().and_then(|s| s.parse().ok());
+        let modified = rest
+            .next()
+            .and_then(|s| u64::from_str_radix(s, 8).ok())
+            .filter(|secs| *secs != 0)
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let mode = rest.next().and_then(|s| u32::from_str_radix(s, 8).ok());
+        Some(Self { name, size, modified, mode })
+    }
+}
+
+/// YMODEM transfer options
+#[derive(Debug, Copy, Clone)]
+pub struct Ymodem {
+    block_size: BlockSize,
+    streaming: bool,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl Default for Ymodem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ymodem {
+    /// YMODEM with 1024 byte blocks
+    pub fn new() -> Self {
+        Self { block_size: BlockSize::OneK, streaming: false, timeout: Duration::from_secs(10), retries: 10 }
+    }
+
+    /// YMODEM-g. When receiving, blocks are streamed without acknowledgements, and
+    /// any error aborts the transfer. This should only be used over error free links
+    pub fn ymodem_g() -> Self {
+        Self { streaming: true, ..Self::new() }
+    }
+
+    /// Sets the block size used when sending. Receiving accepts either size
+    pub fn block_size(mut self, size: BlockSize) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    /// Sets how long to wait for the remote end to reply, or to send the next block
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times a block is retried before the transfer is aborted
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sends a batch of files, returning the total number of bytes sent. Whether
+    /// YMODEM-g is used is up to the receiver.
+    ///
+    /// `progress` is called after each block, and can return false to cancel the
+    /// transfer
+    pub fn send<P, R, I, F>(&self, port: &mut P, files: I, mut progress: F) -> SerialResult<u64>
+    where
+        P: SerialPort + ?Sized,
+        R: Read,
+        I: IntoIterator<Item = (FileInfo, R)>,
+        F: FnMut(&FileInfo, Progress) -> bool,
+    {
+        let mut total = 0;
+        for (info, data) in files {
+            let header = match info.encode() {
+                Ok(h) => h,
+                Err(e) => return abort(port, e),
+            };
+            Link::wait_for_receiver(port, self.timeout, self.retries)?.send_block(port, 0, &header)?;
+            let link = Link::wait_for_receiver(port, self.timeout, self.retries)?;
+            total += link.send_data(port, data, self.block_size, SUB, |transferred| {
+                progress(&info, Progress { transferred, total: info.size })
+            })?;
+            link.send_eot(port)?;
+        }
+        let end = [0u8; 128];
+        Link::wait_for_receiver(port, self.timeout, self.retries)?.send_block(port, 0, &end)?;
+        Ok(total)
+    }
+
+    /// Receives a batch of files, returning their details. `open` is called with
+    /// each file's header, and returns where to write its contents. If the header
+    /// has a size, padding is removed from the last block.
+    ///
+    /// `progress` is called after each block, and can return false to cancel the
+    /// transfer
+    pub fn receive<P, W, O, F>(&self, port: &mut P, mut open: O, mut progress: F) -> SerialResult<Vec<FileInfo>>
+    where
+        P: SerialPort + ?Sized,
+        W: Write,
+        O: FnMut(&FileInfo) -> std::io::Result<W>,
+        F: FnMut(&FileInfo, Progress) -> bool,
+    {
+        let request = match self.streaming {
+            true => STREAM_REQUEST,
+            false => CRC_REQUEST,
+        };
+        let mut files = Vec::new();
+        while let Some(info) = self.receive_header(port, request)? {
+            let mut out = match open(&info) {
+                Ok(out) => out,
+                Err(e) => return abort(port, SerialError::IoError(e)),
+            };
+            let (link, packet) = Link::request_transfer(port, request, false, self.timeout, self.retries)?;
+            let mut received = 0u64;
+            link.receive_blocks(port, packet, !link.streaming, |data| {
+                let len = match info.size {
+                    Some(size) => (size.saturating_sub(received) as usize).min(data.len()),
+                    None => data.len(),
+                };
+                out.write_all(&data[..len]).map_err(SerialError::IoError)?;
+                received += len as u64;
+                match progress(&info, Progress { transferred: received, total: info.size }) {
+                    true => Ok(()),
+                    false => Err(SerialError::Cancelled),
+                }
+            })?;
+            out.flush().map_err(SerialError::IoError)?;
+            files.push(info);
+        }
+        Ok(files)
+    }
+
+    /// Requests and acknowledges the next header block. Returns None at the end of
+    /// the batch
+    fn receive_header<P: SerialPort + ?Sized>(&self, port: &mut P, request: u8) -> SerialResult<Option<FileInfo>> {
+        let (link, mut packet) = Link::request_transfer(port, request, false, self.timeout, self.retries)?;
+        let mut errors = 0;
+        loop {
+            match packet {
+                Packet::Block { num: 0, data } => {
+                    write_all(port, &[ACK])?;
+                    return Ok(FileInfo::decode(&data));
+                }
+                // The sender missed our ACK of the previous file's EOT
+                Packet::Eot => write_all(port, &[ACK])?,
+                Packet::Cancel => return Err(SerialError::Cancelled),
+                _ if link.streaming => return abort(port, SerialError::LibraryError("Corrupted header block".into())),
+                _ => {
+                    errors += 1;
+                    if errors > self.retries {
+                        return abort(port, SerialError::LibraryError("Too many errors".into()));
+                    }
+                    purge(port)?;
+                    write_all(port, &[NAK])?;
+                }
+            }
+            packet = link.read_packet(port, self.timeout)?;
+        }
+    }
+}
@@ -0,0 +1,820 @@
+//! ZMODEM
+//!
+//! Data is streamed without waiting for acknowledgements. The receiver interrupts the
+//! stream with a ZRPOS frame when it sees an error, and the sender rewinds to that
+//! position. Frames use CRC-32 if the receiver supports it, else CRC-16.
+//!
+//! Crash recovery: a receiver which already has part of a file returns
+//! [Accept::Resume] from its `open` callback, and the sender starts from that offset.
+//! Because of this, files to send must implement [Seek].
+//!
+//! ```no_run
+//! use serial_rs::transfer::{ymodem::FileInfo, zmodem::Zmodem};
+//! # fn send(port: &mut dyn serial_rs::SerialPort) -> serial_rs::SerialResult<()> {
+//! let info = FileInfo::from_path("image.bin").unwrap();
+//! let file = std::fs::File::open("image.bin").unwrap();
+//! Zmodem::new().resume(true).send(port, [(info, file)], |_, _| true)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    time::{Duration, Instant},
+};
+
+use super::{crc16, crc32, read_full, send_cancel, ymodem::FileInfo, Progress, CHAR_TIMEOUT};
+use crate::{SerialError, SerialPort, SerialResult};
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const DLE: u8 = 0x10;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+const CR: u8 = 0x0D;
+
+// Header formats
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+
+// Data subpacket terminators
+const ZCRCE: u8 = b'h';
+const ZCRCG: u8 = b'i';
+const ZCRCQ: u8 = b'j';
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// Frame types
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+const ZCRC: u8 = 13;
+const ZCHALLENGE: u8 = 14;
+
+// ZRINIT capabilities
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+const ESCCTL: u8 = 0x40;
+
+// ZFILE conversion options
+const ZCBIN: u8 = 1;
+const ZCRESUM: u8 = 3;
+
+/// Data bytes per subpacket when sending
+const SUBPACKET_LEN: usize = 1024;
+/// Largest subpacket accepted when receiving
+const MAX_SUBPACKET_LEN: usize = 8192;
+
+/// What to do with a file offered by the sender
+#[derive(Debug)]
+pub enum Accept<W> {
+    /// Receive the whole file into the writer
+    Write(W),
+    /// Resume an interrupted transfer. The first `offset` bytes of the file are
+    /// already present, and the writer receives the rest
+    Resume(W, u64),
+    /// Skip the file
+    Skip,
+}
+
+/// ZMODEM transfer options
+#[derive(Debug, Copy, Clone)]
+pub struct Zmodem {
+    timeout: Duration,
+    retries: u32,
+    crc32: bool,
+    escape_ctl: bool,
+    resume: bool,
+}
+
+impl Default for Zmodem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Zmodem {
+    /// ZMODEM using CRC-32 where possible
+    pub fn new() -> Self {
+        Self { timeout: Duration::from_secs(10), retries: 10, crc32: true, escape_ctl: false, resume: false }
+    }
+
+    /// Sets how long to wait for the remote end to reply
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many consecutive errors are tolerated before the transfer is aborted
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Enables CRC-32. When disabled, CRC-16 is used for everything
+    pub fn crc32(mut self, crc32: bool) -> Self {
+        self.crc32 = crc32;
+        self
+    }
+
+    /// Escapes all control characters, for links which swallow them. When
+    /// receiving, the sender is asked to do the same
+    pub fn escape_control(mut self, escape: bool) -> Self {
+        self.escape_ctl = escape;
+        self
+    }
+
+    /// When sending, asks the receiver to resume files it already has part of
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Sends a batch of files, returning the number of bytes sent, not counting
+    /// any which were resumed or retransmitted.
+    ///
+    /// `progress` is called after each subpacket, and can return false to cancel
+    /// the transfer
+    pub fn send<P, R, I, F>(&self, port: &mut P, files: I, mut progress: F) -> SerialResult<u64>
+    where
+        P: SerialPort + ?Sized,
+        R: Read + Seek,
+        I: IntoIterator<Item = (FileInfo, R)>,
+        F: FnMut(&FileInfo, Progress) -> bool,
+    {
+        let res = (|| {
+            let mut s = Session::new(&mut *port, self);
+            let rx_buffer = s.start_send()?;
+            let mut total = 0;
+            for (info, mut data) in files {
+                total += s.send_file(&info, &mut data, self.resume, rx_buffer, &mut progress)?;
+            }
+            s.finish_send()?;
+            Ok(total)
+        })();
+        if res.is_err() {
+            send_cancel(port);
+        }
+        res
+    }
+
+    /// Receives a batch of files, returning the details of those not skipped.
+    /// `open` is called with each file's details, and decides what to do with it.
+    ///
+    /// `progress` is called after each subpacket, and can return false to cancel
+    /// the transfer
+    pub fn receive<P, W, O, F>(&self, port: &mut P, mut open: O, mut progress: F) -> SerialResult<Vec<FileInfo>>
+    where
+        P: SerialPort + ?Sized,
+        W: Write,
+        O: FnMut(&FileInfo) -> std::io::Result<Accept<W>>,
+        F: FnMut(&FileInfo, Progress) -> bool,
+    {
+        let res = (|| {
+            let mut s = Session::new(&mut *port, self);
+            let mut files = Vec::new();
+            let mut errors = 0;
+            let mut send_init = true;
+            loop {
+                if send_init {
+                    s.send_zrinit()?;
+                }
+                send_init = true;
+                let h = match s.read_header(self.timeout)? {
+                    Frame::Header(h) => h,
+                    Frame::Bad | Frame::Timeout => {
+                        s.count_error(&mut errors)?;
+                        continue;
+                    }
+                };
+                match h.kind {
+                    ZSINIT => {
+                        // The attention string is not needed, as we never interrupt the sender
+                        if let Subpacket::Data(..) = s.read_subpacket(h.crc32)? {
+                            s.send_hex_header(Header::pos(ZACK, 0))?;
+                            send_init = false;
+                        }
+                    }
+                    ZFILE => {
+                        let info = match s.read_subpacket(h.crc32)? {
+                            Subpacket::Data(data, _) => FileInfo::decode(&data),
+                            _ => None,
+                        };
+                        let info = match info {
+                            Some(info) => info,
+                            None => {
+                                s.count_error(&mut errors)?;
+                                s.send_hex_header(Header::pos(ZNAK, 0))?;
+                                send_init = false;
+                                continue;
+                            }
+                        };
+                        errors = 0;
+                        let (out, offset) = match open(&info).map_err(SerialError::IoError)? {
+                            Accept::Write(out) => (out, 0),
+                            Accept::Resume(out, offset) => (out, offset),
+                            Accept::Skip => {
+                                s.send_hex_header(Header::pos(ZSKIP, 0))?;
+                                send_init = false;
+                                continue;
+                            }
+                        };
+                        s.receive_file(&info, out, offset, &mut progress)?;
+                        files.push(info);
+                    }
+                    ZFIN => {
+                        s.send_hex_header(Header::pos(ZFIN, 0))?;
+                        // The sender finishes with "OO"
+                        for _ in 0..2 {
+                            s.raw_byte(CHAR_TIMEOUT)?;
+                        }
+                        return Ok(files);
+                    }
+                    ZABORT => return Err(SerialError::Cancelled),
+                    _ => {}
+                }
+            }
+        })();
+        if res.is_err() {
+            send_cancel(port);
+        }
+        res
+    }
+}
+
+/// Frame header. `data` holds ZP0..ZP3, which are ZF3..ZF0 for flag headers
+#[derive(Debug, Copy, Clone)]
+struct Header {
+    kind: u8,
+    data: [u8; 4],
+    /// The header used CRC-32, as do any data subpackets following it
+    crc32: bool,
+}
+
+impl Header {
+    fn pos(kind: u8, pos: u32) -> Self {
+        Self { kind, data: pos.to_le_bytes(), crc32: false }
+    }
+
+    fn flags(kind: u8, f0: u8) -> Self {
+        Self { kind, data: [0, 0, 0, f0], crc32: false }
+    }
+
+    fn position(&self) -> u64 {
+        u32::from_le_bytes(self.data) as u64
+    }
+
+    fn f0(&self) -> u8 {
+        self.data[3]
+    }
+}
+
+#[derive(Debug)]
+enum Frame {
+    Header(Header),
+    /// Corrupted header
+    Bad,
+    Timeout,
+}
+
+#[derive(Debug)]
+enum Subpacket {
+    /// Data, and the terminator
+    Data(Vec<u8>, u8),
+    Bad,
+    Timeout,
+}
+
+/// Decoded byte of ZDLE escaped data
+#[derive(Debug)]
+enum Rx {
+    Byte(u8),
+    /// Subpacket terminator
+    End(u8),
+    /// Invalid escape sequence
+    Bad,
+}
+
+fn to_pos(pos: u64) -> SerialResult<u32> {
+    u32::try_from(pos).map_err(|_| SerialError::LibraryError("ZMODEM does not support files over 4GiB".into()))
+}
+
+/// One side of a ZMODEM session, with a receive buffer
+struct Session<'a, P: SerialPort + ?Sized> {
+    port: &'a mut P,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    crc32: bool,
+    escape_ctl: bool,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl<'a, P: SerialPort + ?Sized> Session<'a, P> {
+    fn new(port: &'a mut P, opts: &Zmodem) -> Self {
+        Self {
+            port,
+            buf: vec![0; 1024],
+            start: 0,
+            end: 0,
+            crc32: opts.crc32,
+            escape_ctl: opts.escape_ctl,
+            timeout: opts.timeout,
+            retries: opts.retries,
+        }
+    }
+
+    fn count_error(&self, errors: &mut u32) -> SerialResult<()> {
+        *errors += 1;
+        match *errors > self.retries {
+            true => Err(SerialError::LibraryError("Too many errors".into())),
+            false => Ok(()),
+        }
+    }
+
+    fn raw_byte(&mut self, timeout: Duration) -> SerialResult<Option<u8>> {
+        let deadline = Instant::now() + timeout;
+        while self.start == self.end {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !self.port.poll_readable(Some(remaining)).map_err(SerialError::IoError)? {
+                return Ok(None);
+            }
+            match self.port.read(&mut self.buf) {
+                Ok(n) => (self.start, self.end) = (0, n),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted) => {}
+                Err(e) => return Err(SerialError::IoError(e)),
+            }
+            if self.start == self.end && Instant::now() >= deadline {
+                return Ok(None);
+            }
+        }
+        self.start += 1;
+        Ok(Some(self.buf[self.start - 1]))
+    }
+
+    fn has_input(&mut self) -> SerialResult<bool> {
+        Ok(self.start < self.end || self.port.poll_readable(Some(Duration::ZERO)).map_err(SerialError::IoError)?)
+    }
+
+    /// Reads a byte of escaped data. Returns None on timeout
+    fn zdl_read(&mut self, timeout: Duration) -> SerialResult<Option<Rx>> {
+        loop {
+            match self.raw_byte(timeout)? {
+                None => return Ok(None),
+                Some(ZDLE) => break,
+                // Flow control characters are never part of the data
+                Some(XON | XOFF) | Some(0x91 | 0x93) => {}
+                Some(c) => return Ok(Some(Rx::Byte(c))),
+            }
+        }
+        let mut cans = 1;
+        loop {
+            let c = match self.raw_byte(timeout)? {
+                None => return Ok(None),
+                Some(c) => c,
+            };
+            return Ok(Some(match c {
+                ZDLE => {
+                    cans += 1;
+                    if cans >= 5 {
+                        return Err(SerialError::Cancelled);
+                    }
+                    continue;
+                }
+                XON | XOFF | 0x91 | 0x93 => continue,
+                _ if cans > 1 => Rx::Bad,
+                ZCRCE | ZCRCG | ZCRCQ | ZCRCW => Rx::End(c),
+                ZRUB0 => Rx::Byte(0x7F),
+                ZRUB1 => Rx::Byte(0xFF),
+                c if c & 0x60 == 0x40 => Rx::Byte(c ^ 0x40),
+                _ => Rx::Bad,
+            }));
+        }
+    }
+
+    /// Skips to the next header and reads it. Five CANs in a row cancel the session
+    fn read_header(&mut self, timeout: Duration) -> SerialResult<Frame> {
+        let deadline = Instant::now() + timeout;
+        let mut cans = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut c = match self.raw_byte(remaining)? {
+                None => return Ok(Frame::Timeout),
+                Some(c) => c,
+            };
+            cans = if c == ZDLE { cans + 1 } else { 0 };
+            if cans >= 5 {
+                return Err(SerialError::Cancelled);
+            }
+            if c != ZPAD {
+                continue;
+            }
+            while c == ZPAD {
+                c = match self.raw_byte(CHAR_TIMEOUT)? {
+                    None => return Ok(Frame::Timeout),
+                    Some(c) => c,
+                };
+            }
+            if c != ZDLE {
+                continue;
+            }
+            return match self.raw_byte(CHAR_TIMEOUT)? {
+                None => Ok(Frame::Timeout),
+                Some(ZBIN) => self.read_bin_header(false),
+                Some(ZBIN32) => self.read_bin_header(true),
+                Some(ZHEX) => self.read_hex_header(),
+                Some(_) => continue,
+            };
+        }
+    }
+
+    fn read_bin_header(&mut self, crc32: bool) -> SerialResult<Frame> {
+        let mut raw = [0u8; 9];
+        let len = if crc32 { 9 } else { 7 };
+        for b in raw[..len].iter_mut() {
+            *b = match self.zdl_read(CHAR_TIMEOUT)? {
+                Some(Rx::Byte(c)) => c,
+                None => return Ok(Frame::Timeout),
+                Some(_) => return Ok(Frame::Bad),
+            };
+        }
+        let valid = match crc32 {
+            true => super::crc32(&raw[..5]).to_le_bytes() == raw[5..9],
+            false => crc16(&raw[..5]).to_be_bytes() == raw[5..7],
+        };
+        Ok(match valid {
+            true => Frame::Header(Header { kind: raw[0], data: [raw[1], raw[2], raw[3], raw[4]], crc32 }),
+            false => Frame::Bad,
+        })
+    }
+
+    fn read_hex_header(&mut self) -> SerialResult<Frame> {
+        let mut raw = [0u8; 7];
+        for b in raw.iter_mut() {
+            let mut digits = [0u8; 2];
+            for d in digits.iter_mut() {
+                *d = match self.raw_byte(CHAR_TIMEOUT)?.map(|c| (c as char).to_digit(16)) {
+                    None => return Ok(Frame::Timeout),
+                    Some(Some(v)) => v as u8,
+                    Some(None) => return Ok(Frame::Bad),
+                };
+            }
+            *b = digits[0] << 4 | digits[1];
+        }
+        Ok(match crc16(&raw[..5]).to_be_bytes() == raw[5..7] {
+            true => Frame::Header(Header { kind: raw[0], data: [raw[1], raw[2], raw[3], raw[4]], crc32: false }),
+            false => Frame::Bad,
+        })
+    }
+
+    /// Reads a data subpacket, checked with CRC-32 if `crc32` is set
+    fn read_subpacket(&mut self, crc32: bool) -> SerialResult<Subpacket> {
+        let mut data = Vec::new();
+        let end = loop {
+            match self.zdl_read(self.timeout)? {
+                None => return Ok(Subpacket::Timeout),
+                Some(Rx::Byte(b)) if data.len() < MAX_SUBPACKET_LEN => data.push(b),
+                Some(Rx::End(end)) => break end,
+                Some(_) => return Ok(Subpacket::Bad),
+            }
+        };
+        let mut check = [0u8; 4];
+        let len = if crc32 { 4 } else { 2 };
+        for b in check[..len].iter_mut() {
+            *b = match self.zdl_read(CHAR_TIMEOUT)? {
+                Some(Rx::Byte(c)) => c,
+                None => return Ok(Subpacket::Timeout),
+                Some(_) => return Ok(Subpacket::Bad),
+            };
+        }
+        data.push(end);
+        let valid = match crc32 {
+            true => super::crc32(&data).to_le_bytes() == check,
+            false => crc16(&data).to_be_bytes() == check[..2],
+        };
+        data.pop();
+        Ok(match valid {
+            true => Subpacket::Data(data, end),
+            false => Subpacket::Bad,
+        })
+    }
+
+    fn write(&mut self, data: &[u8], flush: bool) -> SerialResult<()> {
+        self.port.write_all(data).map_err(SerialError::IoError)?;
+        if flush {
+            self.port.flush().map_err(SerialError::IoError)?;
+        }
+        Ok(())
+    }
+
+    fn escape(&self, data: &[u8], out: &mut Vec<u8>) {
+        // Escaping CR after @ protects against Telenet's escape sequence
+        let mut prev = out.last().copied().unwrap_or(0);
+        for &b in data {
+            let escape = match b {
+                ZDLE | DLE | XON | XOFF => true,
+                0x98 | 0x90 | 0x91 | 0x93 => true,
+                CR | 0x8D => prev & 0x7F == b'@',
+                c => self.escape_ctl && c & 0x60 == 0,
+            };
+            match escape {
+                true => out.extend_from_slice(&[ZDLE, b ^ 0x40]),
+                false => out.push(b),
+            }
+            prev = b;
+        }
+    }
+
+    fn send_hex_header(&mut self, h: Header) -> SerialResult<()> {
+        let mut raw = vec![h.kind];
+        raw.extend_from_slice(&h.data);
+        raw.extend_from_slice(&crc16(&raw).to_be_bytes());
+        let mut frame = vec![ZPAD, ZPAD, ZDLE, ZHEX];
+        for b in raw {
+            frame.extend_from_slice(format!("{b:02x}").as_bytes());
+        }
+        frame.extend_from_slice(&[CR, 0x8A]);
+        // XON restarts a sender stopped by line noise which looked like XOFF
+        if h.kind != ZACK && h.kind != ZFIN {
+            frame.push(XON);
+        }
+        self.write(&frame, true)
+    }
+
+    fn send_bin_header(&mut self, h: Header) -> SerialResult<()> {
+        let mut raw = vec![h.kind];
+        raw.extend_from_slice(&h.data);
+        let mut frame = vec![ZPAD, ZDLE];
+        match self.crc32 {
+            true => {
+                frame.push(ZBIN32);
+                raw.extend_from_slice(&crc32(&raw).to_le_bytes());
+            }
+            false => {
+                frame.push(ZBIN);
+                raw.extend_from_slice(&crc16(&raw).to_be_bytes());
+            }
+        }
+        self.escape(&raw, &mut frame);
+        self.write(&frame, true)
+    }
+
+    /// Sends a data subpacket. Only subpackets which end the frame, or need an
+    /// acknowledgement, are flushed
+    fn send_subpacket(&mut self, data: &[u8], end: u8) -> SerialResult<()> {
+        let mut frame = Vec::with_capacity(data.len() + 16);
+        self.escape(data, &mut frame);
+        frame.extend_from_slice(&[ZDLE, end]);
+        let mut checked = data.to_vec();
+        checked.push(end);
+        match self.crc32 {
+            true => self.escape(&crc32(&checked).to_le_bytes(), &mut frame),
+            false => self.escape(&crc16(&checked).to_be_bytes(), &mut frame),
+        }
+        if end == ZCRCW {
+            frame.push(XON);
+        }
+        self.write(&frame, end != ZCRCG)
+    }
+
+    fn send_zrinit(&mut self) -> SerialResult<()> {
+        let mut flags = CANFDX | CANOVIO;
+        if self.crc32 {
+            flags |= CANFC32;
+        }
+        if self.escape_ctl {
+            flags |= ESCCTL;
+        }
+        self.send_hex_header(Header::flags(ZRINIT, flags))
+    }
+
+    /// Starts a session as sender, returning the receiver's buffer size, or 0 if it
+    /// can receive while writing to disk
+    fn start_send(&mut self) -> SerialResult<u16> {
+        // Starts rz on the remote end, if it is a shell
+        self.write(b"rz\r", false)?;
+        for _ in 0..=self.retries {
+            self.send_hex_header(Header::pos(ZRQINIT, 0))?;
+            match self.read_header(self.timeout)? {
+                Frame::Header(h) if h.kind == ZRINIT => {
+                    self.crc32 &= h.f0() & CANFC32 != 0;
+                    self.escape_ctl |= h.f0() & ESCCTL != 0;
+                    return Ok(u16::from_le_bytes([h.data[0], h.data[1]]));
+                }
+                Frame::Header(h) if h.kind == ZCHALLENGE => self.send_hex_header(Header { kind: ZACK, ..h })?,
+                Frame::Header(h) if h.kind == ZABORT => return Err(SerialError::Cancelled),
+                _ => {}
+            }
+        }
+        Err(SerialError::LibraryError("Receiver did not start the transfer".into()))
+    }
+
+    fn finish_send(&mut self) -> SerialResult<()> {
+        for _ in 0..=self.retries {
+            self.send_hex_header(Header::pos(ZFIN, 0))?;
+            if let Frame::Header(h) = self.read_header(self.timeout)? {
+                if h.kind == ZFIN {
+                    return self.write(b"OO", true);
+                }
+            }
+        }
+        Err(SerialError::LibraryError("Receiver did not end the session".into()))
+    }
+
+    /// Offers a file, and sends it from wherever the receiver asks. Returns the
+    /// number of bytes sent
+    fn send_file<R, F>(&mut self, info: &FileInfo, data: &mut R, resume: bool, rx_buffer: u16, progress: &mut F) -> SerialResult<u64>
+    where
+        R: Read + Seek,
+        F: FnMut(&FileInfo, Progress) -> bool,
+    {
+        let fields = info.to_fields();
+        let mut errors = 0;
+        let start = 'offer: loop {
+            self.send_bin_header(Header::flags(ZFILE, if resume { ZCRESUM } else { ZCBIN }))?;
+            self.send_subpacket(&fields, ZCRCW)?;
+            loop {
+                match self.read_header(self.timeout)? {
+                    Frame::Header(h) => match h.kind {
+                        ZRPOS => break 'offer h.position(),
+                        ZSKIP => return Ok(0),
+                        ZCRC => {
+                            // Lets the receiver check whether its partial copy matches
+                            let crc = file_crc(data, h.position())?;
+                            self.send_hex_header(Header::pos(ZCRC, crc))?;
+                            continue;
+                        }
+                        ZABORT => return Err(SerialError::Cancelled),
+                        ZFERR => return Err(SerialError::LibraryError(format!("Receiver could not write {}", info.name))),
+                        _ => {}
+                    },
+                    Frame::Bad | Frame::Timeout => {}
+                }
+                break;
+            }
+            self.count_error(&mut errors)?;
+        };
+        let mut pos = start;
+        let mut buf = vec![0u8; SUBPACKET_LEN];
+        'data: loop {
+            data.seek(SeekFrom::Start(pos)).map_err(SerialError::IoError)?;
+            self.send_bin_header(Header::pos(ZDATA, to_pos(pos)?))?;
+            let mut unacked = 0;
+            loop {
+                let read = read_full(data, &mut buf)?;
+                let eof = read < buf.len();
+                unacked += read;
+                let end = match (eof, rx_buffer != 0 && unacked + buf.len() > rx_buffer as usize) {
+                    (true, _) => ZCRCE,
+                    (false, true) => ZCRCW,
+                    (false, false) => ZCRCG,
+                };
+                self.send_subpacket(&buf[..read], end)?;
+                pos += read as u64;
+                to_pos(pos)?;
+                if !progress(info, Progress { transferred: pos, total: info.size }) {
+                    return Err(SerialError::Cancelled);
+                }
+                if eof {
+                    break;
+                }
+                // Check the reverse channel for an error report
+                let reply = match end {
+                    ZCRCW => self.read_header(self.timeout)?,
+                    _ if self.has_input()? => self.read_header(CHAR_TIMEOUT)?,
+                    _ => continue,
+                };
+                match reply {
+                    Frame::Header(h) => match h.kind {
+                        ZRPOS => {
+                            self.count_error(&mut errors)?;
+                            pos = h.position();
+                            continue 'data;
+                        }
+                        ZSKIP => return Ok(pos - start),
+                        ZABORT => return Err(SerialError::Cancelled),
+                        ZFERR => return Err(SerialError::LibraryError(format!("Receiver could not write {}", info.name))),
+                        _ => {}
+                    },
+                    Frame::Bad | Frame::Timeout if end == ZCRCW => {
+                        self.count_error(&mut errors)?;
+                        continue 'data;
+                    }
+                    Frame::Bad | Frame::Timeout => {}
+                }
+                errors = 0;
+                if end == ZCRCW {
+                    // The frame has ended, so start a new one
+                    continue 'data;
+                }
+            }
+            loop {
+                self.send_bin_header(Header::pos(ZEOF, to_pos(pos)?))?;
+                match self.read_header(self.timeout)? {
+                    Frame::Header(h) => match h.kind {
+                        ZRINIT => return Ok(pos - start),
+                        ZRPOS => {
+                            self.count_error(&mut errors)?;
+                            pos = h.position();
+                            continue 'data;
+                        }
+                        ZSKIP => return Ok(pos - start),
+                        ZABORT => return Err(SerialError::Cancelled),
+                        ZFERR => return Err(SerialError::LibraryError(format!("Receiver could not write {}", info.name))),
+                        _ => {}
+                    },
+                    Frame::Bad | Frame::Timeout => {}
+                }
+                self.count_error(&mut errors)?;
+            }
+        }
+    }
+
+    /// Receives the data of an accepted file, starting at `offset`
+    fn receive_file<W, F>(&mut self, info: &FileInfo, mut out: W, mut offset: u64, progress: &mut F) -> SerialResult<()>
+    where
+        W: Write,
+        F: FnMut(&FileInfo, Progress) -> bool,
+    {
+        let mut errors = 0;
+        self.send_hex_header(Header::pos(ZRPOS, to_pos(offset)?))?;
+        loop {
+            let h = match self.read_header(self.timeout)? {
+                Frame::Header(h) => h,
+                Frame::Bad | Frame::Timeout => {
+                    self.count_error(&mut errors)?;
+                    self.send_hex_header(Header::pos(ZRPOS, to_pos(offset)?))?;
+                    continue;
+                }
+            };
+            match h.kind {
+                ZDATA if h.position() == offset => loop {
+                    match self.read_subpacket(h.crc32)? {
+                        Subpacket::Data(data, end) => {
+                            out.write_all(&data).map_err(SerialError::IoError)?;
+                            offset += data.len() as u64;
+                            errors = 0;
+                            if !progress(info, Progress { transferred: offset, total: info.size }) {
+                                return Err(SerialError::Cancelled);
+                            }
+                            match end {
+                                ZCRCG => {}
+                                ZCRCQ => self.send_hex_header(Header::pos(ZACK, to_pos(offset)?))?,
+                                ZCRCW => {
+                                    self.send_hex_header(Header::pos(ZACK, to_pos(offset)?))?;
+                                    break;
+                                }
+                                _ => break,
+                            }
+                        }
+                        Subpacket::Bad | Subpacket::Timeout => {
+                            // Anything the sender streams until it sees this is skipped
+                            self.count_error(&mut errors)?;
+                            self.send_hex_header(Header::pos(ZRPOS, to_pos(offset)?))?;
+                            break;
+                        }
+                    }
+                },
+                // Data from before the sender saw our ZRPOS
+                ZDATA => {
+                    self.count_error(&mut errors)?;
+                    self.send_hex_header(Header::pos(ZRPOS, to_pos(offset)?))?;
+                }
+                ZEOF if h.position() == offset => return out.flush().map_err(SerialError::IoError),
+                // The sender missed our ZRPOS
+                ZFILE => {
+                    self.read_subpacket(h.crc32)?;
+                    self.send_hex_header(Header::pos(ZRPOS, to_pos(offset)?))?;
+                }
+                ZABORT | ZFIN => return Err(SerialError::Cancelled),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// CRC-32 of the first `len` bytes of a file, or all of it if `len` is 0
+fn file_crc<R: Read + Seek>(data: &mut R, len: u64) -> SerialResult<u32> {
+    data.seek(SeekFrom::Start(0)).map_err(SerialError::IoError)?;
+    let mut contents = Vec::new();
+    let res = match len {
+        0 => data.read_to_end(&mut contents),
+        _ => data.take(len).read_to_end(&mut contents),
+    };
+    res.map_err(SerialError::IoError)?;
+    Ok(crc32(&contents))
+}
@@ -0,0 +1,238 @@
+//! u-blox UBX protocol, mixed with NMEA
+//!
+//! u-blox receivers output binary UBX frames and NMEA sentences on the same port.
+//! [UbxCodec] separates the two, yielding each as a [GnssFrame]. Bytes which are not
+//! part of either are skipped, so decoding resynchronises after line noise.
+//!
+//! ```no_run
+//! # fn example<P: serial_rs::SerialPort>(port: P) -> std::io::Result<()> {
+//! use serial_rs::{codec::FramedPort, ubx::{GnssFrame, UbxCodec, UbxFrame}};
+//! let mut gps = FramedPort::new(port, UbxCodec::new());
+//! // Poll UBX-NAV-PVT
+//! gps.send(UbxFrame::new(0x01, 0x07, Vec::new()))?;
+//! while let Some(frame) = gps.read_frame()? {
+//!     match frame {
+//!         GnssFrame::Ubx(f) if f.class == 0x01 && f.id == 0x07 => break,
+//!         GnssFrame::Nmea(sentence) => println!("{sentence}"),
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::ErrorKind;
+
+use crate::codec::{Decoder, Encoder};
+
+const SYNC_1: u8 = 0xB5;
+const SYNC_2: u8 = 0x62;
+/// Sync chars, class, id and length
+const UBX_HEADER_LEN: usize = 6;
+/// NMEA 0183 allows 82 characters, but proprietary sentences are often longer
+const MAX_NMEA_LEN: usize = 256;
+
+/// UBX frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UbxFrame {
+    /// Message class
+    pub class: u8,
+    /// Message ID
+    pub id: u8,
+    /// Payload
+    pub payload: Vec<u8>,
+}
+
+impl UbxFrame {
+    /// Creates a frame. An empty payload polls the message
+    pub fn new(class: u8, id: u8, payload: Vec<u8>) -> Self {
+        Self { class, id, payload }
+    }
+
+    /// Appends the frame's wire format to `dst`
+    fn write_to(&self, dst: &mut Vec<u8>) -> std::io::Result<()> {
+        let len = u16::try_from(self.payload.len())
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "UBX payload is too long"))?;
+        let start = dst.len();
+        dst.extend_from_slice(&[SYNC_1, SYNC_2, self.class, self.id]);
+        dst.extend_from_slice(&len.to_le_bytes());
+        dst.extend_from_slice(&self.payload);
+        let (a, b) = checksum(&dst[start + 2..]);
+        dst.extend_from_slice(&[a, b]);
+        Ok(())
+    }
+}
+
+/// Frame decoded from a mixed stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GnssFrame {
+    /// UBX frame
+    Ubx(UbxFrame),
+    /// NMEA sentence, from `$` up to but excluding the line ending
+    Nmea(String),
+}
+
+/// 8 bit Fletcher checksum over the class, id, length and payload
+pub fn checksum(data: &[u8]) -> (u8, u8) {
+    data.iter().fold((0u8, 0u8), |(a, b), x| {
+        let a = a.wrapping_add(*x);
+        (a, b.wrapping_add(a))
+    })
+}
+
+/// Codec for a stream of UBX frames and NMEA sentences
+#[derive(Debug, Copy, Clone)]
+pub struct UbxCodec {
+    max_payload: usize,
+    check_nmea: bool,
+}
+
+impl Default for UbxCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UbxCodec {
+    /// Creates a codec accepting UBX payloads of up to 8192 bytes, and checking
+    /// NMEA checksums
+    pub fn new() -> Self {
+        Self { max_payload: 8192, check_nmea: true }
+    }
+
+    /// Sets the largest UBX payload accepted. A larger length field is treated as
+    /// line noise
+    pub fn max_payload(mut self, max: usize) -> Self {
+        self.max_payload = max;
+        self
+    }
+
+    /// Sets whether NMEA sentences with a `*hh` checksum are verified. Sentences
+    /// without one are always accepted
+    pub fn check_nmea(mut self, check: bool) -> Self {
+        self.check_nmea = check;
+        self
+    }
+
+    /// Attempts to decode a UBX frame at the front of `src`, which starts with the
+    /// sync chars
+    fn decode_ubx(&self, src: &mut Vec<u8>) -> std::io::Result<Option<Option<GnssFrame>>> {
+        if src.len() < UBX_HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u16::from_le_bytes([src[4], src[5]]) as usize;
+        if len > self.max_payload {
+            // Not a real frame, so resync after the first sync char
+            src.remove(0);
+            return Ok(Some(None));
+        }
+        let total = UBX_HEADER_LEN + len + 2;
+        if src.len() < total {
+            return Ok(None);
+        }
+        if checksum(&src[2..total - 2]) != (src[total - 2], src[total - 1]) {
+            // Only drop the sync chars, as a real frame may start inside this one
+            src.drain(..2);
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "UBX checksum mismatch"));
+        }
+        let frame: Vec<u8> = src.drain(..total).collect();
+        Ok(Some(Some(GnssFrame::Ubx(UbxFrame {
+            class: frame[2],
+            id: frame[3],
+            payload: frame[UBX_HEADER_LEN..total - 2].to_vec(),
+        }))))
+    }
+
+    /// Attempts to decode an NMEA sentence at the front of `src`, which starts with `$`
+    fn decode_nmea(&self, src: &mut Vec<u8>) -> std::io::Result<Option<Option<GnssFrame>>> {
+        let end = src.iter().skip(1).position(|b| matches!(*b, b'\r' | b'\n' | b'$' | SYNC_1)).map(|p| p + 1);
+        let end = match end {
+            Some(end) => end,
+            None if src.len() > MAX_NMEA_LEN => {
+                src.remove(0);
+                return Ok(Some(None));
+            }
+            None => return Ok(None),
+        };
+        if !matches!(src[end], b'\r' | b'\n') {
+            // Another frame started before this sentence ended
+            src.drain(..end);
+            return Ok(Some(None));
+        }
+        let line: Vec<u8> = src.drain(..end).collect();
+        let sentence = match String::from_utf8(line) {
+            Ok(s) => s,
+            Err(_) => return Err(std::io::Error::new(ErrorKind::InvalidData, "NMEA sentence is not ASCII")),
+        };
+        if self.check_nmea && !nmea_checksum_ok(&sentence) {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "NMEA checksum mismatch"));
+        }
+        Ok(Some(Some(GnssFrame::Nmea(sentence))))
+    }
+}
+
+fn nmea_checksum_ok(sentence: &str) -> bool {
+    let (body, check) = match sentence[1..].rsplit_once('*') {
+        Some(parts) => parts,
+        None => return true,
+    };
+    let sum = body.bytes().fold(0u8, |acc, b| acc ^ b);
+    u8::from_str_radix(check.trim(), 16).map(|c| c == sum).unwrap_or(false)
+}
+
+impl Decoder for UbxCodec {
+    type Item = GnssFrame;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<GnssFrame>, std::io::Error> {
+        loop {
+            // Skip to the start of the next frame. A lone trailing 0xB5 may be the
+            // first half of the UBX sync
+            let start = src
+                .windows(2)
+                .position(|w| w == [SYNC_1, SYNC_2] || w[0] == b'$')
+                .or_else(|| src.last().filter(|b| **b == SYNC_1 || **b == b'$').map(|_| src.len() - 1));
+            match start {
+                Some(start) => drop(src.drain(..start)),
+                None => {
+                    src.clear();
+                    return Ok(None);
+                }
+            }
+            let res = match src[0] {
+                b'$' => self.decode_nmea(src)?,
+                _ if src.len() < 2 => return Ok(None),
+                _ => self.decode_ubx(src)?,
+            };
+            match res {
+                None => return Ok(None),
+                Some(Some(frame)) => return Ok(Some(frame)),
+                Some(None) => {}
+            }
+        }
+    }
+}
+
+impl Encoder<UbxFrame> for UbxCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: UbxFrame, dst: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        item.write_to(dst)
+    }
+}
+
+impl Encoder<GnssFrame> for UbxCodec {
+    type Error = std::io::Error;
+
+    /// NMEA sentences are sent with a CR LF line ending appended
+    fn encode(&mut self, item: GnssFrame, dst: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        match item {
+            GnssFrame::Ubx(frame) => frame.write_to(dst),
+            GnssFrame::Nmea(sentence) => {
+                dst.extend_from_slice(sentence.as_bytes());
+                dst.extend_from_slice(b"\r\n");
+                Ok(())
+            }
+        }
+    }
+}
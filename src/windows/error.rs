@@ -1,7 +1,15 @@
 use std::ptr;
 use winapi::{
-    shared::{minwindef::DWORD, ntdef::MAKELANGID},
+    shared::{
+        minwindef::DWORD,
+        ntdef::MAKELANGID,
+        winerror::{
+            ERROR_ACCESS_DENIED, ERROR_BAD_COMMAND, ERROR_DEVICE_NOT_CONNECTED, ERROR_DEVICE_REMOVED,
+            ERROR_FILE_NOT_FOUND, ERROR_GEN_FAILURE, ERROR_INVALID_HANDLE,
+        },
+    },
     um::{
+        cfgmgr32::{CONFIGRET, CR_ACCESS_DENIED, CR_NOT_DISABLEABLE, CR_REMOVE_VETOED},
         errhandlingapi::GetLastError,
         winbase::{FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS},
         winnt::{LANG_SYSTEM_DEFAULT, SUBLANG_SYS_DEFAULT, WCHAR},
@@ -13,12 +21,46 @@ use crate::SerialError;
 #[macro_export]
 /// Test macro
 macro_rules! return_win_op {
-    ($op:expr) => {
-        match unsafe { $op } {
+    ($op:expr) => {{
+        // Callers pass bare Win32 calls, which are all unsafe
+        #[allow(clippy::macro_metavars_in_unsafe)]
+        let res = unsafe { $op };
+        match res {
             0 => Err(get_win_error()),
             _ => Ok(()),
         }
+    }};
+}
+
+/// ERROR_NO_SUCH_DEVICE (not exported by winapi)
+const ERROR_NO_SUCH_DEVICE: DWORD = 433;
+
+/// Returns true if `code`, from an operation on an open handle, means the device
+/// has gone away. USB adapters report these once unplugged, and most drivers do
+/// for every handle opened before the system slept
+pub(crate) fn is_disconnect_error(code: DWORD) -> bool {
+    matches!(
+        code,
+        ERROR_ACCESS_DENIED
+            | ERROR_BAD_COMMAND
+            | ERROR_DEVICE_NOT_CONNECTED
+            | ERROR_DEVICE_REMOVED
+            | ERROR_FILE_NOT_FOUND
+            | ERROR_GEN_FAILURE
+            | ERROR_INVALID_HANDLE
+            | ERROR_NO_SUCH_DEVICE
+    )
+}
+
+/// Converts a configuration manager (CM_) return code, which is not a Win32 error
+pub(crate) fn cm_error(code: CONFIGRET) -> SerialError {
+    let desc = match code {
+        CR_ACCESS_DENIED => "Access denied, restarting a device needs administrator rights".to_string(),
+        CR_REMOVE_VETOED => "Another program or driver refused to release the device".to_string(),
+        CR_NOT_DISABLEABLE => "The device cannot be disabled".to_string(),
+        _ => format!("Configuration manager error {code:#x}"),
     };
+    SerialError::OsError { code, desc }
 }
 
 pub(crate) fn get_win_error() -> crate::SerialError {
@@ -50,7 +92,7 @@ pub(crate) fn get_win_error() -> crate::SerialError {
             Ok(msg) => SerialError::OsError { code: e, desc: msg },
             Err(..) => SerialError::OsError {
                 code: e,
-                desc: format!("Unknown, FormatMessageW() returned invalid UTF-16 string"),
+                desc: "Unknown, FormatMessageW() returned invalid UTF-16 string".to_string(),
             },
         }
     }
@@ -4,27 +4,32 @@
 //! by Chris Liechti <cliechti@gmx.net>
 
 use std::fmt::Debug;
+use std::os::windows::io::{AsHandle, AsRawHandle, BorrowedHandle, FromRawHandle, IntoRawHandle, OwnedHandle, RawHandle};
+use std::sync::{atomic::{AtomicU32, Ordering}, Arc, Condvar, Mutex, MutexGuard};
 use std::{cmp::max, io::ErrorKind};
 
-use crate::{return_win_op, SerialPort, SerialPortSettings, SerialResult, FlowControl};
+use crate::{buffered::PeekBuffer, cancel::CancelToken, return_win_op, SerialError, SerialPort, SerialIo, Configurable, BufferControl, SerialPortSettings, SerialResult, FlowControl, DriverConfigDump, LineErrorCounts, LineErrorPolicy, LineErrors, ModemStatus, ParityErrorPolicy, CommEventMask, QueueStatus, SettingMismatch};
+use winapi::um::cfgmgr32::{CM_Disable_DevNode, CM_Enable_DevNode, CM_DISABLE_UI_NOT_OK, CR_SUCCESS};
 use winapi::um::fileapi::CreateFileW;
 use winapi::um::handleapi::DuplicateHandle;
-use winapi::um::ioapiset::GetOverlappedResult;
 use winapi::um::processthreadsapi::GetCurrentProcess;
-use winapi::um::synchapi::CreateEventW;
 use winapi::um::winnt::DUPLICATE_SAME_ACCESS;
+use winapi::um::ioapiset::{CancelIoEx, DeviceIoControl, GetOverlappedResult};
+use winapi::um::synchapi::{CreateEventW, WaitForMultipleObjects, WaitForSingleObject};
 use winapi::{
     shared::{
         minwindef::{DWORD, LPVOID},
         winerror::{
-            ERROR_INVALID_USER_BUFFER, ERROR_IO_PENDING, ERROR_NOT_ENOUGH_MEMORY,
-            ERROR_OPERATION_ABORTED, ERROR_SUCCESS,
+            ERROR_INVALID_FUNCTION, ERROR_INVALID_PARAMETER, ERROR_INVALID_USER_BUFFER, ERROR_IO_INCOMPLETE, ERROR_IO_PENDING,
+            ERROR_NOT_ENOUGH_MEMORY, ERROR_NOT_FOUND, ERROR_NOT_SUPPORTED, ERROR_OPERATION_ABORTED, ERROR_SUCCESS,
+            WAIT_TIMEOUT,
         },
     },
     um::{
         commapi::{
             ClearCommBreak, ClearCommError, EscapeCommFunction, GetCommModemStatus, GetCommState,
-            PurgeComm, SetCommBreak, SetCommMask, SetCommState, SetCommTimeouts, SetupComm,
+            GetCommTimeouts, WaitCommEvent,
+            PurgeComm, SetCommBreak, SetCommMask, SetCommState, SetCommTimeouts, SetupComm, TransmitCommChar,
         },
         errhandlingapi::GetLastError,
         fileapi::{ReadFile, WriteFile, OPEN_EXISTING},
@@ -32,10 +37,10 @@ use winapi::{
         minwinbase::OVERLAPPED,
         synchapi::{ResetEvent},
         winbase::{
-            CLRDTR, CLRRTS, COMMTIMEOUTS, COMSTAT, DCB, DTR_CONTROL_DISABLE,
+            CLRDTR, CLRRTS, COMMTIMEOUTS, COMSTAT, DCB, DTR_CONTROL_DISABLE, DTR_CONTROL_ENABLE,
             DTR_CONTROL_HANDSHAKE, EVENPARITY, FILE_FLAG_OVERLAPPED, MARKPARITY, MS_CTS_ON,
             MS_DSR_ON, MS_RING_ON, MS_RLSD_ON, NOPARITY, ODDPARITY, ONE5STOPBITS, ONESTOPBIT,
-            PURGE_RXABORT, PURGE_RXCLEAR, PURGE_TXABORT, PURGE_TXCLEAR, RTS_CONTROL_DISABLE,
+            INFINITE, PURGE_RXABORT, PURGE_RXCLEAR, WAIT_OBJECT_0, PURGE_TXABORT, PURGE_TXCLEAR, RTS_CONTROL_DISABLE, RTS_CONTROL_ENABLE,
             RTS_CONTROL_HANDSHAKE, SETDTR, SETRTS, SETXOFF, SETXON,
             SPACEPARITY, TWOSTOPBITS,
         },
@@ -48,16 +53,43 @@ use self::error::get_win_error;
 pub (crate) mod error;
 pub mod port_lister;
 
-/// Windows COM Port
+// ClearCommError error flags (not exported by winapi)
+const CE_RXOVER: DWORD = 0x0001;
+const CE_OVERRUN: DWORD = 0x0002;
+const CE_RXPARITY: DWORD = 0x0004;
+const CE_FRAME: DWORD = 0x0008;
+const CE_BREAK: DWORD = 0x0010;
+
+// Serial driver IOCTLs for the raw modem control register (not exported by winapi)
+const IOCTL_SERIAL_GET_DTRRTS: DWORD = 0x001B_0078;
+const IOCTL_SERIAL_GET_MODEM_CONTROL: DWORD = 0x001B_0094;
+const IOCTL_SERIAL_SET_MODEM_CONTROL: DWORD = 0x001B_0098;
+/// Modem control register bit for the UART's internal loopback
+const SERIAL_MCR_LOOP: DWORD = 0x10;
+// IOCTL_SERIAL_GET_DTRRTS output bits
+const SERIAL_DTR_STATE: DWORD = 0x01;
+const SERIAL_RTS_STATE: DWORD = 0x02;
 
+/// Windows COM Port
+///
+/// Clones of a port share the same device handle, which is only closed once
+/// the last clone is dropped. Each clone owns its own OVERLAPPED events
 pub struct COMPort {
     settings: SerialPortSettings,
     handle: HANDLE,
-    overlapped_read: OVERLAPPED,
-    overlapped_write: OVERLAPPED,
+    owner: Arc<HandleOwner>,
+    overlapped_read: Mutex<OVERLAPPED>,
+    overlapped_write: Mutex<WriteState>,
     path: String,
 }
 
+/// OVERLAPPED for writes, and the data of a write without a timeout which the
+/// driver is still sending
+struct WriteState {
+    overlapped: OVERLAPPED,
+    pending: Option<Vec<u8>>,
+}
+
 impl Debug for COMPort {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("COMPort").field("settings", &self.settings).field("path", &self.path).finish()
@@ -67,13 +99,229 @@ impl Debug for COMPort {
 unsafe impl Send for COMPort {}
 unsafe impl Sync for COMPort {}
 
+/// Owns a device handle shared by all clones of a [COMPort]
+#[derive(Debug)]
+struct HandleOwner {
+    handle: HANDLE,
+    errors: Mutex<LineErrorCounts>,
+    lines: Mutex<OutputLines>,
+    /// ClearCommError flags not yet reported by a read or write
+    unreported: AtomicU32,
+    peeked: PeekBuffer,
+    events: EventWaiter,
+}
+
+impl HandleOwner {
+    fn new(handle: HANDLE) -> Self {
+        Self {
+            handle,
+            errors: Mutex::new(LineErrorCounts::default()),
+            lines: Mutex::new(OutputLines::default()),
+            unreported: AtomicU32::new(0),
+            peeked: PeekBuffer::default(),
+            events: EventWaiter::default(),
+        }
+    }
+
+    /// Locks the error counters. Poisoning is ignored, as the counters are always valid
+    fn errors(&self) -> MutexGuard<'_, LineErrorCounts> {
+        self.errors.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Locks the cached output line state. Poisoning is ignored, as it is always valid
+    fn lines(&self) -> MutexGuard<'_, OutputLines> {
+        self.lines.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Last state set on the DTR and RTS outputs, for drivers which cannot report
+/// them. None whilst the driver controls the line for handshaking
+#[derive(Debug, Default, Copy, Clone)]
+struct OutputLines {
+    dtr: Option<bool>,
+    rts: Option<bool>,
+}
+
+/// Comm events of a handle, shared by all clones of a port. The event mask applies
+/// to the whole handle, and a WaitCommEvent cannot be issued whilst another is
+/// pending, so one thread at a time waits for the events every thread wants and
+/// passes on what it receives
+#[derive(Debug, Default)]
+struct EventWaiter {
+    state: Mutex<EventState>,
+    received: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct EventState {
+    /// Events wanted by each subscription
+    wanted: Vec<CommEventMask>,
+    /// Mask last applied with SetCommMask
+    applied: CommEventMask,
+    /// Set whilst a thread is waiting in WaitCommEvent
+    waiting: bool,
+    /// Incremented each time events are received
+    generation: u64,
+    /// Events received last
+    events: CommEventMask,
+}
+
+impl EventState {
+    /// Applies the events wanted by every subscription. A pending WaitCommEvent
+    /// returns without events when the mask changes, and is issued again
+    fn apply_mask(&mut self, handle: HANDLE) -> SerialResult<()> {
+        let mask = self.wanted.iter().fold(CommEventMask::empty(), |mask, wanted| mask | *wanted);
+        if mask != self.applied {
+            return_win_op!(SetCommMask(handle, mask.bits() as DWORD))?;
+            self.applied = mask;
+        }
+        Ok(())
+    }
+}
+
+impl EventWaiter {
+    /// Locks the state. Poisoning is ignored, as it is always valid
+    fn lock(&self) -> MutexGuard<'_, EventState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts reporting the events in `mask` to a new subscription
+    fn subscribe(&self, handle: HANDLE, mask: CommEventMask) -> SerialResult<EventSubscription<'_>> {
+        let mut state = self.lock();
+        state.wanted.push(mask);
+        if let Err(e) = state.apply_mask(handle) {
+            state.wanted.pop();
+            return Err(e);
+        }
+        Ok(EventSubscription { waiter: self, handle, mask, seen: state.generation })
+    }
+}
+
+/// Events wanted by one waiting thread, see [EventWaiter]
+struct EventSubscription<'a> {
+    waiter: &'a EventWaiter,
+    handle: HANDLE,
+    mask: CommEventMask,
+    /// Generation of the events last reported
+    seen: u64,
+}
+
+impl<'a> EventSubscription<'a> {
+    /// Waits for any of the subscribed events received since the last call, or since
+    /// subscribing. Returns None if `timeout` elapsed first
+    fn next(&mut self, timeout: Option<std::time::Duration>) -> SerialResult<Option<CommEventMask>> {
+        let deadline = timeout.map(|t| std::time::Instant::now() + t);
+        let mut state = self.waiter.lock();
+        loop {
+            if state.generation != self.seen {
+                self.seen = state.generation;
+                let events = state.events & self.mask;
+                if !events.is_empty() {
+                    return Ok(Some(events));
+                }
+            }
+            let remaining = deadline.map(|d| d.saturating_duration_since(std::time::Instant::now()));
+            if remaining == Some(std::time::Duration::ZERO) {
+                return Ok(None);
+            }
+            state = match (state.waiting, remaining) {
+                (true, Some(r)) => self.waiter.received.wait_timeout(state, r).unwrap_or_else(|e| e.into_inner()).0,
+                (true, None) => self.waiter.received.wait(state).unwrap_or_else(|e| e.into_inner()),
+                (false, _) => self.wait_comm_event(state, remaining)?,
+            };
+        }
+    }
+
+    /// Waits in WaitCommEvent on behalf of every subscription, and wakes the other
+    /// waiting threads once it returns. The lock is released whilst waiting
+    fn wait_comm_event(
+        &self,
+        mut state: MutexGuard<'a, EventState>,
+        timeout: Option<std::time::Duration>,
+    ) -> SerialResult<MutexGuard<'a, EventState>> {
+        state.apply_mask(self.handle)?;
+        let mut overlapped = new_overlapped(true)?;
+        let mut mask: DWORD = 0;
+        let mut res = Ok(());
+        if unsafe { WaitCommEvent(self.handle, &mut mask, &mut overlapped) } == 0 {
+            if unsafe { GetLastError() } == ERROR_IO_PENDING {
+                state.waiting = true;
+                drop(state);
+                let wait_ms = timeout.map(|t| t.as_millis().clamp(1, (INFINITE - 1) as u128) as DWORD).unwrap_or(INFINITE);
+                let mut unused: DWORD = 0;
+                if unsafe { WaitForSingleObject(overlapped.hEvent, wait_ms) } != WAIT_OBJECT_0 {
+                    unsafe { CancelIoEx(self.handle, &mut overlapped) };
+                }
+                if unsafe { GetOverlappedResult(self.handle, &mut overlapped, &mut unused, 1) } == 0 {
+                    // A cancelled wait has no events, and is not an error
+                    if unsafe { GetLastError() } != ERROR_OPERATION_ABORTED {
+                        res = Err(get_win_error());
+                    }
+                    mask = 0;
+                }
+                state = self.waiter.lock();
+                state.waiting = false;
+            } else {
+                res = Err(get_win_error());
+            }
+        }
+        unsafe { CloseHandle(overlapped.hEvent) };
+        let events = CommEventMask::from_bits_truncate(mask as u16);
+        if !events.is_empty() {
+            state.generation += 1;
+            state.events = events;
+        }
+        self.waiter.received.notify_all();
+        res.map(|()| state)
+    }
+}
+
+impl Drop for EventSubscription<'_> {
+    fn drop(&mut self) {
+        // The mask is narrowed by the next wait, rather than interrupting one in progress
+        let mut state = self.waiter.lock();
+        if let Some(i) = state.wanted.iter().position(|wanted| *wanted == self.mask) {
+            state.wanted.swap_remove(i);
+        }
+    }
+}
+
+unsafe impl Send for HandleOwner {}
+unsafe impl Sync for HandleOwner {}
+
+impl Drop for HandleOwner {
+    fn drop(&mut self) {
+        // A null handle has been released with IntoRawHandle
+        if !self.handle.is_null() {
+            unsafe { CloseHandle(self.handle) };
+        }
+    }
+}
+
+/// Creates an OVERLAPPED struct with its own event
+fn new_overlapped(manual_reset: bool) -> SerialResult<OVERLAPPED> {
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    overlapped.hEvent =
+        unsafe { CreateEventW(std::ptr::null_mut(), manual_reset as i32, 0, std::ptr::null_mut()) };
+    if overlapped.hEvent.is_null() {
+        return Err(get_win_error());
+    }
+    Ok(overlapped)
+}
+
 impl COMPort {
-    /// Creates a new COM Port and opens it
+    /// Creates a new COM Port and opens it.
+    ///
+    /// `path` is either a port name such as `COM3`, or a full device path such as a
+    /// device interface path (`\\?\USB#VID_...#{guid}`) from
+    /// [port_lister::DeviceInterfaceLister], which is opened as is
     #[allow(unused)]
     pub fn new(path: String, settings: Option<SerialPortSettings>) -> SerialResult<Self> {
         let mut name = Vec::<u16>::with_capacity(4 + path.len() + 1);
 
-        name.extend(r"\\.\".encode_utf16());
+        if !path.starts_with(r"\\") {
+            name.extend(r"\\.\".encode_utf16());
+        }
         name.extend(path.encode_utf16());
         name.push(0);
 
@@ -92,31 +340,12 @@ impl COMPort {
         if handle == INVALID_HANDLE_VALUE {
             return Err(get_win_error());
         }
-        let mut overlapped_read: OVERLAPPED = unsafe { std::mem::zeroed() };
-        let mut overlapped_write: OVERLAPPED = unsafe { std::mem::zeroed() };
-        overlapped_read.hEvent =
-            unsafe { CreateEventW(std::ptr::null_mut(), 1, 0, std::ptr::null_mut()) };
-        overlapped_write.hEvent =
-            unsafe { CreateEventW(std::ptr::null_mut(), 0, 0, std::ptr::null_mut()) };
-
-        if overlapped_read.hEvent == INVALID_HANDLE_VALUE {
-            return Err(get_win_error());
-        }
-        if overlapped_write.hEvent == INVALID_HANDLE_VALUE {
-            return Err(get_win_error());
-        }
+        let mut ret = Self::from_shared(handle, Arc::new(HandleOwner::new(handle)), settings.unwrap_or_default(), path)?;
 
         return_win_op!(SetupComm(handle, 4096, 4096))?;
 
-        let mut ret = Self {
-            settings: settings.unwrap_or_default(),
-            handle,
-            path,
-            overlapped_read,
-            overlapped_write,
-        };
-
         ret.reconfigure_port()?;
+        crate::wait_for_open_carrier(&ret)?;
 
         return_win_op!(PurgeComm(
             ret.handle,
@@ -125,19 +354,58 @@ impl COMPort {
         Ok(ret)
     }
 
-    fn get_comm_modem_status(&self) -> DWORD {
-        let mut stat: DWORD = 0;
-        unsafe { GetCommModemStatus(self.handle, &mut stat) };
-        return stat;
+    /// Takes ownership of a COM port handle opened elsewhere and applies `settings` to it.
+    ///
+    /// # Safety
+    /// `handle` must be an open comm device handle, opened with `FILE_FLAG_OVERLAPPED`,
+    /// which is not owned by anything else
+    pub unsafe fn from_raw_handle_with_settings(handle: RawHandle, settings: SerialPortSettings) -> SerialResult<Self> {
+        let handle = handle as HANDLE;
+        let mut port = Self::from_shared(handle, Arc::new(HandleOwner::new(handle)), settings, String::new())?;
+        port.reconfigure_port()?;
+        Ok(port)
     }
-}
 
-impl super::SerialPort for COMPort {
-    fn setting(&mut self) -> &mut SerialPortSettings {
-        &mut self.settings
+    /// Restarts the device behind the port by disabling and re-enabling its device
+    /// node, which recovers USB adapters whose driver or firmware has hung without
+    /// replugging them. The handle stops working, so the port must be reopened, for
+    /// example by [crate::reconnect::ReconnectingPort].
+    ///
+    /// Needs administrator rights, and only works for ports opened by their `COMx` name
+    pub fn usb_reset(&self) -> SerialResult<()> {
+        let dev = port_lister::find_device_instance(&self.path)?;
+        match unsafe { CM_Disable_DevNode(dev, CM_DISABLE_UI_NOT_OK) } {
+            CR_SUCCESS => {}
+            code => return Err(error::cm_error(code)),
+        }
+        match unsafe { CM_Enable_DevNode(dev, 0) } {
+            CR_SUCCESS => Ok(()),
+            code => Err(error::cm_error(code)),
+        }
     }
-    fn reconfigure_port(&mut self) -> SerialResult<()> {
-        // First set timeouts
+
+    /// Creates a port on top of an already shared handle, with its own OVERLAPPED events
+    fn from_shared(handle: HANDLE, owner: Arc<HandleOwner>, settings: SerialPortSettings, path: String) -> SerialResult<Self> {
+        let overlapped_read = new_overlapped(true)?;
+        let overlapped_write = match new_overlapped(false) {
+            Ok(o) => o,
+            Err(e) => {
+                unsafe { CloseHandle(overlapped_read.hEvent) };
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            settings,
+            handle,
+            owner,
+            path,
+            overlapped_read: Mutex::new(overlapped_read),
+            overlapped_write: Mutex::new(WriteState { overlapped: overlapped_write, pending: None }),
+        })
+    }
+
+    /// Builds and applies COMMTIMEOUTS from the current settings
+    fn apply_timeouts(&self) -> SerialResult<()> {
         let mut timeouts: COMMTIMEOUTS = unsafe { std::mem::zeroed() };
         if let Some(timeout) = self.settings.read_timeout {
             if timeout == 0 {
@@ -145,11 +413,8 @@ impl super::SerialPort for COMPort {
             } else {
                 timeouts.ReadTotalTimeoutConstant = max(timeout as u32, 1);
             }
-            if timeout != 0 && self.settings.inter_byte_timeout.is_some() {
-                timeouts.ReadIntervalTimeout = max(
-                    self.settings.inter_byte_timeout.unwrap() as u32,
-                    1,
-                );
+            if let Some(inter_byte) = self.settings.inter_byte_timeout.filter(|_| timeout != 0) {
+                timeouts.ReadIntervalTimeout = max(inter_byte as u32, 1);
             }
         }
 
@@ -160,8 +425,366 @@ impl super::SerialPort for COMPort {
                 timeouts.WriteTotalTimeoutConstant = max(timeout as u32, 1);
             }
         }
-        return_win_op!(SetCommTimeouts(self.handle, &mut timeouts))?;
-        return_win_op!(SetCommMask(self.handle, 0x0080))?;
+        if self.settings.fill_reads && self.settings.blocking {
+            // Return as soon as anything is buffered, read_fill applies the timeouts
+            timeouts.ReadIntervalTimeout = MAXDWORD;
+            timeouts.ReadTotalTimeoutMultiplier = MAXDWORD;
+            timeouts.ReadTotalTimeoutConstant = MAXDWORD - 1;
+        }
+        if !self.settings.blocking {
+            // Return immediately with whatever is buffered
+            timeouts.ReadIntervalTimeout = MAXDWORD;
+            timeouts.ReadTotalTimeoutMultiplier = 0;
+            timeouts.ReadTotalTimeoutConstant = 0;
+        }
+        return_win_op!(SetCommTimeouts(self.handle, &mut timeouts))
+    }
+
+    /// Returns true if the driver's output queue is empty, so a write would not have to wait
+    #[cfg(feature = "embedded-io")]
+    pub(crate) fn is_write_ready(&self) -> SerialResult<bool> {
+        Ok(self.bytes_to_write()? == 0)
+    }
+}
+
+/// Raw driver structures, for use with [SerialPortExt]
+pub use winapi::um::winbase::{COMMTIMEOUTS as CommTimeouts, DCB as Dcb};
+
+/// Windows specific extensions for settings the portable [SerialPort] API does not cover
+pub trait SerialPortExt {
+    /// Reads the live DCB of the port, passes it to `f` for modification, and
+    /// applies the result with SetCommState once `f` returns.
+    ///
+    /// Note that [Configurable::reconfigure_port] rebuilds the DCB fields it manages,
+    /// so changes to those fields made here are overwritten by a later reconfigure
+    fn with_dcb<F: FnOnce(&mut Dcb)>(&self, f: F) -> SerialResult<()>;
+    /// Reads the live COMMTIMEOUTS of the port, passes them to `f` for modification,
+    /// and applies the result with SetCommTimeouts once `f` returns.
+    ///
+    /// Like [SerialPortExt::with_dcb], changes are overwritten by [Configurable::reconfigure_port]
+    fn with_timeouts<F: FnOnce(&mut CommTimeouts)>(&self, f: F) -> SerialResult<()>;
+    /// Waits for one of the events enabled with [SerialPortSettings::comm_events]
+    /// and returns the events which fired, or None if `timeout` elapsed first.
+    ///
+    /// Events which happen whilst nothing is waiting are not reported. Any number of
+    /// threads and clones can wait at once, with every waiting thread told of the
+    /// events it waits for
+    fn wait_comm_event(&self, timeout: Option<std::time::Duration>) -> SerialResult<Option<CommEventMask>>;
+    /// Waits for a line error or break, returning what the driver reported, or None
+    /// if `timeout` elapsed first. [CommEventMask::ERR] and [CommEventMask::BREAK] are
+    /// enabled whilst waiting.
+    ///
+    /// The errors are also counted, and reported by the next read or write under
+    /// [crate::LineErrorPolicy::Fail]
+    fn wait_line_event(&self, timeout: Option<std::time::Duration>) -> SerialResult<Option<LineErrors>>;
+    /// Checks on the data left queued by a write without a write timeout, which
+    /// returns before the driver has sent it. Returns true whilst it is still being
+    /// sent, false once it has been, or the error it failed with
+    fn write_in_progress(&self) -> std::io::Result<bool>;
+}
+
+impl SerialPortExt for COMPort {
+    fn with_dcb<F: FnOnce(&mut Dcb)>(&self, f: F) -> SerialResult<()> {
+        let mut dcb: DCB = unsafe { std::mem::zeroed() };
+        return_win_op!(GetCommState(self.handle, &mut dcb))?;
+        f(&mut dcb);
+        return_win_op!(SetCommState(self.handle, &mut dcb))
+    }
+
+    fn with_timeouts<F: FnOnce(&mut CommTimeouts)>(&self, f: F) -> SerialResult<()> {
+        let mut timeouts: COMMTIMEOUTS = unsafe { std::mem::zeroed() };
+        return_win_op!(GetCommTimeouts(self.handle, &mut timeouts))?;
+        f(&mut timeouts);
+        return_win_op!(SetCommTimeouts(self.handle, &mut timeouts))
+    }
+
+    fn write_in_progress(&self) -> std::io::Result<bool> {
+        match self.finish_pending_write(&mut lock_overlapped(&self.overlapped_write), false) {
+            Ok(()) => Ok(false),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn wait_line_event(&self, timeout: Option<std::time::Duration>) -> SerialResult<Option<LineErrors>> {
+        let deadline = timeout.map(|t| std::time::Instant::now() + t);
+        let mut events = self.owner.events.subscribe(self.handle, CommEventMask::ERR | CommEventMask::BREAK)?;
+        loop {
+            let remaining = deadline.map(|d| d.saturating_duration_since(std::time::Instant::now()));
+            let fired = match events.next(remaining)? {
+                Some(fired) => fired,
+                None => return Ok(None),
+            };
+            let mut errors = line_errors(self.clear_comm_error()?.1);
+            if fired.contains(CommEventMask::BREAK) {
+                errors |= LineErrors::BREAK;
+            }
+            // Another thread may have already taken the errors
+            if !errors.is_empty() {
+                return Ok(Some(errors));
+            }
+        }
+    }
+
+    fn wait_comm_event(&self, timeout: Option<std::time::Duration>) -> SerialResult<Option<CommEventMask>> {
+        self.owner.events.subscribe(self.handle, self.settings.comm_events)?.next(timeout)
+    }
+}
+
+impl AsRawHandle for COMPort {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.handle as RawHandle
+    }
+}
+
+impl AsHandle for COMPort {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        // The handle stays open for as long as self (and therefore owner) is alive
+        unsafe { BorrowedHandle::borrow_raw(self.handle as RawHandle) }
+    }
+}
+
+impl IntoRawHandle for COMPort {
+    /// Releases ownership of the handle. The port's OVERLAPPED events are closed.
+    ///
+    /// If other clones of this port are still alive, they keep the original handle
+    /// and a duplicate is returned instead, which the caller then owns.
+    ///
+    /// # Panics
+    /// Panics if the handle needs to be duplicated and `DuplicateHandle` fails
+    fn into_raw_handle(mut self) -> RawHandle {
+        let owner = std::mem::replace(&mut self.owner, Arc::new(HandleOwner::new(std::ptr::null_mut())));
+        match Arc::try_unwrap(owner) {
+            Ok(owner) => {
+                let handle = owner.handle;
+                std::mem::forget(owner);
+                handle as RawHandle
+            }
+            Err(_) => {
+                let process = unsafe { GetCurrentProcess() };
+                let mut dup: HANDLE = INVALID_HANDLE_VALUE;
+                if unsafe { DuplicateHandle(process, self.handle, process, &mut dup, 0, 0, DUPLICATE_SAME_ACCESS) } == 0 {
+                    panic!("Failed to duplicate COM port handle: {}", get_win_error());
+                }
+                dup as RawHandle
+            }
+        }
+    }
+}
+
+impl FromRawHandle for COMPort {
+    /// Takes ownership of an already open COM port handle. The handle must have
+    /// been opened with `FILE_FLAG_OVERLAPPED`.
+    ///
+    /// The port is not reconfigured, its settings are read back from the
+    /// driver (see [Configurable::current_settings])
+    ///
+    /// # Panics
+    /// Panics if the OVERLAPPED events cannot be created
+    unsafe fn from_raw_handle(handle: RawHandle) -> Self {
+        let handle = handle as HANDLE;
+        let mut port = COMPort::from_shared(handle, Arc::new(HandleOwner::new(handle)), SerialPortSettings::default(), String::new())
+            .expect("Failed to create OVERLAPPED events");
+        if let Ok(settings) = port.current_settings() {
+            port.settings = settings;
+        }
+        port
+    }
+}
+
+impl From<COMPort> for OwnedHandle {
+    fn from(port: COMPort) -> Self {
+        unsafe { OwnedHandle::from_raw_handle(port.into_raw_handle()) }
+    }
+}
+
+impl From<OwnedHandle> for COMPort {
+    fn from(handle: OwnedHandle) -> Self {
+        unsafe { COMPort::from_raw_handle(handle.into_raw_handle()) }
+    }
+}
+
+impl super::SerialPort for COMPort {
+    fn close(self) -> SerialResult<()> {
+        // The handle is closed by HandleOwner once no other clones remain
+        drop(self);
+        Ok(())
+    }
+
+    fn try_clone(&mut self) -> SerialResult<Box<dyn SerialPort>> {
+        Ok(Box::new(COMPort::from_shared(self.handle, self.owner.clone(), self.settings, self.path.clone())?))
+    }
+}
+
+impl super::SerialIo for COMPort {
+    fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn read_shared(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_impl(buf, None)
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.owner.peeked.peek(buf, self.queued_input()?, |chunk| self.with_error_policy(|| self.read_once(chunk, None)))
+    }
+
+    fn write_shared(&self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_impl(buf, None)
+    }
+
+    fn write_urgent(&self, buf: &[u8]) -> std::io::Result<usize> {
+        // TransmitCommChar fails whilst the previous character is still waiting to go out
+        let limit = self.settings.write_timeout.map(|t| std::time::Duration::from_millis(t as u64)).unwrap_or(URGENT_CHAR_TIMEOUT);
+        for (sent, byte) in buf.iter().enumerate() {
+            let start = std::time::Instant::now();
+            while unsafe { TransmitCommChar(self.handle, *byte as i8) } == 0 {
+                if start.elapsed() >= limit {
+                    let e = get_win_error();
+                    return match sent {
+                        0 => Err(e.into()),
+                        _ => Ok(sent),
+                    };
+                }
+                std::thread::yield_now();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn poll_readable(&self, timeout: Option<std::time::Duration>) -> std::io::Result<bool> {
+        Ok(self.wait_for_bytes(1, timeout)?)
+    }
+
+    fn flush_shared(&self) -> std::io::Result<()> {
+        self.finish_pending_write(&mut lock_overlapped(&self.overlapped_write), true)?;
+        // Not every driver reports EV_TXEMPTY, so sleep for about as long as the
+        // queued bytes take to send and check again
+        loop {
+            let queued = self.bytes_to_write()?;
+            if queued == 0 {
+                return Ok(());
+            }
+            std::thread::sleep(self.settings.char_duration().saturating_mul(queued as u32).min(DRAIN_POLL));
+        }
+    }
+
+    fn read_with_cancel(&self, buf: &mut [u8], token: &CancelToken) -> std::io::Result<usize> {
+        token.check()?;
+        self.read_impl(buf, Some(token))
+    }
+
+    fn write_with_cancel(&self, buf: &[u8], token: &CancelToken) -> std::io::Result<usize> {
+        token.check()?;
+        self.write_impl(buf, Some(token))
+    }
+
+    fn cancel_io(&self) -> SerialResult<()> {
+        if unsafe { CancelIoEx(self.handle, std::ptr::null_mut()) } == 0 && unsafe { GetLastError() } != ERROR_NOT_FOUND {
+            return Err(get_win_error());
+        }
+        Ok(())
+    }
+}
+
+impl super::ControlLines for COMPort {
+    fn set_output_flow_control(&self, enable: bool) -> SerialResult<()> {
+        return_win_op!(match enable {
+            true => EscapeCommFunction(self.handle, SETXON),
+            false => EscapeCommFunction(self.handle, SETXOFF),
+        })
+    }
+
+    fn send_xon(&self) -> SerialResult<()> {
+        // SETXON only resumes our own output, TransmitCommChar sends ahead of queued data
+        return_win_op!(TransmitCommChar(self.handle, self.settings.xon_char as i8))
+    }
+
+    fn send_xoff(&self) -> SerialResult<()> {
+        return_win_op!(TransmitCommChar(self.handle, self.settings.xoff_char as i8))
+    }
+
+    fn set_data_terminal_ready(&self, enable: bool) -> SerialResult<()> {
+        let enable = enable != self.settings.invert_dtr;
+        return_win_op!(match enable {
+            true => EscapeCommFunction(self.handle, SETDTR),
+            false => EscapeCommFunction(self.handle, CLRDTR),
+        })?;
+        self.owner.lines().dtr = Some(enable);
+        Ok(())
+    }
+
+    fn set_request_to_send(&self, enable: bool) -> SerialResult<()> {
+        let enable = enable != self.settings.invert_rts;
+        return_win_op!(match enable {
+            true => EscapeCommFunction(self.handle, SETRTS),
+            false => EscapeCommFunction(self.handle, CLRRTS),
+        })?;
+        self.owner.lines().rts = Some(enable);
+        Ok(())
+    }
+
+    fn set_break_state(&self, enable: bool) -> SerialResult<()> {
+        return_win_op!(match enable {
+            true => SetCommBreak(self.handle),
+            false => ClearCommBreak(self.handle),
+        })
+    }
+
+    fn get_data_terminal_ready(&self) -> SerialResult<bool> {
+        Ok(self.output_line(SERIAL_DTR_STATE, self.owner.lines().dtr)? != self.settings.invert_dtr)
+    }
+
+    fn get_request_to_send(&self) -> SerialResult<bool> {
+        Ok(self.output_line(SERIAL_RTS_STATE, self.owner.lines().rts)? != self.settings.invert_rts)
+    }
+
+    fn set_loopback(&self, enable: bool) -> SerialResult<()> {
+        // There is no DCB field for loopback, so flip the bit in the modem control
+        // register, which 16550 style drivers expose
+        let unsupported = |e: SerialError| match e {
+            SerialError::OsError { code: ERROR_INVALID_FUNCTION | ERROR_NOT_SUPPORTED | ERROR_INVALID_PARAMETER, .. } => {
+                SerialError::Unsupported("Driver does not support internal loopback".to_string())
+            }
+            e => e,
+        };
+        let mut mcr: DWORD = 0;
+        self.device_io_control(IOCTL_SERIAL_GET_MODEM_CONTROL, None, Some(&mut mcr)).map_err(unsupported)?;
+        mcr = match enable {
+            true => mcr | SERIAL_MCR_LOOP,
+            false => mcr & !SERIAL_MCR_LOOP,
+        };
+        self.device_io_control(IOCTL_SERIAL_SET_MODEM_CONTROL, Some(&mcr), None).map_err(unsupported)
+    }
+
+    fn modem_status(&self) -> SerialResult<ModemStatus> {
+        let mut stat: DWORD = 0;
+        return_win_op!(GetCommModemStatus(self.handle, &mut stat))?;
+        let mut status = ModemStatus::empty();
+        for (bit, flag) in [
+            (MS_CTS_ON, ModemStatus::CTS),
+            (MS_DSR_ON, ModemStatus::DSR),
+            (MS_RING_ON, ModemStatus::RI),
+            (MS_RLSD_ON, ModemStatus::CD),
+        ] {
+            status.set(flag, stat & bit != 0);
+        }
+        Ok(status)
+    }
+}
+
+impl super::Configurable for COMPort {
+    fn setting(&mut self) -> &mut SerialPortSettings {
+        &mut self.settings
+    }
+
+    fn settings(&self) -> &SerialPortSettings {
+        &self.settings
+    }
+
+    fn reconfigure_port(&mut self) -> SerialResult<()> {
+        // First set timeouts
+        self.apply_timeouts()?;
 
         // Setup DCB
         let mut dcb: DCB = unsafe { std::mem::zeroed() };
@@ -198,8 +821,16 @@ impl super::SerialPort for COMPort {
 
         dcb.set_fBinary(1);
 
+        // Output lines keep the state last set, and otherwise start in the requested one.
+        // Setting them here rather than afterwards means they never glitch on open
+        let lines = *self.owner.lines();
+        let dtr = lines.dtr.unwrap_or(self.settings.initial_dtr.is_some_and(|s| s != self.settings.invert_dtr));
+        let rts = lines.rts.unwrap_or(self.settings.initial_rts.is_some_and(|s| s != self.settings.invert_rts));
+
         if self.settings.flow_control == FlowControl::RtsCts {
             dcb.set_fRtsControl(RTS_CONTROL_HANDSHAKE);
+        } else if rts {
+            dcb.set_fRtsControl(RTS_CONTROL_ENABLE);
         } else {
             dcb.set_fRtsControl(RTS_CONTROL_DISABLE);
         }
@@ -207,6 +838,8 @@ impl super::SerialPort for COMPort {
 
         if self.settings.flow_control == FlowControl::DsrDtr {
             dcb.set_fDtrControl(DTR_CONTROL_HANDSHAKE);
+        } else if dtr {
+            dcb.set_fDtrControl(DTR_CONTROL_ENABLE);
         } else {
             dcb.set_fDtrControl(DTR_CONTROL_DISABLE);
         }
@@ -214,251 +847,693 @@ impl super::SerialPort for COMPort {
         dcb.set_fOutxDsrFlow((self.settings.flow_control == FlowControl::DsrDtr) as u32);
         dcb.set_fOutX((self.settings.flow_control == FlowControl::XonXoff) as u32);
         dcb.set_fInX((self.settings.flow_control == FlowControl::XonXoff) as u32);
-        dcb.set_fNull(0);
-        dcb.set_fErrorChar(0);
-        dcb.set_fAbortOnError(0);
-        dcb.XonChar = super::XON;
-        dcb.XoffChar = super::XOFF;
+        dcb.set_fTXContinueOnXoff(self.settings.tx_continue_on_xoff as u32);
+        dcb.set_fNull(self.settings.discard_nul as u32);
+        match self.settings.parity_error_policy {
+            ParityErrorPolicy::Mark(c) => {
+                dcb.set_fErrorChar(1);
+                dcb.ErrorChar = c as i8;
+            }
+            ParityErrorPolicy::Ignore | ParityErrorPolicy::Error => dcb.set_fErrorChar(0),
+        }
+        dcb.set_fAbortOnError(self.aborts_on_error() as u32);
+        dcb.XonChar = self.settings.xon_char as i8;
+        dcb.XoffChar = self.settings.xoff_char as i8;
+        if let Some((xon_lim, xoff_lim)) = self.settings.xon_xoff_limits {
+            dcb.XonLim = xon_lim;
+            dcb.XoffLim = xoff_lim;
+        }
 
         return_win_op!(SetCommState(self.handle, &mut dcb))?;
+        // Errors from before the policy was chosen are not reported
+        self.comm_status()?;
+        self.owner.unreported.store(0, Ordering::SeqCst);
+        *self.owner.lines() = OutputLines {
+            dtr: (self.settings.flow_control != FlowControl::DsrDtr).then_some(dtr),
+            rts: (self.settings.flow_control != FlowControl::RtsCts).then_some(rts),
+        };
         Ok(())
     }
 
-    fn close(self) -> SerialResult<()> {
-        unsafe {
-            CloseHandle(self.overlapped_read.hEvent);
-            CloseHandle(self.overlapped_write.hEvent);
-            CloseHandle(self.handle);
+    fn reconfigure_port_live(&mut self) -> SerialResult<()> {
+        let mut dcb: DCB = unsafe { std::mem::zeroed() };
+        return_win_op!(GetCommState(self.handle, &mut dcb))?;
+        let byte_size = match self.settings.byte_size {
+            crate::ByteSize::Five => 5,
+            crate::ByteSize::Six => 6,
+            crate::ByteSize::Seven => 7,
+            crate::ByteSize::Eight => 8,
+        };
+        let parity = match self.settings.parity {
+            crate::Parity::None => NOPARITY,
+            crate::Parity::Even => EVENPARITY,
+            crate::Parity::Odd => ODDPARITY,
+        };
+        let stop_bits = match self.settings.stop_bits {
+            crate::StopBits::One => ONESTOPBIT,
+            crate::StopBits::OnePointFive => ONE5STOPBITS,
+            crate::StopBits::Two => TWOSTOPBITS,
+        };
+        if dcb.BaudRate == self.settings.baud_rate && dcb.ByteSize == byte_size
+            && dcb.Parity == parity && dcb.StopBits == stop_bits {
+            return Ok(());
         }
+        // Let queued output go out at the old settings. SetCommState never purges
+        // the driver's buffers, so received data is kept
+        self.flush_shared().map_err(SerialError::IoError)?;
+        dcb.BaudRate = self.settings.baud_rate;
+        dcb.ByteSize = byte_size;
+        dcb.Parity = parity;
+        dcb.set_fParity((parity != NOPARITY) as u32);
+        dcb.StopBits = stop_bits;
+        return_win_op!(SetCommState(self.handle, &mut dcb))?;
         Ok(())
     }
 
+    fn set_blocking(&mut self, blocking: bool) -> SerialResult<()> {
+        self.settings.blocking = blocking;
+        self.apply_timeouts()
+    }
+
+    fn debug_dump(&self) -> SerialResult<DriverConfigDump> {
+        let mut dcb: DCB = unsafe { std::mem::zeroed() };
+        let mut timeouts: COMMTIMEOUTS = unsafe { std::mem::zeroed() };
+        return_win_op!(GetCommState(self.handle, &mut dcb))?;
+        return_win_op!(GetCommTimeouts(self.handle, &mut timeouts))?;
+
+        let mut dump = DriverConfigDump::default();
+        dump.push("BaudRate", dcb.BaudRate);
+        dump.push("ByteSize", dcb.ByteSize);
+        dump.push("Parity", dcb.Parity);
+        dump.push("StopBits", dcb.StopBits);
+        dump.push("fBinary", dcb.fBinary());
+        dump.push("fParity", dcb.fParity());
+        dump.push("fOutxCtsFlow", dcb.fOutxCtsFlow());
+        dump.push("fOutxDsrFlow", dcb.fOutxDsrFlow());
+        dump.push("fDtrControl", dcb.fDtrControl());
+        dump.push("fDsrSensitivity", dcb.fDsrSensitivity());
+        dump.push("fTXContinueOnXoff", dcb.fTXContinueOnXoff());
+        dump.push("fOutX", dcb.fOutX());
+        dump.push("fInX", dcb.fInX());
+        dump.push("fErrorChar", dcb.fErrorChar());
+        dump.push("fNull", dcb.fNull());
+        dump.push("fRtsControl", dcb.fRtsControl());
+        dump.push("fAbortOnError", dcb.fAbortOnError());
+        dump.push("XonLim", dcb.XonLim);
+        dump.push("XoffLim", dcb.XoffLim);
+        dump.push("XonChar", dcb.XonChar);
+        dump.push("XoffChar", dcb.XoffChar);
+        dump.push("ErrorChar", dcb.ErrorChar);
+        dump.push("EofChar", dcb.EofChar);
+        dump.push("EvtChar", dcb.EvtChar);
+        dump.push("ReadIntervalTimeout", timeouts.ReadIntervalTimeout);
+        dump.push("ReadTotalTimeoutMultiplier", timeouts.ReadTotalTimeoutMultiplier);
+        dump.push("ReadTotalTimeoutConstant", timeouts.ReadTotalTimeoutConstant);
+        dump.push("WriteTotalTimeoutMultiplier", timeouts.WriteTotalTimeoutMultiplier);
+        dump.push("WriteTotalTimeoutConstant", timeouts.WriteTotalTimeoutConstant);
+        Ok(dump)
+    }
+
+    fn current_settings(&self) -> SerialResult<SerialPortSettings> {
+        let mut dcb: DCB = unsafe { std::mem::zeroed() };
+        return_win_op!(GetCommState(self.handle, &mut dcb))?;
+        let mut settings = self.settings;
+
+        settings.baud_rate = dcb.BaudRate;
+        settings.byte_size = match dcb.ByteSize {
+            5 => crate::ByteSize::Five,
+            6 => crate::ByteSize::Six,
+            7 => crate::ByteSize::Seven,
+            8 => crate::ByteSize::Eight,
+            x => return Err(SerialError::LibraryError(format!("Driver reports unsupported byte size {x}"))),
+        };
+        settings.parity = match dcb.Parity {
+            NOPARITY => crate::Parity::None,
+            EVENPARITY => crate::Parity::Even,
+            ODDPARITY => crate::Parity::Odd,
+            MARKPARITY => return Err(SerialError::LibraryError("Driver reports mark parity".to_string())),
+            SPACEPARITY => return Err(SerialError::LibraryError("Driver reports space parity".to_string())),
+            x => return Err(SerialError::LibraryError(format!("Driver reports unknown parity {x}"))),
+        };
+        settings.stop_bits = match dcb.StopBits {
+            ONESTOPBIT => crate::StopBits::One,
+            ONE5STOPBITS => crate::StopBits::OnePointFive,
+            TWOSTOPBITS => crate::StopBits::Two,
+            x => return Err(SerialError::LibraryError(format!("Driver reports unknown stop bits {x}"))),
+        };
+        settings.flow_control = if dcb.fOutxCtsFlow() != 0 {
+            FlowControl::RtsCts
+        } else if dcb.fOutxDsrFlow() != 0 {
+            FlowControl::DsrDtr
+        } else if dcb.fOutX() != 0 && dcb.fInX() != 0 {
+            FlowControl::XonXoff
+        } else {
+            FlowControl::None
+        };
+        settings.discard_nul = dcb.fNull() != 0;
+        settings.xon_char = dcb.XonChar as u8;
+        settings.xoff_char = dcb.XoffChar as u8;
+        settings.tx_continue_on_xoff = dcb.fTXContinueOnXoff() != 0;
+        if dcb.fErrorChar() != 0 {
+            settings.parity_error_policy = ParityErrorPolicy::Mark(dcb.ErrorChar as u8);
+        } else if matches!(settings.parity_error_policy, ParityErrorPolicy::Mark(_)) {
+            settings.parity_error_policy = ParityErrorPolicy::Ignore;
+        }
+        if settings.xon_xoff_limits.is_some() {
+            settings.xon_xoff_limits = Some((dcb.XonLim, dcb.XoffLim));
+        }
+        Ok(settings)
+    }
+
+    fn verify_settings(&self) -> SerialResult<Vec<SettingMismatch>> {
+        Ok(self.settings.diff(&self.current_settings()?))
+    }
+}
+
+impl super::BufferControl for COMPort {
     fn set_buffer_size(&mut self, rx_size: usize, tx_size: usize) -> SerialResult<()> {
         return_win_op!(SetupComm(self.handle, rx_size as DWORD, tx_size as DWORD))
     }
 
-    fn set_output_flow_control(&self, enable: bool) -> SerialResult<()> {
-        return_win_op!(match enable {
-            true => EscapeCommFunction(self.handle, SETXON),
-            false => EscapeCommFunction(self.handle, SETXOFF),
-        })
+    fn bytes_to_read(&self) -> SerialResult<usize> {
+        Ok(self.queued_input()? + self.owner.peeked.len())
     }
 
-    fn set_data_terminal_ready(&mut self, enable: bool) -> SerialResult<()> {
-        return_win_op!(match enable {
-            true => EscapeCommFunction(self.handle, SETDTR),
-            false => EscapeCommFunction(self.handle, CLRDTR),
-        })
+    fn bytes_to_write(&self) -> SerialResult<usize> {
+        Ok(self.comm_status()?.cbOutQue as usize)
     }
 
-    fn set_request_to_send(&mut self, enable: bool) -> SerialResult<()> {
-        return_win_op!(match enable {
-            true => EscapeCommFunction(self.handle, SETRTS),
-            false => EscapeCommFunction(self.handle, CLRRTS),
+    fn queue_status(&self) -> SerialResult<QueueStatus> {
+        let comstat = self.comm_status()?;
+        Ok(QueueStatus {
+            input: comstat.cbInQue as usize + self.owner.peeked.len(),
+            output: comstat.cbOutQue as usize,
+            errors: line_errors(self.owner.unreported.load(Ordering::SeqCst)),
         })
     }
 
-    fn set_break_state(&mut self, enable: bool) -> SerialResult<()> {
-        return_win_op!(match enable {
-            true => SetCommBreak(self.handle),
-            false => ClearCommBreak(self.handle),
-        })
+    fn clear_input_buffer(&self) -> SerialResult<()> {
+        self.owner.peeked.clear();
+        return_win_op!(PurgeComm(self.handle, PURGE_RXABORT | PURGE_RXCLEAR))
     }
 
-    fn read_clear_to_send(&self) -> SerialResult<bool> {
-        Ok(MS_CTS_ON & self.get_comm_modem_status() != 0)
+    fn clear_output_buffer(&self) -> SerialResult<()> {
+        return_win_op!(PurgeComm(self.handle, PURGE_TXABORT | PURGE_TXCLEAR))
     }
 
-    fn read_data_set_ready(&self) -> SerialResult<bool> {
-        Ok(MS_DSR_ON & self.get_comm_modem_status() != 0)
+    fn drain(&self) -> SerialResult<()> {
+        self.flush_shared().map_err(SerialError::IoError)
     }
 
-    fn read_ring_indicator(&self) -> SerialResult<bool> {
-        Ok(MS_RING_ON & self.get_comm_modem_status() != 0)
+    fn wait_for_bytes(&self, n: usize, timeout: Option<std::time::Duration>) -> SerialResult<bool> {
+        let deadline = timeout.map(|t| std::time::Instant::now() + t);
+        // Subscribed before checking, so a byte received in between is not missed
+        let mut events = self.owner.events.subscribe(self.handle, CommEventMask::RXCHAR)?;
+        loop {
+            if self.bytes_to_read()? >= n {
+                return Ok(true);
+            }
+            let remaining = deadline.map(|d| d.saturating_duration_since(std::time::Instant::now()));
+            if remaining == Some(std::time::Duration::ZERO) {
+                return Ok(false);
+            }
+            events.next(remaining)?;
+        }
     }
 
-    fn read_carrier_detect(&self) -> SerialResult<bool> {
-        Ok(MS_RLSD_ON & self.get_comm_modem_status() != 0)
+    fn line_error_counts(&self) -> SerialResult<LineErrorCounts> {
+        self.comm_status()?;
+        Ok(*self.owner.errors())
     }
+}
 
-    fn bytes_to_read(&self) -> SerialResult<usize> {
-        let mut flags: DWORD = 0;
-        let mut comstat: COMSTAT = unsafe { std::mem::zeroed() };
+/// Converts a set of ClearCommError flags
+fn line_errors(flags: DWORD) -> LineErrors {
+    [
+        (CE_FRAME, LineErrors::FRAMING),
+        (CE_RXPARITY, LineErrors::PARITY),
+        (CE_OVERRUN, LineErrors::OVERRUN),
+        (CE_RXOVER, LineErrors::BUFFER_OVERRUN),
+        (CE_BREAK, LineErrors::BREAK),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags & flag != 0)
+    .fold(LineErrors::empty(), |errors, (_, error)| errors | *error)
+}
 
-        return_win_op!(ClearCommError(self.handle, &mut flags, &mut comstat))?;
-        Ok(comstat.cbInQue as usize)
+/// Locks an OVERLAPPED struct. A panic whilst holding the lock cannot leave the
+/// struct in a state which is unsafe to reuse, so poisoning is ignored
+fn lock_overlapped<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// How long [SerialIo::write_urgent] waits for each character to be accepted, if
+/// no write timeout is set
+const URGENT_CHAR_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Longest sleep between checks of the output queue whilst draining it
+const DRAIN_POLL: std::time::Duration = std::time::Duration::from_millis(10);
+
+const VALID_PENDING_ERRORS: [DWORD; 2] = [ERROR_SUCCESS, ERROR_IO_PENDING];
+
+impl COMPort {
+    /// Sends a serial driver IOCTL with an optional DWORD in and out, waiting for
+    /// it to complete as the handle is overlapped
+    fn device_io_control(&self, code: DWORD, input: Option<&DWORD>, output: Option<&mut DWORD>) -> SerialResult<()> {
+        let size = std::mem::size_of::<DWORD>() as DWORD;
+        let (in_ptr, in_size) = input.map_or((std::ptr::null_mut(), 0), |i| (i as *const DWORD as LPVOID, size));
+        let (out_ptr, out_size) = output.map_or((std::ptr::null_mut(), 0), |o| (o as *mut DWORD as LPVOID, size));
+        let mut overlapped = new_overlapped(true)?;
+        let mut returned: DWORD = 0;
+        let res = match unsafe { DeviceIoControl(self.handle, code, in_ptr, in_size, out_ptr, out_size, &mut returned, &mut overlapped) } {
+            0 if unsafe { GetLastError() } != ERROR_IO_PENDING => Err(get_win_error()),
+            0 => return_win_op!(GetOverlappedResult(self.handle, &mut overlapped, &mut returned, 1)),
+            _ => Ok(()),
+        };
+        unsafe { CloseHandle(overlapped.hEvent) };
+        res
     }
 
-    fn bytes_to_write(&self) -> SerialResult<usize> {
+    /// Reads an output line from the driver, falling back to the state last set
+    /// through this handle if the driver cannot report it
+    fn output_line(&self, bit: DWORD, cached: Option<bool>) -> SerialResult<bool> {
+        let mut state: DWORD = 0;
+        match (self.device_io_control(IOCTL_SERIAL_GET_DTRRTS, None, Some(&mut state)), cached) {
+            (Ok(()), _) => Ok(state & bit != 0),
+            (Err(_), Some(cached)) => Ok(cached),
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// Reads the queue sizes with ClearCommError, adding any errors it reports to
+    /// the counters shared by all clones
+    fn comm_status(&self) -> SerialResult<COMSTAT> {
+        Ok(self.clear_comm_error()?.0)
+    }
+
+    /// Calls ClearCommError, returning the queue sizes and error flags. The errors
+    /// are added to the counters, and left for the line error policy to report
+    fn clear_comm_error(&self) -> SerialResult<(COMSTAT, DWORD)> {
         let mut flags: DWORD = 0;
         let mut comstat: COMSTAT = unsafe { std::mem::zeroed() };
-
         return_win_op!(ClearCommError(self.handle, &mut flags, &mut comstat))?;
-        Ok(comstat.cbOutQue as usize)
+        if flags != 0 {
+            self.owner.unreported.fetch_or(flags, Ordering::SeqCst);
+            let mut guard = self.owner.errors();
+            let errors = &mut *guard;
+            for (flag, count) in [
+                (CE_FRAME, &mut errors.framing),
+                (CE_RXPARITY, &mut errors.parity),
+                (CE_OVERRUN, &mut errors.overrun),
+                (CE_RXOVER, &mut errors.buffer_overrun),
+                (CE_BREAK, &mut errors.breaks),
+            ] {
+                if flags & flag != 0 {
+                    *count = count.wrapping_add(1);
+                }
+            }
+        }
+        Ok((comstat, flags))
     }
 
-    fn get_path(&self) -> String {
-        self.path.clone()
+    /// Probes the handle after a failed read or write. Fails with
+    /// [SerialError::Disconnected] if the device has gone away, which otherwise
+    /// shows up as a stream of aborted operations and generic driver errors
+    fn check_connected(&self) -> SerialResult<()> {
+        match self.comm_status() {
+            Err(SerialError::OsError { code, desc }) if error::is_disconnect_error(code) => Err(SerialError::Disconnected(desc)),
+            _ => Ok(()),
+        }
     }
 
-    fn try_clone(&mut self) -> SerialResult<Box<dyn SerialPort>> {
-        let process_handle: HANDLE = unsafe { GetCurrentProcess() };
-        let mut cloned_handle: HANDLE = INVALID_HANDLE_VALUE;
-        unsafe {
-            DuplicateHandle(
-                process_handle,
+    /// Returns true if the line error or parity error policy needs fAbortOnError
+    fn aborts_on_error(&self) -> bool {
+        self.settings.line_error_policy != LineErrorPolicy::Ignore
+            || self.settings.parity_error_policy == ParityErrorPolicy::Error
+    }
+
+    /// Runs a read or write, applying the line error and parity error policies. With
+    /// fAbortOnError set, the driver fails every operation after a line error until
+    /// ClearCommError is called, so any failure is checked for one
+    fn with_error_policy<F: FnMut() -> std::io::Result<usize>>(&self, mut op: F) -> std::io::Result<usize> {
+        let policy = self.settings.line_error_policy;
+        loop {
+            let flags = self.owner.unreported.swap(0, Ordering::SeqCst);
+            let mut failed = if policy == LineErrorPolicy::Fail { flags } else { 0 };
+            if self.settings.parity_error_policy == ParityErrorPolicy::Error {
+                failed |= flags & CE_RXPARITY;
+            }
+            if failed != 0 {
+                return Err(SerialError::LineError(line_errors(failed)).into());
+            }
+            let res = op();
+            if let Err(e) = &res {
+                if !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                    self.check_connected()?;
+                }
+            }
+            match res {
+                Err(e) if self.aborts_on_error() => {
+                    self.comm_status()?;
+                    if self.owner.unreported.load(Ordering::SeqCst) == 0 {
+                        return Err(e);
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Reads from the port, returning bytes read ahead by [SerialIo::peek] first
+    fn read_impl(&self, buf: &mut [u8], token: Option<&CancelToken>) -> std::io::Result<usize> {
+        self.owner.peeked.read(buf, |buf| self.with_error_policy(|| self.read_once(buf, token)))
+    }
+
+    /// Number of bytes queued in the driver, not counting bytes read ahead by peek
+    fn queued_input(&self) -> SerialResult<usize> {
+        Ok(self.comm_status()?.cbInQue as usize)
+    }
+
+    fn read_once(&self, buf: &mut [u8], token: Option<&CancelToken>) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.settings.fill_reads && self.settings.blocking {
+            return self.read_fill(buf, token);
+        }
+
+        // Only query the driver queue if we are limited to what is already buffered
+        let to_read = if self.settings.read_timeout.is_none() || !self.settings.blocking {
+            std::cmp::min(self.queued_input()?, buf.len())
+        } else {
+            buf.len()
+        };
+
+        if to_read == 0 {
+            // No bytes to read
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let timeout_ms = match self.settings.read_timeout {
+            Some(t) if t != 0 && self.settings.blocking => Some(t.min(u128::from(MAXDWORD)) as DWORD),
+            _ => None,
+        };
+        self.read_file(&mut buf[..to_read], token, timeout_ms)
+    }
+
+    /// Reads until `buf` is full, the read timeout expires, or the gap between two
+    /// bytes exceeds the inter-byte timeout. [COMPort::apply_timeouts] makes each
+    /// ReadFile return as soon as any data has arrived, and the timeouts are applied here
+    fn read_fill(&self, buf: &mut [u8], token: Option<&CancelToken>) -> std::io::Result<usize> {
+        let start = std::time::Instant::now();
+        let total = self.settings.read_timeout.map(|t| std::time::Duration::from_millis(t as u64));
+        let inter_byte = self.settings.inter_byte_timeout.map(|t| std::time::Duration::from_millis(t as u64));
+        let mut read = 0;
+        while read < buf.len() {
+            let mut limit = total.map(|t| t.saturating_sub(start.elapsed()));
+            if read != 0 {
+                if let Some(gap) = inter_byte {
+                    limit = Some(limit.map_or(gap, |l| l.min(gap)));
+                }
+            }
+            if limit == Some(std::time::Duration::ZERO) {
+                break;
+            }
+            let timeout_ms = limit.map(|l| l.as_millis().clamp(1, (INFINITE - 1) as u128) as DWORD);
+            match self.read_file(&mut buf[read..], token, timeout_ms) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::TimedOut => break,
+                // Bytes already read are returned, the error is left for the next read
+                Err(_) if read != 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if read == 0 {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "Operation timed out"));
+        }
+        Ok(read)
+    }
+
+    /// Issues a single overlapped ReadFile, waiting at most `timeout_ms` for it to complete
+    fn read_file(&self, buf: &mut [u8], token: Option<&CancelToken>, timeout_ms: Option<DWORD>) -> std::io::Result<usize> {
+        let mut overlapped = lock_overlapped(&self.overlapped_read);
+        unsafe { ResetEvent(overlapped.hEvent) };
+        let mut read_count: DWORD = 0;
+        let read_status = unsafe {
+            ReadFile(
                 self.handle,
-                process_handle,
-                &mut cloned_handle,
-                0,
-                1,
-                DUPLICATE_SAME_ACCESS,
-            );
-            if cloned_handle != INVALID_HANDLE_VALUE {
-                Ok(Box::new(COMPort {
-                    handle: cloned_handle,
-                    settings: self.settings,
-                    overlapped_read: self.overlapped_read,
-                    overlapped_write: self.overlapped_write,
-                    path: self.path.clone(),
-                }))
+                buf.as_mut_ptr() as LPVOID,
+                buf.len() as u32,
+                &mut read_count,
+                &mut *overlapped,
+            )
+        };
+
+        if read_count == buf.len() as u32 {
+            return Ok(buf.len());
+        }
+
+        if read_status == 0 && !VALID_PENDING_ERRORS.contains(&unsafe { GetLastError() }) {
+            return Err(get_win_error().into());
+        }
+        let (result_ok, timed_out) = self.wait_overlapped(&mut overlapped, &mut read_count, token, timeout_ms);
+        if result_ok == 0 {
+            if unsafe { GetLastError() } != ERROR_OPERATION_ABORTED {
+                return Err(get_win_error().into());
+            } else if read_count == 0 && timed_out {
+                return Err(std::io::Error::new(ErrorKind::TimedOut, "Operation timed out"));
+            } else if read_count == 0 {
+                return Err(SerialError::cancelled_io());
             } else {
-                Err(get_win_error())
+                return Ok(read_count as usize);
             }
         }
-
+        // A serial port has no end of file, so ReadFile only completes with nothing
+        // read once ReadTotalTimeoutConstant expires
+        if read_count == 0 {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "Operation timed out"));
+        }
+        Ok(read_count as usize)
     }
 
-    fn clear_input_buffer(&mut self) -> SerialResult<()> {
-        return_win_op!(PurgeComm(self.handle, PURGE_RXABORT | PURGE_RXCLEAR))
+    fn write_impl(&self, buf: &[u8], token: Option<&CancelToken>) -> std::io::Result<usize> {
+        self.with_error_policy(|| self.write_once(buf, token))
     }
 
-    fn clear_output_buffer(&mut self) -> SerialResult<()> {
-        return_win_op!(PurgeComm(self.handle, PURGE_TXABORT | PURGE_TXCLEAR))
+    fn write_once(&self, buf: &[u8], token: Option<&CancelToken>) -> std::io::Result<usize> {
+        match self.settings.write_chunk_size {
+            Some(size) if size != 0 && buf.len() > size => {
+                // Each chunk must complete before the next one reuses the OVERLAPPED struct
+                let mut total = 0;
+                for chunk in buf.chunks(size) {
+                    match self.write_chunk(chunk, true, token) {
+                        Ok(written) => {
+                            total += written;
+                            if written < chunk.len() {
+                                break;
+                            }
+                        }
+                        Err(e) if total == 0 => return Err(e),
+                        Err(_) => break,
+                    }
+                }
+                Ok(total)
+            }
+            _ => self.write_chunk(buf, self.settings.write_timeout.is_some() || token.is_some(), token),
+        }
     }
-}
 
-const VALID_PENDING_ERRORS: [DWORD; 2] = [ERROR_SUCCESS, ERROR_IO_PENDING];
+    /// Waits for an overlapped operation to complete. If `token` fires or `timeout_ms`
+    /// expires first, the operation is cancelled and reaped before returning.
+    ///
+    /// Returns the result of GetOverlappedResult, and whether the timeout expired
+    fn wait_overlapped(
+        &self,
+        overlapped: &mut OVERLAPPED,
+        count: &mut DWORD,
+        token: Option<&CancelToken>,
+        timeout_ms: Option<DWORD>,
+    ) -> (i32, bool) {
+        // Some drivers overrun COMMTIMEOUTS, so the wait is bounded here as well
+        let wait_ms = timeout_ms.map(|t| t.min(INFINITE - 1)).unwrap_or(INFINITE);
+        let mut timed_out = false;
+        if token.is_some() || timeout_ms.is_some() {
+            let events = [overlapped.hEvent, token.map(|t| t.event()).unwrap_or(std::ptr::null_mut())];
+            let res = unsafe { WaitForMultipleObjects(1 + token.is_some() as DWORD, events.as_ptr(), 0, wait_ms) };
+            if res == WAIT_OBJECT_0 + 1 || res == WAIT_TIMEOUT {
+                timed_out = res == WAIT_TIMEOUT;
+                unsafe { CancelIoEx(self.handle, overlapped) };
+            }
+        }
+        (unsafe { GetOverlappedResult(self.handle, overlapped, count, 1) }, timed_out)
+    }
 
-impl std::io::Write for COMPort {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        if buf.len() == 0 {
+    /// Issues a single overlapped write. If `wait` is set, blocks until the write completes.
+    ///
+    /// Otherwise the data is copied, so the driver can carry on sending it after this
+    /// returns, and all of it is reported as written. A failure of that write is
+    /// returned by the next write or flush, without sending the new data
+    fn write_chunk(&self, buf: &[u8], wait: bool, token: Option<&CancelToken>) -> std::io::Result<usize> {
+        if buf.is_empty() {
             return Ok(0);
         }
+        let mut state = lock_overlapped(&self.overlapped_write);
+        // The OVERLAPPED cannot be reused until the queued write has finished. A
+        // non-blocking port fails with WouldBlock rather than waiting for it
+        self.finish_pending_write(&mut state, self.settings.blocking)?;
+        let data = if wait { None } else { Some(buf.to_vec()) };
+        let ptr = data.as_deref().unwrap_or(buf).as_ptr();
         let len = buf.len() as DWORD;
         let mut written: DWORD = 0;
         let success = unsafe {
             WriteFile(
                 self.handle,
-                buf.as_ptr() as *const winapi::ctypes::c_void,
+                ptr as *const winapi::ctypes::c_void,
                 len,
                 &mut written,
-                &mut self.overlapped_write,
+                &mut state.overlapped,
             )
         };
-        if self.settings.write_timeout.is_some() {
+        if wait {
             if success == 0 && !VALID_PENDING_ERRORS.contains(&unsafe { GetLastError() }) {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::Interrupted,
                     get_win_error(),
                 ));
             }
-            unsafe { GetOverlappedResult(self.handle, &mut self.overlapped_write, &mut written, 1); }
-            if unsafe { GetLastError() } == ERROR_OPERATION_ABORTED {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Interrupted,
-                    get_win_error(),
-                ));
-            } else {
-                return Ok(written as usize)
+            let (result_ok, _) = self.wait_overlapped(&mut state.overlapped, &mut written, token, None);
+            if result_ok == 0 {
+                if unsafe { GetLastError() } != ERROR_OPERATION_ABORTED {
+                    return Err(get_win_error().into());
+                } else if written == 0 {
+                    return Err(SerialError::cancelled_io());
+                }
             }
+            Ok(written as usize)
         } else {
             let error = if success != 0 {
                 ERROR_SUCCESS
             } else {
                 unsafe { GetLastError() }
             };
-            if error == ERROR_SUCCESS || error == ERROR_IO_PENDING {
-                return Ok(written as usize);
-            } else {
-                let e_type: std::io::ErrorKind = match error {
-                    ERROR_INVALID_USER_BUFFER => ErrorKind::InvalidData,
-                    ERROR_NOT_ENOUGH_MEMORY => ErrorKind::OutOfMemory,
-                    _ => ErrorKind::Interrupted,
-                };
-                return Err(std::io::Error::new(e_type, get_win_error()));
+            match error {
+                ERROR_SUCCESS => Ok(written as usize),
+                ERROR_IO_PENDING => {
+                    state.pending = data;
+                    Ok(buf.len())
+                }
+                _ => {
+                    let e_type: std::io::ErrorKind = match error {
+                        ERROR_INVALID_USER_BUFFER => ErrorKind::InvalidData,
+                        ERROR_NOT_ENOUGH_MEMORY => ErrorKind::OutOfMemory,
+                        _ => ErrorKind::Interrupted,
+                    };
+                    Err(std::io::Error::new(e_type, get_win_error()))
+                }
             }
         }
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        loop {
-            if self.bytes_to_write()? == 0 {break;}
+    /// Reaps the write left queued by [COMPort::write_chunk], returning its error if
+    /// it failed or did not send everything. If `wait` is not set and the write is
+    /// still in progress, fails with [ErrorKind::WouldBlock]
+    fn finish_pending_write(&self, state: &mut WriteState, wait: bool) -> std::io::Result<()> {
+        let len = match &state.pending {
+            Some(data) => data.len(),
+            None => return Ok(()),
+        };
+        let mut written: DWORD = 0;
+        if unsafe { GetOverlappedResult(self.handle, &mut state.overlapped, &mut written, wait as i32) } == 0 {
+            if unsafe { GetLastError() } == ERROR_IO_INCOMPLETE {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let e = get_win_error();
+            state.pending = None;
+            return Err(e.into());
+        }
+        state.pending = None;
+        if (written as usize) < len {
+            return Err(std::io::Error::new(ErrorKind::WriteZero, format!("Queued write only sent {written} of {len} bytes")));
         }
         Ok(())
     }
 }
 
-impl std::io::Read for COMPort {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if buf.len() == 0 {
-            return Ok(0);
-        }
+impl std::io::Write for COMPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        (&*self).write(buf)
+    }
 
-        unsafe { ResetEvent(self.overlapped_read.hEvent) };
+    fn write_vectored(&mut self, bufs: &[std::io::IoSlice<'_>]) -> std::io::Result<usize> {
+        (&*self).write_vectored(bufs)
+    }
 
-        let mut flags: DWORD = 0;
-        let mut comstat: COMSTAT = unsafe { std::mem::zeroed() };
-        return_win_op!(ClearCommError(self.handle, &mut flags, &mut comstat))?;
+    fn flush(&mut self) -> std::io::Result<()> {
+        (&*self).flush()
+    }
+}
 
-        let to_read = if self.settings.read_timeout.is_none() || !self.settings.blocking {
-            std::cmp::min(comstat.cbInQue as usize, buf.len())
-        } else {
-            buf.len()
-        };
+/// Writes through a shared reference, like `&TcpStream`, so one thread can write
+/// whilst another reads from the same port. Each direction has its own OVERLAPPED
+/// state, so concurrent writes from several threads are serialized
+impl std::io::Write for &COMPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_shared(buf)
+    }
 
-        if to_read == 0 {
-            // No bytes to read
-            return Err(get_win_error().into());
+    fn write_vectored(&mut self, bufs: &[std::io::IoSlice<'_>]) -> std::io::Result<usize> {
+        // WriteFile has no gather variant for comm devices, so coalesce the buffers
+        // into a single overlapped write rather than issuing one write per buffer
+        let mut non_empty = bufs.iter().filter(|b| !b.is_empty());
+        match (non_empty.next(), non_empty.next()) {
+            (None, _) => Ok(0),
+            (Some(b), None) => self.write_shared(b),
+            _ => {
+                let total = bufs.iter().map(|b| b.len()).sum();
+                let mut tmp = Vec::with_capacity(total);
+                bufs.iter().for_each(|b| tmp.extend_from_slice(b));
+                self.write_shared(&tmp)
+            }
         }
-        let mut read_count: DWORD = 0;
-        let read_status = unsafe {
-            ReadFile(
-                self.handle,
-                buf.as_mut_ptr() as LPVOID,
-                to_read as u32,
-                &mut read_count,
-                &mut self.overlapped_read,
-            )
-        };
+    }
 
-        if read_count == to_read as u32 {
-            return Ok(to_read);
-        }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_shared()
+    }
+}
 
-        if read_status == 0 && !VALID_PENDING_ERRORS.contains(&unsafe { GetLastError() }) {
-            return Err(get_win_error().into());
-        }
-        let result_ok = unsafe {
-            GetOverlappedResult(self.handle, &mut self.overlapped_read, &mut read_count, 1)
-        };
-        if result_ok == 0 {
-            if unsafe { GetLastError() } != ERROR_OPERATION_ABORTED {
-                return Err(get_win_error().into());
-            } else {
-                return Ok(read_count as usize);
-            }
-        }
-        Ok(read_count as usize)
+impl std::io::Read for COMPort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        (&*self).read(buf)
     }
 }
 
+/// Reads through a shared reference. See the `Write` implementation for `&COMPort`
+impl std::io::Read for &COMPort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_shared(buf)
+    }
+}
+
+/// A write still queued when the port is dropped is given as long as its data takes
+/// to send on a blocking port, and none on a non-blocking one. Whatever is unsent by
+/// then, such as output held back by flow control, is cancelled and discarded
 impl Drop for COMPort {
     fn drop(&mut self) {
         unsafe {
-            CloseHandle(self.overlapped_read.hEvent);
-            CloseHandle(self.overlapped_write.hEvent);
-            CloseHandle(self.handle);
+            CloseHandle(lock_overlapped(&self.overlapped_read).hEvent);
+            let mut state = lock_overlapped(&self.overlapped_write);
+            if let Some(len) = state.pending.as_ref().map(|data| data.len()) {
+                let grace = match self.settings.blocking {
+                    true => self.settings.char_duration().mul_f64(len as f64) + DRAIN_POLL,
+                    false => std::time::Duration::ZERO,
+                };
+                if WaitForSingleObject(state.overlapped.hEvent, grace.as_millis() as DWORD) == WAIT_TIMEOUT {
+                    CancelIoEx(self.handle, &mut state.overlapped);
+                }
+            }
+            let _ = self.finish_pending_write(&mut state, true);
+            CloseHandle(state.overlapped.hEvent);
         }
     }
 }
@@ -1,119 +1,222 @@
-//! Windows port lister and enumerator
-
-use std::{ffi::CString, ptr};
-
-use regex::{RegexBuilder};
-use winapi::{um::{setupapi::{SetupDiClassGuidsFromNameA, SetupDiGetClassDevsA, DIGCF_PRESENT, SP_DEVINFO_DATA, SetupDiEnumDeviceInfo, SetupDiOpenDevRegKey, DICS_FLAG_GLOBAL, DIREG_DEV, SetupDiGetDeviceInstanceIdA, SetupDiGetDeviceRegistryPropertyA, SPDRP_HARDWAREID, SPDRP_FRIENDLYNAME, SPDRP_MFG, SetupDiDestroyDeviceInfoList}, cguid::GUID_NULL, winnt::KEY_READ, winreg::{RegQueryValueExA, RegCloseKey}}, shared::{minwindef::DWORD, guiddef::GUID, ntdef::ULONG}};
-
-use crate::{return_win_op, windows::error::get_win_error, SerialResult, PortInfo};
-
-#[derive(Debug, Copy, Clone)]
-/// Windows COM Port lister
-pub struct COMPortLister {}
-
-const PORT_NAME_LEN: usize = 500;
-
-impl crate::PortScanner for COMPortLister {
-    fn list_devices(&mut self) -> SerialResult<Vec<crate::PortInfo>> {
-        let mut port_name_class = CString::new("Ports").unwrap();
-        let mut num_guids: DWORD = 0;
-        let mut guids: Vec<GUID> = Vec::new();
-        guids.push(GUID_NULL);
-        return_win_op!(SetupDiClassGuidsFromNameA(port_name_class.as_ptr(), guids.as_mut_ptr(), guids.len() as DWORD, &mut num_guids))?;
-
-        if num_guids == 0 {
-            guids.pop();
-        }
-
-        // Now add any modems
-        port_name_class = CString::new("Modem").unwrap();
-        let mut modem_guids: Vec<GUID> = Vec::new();
-        modem_guids.push(GUID_NULL);
-        return_win_op!(SetupDiClassGuidsFromNameA(port_name_class.as_ptr(), modem_guids.as_mut_ptr(), modem_guids.len() as DWORD, &mut num_guids))?;
-
-        if num_guids == 0 {
-            modem_guids.pop();
-        }
-
-        // Append modems to list of GUIDS
-        guids.append(&mut modem_guids);
-        let mut devices: Vec<PortInfo> = Vec::new();
-        for mut guid in guids {
-            //let mut b_interface_num: Option<u32> = None;
-            let g_hdi = unsafe {
-                SetupDiGetClassDevsA(&mut guid, ptr::null_mut(), ptr::null_mut(), DIGCF_PRESENT)
-            };
-            let mut dev_info: SP_DEVINFO_DATA = unsafe { std::mem::zeroed() };
-            dev_info.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as u32;
-            let mut idx = 0;
-            while unsafe { SetupDiEnumDeviceInfo(g_hdi, idx, &mut dev_info) } != 0 {
-                idx += 1;
-
-                let hkey = unsafe {
-                    SetupDiOpenDevRegKey(g_hdi, &mut dev_info, DICS_FLAG_GLOBAL, 0, DIREG_DEV, KEY_READ)
-                };
-                let mut port_name_buffer: [u8; PORT_NAME_LEN] = [0; PORT_NAME_LEN];
-                let mut port_name_len = PORT_NAME_LEN as ULONG;
-
-                let port_name_key = CString::new("PortName").unwrap();
-                unsafe { RegQueryValueExA(hkey, port_name_key.as_ptr(), ptr::null_mut(), ptr::null_mut(), port_name_buffer.as_mut_ptr(), &mut port_name_len) };
-                unsafe { RegCloseKey(hkey) };
-
-                let port_name = String::from_utf8(port_name_buffer[..port_name_len as usize].to_vec()).unwrap();
-
-                // Discard LPT Parallel ports
-                if port_name.starts_with("LPT") { continue; }
-                let mut hw_id_buffer: [u8; 500] = [0; 500];
-                let hw_id_len = 500 as ULONG;
-
-                if unsafe {
-                    SetupDiGetDeviceInstanceIdA(g_hdi, &mut dev_info, hw_id_buffer.as_mut_ptr() as *mut i8, hw_id_len-1, ptr::null_mut())
-                } == 0 {
-                    if unsafe {
-                        SetupDiGetDeviceRegistryPropertyA(g_hdi, &mut dev_info, SPDRP_HARDWAREID, ptr::null_mut(), hw_id_buffer.as_mut_ptr(), hw_id_len-1, ptr::null_mut())
-                    } == 0 {
-                        return Err(get_win_error())
-                    }
-                }
-
-                let mut tmp = String::from_utf8(hw_id_buffer.to_vec()).unwrap();
-                let hw_string = tmp.trim_matches(char::from(0x00));
-                let mut info = crate::PortInfo::default();
-                info.port = port_name;
-                if hw_string.starts_with("USB") {
-                    let regex = RegexBuilder::new(r"VID_([0-9a-f]{4})(&PID_([0-9a-f]{4}))?(&MI_(\d{2}))?(\\(.*))?").case_insensitive(true).build().unwrap();
-                    if let Some(captures) = regex.captures(&hw_string) {
-                        info.vid = u16::from_str_radix(captures.get(1).unwrap().as_str(), 16).unwrap();
-                        if let Some(m) = captures.get(3) {
-                            info.pid = u16::from_str_radix(m.as_str(), 16).unwrap();
-                        }
-                    }
-                } else if hw_string.starts_with("FTDIBUS") {
-                    
-                } else {
-                    info.hwid = hw_string.to_string();
-                }
-
-                let mut friendly_name_buffer: [u8; 500] = [0; 500];
-                let friendly_name_buffer_len = 500 as ULONG;
-                if unsafe {
-                    SetupDiGetDeviceRegistryPropertyA(g_hdi, &mut dev_info, SPDRP_FRIENDLYNAME, std::ptr::null_mut(), friendly_name_buffer.as_mut_ptr(), friendly_name_buffer_len-1, std::ptr::null_mut())
-                } != 0 {
-                    tmp = String::from_utf8_lossy(&friendly_name_buffer).to_string();
-                    info.description = tmp.trim_matches(char::from(0x00)).to_string();
-                }
-
-                friendly_name_buffer = [0x00; 500];
-                if unsafe {
-                    SetupDiGetDeviceRegistryPropertyA(g_hdi, &mut dev_info, SPDRP_MFG, std::ptr::null_mut(), friendly_name_buffer.as_mut_ptr(), friendly_name_buffer_len-1, std::ptr::null_mut())
-                } != 0 {
-                    tmp = String::from_utf8_lossy(&friendly_name_buffer).to_string();
-                    info.manufacturer = tmp.trim_matches(char::from(0x00)).to_string();
-                }
-                devices.push(info);
-            }
-            unsafe { SetupDiDestroyDeviceInfoList(g_hdi) };
-        }
-        return Ok(devices)
-    }
-}
\ No newline at end of file
+//! Windows port lister and enumerator
+
+use std::{ffi::CString, ptr};
+
+use regex::{RegexBuilder};
+use winapi::{um::{setupapi::{SetupDiClassGuidsFromNameA, SetupDiGetClassDevsA, DIGCF_ALLCLASSES, DIGCF_PRESENT, SP_DEVINFO_DATA, SetupDiEnumDeviceInfo, SetupDiOpenDevRegKey, DICS_FLAG_GLOBAL, DIREG_DEV, SetupDiGetDeviceInstanceIdA, SetupDiGetDeviceRegistryPropertyA, SPDRP_HARDWAREID, SPDRP_FRIENDLYNAME, SPDRP_MFG, SetupDiDestroyDeviceInfoList, HDEVINFO, DIGCF_DEVICEINTERFACE, SP_DEVICE_INTERFACE_DATA, SP_DEVICE_INTERFACE_DETAIL_DATA_W, SetupDiEnumDeviceInterfaces, SetupDiGetDeviceInterfaceDetailW}, cguid::GUID_NULL, handleapi::INVALID_HANDLE_VALUE, winnt::KEY_READ, winreg::{RegQueryValueExA, RegCloseKey}}, shared::{minwindef::DWORD, guiddef::GUID, ntdef::ULONG, ntddser::GUID_DEVINTERFACE_COMPORT}};
+
+use crate::{return_win_op, windows::error::get_win_error, SerialError, SerialResult, PortInfo};
+
+#[derive(Debug, Copy, Clone)]
+/// Windows COM Port lister, listing devices of the "Ports" and "Modem" setup classes
+pub struct COMPortLister {}
+
+#[derive(Debug, Copy, Clone)]
+/// Windows COM Port lister which lists devices exposing the COM port or modem
+/// device interface, whatever their setup class. This finds ports from drivers
+/// which install under another class, such as some USB CDC drivers.
+///
+/// Ports are named by their `COMx` name when they have one, otherwise by their
+/// device interface path, which [super::COMPort::new] can open directly
+pub struct DeviceInterfaceLister {}
+
+/// GUID_DEVINTERFACE_MODEM (not exported by winapi)
+const GUID_DEVINTERFACE_MODEM: GUID = GUID {
+    Data1: 0x2C7089AA,
+    Data2: 0x2E0E,
+    Data3: 0x11D1,
+    Data4: [0xB1, 0x14, 0x00, 0xC0, 0x4F, 0xC2, 0xAA, 0xE4],
+};
+
+const PORT_NAME_LEN: usize = 500;
+
+impl crate::PortScanner for COMPortLister {
+    fn list_devices(&mut self) -> SerialResult<Vec<crate::PortInfo>> {
+        let mut port_name_class = CString::new("Ports").unwrap();
+        let mut num_guids: DWORD = 0;
+        let mut guids: Vec<GUID> = Vec::new();
+        guids.push(GUID_NULL);
+        return_win_op!(SetupDiClassGuidsFromNameA(port_name_class.as_ptr(), guids.as_mut_ptr(), guids.len() as DWORD, &mut num_guids))?;
+
+        if num_guids == 0 {
+            guids.pop();
+        }
+
+        // Now add any modems
+        port_name_class = CString::new("Modem").unwrap();
+        let mut modem_guids: Vec<GUID> = Vec::new();
+        modem_guids.push(GUID_NULL);
+        return_win_op!(SetupDiClassGuidsFromNameA(port_name_class.as_ptr(), modem_guids.as_mut_ptr(), modem_guids.len() as DWORD, &mut num_guids))?;
+
+        if num_guids == 0 {
+            modem_guids.pop();
+        }
+
+        // Append modems to list of GUIDS
+        guids.append(&mut modem_guids);
+        let mut devices: Vec<PortInfo> = Vec::new();
+        for guid in guids {
+            //let mut b_interface_num: Option<u32> = None;
+            let g_hdi = unsafe {
+                SetupDiGetClassDevsA(&guid, ptr::null_mut(), ptr::null_mut(), DIGCF_PRESENT)
+            };
+            let mut dev_info: SP_DEVINFO_DATA = unsafe { std::mem::zeroed() };
+            dev_info.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as u32;
+            let mut idx = 0;
+            while unsafe { SetupDiEnumDeviceInfo(g_hdi, idx, &mut dev_info) } != 0 {
+                idx += 1;
+
+                let port_name = read_port_name(g_hdi, &mut dev_info).unwrap_or_default();
+                // Discard LPT Parallel ports
+                if port_name.starts_with("LPT") { continue; }
+                let info = describe_device(g_hdi, &mut dev_info, port_name)?;
+                devices.push(info);
+            }
+            unsafe { SetupDiDestroyDeviceInfoList(g_hdi) };
+        }
+        Ok(devices)
+    }
+}
+
+impl crate::PortScanner for DeviceInterfaceLister {
+    fn list_devices(&mut self) -> SerialResult<Vec<crate::PortInfo>> {
+        let mut devices: Vec<PortInfo> = Vec::new();
+        for guid in [GUID_DEVINTERFACE_COMPORT, GUID_DEVINTERFACE_MODEM] {
+            let g_hdi = unsafe {
+                SetupDiGetClassDevsA(&guid, ptr::null_mut(), ptr::null_mut(), DIGCF_PRESENT | DIGCF_DEVICEINTERFACE)
+            };
+            if g_hdi == INVALID_HANDLE_VALUE {
+                return Err(get_win_error());
+            }
+            let res = list_interfaces(g_hdi, &guid, &mut devices);
+            unsafe { SetupDiDestroyDeviceInfoList(g_hdi) };
+            res?;
+        }
+        Ok(devices)
+    }
+}
+
+/// Adds a [PortInfo] for every interface of class `guid` in `g_hdi`
+fn list_interfaces(g_hdi: HDEVINFO, guid: &GUID, devices: &mut Vec<PortInfo>) -> SerialResult<()> {
+    let mut if_data: SP_DEVICE_INTERFACE_DATA = unsafe { std::mem::zeroed() };
+    if_data.cbSize = std::mem::size_of::<SP_DEVICE_INTERFACE_DATA>() as u32;
+    let mut idx = 0;
+    while unsafe { SetupDiEnumDeviceInterfaces(g_hdi, ptr::null_mut(), guid, idx, &mut if_data) } != 0 {
+        idx += 1;
+        let mut required: DWORD = 0;
+        unsafe { SetupDiGetDeviceInterfaceDetailW(g_hdi, &mut if_data, ptr::null_mut(), 0, &mut required, ptr::null_mut()) };
+        if required == 0 {
+            return Err(get_win_error());
+        }
+        // u32 backing keeps the detail structure aligned
+        let mut detail_buffer = vec![0u32; (required as usize).div_ceil(4)];
+        let detail = detail_buffer.as_mut_ptr() as *mut SP_DEVICE_INTERFACE_DETAIL_DATA_W;
+        unsafe { (*detail).cbSize = std::mem::size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as u32 };
+        let mut dev_info: SP_DEVINFO_DATA = unsafe { std::mem::zeroed() };
+        dev_info.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as u32;
+        return_win_op!(SetupDiGetDeviceInterfaceDetailW(g_hdi, &mut if_data, detail, required, ptr::null_mut(), &mut dev_info))?;
+
+        let path_offset = std::mem::size_of::<DWORD>();
+        let path_units = (required as usize - path_offset) / 2;
+        let path_ptr = unsafe { (detail as *const u8).add(path_offset) as *const u16 };
+        let path_wide = unsafe { std::slice::from_raw_parts(path_ptr, path_units) };
+        let path_len = path_wide.iter().position(|c| *c == 0).unwrap_or(path_wide.len());
+        let path = String::from_utf16_lossy(&path_wide[..path_len]);
+
+        let port_name = read_port_name(g_hdi, &mut dev_info).unwrap_or(path);
+        if port_name.starts_with("LPT") { continue; }
+        devices.push(describe_device(g_hdi, &mut dev_info, port_name)?);
+    }
+    Ok(())
+}
+
+/// Finds the device instance of the present device whose `COMx` name is `port`
+pub(crate) fn find_device_instance(port: &str) -> SerialResult<DWORD> {
+    let name = port.trim_start_matches(r"\\.\");
+    let g_hdi = unsafe {
+        SetupDiGetClassDevsA(ptr::null(), ptr::null_mut(), ptr::null_mut(), DIGCF_PRESENT | DIGCF_ALLCLASSES)
+    };
+    if g_hdi == INVALID_HANDLE_VALUE {
+        return Err(get_win_error());
+    }
+    let mut dev_info: SP_DEVINFO_DATA = unsafe { std::mem::zeroed() };
+    dev_info.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as u32;
+    let mut idx = 0;
+    let mut found = None;
+    while unsafe { SetupDiEnumDeviceInfo(g_hdi, idx, &mut dev_info) } != 0 {
+        idx += 1;
+        if read_port_name(g_hdi, &mut dev_info).map(|n| n.eq_ignore_ascii_case(name)).unwrap_or(false) {
+            found = Some(dev_info.DevInst);
+            break;
+        }
+    }
+    unsafe { SetupDiDestroyDeviceInfoList(g_hdi) };
+    found.ok_or_else(|| SerialError::LibraryError(format!("No device found for {port}")))
+}
+
+/// Reads the `COMx` name of a device from its registry key
+fn read_port_name(g_hdi: HDEVINFO, dev_info: &mut SP_DEVINFO_DATA) -> Option<String> {
+    let hkey = unsafe {
+        SetupDiOpenDevRegKey(g_hdi, dev_info, DICS_FLAG_GLOBAL, 0, DIREG_DEV, KEY_READ)
+    };
+    let mut port_name_buffer: [u8; PORT_NAME_LEN] = [0; PORT_NAME_LEN];
+    let mut port_name_len = PORT_NAME_LEN as ULONG;
+
+    let port_name_key = CString::new("PortName").unwrap();
+    unsafe { RegQueryValueExA(hkey, port_name_key.as_ptr(), ptr::null_mut(), ptr::null_mut(), port_name_buffer.as_mut_ptr(), &mut port_name_len) };
+    unsafe { RegCloseKey(hkey) };
+
+    // The value is NUL terminated
+    String::from_utf8(port_name_buffer[..port_name_len as usize].to_vec()).ok()
+        .map(|name| name.trim_matches(char::from(0x00)).to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Builds the [PortInfo] for a device, reading its IDs and description
+fn describe_device(g_hdi: HDEVINFO, dev_info: &mut SP_DEVINFO_DATA, port_name: String) -> SerialResult<PortInfo> {
+    let mut hw_id_buffer: [u8; 500] = [0; 500];
+    let hw_id_len = 500 as ULONG;
+
+    if unsafe {
+        SetupDiGetDeviceInstanceIdA(g_hdi, dev_info, hw_id_buffer.as_mut_ptr() as *mut i8, hw_id_len-1, ptr::null_mut())
+    } == 0 && unsafe {
+        SetupDiGetDeviceRegistryPropertyA(g_hdi, dev_info, SPDRP_HARDWAREID, ptr::null_mut(), hw_id_buffer.as_mut_ptr(), hw_id_len-1, ptr::null_mut())
+    } == 0 {
+        return Err(get_win_error())
+    }
+
+    let mut tmp = String::from_utf8(hw_id_buffer.to_vec()).unwrap();
+    let hw_string = tmp.trim_matches(char::from(0x00));
+    let mut info = crate::PortInfo { port: port_name, ..Default::default() };
+    if hw_string.starts_with("USB") {
+        let regex = RegexBuilder::new(r"VID_([0-9a-f]{4})(&PID_([0-9a-f]{4}))?(&MI_(\d{2}))?(\\(.*))?").case_insensitive(true).build().unwrap();
+        if let Some(captures) = regex.captures(hw_string) {
+            info.vid = u16::from_str_radix(captures.get(1).unwrap().as_str(), 16).unwrap();
+            if let Some(m) = captures.get(3) {
+                info.pid = u16::from_str_radix(m.as_str(), 16).unwrap();
+            }
+        }
+    } else if hw_string.starts_with("FTDIBUS") {
+
+    } else {
+        info.hwid = hw_string.to_string();
+    }
+
+    let mut friendly_name_buffer: [u8; 500] = [0; 500];
+    let friendly_name_buffer_len = 500 as ULONG;
+    if unsafe {
+        SetupDiGetDeviceRegistryPropertyA(g_hdi, dev_info, SPDRP_FRIENDLYNAME, std::ptr::null_mut(), friendly_name_buffer.as_mut_ptr(), friendly_name_buffer_len-1, std::ptr::null_mut())
+    } != 0 {
+        tmp = String::from_utf8_lossy(&friendly_name_buffer).to_string();
+        info.description = tmp.trim_matches(char::from(0x00)).to_string();
+    }
+
+    friendly_name_buffer = [0x00; 500];
+    if unsafe {
+        SetupDiGetDeviceRegistryPropertyA(g_hdi, dev_info, SPDRP_MFG, std::ptr::null_mut(), friendly_name_buffer.as_mut_ptr(), friendly_name_buffer_len-1, std::ptr::null_mut())
+    } != 0 {
+        tmp = String::from_utf8_lossy(&friendly_name_buffer).to_string();
+        info.manufacturer = tmp.trim_matches(char::from(0x00)).to_string();
+    }
+    Ok(info)
+}
@@ -0,0 +1,145 @@
+//! Bit error rate tests, over a virtual port pair whose other end echoes
+
+mod common;
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use serial_rs::{
+    bert::{run_loopback, Limit, Pattern, PatternChecker, PatternGenerator},
+    prelude::*,
+    SerialPortSettings,
+};
+
+fn generate(pattern: Pattern, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    PatternGenerator::new(pattern).fill(&mut buf);
+    buf
+}
+
+/// Bits of `data`, least significant first as they are sent
+fn bits(data: &[u8]) -> Vec<u8> {
+    data.iter().flat_map(|b| (0..8).map(move |i| (b >> i) & 1)).collect()
+}
+
+#[test]
+fn prbs_periods() {
+    for (pattern, period) in [(Pattern::Prbs9, 511), (Pattern::Prbs15, 32767)] {
+        let bits = bits(&generate(pattern, period * 2 / 8 + 1));
+        assert_eq!(bits[..period], bits[period..period * 2], "{pattern:?} does not repeat every {period} bits");
+        // A maximal length sequence has no shorter period
+        assert!((1..period).all(|p| bits[..period] != bits[p..p + period]), "{pattern:?} repeats early");
+        // and one more one than zero in each period
+        assert_eq!(bits[..period].iter().filter(|b| **b == 1).count(), period / 2 + 1);
+    }
+}
+
+#[test]
+fn fixed_patterns() {
+    assert!(generate(Pattern::Alternating, 16).iter().all(|b| *b == 0x55));
+    assert!(generate(Pattern::Fixed(0xA3), 16).iter().all(|b| *b == 0xA3));
+}
+
+#[test]
+fn checker_syncs_anywhere_in_the_pattern() {
+    for pattern in [Pattern::Prbs9, Pattern::Prbs15] {
+        let data = generate(pattern, 5000);
+        let mut checker = PatternChecker::new(pattern);
+        checker.check(&data[1234..]);
+        let report = checker.report();
+        assert_eq!(report.bytes_received, 5000 - 1234);
+        assert!(report.bytes_checked > 0);
+        assert_eq!((report.bit_errors, report.errored_bytes, report.sync_losses), (0, 0, 0));
+    }
+}
+
+#[test]
+fn checker_counts_bit_errors() {
+    let mut data = generate(Pattern::Prbs15, 4000);
+    data[1000] ^= 0x01;
+    data[2000] ^= 0x81;
+    let mut checker = PatternChecker::new(Pattern::Prbs15);
+    checker.check(&data);
+    let report = checker.report();
+    assert_eq!((report.bit_errors, report.errored_bytes, report.sync_losses), (3, 2, 0));
+    assert_eq!(report.bit_error_rate(), 3.0 / report.bits_checked() as f64);
+}
+
+#[test]
+fn checker_regains_sync_after_a_dropped_byte() {
+    let mut data = generate(Pattern::Prbs9, 4000);
+    data.remove(1000);
+    let mut checker = PatternChecker::new(Pattern::Prbs9);
+    checker.check(&data);
+    let report = checker.report();
+    assert_eq!(report.sync_losses, 1);
+    // Only the bytes up to the loss of sync are wrong, and everything after it is
+    // checked again once synchronised
+    assert!(report.errored_bytes <= 4, "{report:?}");
+    assert!(report.bytes_checked >= 3990, "{report:?}");
+}
+
+/// Runs a loopback test on one end of a pair, whilst the other end echoes what it
+/// receives, passed through `corrupt`
+fn loopback(pattern: Pattern, limit: Limit, corrupt: impl Fn(usize, &mut [u8]) + Sync) -> Option<serial_rs::bert::BertReport> {
+    let (port, echo) = common::pair(SerialPortSettings::default())?;
+    let stop = AtomicBool::new(false);
+    let report = std::thread::scope(|s| {
+        s.spawn(|| {
+            let mut buf = [0u8; 256];
+            let mut echoed = 0;
+            while !stop.load(Ordering::SeqCst) {
+                if !echo.poll_readable(Some(Duration::from_millis(20))).unwrap() {
+                    continue;
+                }
+                let n = echo.read_shared(&mut buf).unwrap();
+                corrupt(echoed, &mut buf[..n]);
+                echoed += n;
+                let mut written = 0;
+                while written < n {
+                    written += echo.write_shared(&buf[written..n]).unwrap();
+                }
+            }
+        });
+        let report = run_loopback(&port, pattern, limit);
+        stop.store(true, Ordering::SeqCst);
+        report
+    });
+    Some(report.unwrap())
+}
+
+#[test]
+fn clean_loopback() {
+    for pattern in [Pattern::Prbs9, Pattern::Prbs15, Pattern::Alternating] {
+        let Some(report) = loopback(pattern, Limit::Bytes(20_000), |_, _| {}) else { return };
+        assert_eq!(report.bytes_sent, 20_000);
+        assert_eq!(report.bytes_received, 20_000);
+        assert_eq!(report.bytes_lost(), 0);
+        assert_eq!(report.bit_errors, 0, "{pattern:?}: {report:?}");
+    }
+}
+
+#[test]
+fn corrupted_loopback() {
+    // Flip the lowest bit of every 1000th byte
+    let corrupt = |offset: usize, data: &mut [u8]| {
+        for (i, b) in data.iter_mut().enumerate() {
+            if (offset + i) % 1000 == 500 {
+                *b ^= 1;
+            }
+        }
+    };
+    let Some(report) = loopback(Pattern::Prbs15, Limit::Bytes(20_000), corrupt) else { return };
+    assert_eq!(report.bytes_received, 20_000);
+    assert_eq!((report.bit_errors, report.errored_bytes, report.sync_losses), (20, 20, 0));
+}
+
+#[test]
+fn timed_loopback() {
+    let Some(report) = loopback(Pattern::Prbs9, Limit::Duration(Duration::from_millis(300)), |_, _| {}) else { return };
+    assert!(report.bytes_sent > 0);
+    assert!(report.elapsed >= Duration::from_millis(300));
+    assert_eq!((report.bytes_lost(), report.bit_errors), (0, 0));
+}
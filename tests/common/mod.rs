@@ -0,0 +1,47 @@
+//! Helpers shared by the integration tests
+//!
+//! Tests run over two connected ports. On POSIX these are the two ends of a pseudo
+//! terminal. Any platform can instead use a pair of real or virtual ports (such as
+//! a com0com pair on Windows) named in [PAIR_VAR]
+
+#![allow(dead_code)]
+
+use serial_rs::SerialPortSettings;
+
+#[cfg(unix)]
+pub type Port = serial_rs::posix::TTYPort;
+#[cfg(windows)]
+pub type Port = serial_rs::windows::COMPort;
+
+/// Environment variable naming two connected ports, as `first,second`
+pub const PAIR_VAR: &str = "SERIAL_RS_TEST_PAIR";
+
+/// Opens two connected ports with `settings`. Returns None if no pair is available,
+/// in which case the test should return without checking anything
+pub fn pair(settings: SerialPortSettings) -> Option<(Port, Port)> {
+    match std::env::var(PAIR_VAR) {
+        Ok(names) => {
+            let (a, b) = names.split_once(',').unwrap_or_else(|| panic!("{PAIR_VAR} must be two comma separated port names"));
+            Some((Port::new(a.trim().into(), Some(settings)).unwrap(), Port::new(b.trim().into(), Some(settings)).unwrap()))
+        }
+        Err(_) => virtual_pair(settings),
+    }
+}
+
+#[cfg(unix)]
+fn virtual_pair(settings: SerialPortSettings) -> Option<(Port, Port)> {
+    use std::os::unix::io::IntoRawFd;
+    let pty = nix::pty::openpty(None, None).unwrap();
+    unsafe {
+        Some((
+            Port::from_raw_fd_with_settings(pty.master.into_raw_fd(), settings).unwrap(),
+            Port::from_raw_fd_with_settings(pty.slave.into_raw_fd(), settings).unwrap(),
+        ))
+    }
+}
+
+#[cfg(windows)]
+fn virtual_pair(_settings: SerialPortSettings) -> Option<(Port, Port)> {
+    eprintln!("Skipped, set {PAIR_VAR} to a pair of connected ports to run");
+    None
+}
@@ -0,0 +1,49 @@
+//! embedded-io and embedded-io-async traits over a virtual port pair
+#![cfg(feature = "embedded-io-async")]
+
+mod common;
+
+use std::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use embedded_io::{Read, ReadReady, Write};
+use serial_rs::SerialPortSettings;
+
+/// Polls `fut` once, as the async implementations complete without waiting
+fn now<F: Future>(fut: F) -> F::Output {
+    match pin!(fut).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(out) => out,
+        Poll::Pending => panic!("future was not ready"),
+    }
+}
+
+#[test]
+fn blocking_round_trip() {
+    let Some((mut tx, mut rx)) = common::pair(SerialPortSettings::default().read_timeout(Some(1000))) else { return };
+    tx.write_all(b"hello").unwrap();
+    tx.flush().unwrap();
+    let mut buf = [0u8; 5];
+    rx.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    assert!(!rx.read_ready().unwrap());
+}
+
+#[test]
+fn async_round_trip() {
+    let Some((mut tx, mut rx)) = common::pair(SerialPortSettings::default().read_timeout(Some(1000))) else { return };
+    now(embedded_io_async::Write::write_all(&mut tx, b"hello")).unwrap();
+    now(embedded_io_async::Write::flush(&mut tx)).unwrap();
+    let mut buf = [0u8; 5];
+    now(embedded_io_async::Read::read_exact(&mut rx, &mut buf)).unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn timeouts_map_to_timed_out() {
+    let Some((_tx, mut rx)) = common::pair(SerialPortSettings::default().read_timeout(Some(50))) else { return };
+    let err = now(embedded_io_async::Read::read(&mut rx, &mut [0u8; 4])).unwrap_err();
+    assert_eq!(embedded_io::Error::kind(&err), embedded_io::ErrorKind::TimedOut);
+}
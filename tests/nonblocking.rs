@@ -0,0 +1,64 @@
+//! Non-blocking reads and writes fail with WouldBlock on every platform
+
+mod common;
+
+use std::{io::ErrorKind, time::{Duration, Instant}};
+
+use serial_rs::{prelude::*, SerialPortSettings};
+
+/// Longest a non-blocking call may take, well under the timeouts set below
+const PROMPT: Duration = Duration::from_millis(100);
+
+#[test]
+fn read_without_data_would_block() {
+    let settings = SerialPortSettings::default().set_blocking(false).read_timeout(Some(1000));
+    let Some((_remote, port)) = common::pair(settings) else { return };
+    let start = Instant::now();
+    let err = port.read_shared(&mut [0u8; 8]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    assert!(start.elapsed() < PROMPT, "non-blocking read waited {:?}", start.elapsed());
+}
+
+#[test]
+fn read_returns_queued_data() {
+    let settings = SerialPortSettings::default().set_blocking(false);
+    let Some((remote, port)) = common::pair(settings) else { return };
+    remote.write_shared(b"abc").unwrap();
+    assert!(port.poll_readable(Some(Duration::from_secs(1))).unwrap());
+    let mut buf = [0u8; 8];
+    let read = port.read_shared(&mut buf).unwrap();
+    assert_eq!(&buf[..read], b"abc");
+    assert_eq!(port.read_shared(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+}
+
+#[test]
+fn set_blocking_switches_an_open_port() {
+    let settings = SerialPortSettings::default().read_timeout(Some(200));
+    let Some((_remote, mut port)) = common::pair(settings) else { return };
+    let mut buf = [0u8; 8];
+    assert_eq!(port.read_shared(&mut buf).unwrap_err().kind(), ErrorKind::TimedOut);
+    port.set_blocking(false).unwrap();
+    let start = Instant::now();
+    assert_eq!(port.read_shared(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert!(start.elapsed() < PROMPT);
+}
+
+#[test]
+fn write_to_full_buffer_would_block() {
+    let settings = SerialPortSettings::default().set_blocking(false).write_timeout(Some(1000));
+    let Some((_remote, port)) = common::pair(settings) else { return };
+    let chunk = [0x55u8; 1024];
+    let start = Instant::now();
+    // Nothing reads the other end, so the buffers fill up
+    let err = loop {
+        match port.write_shared(&chunk) {
+            Ok(_) if start.elapsed() < Duration::from_secs(10) => {}
+            Ok(_) => panic!("write never filled the buffers"),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    let start = Instant::now();
+    assert_eq!(port.write_shared(&chunk).unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert!(start.elapsed() < PROMPT);
+}
@@ -0,0 +1,42 @@
+//! Looking at received data without consuming it
+
+mod common;
+
+use std::time::Duration;
+
+use serial_rs::{prelude::*, SerialPortSettings};
+
+fn read(port: &common::Port) -> Vec<u8> {
+    let mut buf = [0u8; 16];
+    let n = port.read_shared(&mut buf).unwrap();
+    buf[..n].to_vec()
+}
+
+#[test]
+fn peeked_data_is_read_in_order() {
+    let Some((remote, port)) = common::pair(SerialPortSettings::default().read_timeout(Some(1000))) else { return };
+    remote.write_shared(b"abc").unwrap();
+    assert!(port.wait_for_bytes(3, Some(Duration::from_secs(1))).unwrap());
+    let mut buf = [0u8; 2];
+    assert_eq!(port.peek(&mut buf).unwrap(), 2);
+    assert_eq!(&buf, b"ab");
+    remote.write_shared(b"d").unwrap();
+    assert!(port.wait_for_bytes(4, Some(Duration::from_secs(1))).unwrap());
+    let mut all = Vec::new();
+    while all.len() < 4 {
+        all.extend(read(&port));
+    }
+    assert_eq!(all, b"abcd");
+}
+
+#[test]
+fn clearing_drops_peeked_data() {
+    let Some((remote, port)) = common::pair(SerialPortSettings::default().read_timeout(Some(1000))) else { return };
+    remote.write_shared(b"abc").unwrap();
+    assert!(port.wait_for_bytes(3, Some(Duration::from_secs(1))).unwrap());
+    assert_eq!(port.peek(&mut [0u8; 8]).unwrap(), 3);
+    port.clear_input_buffer().unwrap();
+    assert_eq!(port.bytes_to_read().unwrap(), 0);
+    remote.write_shared(b"d").unwrap();
+    assert_eq!(read(&port), b"d");
+}
@@ -0,0 +1,27 @@
+//! Reading whatever has been received, without waiting for more
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use serial_rs::{prelude::*, SerialPortSettings};
+
+#[test]
+fn read_available_returns_queued_data() {
+    let Some((remote, port)) = common::pair(SerialPortSettings::default().read_timeout(Some(2000))) else { return };
+    remote.write_shared(b"abc").unwrap();
+    assert!(port.wait_for_bytes(3, Some(Duration::from_secs(1))).unwrap());
+    assert_eq!(port.read_available().unwrap(), b"abc");
+    assert_eq!(port.read_available().unwrap(), b"");
+}
+
+#[test]
+fn read_available_does_not_wait_for_dropped_bytes() {
+    let settings = SerialPortSettings::default().read_timeout(Some(2000)).discard_nul(true);
+    let Some((remote, port)) = common::pair(settings) else { return };
+    remote.write_shared(b"a\0\0\0b").unwrap();
+    assert!(port.wait_for_bytes(5, Some(Duration::from_secs(1))).unwrap());
+    let start = Instant::now();
+    assert_eq!(port.read_available().unwrap(), b"ab");
+    assert!(start.elapsed() < Duration::from_millis(500), "read_available waited {:?}", start.elapsed());
+}
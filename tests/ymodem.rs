@@ -0,0 +1,240 @@
+//! YMODEM transfers over a pair of connected ports.
+//!
+//! The ignored tests compare against lrzsz, the reference implementation, and need
+//! its `sb` and `rb` commands on the PATH. Run them with `cargo test -- --ignored`
+
+mod common;
+
+use std::{
+    cell::RefCell,
+    io::Write,
+    rc::Rc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use serial_rs::{
+    prelude::*,
+    transfer::{crc16, ymodem::{FileInfo, Ymodem}, BlockSize},
+    SerialPortSettings,
+};
+
+/// A file's details and contents
+type FileData = (FileInfo, Vec<u8>);
+
+/// Files received in memory, in the order they arrived
+#[derive(Debug, Default, Clone)]
+struct Received(Rc<RefCell<Vec<FileData>>>);
+
+impl Received {
+    /// Starts a new file, returning where to write its contents
+    fn open(&self, info: &FileInfo) -> std::io::Result<Received> {
+        self.0.borrow_mut().push((info.clone(), Vec::new()));
+        Ok(self.clone())
+    }
+
+    fn files(&self) -> Vec<FileData> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Write for Received {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().last_mut().expect("write before open").1.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+fn batch() -> Vec<FileData> {
+    let sized = |name: &str, data: Vec<u8>| (FileInfo { size: Some(data.len() as u64), ..FileInfo::new(name) }, data);
+    vec![
+        sized("first.bin", pattern(3000, 1)),
+        sized("empty.txt", Vec::new()),
+        sized("exact.bin", pattern(2048, 7)),
+        (
+            FileInfo { size: Some(100), modified: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)), mode: Some(0o100644), ..FileInfo::new("dir/details.txt") },
+            pattern(100, 3),
+        ),
+    ]
+}
+
+/// Sends `files` from one port of a pair and receives them on the other
+fn loopback(sender: Ymodem, receiver: Ymodem, files: Vec<FileData>) -> Vec<FileData> {
+    let Some((mut tx, mut rx)) = common::pair(SerialPortSettings::default()) else { return files };
+    // The port is returned so it stays open, as closing one end of a pseudo terminal
+    // hangs up the other before it has read everything
+    let send = std::thread::spawn(move || {
+        let files = files.into_iter().map(|(info, data)| (info, std::io::Cursor::new(data)));
+        sender.send(&mut tx, files, |_, _| true).map(|_| tx)
+    });
+    let received = Received::default();
+    let infos = receiver.receive(&mut rx, |info| received.open(info), |_, _| true).unwrap();
+    send.join().unwrap().unwrap();
+    assert_eq!(infos, received.files().into_iter().map(|(info, _)| info).collect::<Vec<_>>());
+    received.files()
+}
+
+#[test]
+fn batch_loopback() {
+    assert_eq!(loopback(Ymodem::new(), Ymodem::new(), batch()), batch());
+}
+
+#[test]
+fn standard_blocks_loopback() {
+    assert_eq!(loopback(Ymodem::new().block_size(BlockSize::Standard), Ymodem::new(), batch()), batch());
+}
+
+#[test]
+fn ymodem_g_loopback() {
+    assert_eq!(loopback(Ymodem::new(), Ymodem::ymodem_g(), batch()), batch());
+}
+
+#[test]
+fn padding_is_kept_without_a_size() {
+    let files = loopback(Ymodem::new(), Ymodem::new(), vec![(FileInfo::new("unsized"), b"hello".to_vec())]);
+    let (info, data) = &files[0];
+    assert_eq!(info.size, None);
+    // A short last block is sent as a 128 byte block
+    assert_eq!(data.len(), 128);
+    assert!(data.starts_with(b"hello") && data[5..].iter().all(|b| *b == 0x1A));
+}
+
+/// Waits for `byte` from the receiver, skipping repeated start requests
+fn expect<P: SerialIo>(port: &P, byte: u8) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut buf = [0u8; 1];
+    while Instant::now() < deadline {
+        if port.poll_readable(Some(deadline - Instant::now())).unwrap() && port.read_shared(&mut buf).unwrap() == 1 && buf[0] == byte {
+            return;
+        }
+    }
+    panic!("Timed out waiting for {byte:#04x}");
+}
+
+/// Sends a 128 byte block with a CRC, as laid out in the YMODEM specification
+fn send_block<P: SerialIo>(port: &P, num: u8, data: &[u8]) {
+    let mut block = vec![0x01, num, !num];
+    let start = block.len();
+    block.extend_from_slice(data);
+    block.resize(start + 128, if num == 0 { 0 } else { 0x1A });
+    block.extend_from_slice(&crc16(&block[start..]).to_be_bytes());
+    port.write_shared(&block).unwrap();
+}
+
+/// Plays the sender's side of a transfer byte for byte, with the header lrzsz sends
+#[test]
+fn receives_reference_header() {
+    let Some((tx, mut rx)) = common::pair(SerialPortSettings::default()) else { return };
+    let receive = std::thread::spawn(move || {
+        let received = Received::default();
+        Ymodem::new().receive(&mut rx, |info| received.open(info), |_, _| true).unwrap();
+        (received.files(), rx)
+    });
+    expect(&tx, b'C');
+    send_block(&tx, 0, b"hello.txt\x0011 14435621234 100644 0 1 11");
+    expect(&tx, 0x06);
+    expect(&tx, b'C');
+    send_block(&tx, 1, b"hello world");
+    expect(&tx, 0x06);
+    tx.write_shared(&[0x04]).unwrap();
+    expect(&tx, 0x15);
+    tx.write_shared(&[0x04]).unwrap();
+    expect(&tx, 0x06);
+    expect(&tx, b'C');
+    send_block(&tx, 0, &[]);
+    expect(&tx, 0x06);
+
+    let (files, _rx) = receive.join().unwrap();
+    let expected = FileInfo {
+        size: Some(11),
+        modified: Some(UNIX_EPOCH + Duration::from_secs(0o14435621234)),
+        mode: Some(0o100644),
+        ..FileInfo::new("hello.txt")
+    };
+    assert_eq!(files, vec![(expected, b"hello world".to_vec())]);
+}
+
+#[cfg(unix)]
+mod lrzsz {
+    use std::{
+        fs::File,
+        os::unix::io::FromRawFd,
+        path::PathBuf,
+        process::{Child, Command, Stdio},
+    };
+
+    use super::*;
+
+    /// Runs `cmd` with its stdin and stdout on one end of a pseudo terminal,
+    /// returning the other end. The returned file keeps the command's end open
+    /// after it exits, so nothing it sent last is lost to the hangup
+    fn spawn(cmd: &mut Command) -> (common::Port, Child, File) {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let port = unsafe { common::Port::from_raw_fd_with_settings(pty.master, SerialPortSettings::default()) }.unwrap();
+        let remote = unsafe { File::from_raw_fd(pty.slave) };
+        let child = cmd
+            .stdin(Stdio::from(remote.try_clone().unwrap()))
+            .stdout(Stdio::from(remote.try_clone().unwrap()))
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| panic!("Failed to run {cmd:?}, is lrzsz installed? {e}"));
+        (port, child, remote)
+    }
+
+    /// Empty directory for the files of one test
+    fn work_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("serial-rs-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn receive_from_sb(receiver: Ymodem, test: &str) {
+        let dir = work_dir(test);
+        let files = [("first.bin", pattern(3000, 1)), ("empty.txt", Vec::new()), ("exact.bin", pattern(2048, 7))];
+        for (name, data) in &files {
+            std::fs::write(dir.join(name), data).unwrap();
+        }
+        let (mut port, mut child, _remote) = spawn(Command::new("sb").current_dir(&dir).args(files.iter().map(|(name, _)| name)));
+        let received = Received::default();
+        receiver.receive(&mut port, |info| received.open(info), |_, _| true).unwrap();
+        assert!(child.wait().unwrap().success());
+        let received = received.files().into_iter().map(|(info, data)| (info.name, data)).collect::<Vec<_>>();
+        assert_eq!(received, files.map(|(name, data)| (name.to_string(), data)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs lrzsz"]
+    fn receives_from_sb() {
+        receive_from_sb(Ymodem::new(), "receives-from-sb");
+    }
+
+    #[test]
+    #[ignore = "needs lrzsz"]
+    fn receives_ymodem_g_from_sb() {
+        receive_from_sb(Ymodem::ymodem_g(), "receives-ymodem-g-from-sb");
+    }
+
+    #[test]
+    #[ignore = "needs lrzsz"]
+    fn sends_to_rb() {
+        let dir = work_dir("sends-to-rb");
+        let (mut port, mut child, _remote) = spawn(Command::new("rb").current_dir(&dir));
+        let files = batch().into_iter().filter(|(info, _)| !info.name.contains('/')).collect::<Vec<_>>();
+        let sending = files.iter().map(|(info, data)| (info.clone(), data.as_slice()));
+        Ymodem::new().send(&mut port, sending, |_, _| true).unwrap();
+        assert!(child.wait().unwrap().success());
+        for (info, data) in files {
+            assert_eq!(std::fs::read(dir.join(&info.name)).unwrap(), data, "{}", info.name);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}